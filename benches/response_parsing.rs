@@ -0,0 +1,79 @@
+//! Compares decoding responses into `XcpFrameBuf` with copying their payload
+//! into a `Vec<u8>`, the way the parsers used to.
+//!
+//! Run with `cargo bench --bench response_parsing`. Prints the time and heap
+//! allocations per decoded frame for both approaches.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use xcp_tools::xcp::frame::XcpResponseFrame;
+use xcp_tools::xcp::xcp_command::{GetIdResponse, GetSeedResponse, NegativeResponse, UploadResponse};
+
+/// Counts every allocation made through the global allocator.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: usize = 1_000_000;
+
+/// Runs `parse` over `frame` `ITERATIONS` times, returning ns and allocations per run.
+fn measure<F: Fn(&[u8])>(frame: &[u8], parse: F) -> (f64, f64) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        parse(black_box(frame));
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    (
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        allocations as f64 / ITERATIONS as f64,
+    )
+}
+
+fn report<F: Fn(&[u8]), G: Fn(&[u8])>(name: &str, frame: &[u8], parse: F, copy: G) {
+    let (parse_ns, parse_allocs) = measure(frame, parse);
+    let (copy_ns, copy_allocs) = measure(frame, copy);
+    println!(
+        "{:<10} XcpFrameBuf {:>7.1} ns {:>4.1} allocs | Vec<u8> {:>7.1} ns {:>4.1} allocs",
+        name, parse_ns, parse_allocs, copy_ns, copy_allocs
+    );
+}
+
+fn main() {
+    let upload = [0xFF, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+    let get_seed = [0xFF, 0x06, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6];
+    // CAN FD: the identification follows the header in the response itself
+    let get_id = [0xFF, 0x01, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, b'E', b'C', b'U', b'_', b'A', b'S', b'A', b'P'];
+    let negative = [0xFE, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00];
+
+    report("UPLOAD", &upload,
+        |frame| { black_box(XcpResponseFrame::<UploadResponse>::from_can_frame(frame)); },
+        |frame| { black_box(frame[1..].to_vec()); });
+    report("GET_SEED", &get_seed,
+        |frame| { black_box(XcpResponseFrame::<GetSeedResponse>::from_can_frame(frame)); },
+        |frame| { black_box(frame[2..].to_vec()); });
+    report("GET_ID", &get_id,
+        |frame| { black_box(XcpResponseFrame::<GetIdResponse>::from_can_frame(frame)); },
+        |frame| { black_box(frame[8..].to_vec()); });
+    report("ERR", &negative,
+        |frame| { black_box(XcpResponseFrame::<NegativeResponse>::from_can_frame(frame)); },
+        |frame| { black_box(frame[2..].to_vec()); });
+}
@@ -0,0 +1,73 @@
+//! Programs an Intel HEX, S-record or ELF image into a slave and prints the
+//! `FlashReport`, as JSON on stdout with `--json` for CI pipelines.
+//!
+//! ```text
+//! cargo run --example flash --features serde -- [--json] <interface> <cmd>:<res> <image>
+//! ```
+//!
+//! The image format follows the file extension, Intel HEX unless it is an ELF or
+//! S-record extension. Exits with 1 if flashing failed or a sector did not
+//! verify, also printing the report of how far it got.
+
+use std::env;
+use std::path::Path;
+use std::process::ExitCode;
+use socketcan::{CanSocket, Socket};
+use xcp_tools::xcp::can_ids::CanIdPair;
+use xcp_tools::xcp::error::XcpError;
+use xcp_tools::xcp::flash::{ElfOptions, FlashOptions, FlashReport};
+use xcp_tools::xcp::quick;
+
+const USAGE: &str = "usage: flash [--json] <interface> <cmd>:<res> <image.hex|.s19|.elf>";
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == "--json");
+    args.retain(|arg| arg != "--json");
+    let [iface, ids, image] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    let ids: CanIdPair = match ids.parse() {
+        Ok(ids) => ids,
+        Err(e) => {
+            eprintln!("invalid CAN IDs {}: {}\n{}", ids, e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = CanSocket::open(iface).map_err(XcpError::from).and_then(|mut socket| {
+        quick::session(&mut socket, ids, |master| {
+            let options = FlashOptions::default();
+            match Path::new(image).extension().and_then(|extension| extension.to_str()) {
+                Some("elf") => master.flash_elf_file(image, &ElfOptions::default(), options, None),
+                Some("s19" | "s28" | "s37" | "srec" | "mot") => master.flash_srec_file(image, options, None),
+                _ => master.flash_ihex_file(image, options, None),
+            }
+        })
+    });
+
+    match result {
+        Ok(report) => {
+            print_report(&report, json);
+            if report.is_verified() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Err(XcpError::FlashFailed { report, source, .. }) => {
+            print_report(&report, json);
+            eprintln!("flashing failed: {}", source);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("flashing failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_report(report: &FlashReport, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report).expect("reports always serialize"));
+    } else {
+        println!("{}", report);
+    }
+}
@@ -2,9 +2,26 @@
 //!
 //! This module sets up and manages XCP communication, including
 //! connecting to the XCP server and retrieving seed data.
+//!
+//! # Getting started
+//!
+//! `xcp::quick` runs one operation in a session of its own, connecting and
+//! disconnecting around it:
+//!
+//! ```no_run
+//! use xcp_tools::xcp::quick;
+//!
+//! // slave on can0 receiving commands on 0x7E0 and answering on 0x7E8
+//! let data = quick::read("can0", 0x7E0, 0x7E8, 0x2000_0000, 16)?;
+//! quick::write("can0", 0x7E0, 0x7E8, 0x2000_0000, &data)?;
+//! # Ok::<(), xcp_tools::xcp::error::XcpError>(())
+//! ```
+//!
+//! For more than one operation, create an `xcp::master::XcpMaster` on a socket
+//! and keep the session open.
 
 extern crate socketcan;
-mod xcp;
+pub mod xcp;
 
 use socketcan::{CanSocket, Socket};
 use xcp::xcp_command::{ConnectMode, XcpResource, XcpResourceFlags};
@@ -18,19 +35,15 @@ mod tests {
     /// CAN bus ID for XCP response frames.
     const XCP_RESPONSE_ID: u32 = 0x000;
     use super::*;
+    use xcp::can_ids::{CanId, CanIdPair};
 
     #[test]
     fn unlock() {
         let iface = "can0";
-        let mut sock: CanSocket = CanSocket::open(&iface).expect("Failed to open socket on interface");
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
 
-        let mut master = xcp::master::XcpMaster {
-            tx_id: XCP_REQUEST_ID,
-            rx_id: XCP_RESPONSE_ID,
-            max_cto: 8, // @todo this can be acquired dynamically, better to do it that way
-            max_dto: 8,
-            socket: &mut sock
-        };
+        // @todo max_cto can be acquired dynamically, better to do it that way
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
 
         let _ = master.connect(ConnectMode::Normal); // @todo handle negative response to this
         let seed = master.get_seed(XcpResource::Pgm.into());
@@ -53,15 +66,9 @@ mod tests {
     #[serial]
     fn connect() {
         let iface = "can0";
-        let mut sock: CanSocket = CanSocket::open(&iface).expect("Failed to open socket on interface");
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
 
-        let mut master = xcp::master::XcpMaster {
-            tx_id: XCP_REQUEST_ID,
-            rx_id: XCP_RESPONSE_ID,
-            max_cto: 8,
-            max_dto: 8,
-            socket: &mut sock
-        };
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
 
         master.connect(ConnectMode::Normal);
     }
@@ -70,15 +77,9 @@ mod tests {
     #[serial]
     fn get_seed() {
         let iface = "can0";
-        let mut sock: CanSocket = CanSocket::open(&iface).expect("Failed to open socket on interface");
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
 
-        let mut master = xcp::master::XcpMaster {
-            tx_id: XCP_REQUEST_ID,
-            rx_id: XCP_RESPONSE_ID,
-            max_cto: 8,
-            max_dto: 8,
-            socket: &mut sock
-        };
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
 
         master.connect(ConnectMode::Normal);
         let seed = master.get_seed(XcpResource::Pgm.into());
@@ -92,15 +93,9 @@ mod tests {
     #[serial]
     fn negative_response() {
         let iface = "can0";
-        let mut sock: CanSocket = CanSocket::open(&iface).expect("Failed to open socket on interface");
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
 
-        let mut master = xcp::master::XcpMaster {
-            tx_id: XCP_REQUEST_ID,
-            rx_id: XCP_RESPONSE_ID,
-            max_cto: 8,
-            max_dto: 8,
-            socket: &mut sock
-        };
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
 
         master.connect(ConnectMode::Normal);
         let seed = master.get_seed(XcpResourceFlags::from(0xff));
@@ -109,4 +104,3762 @@ mod tests {
             Err(res) => { println!("Response error:\n{:#x?}", res); assert!(true) }
         }
     }
+
+    #[test]
+    fn command_hooks() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use xcp::error::XcpError;
+        use xcp::frame::XcpCommandCode;
+        use xcp::transport::MockTransport;
+
+        let log = Rc::new(RefCell::new(Vec::<&str>::new()));
+        let slave_log = log.clone();
+        let mut transport = MockTransport::new(mock_ids(), move |cmd| match cmd[0] {
+            0xFF => { slave_log.borrow_mut().push("CONNECT"); vec![mock_connect_response()] }
+            0xF1 => { slave_log.borrow_mut().push("USER_CMD"); vec![vec![0xFF]] }
+            _ => vec![vec![0xFE, 0x20]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+
+        let pre_log = log.clone();
+        master.add_pre_hook(XcpCommandCode::Connect, move |m| {
+            pre_log.borrow_mut().push("pre connect");
+            m.user_cmd(0x01, &[]).expect("user_cmd from hook failed");
+        });
+        let post_log = log.clone();
+        master.add_post_hook(XcpCommandCode::Connect, move |_, resp| {
+            post_log.borrow_mut().push("post connect");
+            assert_eq!(resp[0], 0xFF);
+        });
+        // must not fire for the USER_CMD issued from within the connect pre-hook
+        let user_log = log.clone();
+        master.add_pre_hook(XcpCommandCode::UserCmd, move |_| user_log.borrow_mut().push("pre user"));
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        assert_eq!(*log.borrow(), vec!["pre connect", "USER_CMD", "CONNECT", "post connect"]);
+
+        log.borrow_mut().clear();
+        master.user_cmd(0x01, &[]).expect("user_cmd failed");
+        assert_eq!(*log.borrow(), vec!["pre user", "USER_CMD"]);
+
+        // parameters beyond the CTO are refused before anything is sent or hooked
+        log.borrow_mut().clear();
+        match master.user_cmd(0x01, &[0; 7]) {
+            Err(XcpError::PayloadTooLarge { command: XcpCommandCode::UserCmd, requested: 7, max: 6 }) => {}
+            other => panic!("expected PayloadTooLarge, got {:?}", other.map(|_| ())),
+        }
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn can_id_parsing() {
+        use xcp::can_ids::{DEFAULT_BROADCAST_ID, DEFAULT_IDS};
+
+        let std_id: CanId = "0x7E0".parse().unwrap();
+        assert!(!std_id.is_extended());
+        assert_eq!(std_id.raw(), 0x7E0);
+        assert_eq!(std_id.to_string(), "0x7E0");
+
+        let ext_id: CanId = "0x18DB33F1".parse().unwrap();
+        assert!(ext_id.is_extended());
+        assert_eq!(ext_id.to_string(), "0x18DB33F1");
+
+        // 8 hex digits or the A2L flag force an extended ID even for small values
+        assert!("0x00000100".parse::<CanId>().unwrap().is_extended());
+        assert!(CanId::from_raw(0x8000_0100).unwrap().is_extended());
+        assert_eq!("256".parse::<CanId>().unwrap(), CanId::standard(0x100).unwrap());
+
+        assert!(CanId::standard(0x800).is_err());
+        assert!(CanId::extended(0x2000_0000).is_err());
+        assert!("0xZZ".parse::<CanId>().is_err());
+
+        let pair: CanIdPair = "0x7E0:0x18DB33F1".parse().unwrap();
+        assert_eq!(pair.cmd, std_id);
+        assert_eq!(pair.res, ext_id);
+        assert_eq!(pair.to_string().parse::<CanIdPair>().unwrap(), pair);
+
+        assert_eq!(DEFAULT_BROADCAST_ID, CanId::standard(0x100).unwrap());
+        assert_eq!(DEFAULT_IDS, CanIdPair::from_raw(0x200, 0x300).unwrap());
+    }
+
+    #[test]
+    fn session_event_bus() {
+        use xcp::session::{SessionEvent, SessionEventBus};
+
+        let mut bus = SessionEventBus::default();
+        let rx = bus.subscribe(2);
+        for _ in 0..3 {
+            bus.publish(SessionEvent::LinkError { message: "test".to_string() });
+        }
+        assert_eq!(rx.try_iter().count(), 2);
+        assert_eq!(bus.dropped(), 1);
+
+        drop(rx);
+        bus.publish(SessionEvent::LinkError { message: "test".to_string() });
+        assert_eq!(bus.dropped(), 1);
+    }
+
+    #[test]
+    fn session_events() {
+        use xcp::session::SessionEvent;
+        use xcp::transport::MockTransport;
+        use xcp::xcp_command::StartStopSynchMode;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            // PGM protected
+            0xFF => vec![vec![0xFF, 0x10, 0x00, 0x08, 0x08, 0x01, 0x01]],
+            0xF8 => vec![vec![0xFF, 0x02, 0xAA, 0xBB]],
+            0xF7 => vec![vec![0xFF, 0x00]],
+            0xDE => vec![vec![0xFF, 0x00]],
+            _ => vec![vec![0xFF]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        let events = master.subscribe_events();
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let key = |seed: &[u8]| seed.iter().map(|b| !b).collect::<Vec<u8>>();
+        master.unlock_resource(XcpResource::Pgm, &key).expect("unlock failed");
+        master.start_daq_lists(&[0]).expect("start failed");
+        master.start_stop_synch(StartStopSynchMode::StopAll).expect("stop failed");
+        master.disconnect().expect("disconnect failed");
+
+        let events: Vec<SessionEvent> = events.try_iter().map(|e| e.event).collect();
+        match events.as_slice() {
+            [SessionEvent::Connected { protection },
+             SessionEvent::ResourceProtectionChanged { protection: unlocked },
+             SessionEvent::DaqStarted { daq_list: None },
+             SessionEvent::DaqStopped { daq_list: None },
+             SessionEvent::Disconnected] => {
+                assert_eq!(u8::from(*protection), 0x10);
+                assert_eq!(u8::from(*unlocked), 0x00);
+            }
+            other => panic!("unexpected events {:?}", other),
+        }
+    }
+
+    #[test]
+    fn protocol_version_gate() {
+        use xcp::frame::{XcpCommandCode, XcpVersion};
+        use xcp::error::XcpError;
+
+        let v1_0 = XcpVersion::new(1, 0);
+        let v1_4 = XcpVersion::new(1, 4);
+
+        assert!(v1_0.require(XcpCommandCode::Connect).is_ok());
+        assert!(v1_0.require(XcpCommandCode::ProgramVerify).is_ok());
+        match v1_0.require(XcpCommandCode::WriteDaqMultiple) {
+            Err(XcpError::UnsupportedByProtocolVersion { required, negotiated, .. }) => {
+                assert_eq!(required, XcpVersion::new(1, 3));
+                assert_eq!(negotiated, v1_0);
+            }
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(v1_0.require(XcpCommandCode::Level1Command).is_err());
+
+        assert!(v1_4.require(XcpCommandCode::Connect).is_ok());
+        assert!(v1_4.require(XcpCommandCode::WriteDaqMultiple).is_ok());
+        assert!(v1_4.require(XcpCommandCode::TimeCorrelationProperties).is_ok());
+        assert!(v1_4.require(XcpCommandCode::Level1Command).is_ok());
+    }
+
+    #[test]
+    fn duplicate_response_filter() {
+        use std::time::{Duration, Instant};
+        use xcp::dedup::DuplicateFilter;
+
+        let get_status_resp = [0xFF, 0x00, 0x15, 0x00, 0x00, 0x00];
+        let upload_resp = [0xFF, 0xDE, 0xAD, 0xBE, 0xEF];
+        let t0 = Instant::now();
+
+        let mut filter = DuplicateFilter::new(Some(Duration::from_millis(5)));
+        assert!(!filter.is_duplicate(&get_status_resp, t0));
+        assert!(filter.is_duplicate(&get_status_resp, t0 + Duration::from_millis(1)));
+        assert!(!filter.is_duplicate(&upload_resp, t0 + Duration::from_millis(2)));
+        // an identical response outside the window is a real response
+        assert!(!filter.is_duplicate(&upload_resp, t0 + Duration::from_millis(10)));
+
+        let mut disabled = DuplicateFilter::new(None);
+        assert!(!disabled.is_duplicate(&get_status_resp, t0));
+        assert!(!disabled.is_duplicate(&get_status_resp, t0));
+    }
+
+    #[test]
+    fn duplicate_response_dropped() {
+        use std::time::Duration;
+        use xcp::transport::MockTransport;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            // the gateway delivers the GET_STATUS response twice
+            0xFD => vec![vec![0xFF, 0x00, 0x00, 0x00, 0x00, 0x00]; 2],
+            0xF5 => vec![vec![0xFF, 0xDE, 0xAD, 0xBE, 0xEF]],
+            _ => vec![vec![0xFF]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.set_duplicate_window(Some(Duration::from_millis(50)));
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        master.get_status().expect("get_status failed");
+        // identical acknowledgements of consecutive commands are not merged
+        master.set_mta(0x1000, 0).expect("set_mta failed");
+        master.set_mta(0x1000, 0).expect("set_mta failed");
+        assert_eq!(master.upload(4).expect("upload failed"), vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(master.statistics().duplicate_responses_dropped, 1);
+    }
+
+    #[test]
+    fn get_status_decode() {
+        use xcp::frame::{XcpResponse, XcpVersion};
+        use xcp::xcp_command::{GetStatusResponse, XcpCommModeBasic};
+
+        // XCP 1.0 slave sending only the mandatory bytes
+        let short = GetStatusResponse::from_can_frame(&[0xFF, 0x41, 0x11, 0x00]);
+        assert!(short.session_status.store_cal_req());
+        assert!(short.session_status.daq_running());
+        assert!(!short.session_status.resume());
+        assert!(short.protection.cal_page && short.protection.pgm);
+        assert!(!short.protection.daq && !short.protection.stim);
+        assert_eq!(short.session_configuration_id, None);
+
+        // full XCP 1.1 response
+        let frame = [0xFF, 0x80, 0x00, 0x00, 0x12, 0x34];
+        let intel = GetStatusResponse::from_can_frame(&frame).for_protocol_version(XcpVersion::new(1, 1));
+        assert!(intel.session_status.resume());
+        assert_eq!(intel.state_number, None);
+        assert_eq!(intel.session_configuration_id, Some(0x3412));
+        let motorola = GetStatusResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01));
+        assert_eq!(motorola.session_configuration_id, Some(0x1234));
+
+        // padded to 8 bytes by the CAN driver, state number defined from 1.2 on
+        let padded = [0xFF, 0x00, 0x04, 0x02, 0x01, 0x00, 0xCC, 0xCC];
+        let status = GetStatusResponse::from_can_frame(&padded).for_protocol_version(XcpVersion::new(1, 4));
+        assert!(status.protection.daq);
+        assert_eq!(status.state_number, Some(0x02));
+        assert_eq!(status.session_configuration_id, Some(0x0001));
+    }
+
+    #[test]
+    #[serial]
+    fn get_status() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let status = master.get_status().expect("get_status failed");
+        println!("{:#x?}", status);
+    }
+
+    #[test]
+    fn frame_buf() {
+        use xcp::error::XcpError;
+        use xcp::frame::{XcpCommand, XcpFrameBuf, XCP_MAX_FRAME_LEN};
+        use xcp::xcp_command::ConnectCommand;
+
+        let mut buf = XcpFrameBuf::new();
+        assert!(buf.is_empty());
+        buf.push(0xFF).unwrap();
+        buf.extend_from_slice(&[0x00, 0x01]).unwrap();
+        assert_eq!(buf.as_slice(), &[0xFF, 0x00, 0x01]);
+        assert_eq!(format!("{:?}", buf), "[ff, 00, 01]");
+
+        buf.extend_from_slice(&[0; XCP_MAX_FRAME_LEN - 3]).unwrap();
+        assert_eq!(buf.len(), XCP_MAX_FRAME_LEN);
+        assert!(matches!(buf.push(0), Err(XcpError::FrameOverflow { requested }) if requested == XCP_MAX_FRAME_LEN + 1));
+        assert_eq!(buf.len(), XCP_MAX_FRAME_LEN);
+        assert_eq!(XcpFrameBuf::from_prefix(&[0xAA; XCP_MAX_FRAME_LEN + 8]).len(), XCP_MAX_FRAME_LEN);
+        assert!(XcpFrameBuf::from_slice(&[0xAA; XCP_MAX_FRAME_LEN + 8]).is_err());
+
+        let connect = ConnectCommand { mode: ConnectMode::Normal }.to_can_frame().unwrap();
+        assert_eq!(Vec::from(connect), vec![0xFF, 0x00]);
+    }
+
+    #[test]
+    #[serial]
+    fn synch() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        master.synch().expect("synch failed");
+    }
+
+    #[test]
+    fn get_comm_mode_info_decode() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::GetCommModeInfoResponse;
+
+        let info = GetCommModeInfoResponse::from_can_frame(&[0xFF, 0x00, 0x01, 0x00, 0x10, 0x02, 0x00, 0x13]);
+        assert!(info.comm_mode_optional.master_block_mode());
+        assert!(!info.comm_mode_optional.interleaved_mode());
+        assert_eq!(info.max_bs, 0x10);
+        assert_eq!(info.min_st, 0x02);
+        assert_eq!(info.queue_size, 0x00);
+        assert_eq!(info.driver_version, 0x13);
+    }
+
+    #[test]
+    fn tx_backpressure_classification() {
+        use std::io;
+        use xcp::master::is_tx_backpressure;
+
+        assert!(is_tx_backpressure(&io::Error::from_raw_os_error(105)));
+        assert!(is_tx_backpressure(&io::Error::from(io::ErrorKind::WouldBlock)));
+        assert!(!is_tx_backpressure(&io::Error::from(io::ErrorKind::NotFound)));
+        assert!(!is_tx_backpressure(&io::Error::from_raw_os_error(100)));
+    }
+
+    #[test]
+    fn get_id_encode_decode() {
+        use xcp::frame::{XcpCommand, XcpResponse};
+        use xcp::xcp_command::{GetIdCommand, GetIdResponse, IdType, XcpCommModeBasic};
+
+        assert_eq!(GetIdCommand { id_type: IdType::AsciiText }.to_can_frame().unwrap().as_slice(), &[0xFA, 0x00]);
+        assert_eq!(GetIdCommand { id_type: IdType::FileToUpload }.to_can_frame().unwrap().as_slice(), &[0xFA, 0x04]);
+        assert_eq!(GetIdCommand { id_type: IdType::UserDefined(0x80) }.to_can_frame().unwrap().as_slice(), &[0xFA, 0x80]);
+
+        let frame = [0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20];
+        let intel = GetIdResponse::from_can_frame(&frame);
+        assert!(!intel.in_response());
+        assert_eq!(intel.length, 0x2001_0000);
+        let motorola = GetIdResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01));
+        assert_eq!(motorola.length, 0x0000_0120);
+    }
+
+    #[test]
+    #[serial]
+    fn get_id() {
+        use xcp::xcp_command::IdType;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let id = master.get_id_string(IdType::AsciiText).expect("get_id failed");
+        println!("slave id: {}", id);
+    }
+
+    #[test]
+    fn access_flags() {
+        use xcp::xcp_command::{CalPageAccessFlags, ClearAccessFlags};
+
+        let clear = ClearAccessFlags::new().with_calibration().with_nvram();
+        assert_eq!(u8::from(clear), 0x05);
+        assert_eq!(ClearAccessFlags::try_from(0x05).unwrap(), clear);
+        assert_eq!(clear.to_string(), "CAL | NVRAM");
+        assert_eq!(ClearAccessFlags::new().to_string(), "NONE");
+        assert!(ClearAccessFlags::try_from(0x08).is_err());
+
+        let page = CalPageAccessFlags::new().with_ecu().with_xcp().with_all();
+        assert_eq!(u8::from(page), 0x83);
+        assert_eq!(CalPageAccessFlags::try_from(0x83).unwrap(), page);
+        assert_eq!(format!("{:?}", page), "CalPageAccessFlags(ECU | XCP | ALL)");
+        assert!(CalPageAccessFlags::try_from(0x04).is_err());
+        assert!(CalPageAccessFlags::try_from(0x40).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn upload_a2l() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let mut last = (0, 0);
+        let a2l = master.upload_a2l(Some(&mut |done, total| last = (done, total))).expect("upload_a2l failed");
+        assert_eq!(last, (a2l.len(), a2l.len()));
+    }
+
+    #[test]
+    fn set_request_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::{SetRequestCommand, XcpSetRequestMode};
+
+        let mut mode = XcpSetRequestMode(0);
+        mode.set_store_cal_req(true);
+        let intel = SetRequestCommand { mode, session_configuration_id: 0x1234, byte_order: ByteOrder::Intel };
+        assert_eq!(intel.to_can_frame().unwrap().as_slice(), &[0xF9, 0x01, 0x34, 0x12]);
+
+        let mut mode = XcpSetRequestMode(0);
+        mode.set_store_daq_req(true);
+        mode.set_clear_daq_req(true);
+        let motorola = SetRequestCommand { mode, session_configuration_id: 0x1234, byte_order: ByteOrder::Motorola };
+        assert_eq!(motorola.to_can_frame().unwrap().as_slice(), &[0xF9, 0x0C, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn set_request_pending_status() {
+        use xcp::frame::{XcpEventCode, XcpResponseCode};
+        use xcp::xcp_command::XcpSetRequestMode;
+
+        assert_eq!(XcpResponseCode::from_code(0xFD), XcpResponseCode::Event);
+        assert_eq!(XcpResponseCode::from_code(0xFC), XcpResponseCode::ServiceRequest);
+        assert_eq!(XcpEventCode::from_code(0x03), XcpEventCode::StoreCal);
+        assert_eq!(XcpEventCode::from_code(0x02), XcpEventCode::StoreDaq);
+        assert_eq!(XcpEventCode::from_code(0x04), XcpEventCode::Unknown(0x04));
+        assert_eq!(XcpEventCode::Unknown(0x04).to_code(), 0x04);
+
+        let mut mode = XcpSetRequestMode(0);
+        mode.set_store_cal_req(true);
+        mode.set_store_daq_req_no_resume(true);
+        assert_eq!(mode.pending_status().0, 0x05);
+
+        let mut mode = XcpSetRequestMode(0);
+        mode.set_clear_daq_req(true);
+        assert_eq!(mode.pending_status().0, 0x08);
+    }
+
+    #[test]
+    fn set_mta_layout() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::SetMtaCommand;
+
+        let intel = SetMtaCommand { address: 0x1234_5678, address_extension: 0xAB, byte_order: ByteOrder::Intel };
+        assert_eq!(intel.to_can_frame().unwrap().as_slice(), &[0xF6, 0x00, 0x00, 0xAB, 0x78, 0x56, 0x34, 0x12]);
+
+        let motorola = SetMtaCommand { address: 0x1234_5678, address_extension: 0xAB, byte_order: ByteOrder::Motorola };
+        assert_eq!(motorola.to_can_frame().unwrap().as_slice(), &[0xF6, 0x00, 0x00, 0xAB, 0x12, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn upload_response_alignment() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::{UploadResponse, XcpCommModeBasic};
+
+        let frame = [0xFF, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+
+        let byte = XcpCommModeBasic(0);
+        let resp = UploadResponse::from_can_frame_with_mode(&frame, byte);
+        assert_eq!(resp.data.as_slice(), &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+
+        let mut word = XcpCommModeBasic(0);
+        word.set_address_granularity(1);
+        let resp = UploadResponse::from_can_frame_with_mode(&frame, word);
+        assert_eq!(resp.data.as_slice(), &[0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+
+        let mut dword = XcpCommModeBasic(0);
+        dword.set_address_granularity(2);
+        let resp = UploadResponse::from_can_frame_with_mode(&frame, dword);
+        assert_eq!(resp.data.as_slice(), &[0x44, 0x55, 0x66, 0x77]);
+    }
+
+    #[test]
+    fn short_upload_layout() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::ShortUploadCommand;
+
+        let intel = ShortUploadCommand { n_elements: 4, address: 0x1234_5678, address_extension: 0x01, byte_order: ByteOrder::Intel };
+        assert_eq!(intel.to_can_frame().unwrap().as_slice(), &[0xF4, 0x04, 0x00, 0x01, 0x78, 0x56, 0x34, 0x12]);
+
+        let motorola = ShortUploadCommand { byte_order: ByteOrder::Motorola, ..intel };
+        assert_eq!(motorola.to_can_frame().unwrap().as_slice(), &[0xF4, 0x04, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn build_checksum_encode_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{BuildChecksumCommand, BuildChecksumResponse, ChecksumType, NegativeResponse, XcpCommModeBasic};
+
+        let cmd = BuildChecksumCommand { block_size: 0x0001_0000, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xF3, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+
+        let resp = BuildChecksumResponse::from_can_frame(&[0xFF, 0x09, 0x00, 0x00, 0x26, 0x39, 0xF4, 0xCB]);
+        assert_eq!(resp.checksum_type, ChecksumType::Crc32);
+        assert_eq!(resp.checksum, 0xCBF4_3926);
+
+        let mut motorola = XcpCommModeBasic(0);
+        motorola.set_byte_order(true);
+        let resp = BuildChecksumResponse::from_can_frame_with_mode(&[0xFF, 0x07, 0x00, 0x00, 0x00, 0x00, 0xBB, 0x3D], motorola);
+        assert_eq!(resp.checksum_type, ChecksumType::Crc16);
+        assert_eq!(resp.checksum, 0xBB3D);
+        assert_eq!(ChecksumType::from_code(0x42), ChecksumType::Unknown(0x42));
+
+        let err = NegativeResponse::from_can_frame(&[0xFE, 0x22, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(err.parameters.as_slice(), &[0x00, 0x00, 0x00, 0x10, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn checksum_algorithms() {
+        use xcp::checksum::{compute, crc16, crc16_ccitt, crc32};
+        use xcp::frame::ByteOrder;
+        use xcp::xcp_command::ChecksumType;
+
+        let check = b"123456789";
+        assert_eq!(crc16(check), 0xBB3D);
+        assert_eq!(crc16_ccitt(check), 0x29B1);
+        assert_eq!(crc32(check), 0xCBF4_3926);
+
+        let data = [0xFF, 0x01, 0x80, 0x00];
+        assert_eq!(compute(ChecksumType::Add11, &data, ByteOrder::Intel), Some(0x80));
+        assert_eq!(compute(ChecksumType::Add12, &data, ByteOrder::Intel), Some(0x0180));
+        assert_eq!(compute(ChecksumType::Add22, &data, ByteOrder::Intel), Some(0x01FF + 0x0080));
+        assert_eq!(compute(ChecksumType::Add22, &data, ByteOrder::Motorola), Some(0xFF01 + 0x8000 - 0x10000));
+        assert_eq!(compute(ChecksumType::Add24, &data, ByteOrder::Motorola), Some(0xFF01 + 0x8000));
+        assert_eq!(compute(ChecksumType::Add44, &data, ByteOrder::Intel), Some(0x0080_01FF));
+        assert_eq!(compute(ChecksumType::UserDefined, &data, ByteOrder::Intel), None);
+    }
+
+    #[test]
+    fn download_split_encode() {
+        use xcp::frame::{AddressGranularity, XcpCommand};
+        use xcp::xcp_command::DownloadCommand;
+
+        // the payload exactly fills the frame
+        let cmds = DownloadCommand::split(&[1, 2, 3, 4, 5, 6], AddressGranularity::Byte, 8);
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].to_can_frame().unwrap().as_slice(), &[0xF0, 0x06, 1, 2, 3, 4, 5, 6]);
+
+        // a single trailing byte remains
+        let cmds = DownloadCommand::split(&[1, 2, 3, 4, 5, 6, 7], AddressGranularity::Byte, 8);
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(cmds[0].to_can_frame().unwrap().as_slice(), &[0xF0, 0x06, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(cmds[1].to_can_frame().unwrap().as_slice(), &[0xF0, 0x01, 7]);
+
+        let cmds = DownloadCommand::split(&[1, 2, 3, 4, 5, 6], AddressGranularity::Word, 8);
+        assert_eq!(cmds[0].to_can_frame().unwrap().as_slice(), &[0xF0, 0x03, 1, 2, 3, 4, 5, 6]);
+
+        let cmds = DownloadCommand::split(&[1, 2, 3, 4, 5, 6, 7, 8], AddressGranularity::DWord, 8);
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(cmds[0].to_can_frame().unwrap().as_slice(), &[0xF0, 0x01, 0x00, 0x00, 1, 2, 3, 4]);
+        assert_eq!(cmds[1].to_can_frame().unwrap().as_slice(), &[0xF0, 0x01, 0x00, 0x00, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn download_next_block() {
+        use xcp::frame::{AddressGranularity, XcpCommand};
+        use xcp::xcp_command::DownloadNextCommand;
+
+        let data: Vec<u8> = (1..=14).collect();
+        let (download, next) = DownloadNextCommand::block(&data, AddressGranularity::Byte, 8);
+        assert_eq!(download.to_can_frame().unwrap().as_slice(), &[0xF0, 14, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].to_can_frame().unwrap().as_slice(), &[0xEF, 8, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(next[1].to_can_frame().unwrap().as_slice(), &[0xEF, 2, 13, 14]);
+
+        let (download, next) = DownloadNextCommand::block(&data[..4], AddressGranularity::Word, 8);
+        assert_eq!(download.to_can_frame().unwrap().as_slice(), &[0xF0, 2, 1, 2, 3, 4]);
+        assert!(next.is_empty());
+    }
+
+    #[test]
+    fn download_max_encode() {
+        use xcp::frame::{AddressGranularity, XcpCommand};
+        use xcp::xcp_command::DownloadMaxCommand;
+
+        let cmd = DownloadMaxCommand { data: vec![1, 2, 3, 4, 5, 6, 7], granularity: AddressGranularity::Byte };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEE, 1, 2, 3, 4, 5, 6, 7]);
+
+        let cmd = DownloadMaxCommand { data: vec![1, 2, 3, 4, 5, 6], granularity: AddressGranularity::Word };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEE, 0x00, 1, 2, 3, 4, 5, 6]);
+
+        let cmd = DownloadMaxCommand { data: vec![1, 2, 3, 4], granularity: AddressGranularity::DWord };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEE, 0x00, 0x00, 0x00, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn short_download_layout() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::ShortDownloadCommand;
+
+        let cmd = ShortDownloadCommand {
+            n_elements: 2,
+            address: 0x1234_5678,
+            address_extension: 0x01,
+            data: vec![0xAA, 0xBB],
+            byte_order: ByteOrder::Motorola,
+        };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xED, 0x02, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn modify_bits_layout() {
+        use xcp::error::XcpError;
+        use xcp::frame::{ByteOrder, XcpCommand, XcpErrorCode, XcpResponse, XcpResponseFrame};
+        use xcp::xcp_command::{ModifyBitsCommand, NegativeResponse};
+
+        let cmd = ModifyBitsCommand { shift: 4, and_mask: 0xFFF0, xor_mask: 0x0003, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEC, 0x04, 0xF0, 0xFF, 0x03, 0x00]);
+
+        let cmd = ModifyBitsCommand { byte_order: ByteOrder::Motorola, ..cmd };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEC, 0x04, 0xFF, 0xF0, 0x00, 0x03]);
+
+        let err = XcpError::from(XcpResponseFrame { data: NegativeResponse::from_can_frame(&[0xFE, 0x22]) });
+        assert_eq!(err.error_code(), Some(XcpErrorCode::ErrOutOfRange));
+    }
+
+    #[test]
+    fn partial_transfer_error() {
+        use std::error::Error;
+        use xcp::error::XcpError;
+        use xcp::frame::{XcpErrorCode, XcpResponse, XcpResponseFrame};
+        use xcp::xcp_command::NegativeResponse;
+
+        let denied = XcpError::from(XcpResponseFrame { data: NegativeResponse::from_can_frame(&[0xFE, 0x24]) });
+        let err = XcpError::PartialTransfer { offset: 14, data: vec![0; 14], source: Box::new(denied) };
+        assert_eq!(err.error_code(), Some(XcpErrorCode::ErrAccessDenied));
+        assert!(err.source().is_some());
+        assert_eq!(err.to_string(), "transfer failed at offset 14: negative response: ErrAccessDenied");
+    }
+
+    #[test]
+    #[serial]
+    fn read_memory() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let data = master.read_memory(0x0000_0000, 0, 32).expect("read_memory failed");
+        assert_eq!(data.len(), 32);
+    }
+
+    #[test]
+    #[serial]
+    fn write_memory() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let data: Vec<u8> = (0..16).collect();
+        master.write_memory(0x0000_0000, 0, &data).expect("write_memory failed");
+        assert_eq!(master.read_memory(0x0000_0000, 0, data.len()).expect("read_memory failed"), data);
+
+        master.write_memory_verified(0x0000_0000, 0, &data, xcp::master::WriteVerification::ReadBack)
+            .expect("read-back verification failed");
+        master.write_memory_verified(0x0000_0000, 0, &data, xcp::master::WriteVerification::Checksum)
+            .expect("checksum verification failed");
+    }
+
+    #[test]
+    fn ihex_writer() {
+        use xcp::dump::IhexWriter;
+
+        let mut ihex = IhexWriter::new(Vec::<u8>::new());
+        ihex.write(0x0000_0100, &[0x01, 0x02, 0x03]).unwrap();
+        ihex.write(0x0001_FFFE, &[0xAA, 0xBB, 0xCC, 0xDD]).unwrap();
+        let out = String::from_utf8(ihex.finish().unwrap()).unwrap();
+
+        assert_eq!(out, concat!(
+            ":020000040000FA\n",
+            ":03010000010203F6\n",
+            ":020000040001F9\n",
+            ":02FFFE00AABB9C\n",
+            ":020000040002F8\n",
+            ":02000000CCDD55\n",
+            ":00000001FF\n",
+        ));
+    }
+
+    #[test]
+    fn upload_stream() {
+        use std::cell::RefCell;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().memory.extend((0..64).map(|i| (0x100 + i, i as u8)));
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let max_chunk = master.max_cto - 1;
+        {
+            let mut stream = master.upload_stream(0x0000_0100, 0, 64).expect("set_mta failed");
+            let first = stream.next().expect("stream ended early").expect("upload failed");
+            assert_eq!(first, (0..max_chunk as u8).collect::<Vec<u8>>());
+        }
+
+        // the master is usable again after abandoning the stream
+        master.get_status().expect("get_status failed");
+        assert_eq!(master.read_memory(0x0000_0100, 0, 4).expect("read failed"), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn set_cal_page_encode() {
+        use xcp::frame::XcpCommand;
+        use xcp::xcp_command::{CalPageAccessFlags, SetCalPageCommand};
+
+        let cmd = SetCalPageCommand { mode: CalPageAccessFlags::new().with_ecu().with_xcp(), segment: 0, page: 1 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEB, 0x03, 0x00, 0x01]);
+
+        let cmd = SetCalPageCommand { mode: CalPageAccessFlags::new().with_xcp().with_all(), segment: 0, page: 0 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEB, 0x82, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn get_cal_page_encode_decode() {
+        use xcp::frame::{XcpCommand, XcpResponse};
+        use xcp::xcp_command::{CalPageAccessFlags, GetCalPageCommand, GetCalPageResponse};
+
+        let cmd = GetCalPageCommand { mode: CalPageAccessFlags::new().with_xcp(), segment: 2 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xEA, 0x02, 0x02]);
+
+        let resp = GetCalPageResponse::from_can_frame(&[0xFF, 0x00, 0x00, 0x01]);
+        assert_eq!(resp.page, 1);
+    }
+
+    #[test]
+    fn get_pag_processor_info_decode() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::GetPagProcessorInfoResponse;
+
+        let info = GetPagProcessorInfoResponse::from_can_frame(&[0xFF, 0x03, 0x01]);
+        assert_eq!(info.max_segments, 3);
+        assert!(info.properties.freeze_supported());
+
+        let info = GetPagProcessorInfoResponse::from_can_frame(&[0xFF, 0x01, 0x00]);
+        assert!(!info.properties.freeze_supported());
+    }
+
+    #[test]
+    fn get_segment_info_encode_decode() {
+        use xcp::frame::{XcpCommand, XcpResponse};
+        use xcp::xcp_command::{
+            GetSegmentInfoCommand, SegmentInfoMode, XcpCommModeBasic,
+            SegmentBasicInfoResponse, SegmentStandardInfoResponse, SegmentMappingInfoResponse,
+        };
+
+        let cmd = GetSegmentInfoCommand { mode: SegmentInfoMode::BasicAddress, segment: 1, segment_info: 1, mapping_index: 0 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE8, 0x00, 0x01, 0x01, 0x00]);
+        let mut motorola = XcpCommModeBasic(0);
+        motorola.set_byte_order(true);
+        let basic = SegmentBasicInfoResponse::from_can_frame_with_mode(&[0xFF, 0, 0, 0, 0x00, 0x01, 0x00, 0x00], motorola);
+        assert_eq!(basic.basic_info, 0x0001_0000);
+        let basic = SegmentBasicInfoResponse::from_can_frame(&[0xFF, 0, 0, 0, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(basic.basic_info, 0x0000_0100);
+
+        let cmd = GetSegmentInfoCommand { mode: SegmentInfoMode::Standard, segment: 2, segment_info: 0, mapping_index: 0 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE8, 0x01, 0x02, 0x00, 0x00]);
+        let standard = SegmentStandardInfoResponse::from_can_frame(&[0xFF, 0x02, 0x01, 0x03, 0x00, 0x00]);
+        assert_eq!(standard.max_pages, 2);
+        assert_eq!(standard.address_extension, 1);
+        assert_eq!(standard.max_mapping, 3);
+
+        let cmd = GetSegmentInfoCommand { mode: SegmentInfoMode::AddressMapping, segment: 0, segment_info: 2, mapping_index: 4 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE8, 0x02, 0x00, 0x02, 0x04]);
+        let mapping = SegmentMappingInfoResponse::from_can_frame(&[0xFF, 0, 0, 0, 0x00, 0x40, 0x00, 0x00]);
+        assert_eq!(mapping.mapping_info, 0x4000);
+    }
+
+    #[test]
+    fn get_page_info_encode_decode() {
+        use xcp::frame::{XcpCommand, XcpResponse};
+        use xcp::xcp_command::{GetPageInfoCommand, GetPageInfoResponse};
+
+        let cmd = GetPageInfoCommand { segment: 1, page: 2 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE7, 0x00, 0x01, 0x02]);
+
+        let expected = [
+            (0x01, "ecu_access_without_xcp"),
+            (0x02, "ecu_access_with_xcp"),
+            (0x04, "xcp_read_access_without_ecu"),
+            (0x08, "xcp_read_access_with_ecu"),
+            (0x10, "xcp_write_access_without_ecu"),
+            (0x20, "xcp_write_access_with_ecu"),
+        ];
+        for (bit, name) in expected {
+            let p = GetPageInfoResponse::from_can_frame(&[0xFF, bit, 0x00]).properties;
+            let set = [
+                ("ecu_access_without_xcp", p.ecu_access_without_xcp()),
+                ("ecu_access_with_xcp", p.ecu_access_with_xcp()),
+                ("xcp_read_access_without_ecu", p.xcp_read_access_without_ecu()),
+                ("xcp_read_access_with_ecu", p.xcp_read_access_with_ecu()),
+                ("xcp_write_access_without_ecu", p.xcp_write_access_without_ecu()),
+                ("xcp_write_access_with_ecu", p.xcp_write_access_with_ecu()),
+            ];
+            for (flag, value) in set {
+                assert_eq!(value, flag == name, "bit {:#04x}, flag {}", bit, flag);
+            }
+        }
+
+        let info = GetPageInfoResponse::from_can_frame(&[0xFF, 0x0C, 0x03]);
+        assert!(!info.properties.ecu_access());
+        assert!(info.properties.xcp_read_access());
+        assert!(!info.properties.xcp_write_access());
+        assert_eq!(info.init_segment, 3);
+    }
+
+    #[test]
+    fn segment_mode_encode_decode() {
+        use xcp::frame::{XcpCommand, XcpResponse};
+        use xcp::xcp_command::{SetSegmentModeCommand, GetSegmentModeCommand, GetSegmentModeResponse, XcpSegmentMode};
+
+        let mut mode = XcpSegmentMode(0);
+        mode.set_freeze(true);
+        let cmd = SetSegmentModeCommand { mode, segment: 3 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE6, 0x01, 0x03]);
+
+        let cmd = GetSegmentModeCommand { segment: 3 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE5, 0x00, 0x03]);
+
+        let resp = GetSegmentModeResponse::from_can_frame(&[0xFF, 0x00, 0x01]);
+        assert!(resp.mode.freeze());
+    }
+
+    #[test]
+    fn copy_cal_page_encode() {
+        use xcp::frame::XcpCommand;
+        use xcp::xcp_command::CopyCalPageCommand;
+
+        let cmd = CopyCalPageCommand { source_segment: 0, source_page: 1, destination_segment: 0, destination_page: 0 };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE4, 0x00, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    #[serial]
+    fn freeze_calibration() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        match master.freeze_calibration() {
+            Ok(frozen) => println!("frozen segments: {:?}", frozen),
+            Err(e) => println!("freeze failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn page_access_flags() {
+        use xcp::xcp_command::PageAccess;
+
+        assert_eq!(u8::from(PageAccess::Ecu.to_flags()), 0x01);
+        assert_eq!(u8::from(PageAccess::Xcp.to_flags()), 0x02);
+        assert_eq!(u8::from(PageAccess::EcuAndXcp.to_flags()), 0x03);
+    }
+
+    #[test]
+    #[serial]
+    fn switch_cal_page_all() {
+        use xcp::xcp_command::PageAccess;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        master.switch_cal_page_all(1, PageAccess::EcuAndXcp).expect("switch to working page failed");
+        master.switch_cal_page_all(0, PageAccess::EcuAndXcp).expect("switch to reference page failed");
+    }
+
+    #[test]
+    fn cal_snapshot_roundtrip() {
+        use xcp::snapshot::{CalSnapshot, SnapshotRegion};
+
+        let snapshot = CalSnapshot {
+            id: b"ECU_V1".to_vec(),
+            regions: vec![
+                SnapshotRegion { address_extension: 0, address: 0x8000_0000, data: vec![1, 2, 3, 4] },
+                SnapshotRegion { address_extension: 1, address: 0x0000_1000, data: vec![] },
+            ],
+        };
+
+        let mut file = Vec::new();
+        snapshot.write_to(&mut file).unwrap();
+        assert_eq!(&file[..8], b"XCPCAL01");
+        assert_eq!(CalSnapshot::read_from(file.as_slice()).unwrap(), snapshot);
+        assert!(CalSnapshot::read_from(&b"NOTASNAP"[..]).is_err());
+    }
+
+    #[test]
+    fn clear_daq_list_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::ClearDaqListCommand;
+
+        let cmd = ClearDaqListCommand { daq_list: 0x0102, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE3, 0x00, 0x02, 0x01]);
+
+        let cmd = ClearDaqListCommand { daq_list: 0x0102, byte_order: ByteOrder::Motorola };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE3, 0x00, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn set_daq_ptr_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::SetDaqPtrCommand;
+
+        let cmd = SetDaqPtrCommand { daq_list: 0x0102, odt: 3, entry: 4, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE2, 0x00, 0x02, 0x01, 0x03, 0x04]);
+
+        let cmd = SetDaqPtrCommand { daq_list: 0x0102, odt: 3, entry: 4, byte_order: ByteOrder::Motorola };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE2, 0x00, 0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn write_daq_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::WriteDaqCommand;
+
+        let cmd = WriteDaqCommand {
+            bit_offset: None,
+            size: 4,
+            address_extension: 1,
+            address: 0x1122_3344,
+            byte_order: ByteOrder::Intel,
+        };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE1, 0xFF, 0x04, 0x01, 0x44, 0x33, 0x22, 0x11]);
+
+        let cmd = WriteDaqCommand { bit_offset: Some(7), size: 1, byte_order: ByteOrder::Motorola, ..cmd };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE1, 0x07, 0x01, 0x01, 0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn write_daq_multiple_split_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::{DaqEntry, WriteDaqMultipleCommand};
+
+        let entry = |address| DaqEntry { bit_offset: None, size: 2, address_extension: 0, address };
+        let entries: Vec<DaqEntry> = (0..9).map(|i| entry(0x1000 + i)).collect();
+
+        // classic CAN cannot carry a single entry
+        assert_eq!(WriteDaqMultipleCommand::max_entries(8), 0);
+
+        // 7 entries fit into 64 bytes
+        let commands = WriteDaqMultipleCommand::split(&entries, ByteOrder::Intel, 64);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].entries.len(), 7);
+        assert_eq!(commands[1].entries.len(), 2);
+
+        let frame = commands[1].to_can_frame().unwrap();
+        assert_eq!(frame.len(), 2 + 2 * 8);
+        assert_eq!(&frame[..10], &[0xC7, 0x02, 0xFF, 0x02, 0x07, 0x10, 0x00, 0x00, 0x00, 0x00]);
+
+        let cmd = WriteDaqMultipleCommand {
+            entries: vec![DaqEntry { bit_offset: Some(3), size: 1, address_extension: 5, address: 0x1122_3344 }],
+            byte_order: ByteOrder::Motorola,
+        };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xC7, 0x01, 0x03, 0x01, 0x11, 0x22, 0x33, 0x44, 0x05, 0x00]);
+    }
+
+    #[test]
+    fn set_daq_list_mode_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::{SetDaqListModeCommand, XcpDaqListMode};
+
+        let mut mode = XcpDaqListMode(0);
+        mode.set_timestamp(true);
+        mode.set_pid_off(true);
+        assert_eq!(mode.0, 0x30);
+
+        let cmd = SetDaqListModeCommand {
+            mode,
+            daq_list: 0x0102,
+            event_channel: 0x0304,
+            prescaler: 5,
+            priority: 0xFF,
+            byte_order: ByteOrder::Intel,
+        };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE0, 0x30, 0x02, 0x01, 0x04, 0x03, 0x05, 0xFF]);
+
+        let cmd = SetDaqListModeCommand { byte_order: ByteOrder::Motorola, ..cmd };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xE0, 0x30, 0x01, 0x02, 0x03, 0x04, 0x05, 0xFF]);
+    }
+
+    #[test]
+    fn get_daq_list_mode_encode_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{
+            DaqListModeConfig, GetDaqListModeCommand, GetDaqListModeResponse, SetDaqListModeCommand,
+            XcpCommModeBasic, XcpDaqListMode
+        };
+
+        let cmd = GetDaqListModeCommand { daq_list: 0x0102, byte_order: ByteOrder::Motorola };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xDF, 0x00, 0x01, 0x02]);
+
+        // what SET_DAQ_LIST_MODE writes comes back unchanged, apart from the byte order
+        let config = DaqListModeConfig { mode: XcpDaqListMode(0x12), event_channel: 0x0304, prescaler: 2, priority: 7 };
+        let set = SetDaqListModeCommand {
+            mode: config.mode,
+            daq_list: 0x0102,
+            event_channel: config.event_channel,
+            prescaler: config.prescaler,
+            priority: config.priority,
+            byte_order: ByteOrder::Motorola,
+        }.to_can_frame().unwrap();
+
+        let frame = [0xFF, set[1], 0x00, 0x00, set[4], set[5], set[6], set[7]];
+        let motorola = XcpCommModeBasic(0x01);
+        assert_eq!(GetDaqListModeResponse::from_can_frame_with_mode(&frame, motorola).config, config);
+
+        let frame = [0xFF, 0x40, 0x00, 0x00, 0x04, 0x03, 0x01, 0x00];
+        let resp = GetDaqListModeResponse::from_can_frame(&frame);
+        assert!(resp.config.mode.running());
+        assert_eq!(resp.config.event_channel, 0x0304);
+    }
+
+    #[test]
+    fn start_stop_daq_list_encode_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{StartStopDaqListCommand, StartStopDaqListResponse, StartStopMode};
+
+        let cmd = StartStopDaqListCommand { mode: StartStopMode::Select, daq_list: 0x0102, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xDE, 0x02, 0x02, 0x01]);
+
+        let cmd = StartStopDaqListCommand { mode: StartStopMode::Stop, byte_order: ByteOrder::Motorola, ..cmd };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xDE, 0x00, 0x01, 0x02]);
+
+        assert_eq!(StartStopDaqListResponse::from_can_frame(&[0xFF, 0x0C]).first_pid, 0x0C);
+    }
+
+    #[test]
+    fn start_stop_synch_encode() {
+        use xcp::frame::{XcpCommand, XcpVersion};
+        use xcp::xcp_command::{StartStopSynchCommand, StartStopSynchMode};
+
+        let cmd = StartStopSynchCommand { mode: StartStopSynchMode::StartSelected };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xDD, 0x01]);
+
+        let cmd = StartStopSynchCommand { mode: StartStopSynchMode::PrepareStartSelected };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xDD, 0x03]);
+        assert_eq!(cmd.mode.min_protocol_version(), XcpVersion::new(1, 3));
+        assert_eq!(StartStopSynchMode::StopAll.min_protocol_version(), XcpVersion::new(1, 0));
+    }
+
+    #[test]
+    fn get_daq_clock_decode() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::{GetDaqClockResponse, XcpCommModeBasic};
+
+        let legacy = GetDaqClockResponse::from_can_frame(&[0xFF, 0x00, 0x00, 0x00, 0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(legacy, GetDaqClockResponse { timestamp: 0x1122_3344, trigger_info: None, payload_format: None });
+
+        // a 2 byte timestamp in a short frame
+        let short = GetDaqClockResponse::from_can_frame(&[0xFF, 0x00, 0x00, 0x00, 0x34, 0x12]);
+        assert_eq!(short.timestamp, 0x1234);
+
+        let motorola = XcpCommModeBasic(0x01);
+        let extended = GetDaqClockResponse::from_can_frame_with_mode(&[0xFF, 0x00, 0x18, 0x01, 0x11, 0x22, 0x33, 0x44], motorola);
+        assert_eq!(extended, GetDaqClockResponse { timestamp: 0x1122_3344, trigger_info: Some(0x18), payload_format: Some(0x01) });
+    }
+
+    #[test]
+    fn read_daq_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{DaqEntry, ReadDaqCommand, ReadDaqResponse, WriteDaqCommand, XcpCommModeBasic};
+
+        assert_eq!(ReadDaqCommand.to_can_frame().unwrap().as_slice(), &[0xDB]);
+
+        // READ_DAQ answers with the layout WRITE_DAQ sends
+        let written = WriteDaqCommand {
+            bit_offset: None,
+            size: 4,
+            address_extension: 2,
+            address: 0x1122_3344,
+            byte_order: ByteOrder::Motorola,
+        }.to_can_frame().unwrap();
+        let mut frame = written.as_slice().to_vec();
+        frame[0] = 0xFF;
+
+        let entry = ReadDaqResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01)).entry;
+        assert_eq!(entry, DaqEntry { bit_offset: None, size: 4, address_extension: 2, address: 0x1122_3344 });
+
+        let entry = ReadDaqResponse::from_can_frame(&[0xFF, 0x05, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00]).entry;
+        assert_eq!(entry.bit_offset, Some(5));
+        assert_eq!(entry.address, 0x1000);
+    }
+
+    #[test]
+    fn get_daq_processor_info_decode() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::{
+            DaqAddressExtension, DaqConfigType, DaqIdentificationFieldType, DaqOptimisationType,
+            DaqOverloadIndication, GetDaqProcessorInfoResponse, XcpCommModeBasic
+        };
+
+        let frame = [0xFF, 0x93, 0x00, 0x10, 0x00, 0x04, 0x02, 0x52];
+        let info = GetDaqProcessorInfoResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01));
+        assert_eq!(info.properties.config_type(), DaqConfigType::Dynamic);
+        assert!(info.properties.prescaler_supported());
+        assert!(!info.properties.resume_supported());
+        assert!(info.properties.timestamp_supported());
+        assert_eq!(info.properties.overload_indication(), Some(DaqOverloadIndication::EventPacket));
+        assert_eq!(info.max_daq, 0x0010);
+        assert_eq!(info.max_event_channel, 0x0004);
+        assert_eq!(info.min_daq, 2);
+        assert_eq!(info.key_byte.optimisation_type(), DaqOptimisationType::OdtType32);
+        assert_eq!(info.key_byte.address_extension_type(), DaqAddressExtension::SameWithinOdt);
+        assert_eq!(info.key_byte.identification_field_type(), DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte);
+
+        let info = GetDaqProcessorInfoResponse::from_can_frame(&[0xFF, 0x40, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(info.properties.config_type(), DaqConfigType::Static);
+        assert_eq!(info.properties.overload_indication(), Some(DaqOverloadIndication::PidMsb));
+        assert_eq!(info.max_daq, 0x0010);
+        assert_eq!(info.key_byte.identification_field_type(), DaqIdentificationFieldType::AbsolutePid);
+    }
+
+    #[test]
+    fn get_daq_resolution_info_decode() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::{GetDaqResolutionInfoResponse, XcpCommModeBasic, XcpTimeUnit};
+
+        // DWORD timestamps, fixed, 10 us unit, 5 units per tick
+        let frame = [0xFF, 0x01, 0x07, 0x02, 0x04, 0x4C, 0x00, 0x05];
+        let info = GetDaqResolutionInfoResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01));
+        assert_eq!(info.granularity_odt_entry_size_daq, 1);
+        assert_eq!(info.max_odt_entry_size_daq, 7);
+        assert_eq!(info.granularity_odt_entry_size_stim, 2);
+        assert_eq!(info.max_odt_entry_size_stim, 4);
+        assert_eq!(info.timestamp_mode.size(), 4);
+        assert!(info.timestamp_mode.fixed());
+        assert_eq!(info.timestamp_mode.unit(), XcpTimeUnit::Us10);
+        assert_eq!(info.timestamp_ticks, 5);
+        assert!((info.tick_seconds().unwrap() - 50e-6).abs() < 1e-12);
+
+        let info = GetDaqResolutionInfoResponse::from_can_frame(&[0xFF, 0x01, 0x07, 0x01, 0x07, 0xF2, 0x05, 0x00]);
+        assert_eq!(info.timestamp_ticks, 5);
+        assert_eq!(info.timestamp_mode.unit(), XcpTimeUnit::Unknown(0x0F));
+        assert_eq!(info.tick_seconds(), None);
+    }
+
+    #[test]
+    fn get_daq_list_info_encode_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{GetDaqListInfoCommand, GetDaqListInfoResponse, XcpCommModeBasic};
+
+        let cmd = GetDaqListInfoCommand { daq_list: 0x0102, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xD8, 0x00, 0x02, 0x01]);
+
+        let frame = [0xFF, 0x07, 0x03, 0x07, 0x00, 0x02];
+        let info = GetDaqListInfoResponse::from_can_frame_with_mode(&frame, XcpCommModeBasic(0x01));
+        assert!(info.properties.predefined());
+        assert!(info.properties.daq());
+        assert!(!info.properties.stim());
+        assert_eq!(info.max_odt, 3);
+        assert_eq!(info.max_odt_entries, 7);
+        assert_eq!(info.fixed_event, Some(0x0002));
+
+        let info = GetDaqListInfoResponse::from_can_frame(&[0xFF, 0x0C, 0x10, 0x08, 0xAA, 0xBB]);
+        assert!(!info.properties.predefined());
+        assert!(info.properties.stim());
+        assert_eq!(info.fixed_event, None);
+    }
+
+    #[test]
+    fn get_daq_event_info_encode_decode() {
+        use xcp::frame::{ByteOrder, XcpCommand, XcpResponse};
+        use xcp::xcp_command::{DaqEventConsistency, GetDaqEventInfoCommand, GetDaqEventInfoResponse, XcpTimeUnit};
+
+        let cmd = GetDaqEventInfoCommand { event_channel: 0x0102, byte_order: ByteOrder::Motorola };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xD7, 0x00, 0x01, 0x02]);
+
+        // DAQ only, consistency on DAQ level, 10 x 1 ms
+        let info = GetDaqEventInfoResponse::from_can_frame(&[0xFF, 0x44, 0x01, 0x09, 0x0A, 0x06, 0x80]);
+        assert!(info.properties.daq());
+        assert!(!info.properties.stim());
+        assert_eq!(info.properties.consistency(), Some(DaqEventConsistency::Daq));
+        assert_eq!(info.max_daq_list, 1);
+        assert_eq!(info.name_length, 9);
+        assert_eq!(info.time_unit, XcpTimeUnit::Ms1);
+        assert_eq!(info.priority, 0x80);
+        assert!((info.cycle_seconds().unwrap() - 0.01).abs() < 1e-12);
+
+        let info = GetDaqEventInfoResponse::from_can_frame(&[0xFF, 0x0C, 0xFF, 0x00, 0x00, 0x06, 0x00]);
+        assert_eq!(info.cycle_seconds(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn enumerate_event_names() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let events = master.enumerate_events(true).expect("enumerate_events failed");
+        for event in &events {
+            let name = event.name.as_deref().unwrap_or_default();
+            assert!(name.len() <= event.info.name_length as usize);
+            println!("{}: {} ({:?} s)", event.event_channel, name, event.info.cycle_seconds());
+        }
+    }
+
+    #[test]
+    fn daq_allocation_encode() {
+        use xcp::frame::{ByteOrder, XcpCommand};
+        use xcp::xcp_command::{AllocDaqCommand, AllocOdtCommand, AllocOdtEntryCommand, FreeDaqCommand};
+
+        assert_eq!(FreeDaqCommand.to_can_frame().unwrap().as_slice(), &[0xD6]);
+
+        let cmd = AllocDaqCommand { daq_count: 0x0102, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xD5, 0x00, 0x02, 0x01]);
+
+        let cmd = AllocOdtCommand { daq_list: 0x0102, odt_count: 3, byte_order: ByteOrder::Motorola };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xD4, 0x00, 0x01, 0x02, 0x03]);
+
+        let cmd = AllocOdtEntryCommand { daq_list: 0x0102, odt: 3, entry_count: 7, byte_order: ByteOrder::Intel };
+        assert_eq!(cmd.to_can_frame().unwrap().as_slice(), &[0xD3, 0x00, 0x02, 0x01, 0x03, 0x07]);
+    }
+
+    #[test]
+    #[serial]
+    fn daq_allocation_sequence() {
+        use xcp::error::XcpError;
+        use xcp::master::DaqAllocPhase;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        match master.alloc_odt(0, 1) {
+            Err(XcpError::DaqAllocationSequence { phase: DaqAllocPhase::NotFreed, .. }) => {}
+            other => panic!("expected a sequence error, got {:?}", other),
+        }
+
+        master.free_daq().expect("free_daq failed");
+        master.alloc_daq(1).expect("alloc_daq failed");
+        master.alloc_odt(0, 1).expect("alloc_odt failed");
+        master.alloc_odt_entry(0, 0, 2).expect("alloc_odt_entry failed");
+        assert!(master.alloc_daq(1).is_err());
+    }
+
+    #[test]
+    fn daq_capacity_error_message() {
+        use xcp::error::XcpError;
+
+        let e = XcpError::DaqCapacityExceeded { daq_list: 2, odt: None, requested: 5, limit: 4 };
+        assert_eq!(e.to_string(), "DAQ list 2 has 4 ODTs, 5 requested");
+
+        let e = XcpError::DaqCapacityExceeded { daq_list: 2, odt: Some(1), requested: 9, limit: 7 };
+        assert_eq!(e.to_string(), "ODT 1 of DAQ list 2 has 7 entries, 9 requested");
+    }
+
+    #[test]
+    fn daq_list_builder_packs_odts() {
+        use xcp::daq::DaqListBuilder;
+        use xcp::error::XcpError;
+
+        let list = DaqListBuilder::new(1)
+            .add(0x1000, 0, 4)
+            .add(0x1004, 0, 2)
+            .add(0x1008, 0, 4)
+            .add(0x100c, 0, 1)
+            .event(2, 1, 0);
+        let layout = list.layout(7, 1, 0).unwrap();
+        assert_eq!(layout.daq_list, 1);
+        assert_eq!(layout.odts.iter().map(|odt| odt.len()).collect::<Vec<_>>(), vec![2, 2]);
+        assert_eq!(layout.odts[1][0].address, 0x1008);
+
+        let list = DaqListBuilder::new(3).add(0x2000, 0, 2).add(0x2002, 0, 4);
+        match list.layout(7, 2, 0) {
+            Err(XcpError::DaqEntryTooLarge { daq_list: 3, entry: 1, size: 8, max: 7 }) => {}
+            other => panic!("expected DaqEntryTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn daq_list_builder_optimised_layout() {
+        use xcp::daq::DaqListBuilder;
+        use xcp::xcp_command::DaqOptimisationType;
+
+        let list = DaqListBuilder::new(0)
+            .add(0x1000, 0, 4)
+            .add(0x1004, 0, 1)
+            .add(0x1005, 0, 2)
+            .add(0x1008, 0, 4);
+        let sizes = |optimisation| list.optimised_layout(8, 1, 0, optimisation).unwrap().odts.iter()
+            .map(|odt| odt.iter().map(|entry| entry.size).collect::<Vec<u8>>())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes(DaqOptimisationType::Default), vec![vec![4, 1, 2], vec![4]]);
+        // 32 bit entries get ODTs of their own
+        assert_eq!(sizes(DaqOptimisationType::OdtType32), vec![vec![4], vec![1, 2], vec![4]]);
+        assert_eq!(sizes(DaqOptimisationType::OdtType16), vec![vec![4, 1], vec![2], vec![4]]);
+    }
+
+    #[test]
+    fn daq_overload_indication_required() {
+        use xcp::daq::{CanBitrate, DaqListBuilder};
+        use xcp::error::XcpError;
+        use xcp::transport::MockTransport;
+
+        let estimate = |properties: u8| {
+            let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+                0xFF => vec![mock_connect_response()],
+                // GET_DAQ_PROCESSOR_INFO, absolute PIDs
+                0xDA => vec![vec![0xFF, properties, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00]],
+                // GET_DAQ_EVENT_INFO
+                0xD7 => vec![vec![0xFF, 0x04, 0xFF, 0x00, 0x0A, 0x06, 0x00]],
+                _ => vec![vec![0xFE, 0x20]],
+            });
+            let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+            master.connect(ConnectMode::Normal).expect("connect failed");
+            let list = DaqListBuilder::new(0).add(0x1000, 0, 4).require_overload_indication(true);
+            master.estimate_daq_bus_load(&[list], CanBitrate::Classic(500_000)).map(|_| ())
+        };
+
+        assert!(estimate(0x40).is_ok());
+        assert!(estimate(0x80).is_ok());
+        for properties in [0x00, 0xC0] {
+            match estimate(properties) {
+                Err(XcpError::OverloadIndicationUnsupported { daq_list: 0 }) => {}
+                other => panic!("expected OverloadIndicationUnsupported for {:#04x}, got {:?}", properties, other),
+            }
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn configure_daq_session() {
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.get_daq_processor_info().expect("get_daq_processor_info failed");
+        let list = DaqListBuilder::new(info.min_daq as u16)
+            .add(0x2000_0000, 0, 4)
+            .add(0x2000_0004, 0, 2)
+            .event(0, 1, 0);
+
+        let mut session = master.configure_daq(&[list]).expect("configure_daq failed");
+        for sample in session.samples(Duration::from_millis(500)).take(5) {
+            let sample = sample.expect("receiving samples failed");
+            assert_eq!(sample.daq_list, info.min_daq as u16);
+            assert_eq!(sample.values[0].len(), 4);
+        }
+        session.stop().expect("stop failed");
+    }
+
+    #[test]
+    fn dto_decoder_identification_fields() {
+        use std::time::Instant;
+        use xcp::daq::{Dto, DtoDecoder};
+        use xcp::error::XcpError;
+        use xcp::frame::ByteOrder;
+        use xcp::xcp_command::DaqIdentificationFieldType;
+
+        let now = Instant::now();
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel);
+        decoder.add_list(0, 0x00, vec![vec![4, 2], vec![1]], false);
+        decoder.add_list(1, 0x02, vec![vec![2]], false);
+        match decoder.decode(&[0x01, 0xAA], now).unwrap() {
+            Dto::Sample(sample) => assert_eq!((sample.daq_list, sample.odt, sample.values), (0, 1, vec![vec![0xAA]])),
+            other => panic!("expected a sample, got {:?}", other),
+        }
+        match decoder.decode(&[0x02, 0x34, 0x12], now).unwrap() {
+            Dto::Sample(sample) => assert_eq!((sample.daq_list, sample.values), (1, vec![vec![0x34, 0x12]])),
+            other => panic!("expected a sample, got {:?}", other),
+        }
+        assert_eq!(decoder.decode(&[0x07, 0x01], now).unwrap(), Dto::Unknown(vec![0x07, 0x01]));
+        match decoder.decode(&[0x00, 1, 2, 3, 4, 5], now) {
+            Err(XcpError::DtoLengthMismatch { daq_list: 0, odt: 0, length: 5, expected: 6 }) => {}
+            other => panic!("expected a length mismatch, got {:?}", other),
+        }
+
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord, ByteOrder::Motorola);
+        decoder.add_list(0x0102, 0, vec![vec![1], vec![2]], false);
+        assert_eq!(decoder.identify(&[0x01, 0x01, 0x02, 0xAB, 0xCD]), Some((0x0102, 1, &[0xAB, 0xCD][..])));
+
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned, ByteOrder::Intel)
+            .allow_padding(8);
+        decoder.add_list(3, 0, vec![vec![2]], false);
+        match decoder.decode(&[0x00, 0x00, 0x03, 0x00, 0x11, 0x22, 0x00, 0x00], now).unwrap() {
+            Dto::Sample(sample) => assert_eq!(sample.values, vec![vec![0x11, 0x22]]),
+            other => panic!("expected a sample, got {:?}", other),
+        }
+        assert_eq!(decoder.identify(&[0x00, 0x00, 0x04, 0x00]), None);
+    }
+
+    #[test]
+    fn dto_decoder_timestamps() {
+        use std::time::{Duration, Instant};
+        use xcp::daq::{DaqListBuilder, DaqTimestamp, Dto, DtoDecoder};
+        use xcp::frame::ByteOrder;
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::{DaqIdentificationFieldType, GetDaqResolutionInfoResponse};
+
+        // 2 byte timestamps in units of 10 us.
+        let resolution = GetDaqResolutionInfoResponse::from_can_frame(&[0xFF, 1, 7, 1, 7, 0x42, 0x01, 0x00]);
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel)
+            .with_timestamps(&resolution);
+        assert_eq!(decoder.timestamp_len(true), 2);
+        assert_eq!(decoder.timestamp_len(false), 0);
+        decoder.add_list(0, 0, vec![vec![1], vec![2]], true);
+
+        let now = Instant::now();
+        let mut timestamps = Vec::new();
+        for dto in [&[0x00, 0xF0, 0xFF, 0x01][..], &[0x01, 0xAA, 0xBB], &[0x00, 0x10, 0x00, 0x02]] {
+            match decoder.decode(dto, now).unwrap() {
+                Dto::Sample(sample) => timestamps.push((sample.values, sample.timestamp.unwrap())),
+                other => panic!("expected a sample, got {:?}", other),
+            }
+        }
+        assert_eq!(timestamps[0], (vec![vec![0x01]], DaqTimestamp { ticks: 0xFFF0, elapsed: 0, time: Some(Duration::ZERO) }));
+        assert_eq!(timestamps[1].1, timestamps[0].1);
+        assert_eq!(timestamps[2].1.ticks, 0x0010);
+        assert_eq!(timestamps[2].1.elapsed, 0x20);
+        assert_eq!(timestamps[2].1.time.unwrap().as_micros(), 0x20 * 10);
+
+        let list = DaqListBuilder::new(0).add(0x1000, 0, 4).add(0x1004, 0, 2).timestamp(true);
+        let layout = list.layout(7, 1, 2).unwrap();
+        assert_eq!(layout.odts.iter().map(|odt| odt.len()).collect::<Vec<_>>(), vec![1, 1]);
+    }
+
+    #[test]
+    #[serial]
+    fn daq_background_receiver() {
+        use std::time::Duration;
+        use xcp::daq::{Backpressure, DaqListBuilder};
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+        let dto_sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.get_daq_processor_info().expect("get_daq_processor_info failed");
+        let list = DaqListBuilder::new(info.min_daq as u16).add(0x2000_0000, 0, 4).event(0, 1, 0);
+
+        let session = master.configure_daq(&[list]).expect("configure_daq failed");
+        let mut receiver = session.spawn_receiver(dto_sock, 4, Backpressure::DropOldest);
+        receiver.master().get_status().expect("commands must still work while receiving");
+        std::thread::sleep(Duration::from_millis(500));
+
+        let remaining = receiver.stop().expect("stop failed");
+        assert!(remaining.len() <= 4);
+        assert!(remaining.iter().all(|sample| sample.is_ok()));
+    }
+
+    #[test]
+    fn csv_recorder_rows() {
+        use std::time::{Duration, Instant};
+        use xcp::daq::{ValueFormat, CsvRecorder, DaqListBuilder, DaqSample};
+        use xcp::frame::ByteOrder;
+
+        let lists = [
+            DaqListBuilder::new(0).add(0x1000, 0, 2).add(0x1002, 0, 1).add(0x1003, 0, 4),
+            DaqListBuilder::new(1).add(0x2000, 0, 1),
+        ];
+        let mut recorder = CsvRecorder::new(Vec::new(), ByteOrder::Intel, &lists)
+            .label(0, 0, "speed, rpm")
+            .format(0, 0, ValueFormat::Unsigned)
+            .format(0, 1, ValueFormat::Signed)
+            .format(1, 0, ValueFormat::Unsigned);
+
+        let start = Instant::now();
+        let sample = |daq_list, odt, first_entry, values: Vec<Vec<u8>>, ms| DaqSample {
+            daq_list, odt, first_entry, values, timestamp: None, received: start + Duration::from_millis(ms), overrun: false,
+        };
+        recorder.record(&sample(0, 0, 0, vec![vec![0x34, 0x12], vec![0xFF]], 0)).unwrap();
+        recorder.record(&sample(1, 0, 0, vec![vec![7]], 1)).unwrap();
+        recorder.record(&sample(0, 1, 2, vec![vec![0xDE, 0xAD, 0xBE, 0xEF]], 2)).unwrap();
+        recorder.record(&sample(0, 0, 0, vec![vec![0x01, 0x00], vec![0x01]], 10)).unwrap();
+        let csv = String::from_utf8(recorder.finish().unwrap()).unwrap();
+
+        assert_eq!(csv, "host_time,daq_list,\"speed, rpm\",0x1002:1,0x1003:4,0x2000:1,overrun\n\
+                         0.001000000,1,,,,7,0\n\
+                         0.000000000,0,4660,-1,deadbeef,,0\n\
+                         0.010000000,0,1,1,,,1\n");
+    }
+
+    #[test]
+    fn recorder_time_bases() {
+        use std::time::{Duration, Instant};
+        use xcp::daq::{CsvRecorder, DaqListBuilder, DaqSample, DaqTimestamp, DtoDecoder, TimeBase};
+        use xcp::frame::{ByteOrder, XcpResponse};
+        use xcp::xcp_command::{DaqIdentificationFieldType, GetDaqResolutionInfoResponse, XcpTimeUnit};
+
+        // 2 byte timestamps with the unit in the upper nibble
+        let decoder = |timestamp_mode: u8| {
+            let resolution = GetDaqResolutionInfoResponse::from_can_frame(&[0xFF, 1, 7, 1, 7, timestamp_mode, 0x01, 0x00]);
+            DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel).with_timestamps(&resolution)
+        };
+        let untimed = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel);
+        assert_eq!(untimed.time_base(), TimeBase::Host);
+        assert_eq!(decoder(0x42).time_base(), TimeBase::Slave { tick_seconds: 1e-5 });
+        assert_eq!(decoder(0xF2).time_base(), TimeBase::Ticks);
+        assert_eq!(TimeBase::from_unit(XcpTimeUnit::Unknown(0x0F), 1), TimeBase::Ticks);
+
+        let lists = [
+            DaqListBuilder::new(0).add(0x1000, 0, 1).timestamp(true),
+            DaqListBuilder::new(1).add(0x2000, 0, 1),
+        ];
+        let start = Instant::now();
+        let sample = |daq_list, value, timestamp: Option<(u32, u64)>, ms| DaqSample {
+            daq_list, odt: 0, first_entry: 0, values: vec![vec![value]],
+            timestamp: timestamp.map(|(ticks, elapsed)| DaqTimestamp { ticks, elapsed, time: None }),
+            received: start + Duration::from_millis(ms), overrun: false,
+        };
+        let samples = [sample(0, 1, Some((0xFFF0, 0)), 0), sample(1, 2, None, 5), sample(0, 3, Some((0x0010, 0x20)), 10)];
+        let record = |time_base| {
+            let mut recorder = CsvRecorder::new(Vec::new(), ByteOrder::Intel, &lists).time_base(time_base);
+            for sample in &samples {
+                recorder.record(sample).unwrap();
+            }
+            String::from_utf8(recorder.finish().unwrap()).unwrap()
+        };
+
+        assert_eq!(record(TimeBase::Host), "host_time,daq_list,0x1000:1,0x2000:1,overrun\n\
+                                            0.000000000,0,01,,0\n\
+                                            0.005000000,1,,02,0\n\
+                                            0.010000000,0,03,,0\n");
+        assert_eq!(record(decoder(0x42).time_base()), "time,host_time,daq_list,0x1000:1,0x2000:1,overrun\n\
+                                                       0.000000000,0.000000000,0,01,,0\n\
+                                                       ,0.005000000,1,,02,0\n\
+                                                       0.000320000,0.010000000,0,03,,0\n");
+        // a slave reporting 10 us ticks that really are 1 ms
+        assert!(record(TimeBase::from_unit(XcpTimeUnit::Ms1, 1)).ends_with("\n0.032000000,0.010000000,0,03,,0\n"));
+        assert_eq!(record(decoder(0xF2).time_base()), "ticks,host_time,daq_list,0x1000:1,0x2000:1,overrun\n\
+                                                       0,0.000000000,0,01,,0\n\
+                                                       ,0.005000000,1,,02,0\n\
+                                                       32,0.010000000,0,03,,0\n");
+    }
+
+    #[test]
+    #[cfg(feature = "mdf")]
+    fn mdf4_recorder_time_channels() {
+        use std::io::Cursor;
+        use std::time::Instant;
+        use xcp::daq::{DaqListBuilder, DaqSample, DaqTimestamp, TimeBase};
+        use xcp::mdf::Mdf4Recorder;
+        use xcp::xcp_command::{XcpCommModeBasic, XcpTimeUnit};
+
+        let u64_at = |file: &[u8], offset: u64| u64::from_le_bytes(file[offset as usize..][..8].try_into().unwrap());
+        let link = |file: &[u8], block: u64, n: u64| u64_at(file, block + 24 + 8 * n);
+        let text = |file: &[u8], block: u64| {
+            assert_eq!(&file[block as usize..][..4], b"##TX");
+            let data = &file[block as usize + 24..][..u64_at(file, block + 8) as usize - 24];
+            String::from_utf8(data.split(|&b| b == 0).next().unwrap().to_vec()).unwrap()
+        };
+        // name, unit, cn_type and data type of the channels of a channel group
+        let channels = |file: &[u8], cg: u64| {
+            let mut channels = Vec::new();
+            let mut cn = link(file, cg, 1);
+            while cn != 0 {
+                let unit = match link(file, cn, 6) { 0 => None, unit => Some(text(file, unit)) };
+                let data = &file[cn as usize + 24 + 64..];
+                channels.push((text(file, link(file, cn, 2)), unit, data[0], data[2]));
+                cn = link(file, cn, 0);
+            }
+            channels
+        };
+        let seconds = |name: &str, cn_type| (name.to_string(), Some("s".to_string()), cn_type, 4);
+
+        let lists = [
+            DaqListBuilder::new(0).add(0x1000, 0, 1).timestamp(true),
+            DaqListBuilder::new(1).add(0x2000, 0, 1),
+        ];
+        let start = Instant::now();
+        let record = |time_base| {
+            let mut recorder = Mdf4Recorder::new(Cursor::new(Vec::new()), XcpCommModeBasic::default(), &lists)
+                .time_base(time_base);
+            recorder.record(&DaqSample {
+                daq_list: 0, odt: 0, first_entry: 0, values: vec![vec![7]],
+                timestamp: Some(DaqTimestamp { ticks: 0x10, elapsed: 250, time: None }),
+                received: start, overrun: false,
+            }).unwrap();
+            let file = recorder.finish().unwrap().into_inner();
+            let dg = link(&file, 64, 0);
+            let cg0 = link(&file, dg, 1);
+            let cg1 = link(&file, cg0, 0);
+            let dt = link(&file, dg, 2);
+            (channels(&file, cg0), channels(&file, cg1), file[dt as usize + 24..].to_vec())
+        };
+
+        let (list0, list1, data) = record(TimeBase::from_unit(XcpTimeUnit::S1, 2));
+        assert_eq!(list0[..2], [seconds("time", 2), seconds("host_time", 0)]);
+        assert_eq!(list1[0], seconds("host_time", 2));
+        assert_eq!(data[0], 1); // record id
+        assert_eq!(f64::from_le_bytes(data[1..9].try_into().unwrap()), 500.0);
+        assert_eq!(data.len(), 1 + 16 + 1 + 1);
+
+        let (list0, list1, data) = record(TimeBase::Ticks);
+        assert_eq!(list0[..2], [seconds("host_time", 2), ("ticks".to_string(), None, 0, 0)]);
+        assert_eq!(list1[0], seconds("host_time", 2));
+        assert_eq!(u64::from_le_bytes(data[9..17].try_into().unwrap()), 250);
+
+        let (list0, _, data) = record(TimeBase::Host);
+        assert_eq!(list0.iter().map(|channel| channel.0.as_str()).collect::<Vec<_>>(), ["host_time", "0x1000:1", "overrun"]);
+        assert_eq!(data.len(), 1 + 8 + 1 + 1);
+    }
+
+    #[test]
+    #[cfg(feature = "mdf")]
+    fn mdf4_recorder_blocks() {
+        use std::io::Cursor;
+        use std::time::{Duration, Instant};
+        use xcp::daq::{DaqListBuilder, DaqSample, ValueFormat};
+        use xcp::mdf::Mdf4Recorder;
+        use xcp::xcp_command::XcpCommModeBasic;
+
+        let u64_at = |file: &[u8], offset: u64| u64::from_le_bytes(file[offset as usize..][..8].try_into().unwrap());
+        let link = |file: &[u8], block: u64, n: u64| u64_at(file, block + 24 + 8 * n);
+        let text = |file: &[u8], block: u64| {
+            assert_eq!(&file[block as usize..][..4], b"##TX");
+            let data = &file[block as usize + 24..][..u64_at(file, block + 8) as usize - 24];
+            String::from_utf8(data.split(|&b| b == 0).next().unwrap().to_vec()).unwrap()
+        };
+
+        let lists = [
+            DaqListBuilder::new(0).add(0x1000, 0, 2).add(0x1002, 0, 4),
+            DaqListBuilder::new(1).add(0x2000, 0, 1),
+        ];
+        let mut recorder = Mdf4Recorder::new(Cursor::new(Vec::new()), XcpCommModeBasic::default(), &lists)
+            .channel(0, 0, "speed", Some("rpm"))
+            .format(0, 0, ValueFormat::Unsigned);
+
+        let start = Instant::now();
+        let sample = |daq_list, odt, first_entry, values: Vec<Vec<u8>>, ms| DaqSample {
+            daq_list, odt, first_entry, values, timestamp: None, received: start + Duration::from_millis(ms), overrun: false,
+        };
+        recorder.record(&sample(0, 0, 0, vec![vec![0x34, 0x12]], 0)).unwrap();
+        recorder.record(&sample(0, 1, 1, vec![vec![1, 2, 3, 4]], 0)).unwrap();
+        recorder.record(&sample(1, 0, 0, vec![vec![7]], 500)).unwrap();
+        recorder.record(&sample(0, 0, 0, vec![vec![0, 0]], 1000)).unwrap();
+        assert_eq!(recorder.incomplete_samples(), 0);
+        let file = recorder.finish().unwrap().into_inner();
+
+        assert_eq!(&file[..16], b"MDF     4.10    ");
+        assert_eq!(u16::from_le_bytes([file[28], file[29]]), 410);
+        assert_eq!(&file[60..64], &[0; 4]);
+        assert_eq!(&file[64..68], b"##HD");
+
+        let dg = link(&file, 64, 0);
+        assert_eq!(&file[dg as usize..][..4], b"##DG");
+        assert_eq!(file[dg as usize + 24 + 32], 1);
+
+        let cg0 = link(&file, dg, 1);
+        assert_eq!(&file[cg0 as usize..][..4], b"##CG");
+        assert_eq!(text(&file, link(&file, cg0, 2)), "DAQ list 0");
+        assert_eq!(u64_at(&file, cg0 + 72), 1); // record id
+        assert_eq!(u64_at(&file, cg0 + 80), 1); // cycle count
+        let time = link(&file, cg0, 1);
+        assert_eq!(text(&file, link(&file, time, 2)), "host_time");
+        let speed = link(&file, time, 0);
+        assert_eq!(text(&file, link(&file, speed, 2)), "speed");
+        assert_eq!(text(&file, link(&file, speed, 6)), "rpm");
+        assert_eq!(file[speed as usize + 24 + 64 + 2], 0); // unsigned LE
+        let raw = link(&file, speed, 0);
+        assert_eq!(text(&file, link(&file, raw, 2)), "0x1002:4");
+        assert_eq!(file[raw as usize + 24 + 64 + 2], 10); // byte array
+        let overrun = link(&file, raw, 0);
+        assert_eq!(text(&file, link(&file, overrun, 2)), "overrun");
+        assert_eq!(link(&file, overrun, 0), 0);
+
+        let cg1 = link(&file, cg0, 0);
+        assert_eq!(u64_at(&file, cg1 + 80), 1);
+        assert_eq!(link(&file, cg1, 0), 0);
+
+        let dt = link(&file, dg, 2);
+        assert_eq!(&file[dt as usize..][..4], b"##DT");
+        assert_eq!(u64_at(&file, dt + 8), 24 + (1 + 15) + (1 + 10));
+        assert_eq!(dt as usize + 24 + 27, file.len());
+        let records = &file[dt as usize + 24..];
+        assert_eq!(records[0], 1);
+        assert_eq!(&records[9..16], &[0x34, 0x12, 1, 2, 3, 4, 0]);
+        assert_eq!(records[16], 2);
+        assert_eq!(f64::from_le_bytes(records[17..25].try_into().unwrap()), 0.5);
+        assert_eq!(&records[25..27], &[7, 0]);
+    }
+
+    #[test]
+    fn dto_decoder_overruns() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Instant;
+        use xcp::daq::{DaqOverrun, Dto, DtoDecoder};
+        use xcp::frame::ByteOrder;
+        use xcp::xcp_command::{DaqIdentificationFieldType, DaqOverloadIndication};
+
+        let now = Instant::now();
+        let overruns = Arc::new(Mutex::new(Vec::new()));
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel)
+            .with_overload_indication(DaqOverloadIndication::PidMsb);
+        let reported = overruns.clone();
+        decoder.on_overrun(move |overrun| reported.lock().unwrap().push(*overrun));
+        decoder.add_list(0, 0, vec![vec![1], vec![1], vec![1]], false);
+
+        let mut flags = Vec::new();
+        for dto in [[0x00, 1], [0x01, 2], [0x02, 3], [0x01, 4], [0x82, 5], [0x00, 6]] {
+            match decoder.decode(&dto, now).unwrap() {
+                Dto::Sample(sample) => flags.push(sample.overrun),
+                other => panic!("expected a sample, got {:?}", other),
+            }
+        }
+        assert_eq!(flags, vec![false, false, false, true, true, false]);
+        assert_eq!(*overruns.lock().unwrap(), vec![
+            DaqOverrun::MissingOdts { daq_list: 0, expected: 0, received: 1, missing: 1 },
+            DaqOverrun::Overload { daq_list: Some(0) },
+        ]);
+        let stats = decoder.stats();
+        assert_eq!((stats.samples, stats.overloads, stats.missing_odts), (6, 1, 1));
+
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel)
+            .with_overload_indication(DaqOverloadIndication::EventPacket);
+        decoder.add_list(0, 0, vec![vec![1]], false);
+        assert!(matches!(decoder.decode(&[0xFD, 0x06], now), Ok(Dto::Unknown(_))));
+        match decoder.decode(&[0x00, 1], now).unwrap() {
+            Dto::Sample(sample) => assert!(sample.overrun),
+            other => panic!("expected a sample, got {:?}", other),
+        }
+        assert_eq!(decoder.stats().overloads, 1);
+    }
+
+    #[test]
+    fn encode_stim_dto() {
+        use xcp::daq::{encode_dto, Dto, DtoDecoder};
+        use xcp::frame::ByteOrder;
+        use xcp::xcp_command::DaqIdentificationFieldType;
+
+        assert_eq!(encode_dto(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel, 3, 0x12, &[0xAA]), vec![0x12, 0xAA]);
+        assert_eq!(encode_dto(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte, ByteOrder::Intel, 3, 1, &[0xAA]), vec![1, 3, 0xAA]);
+        assert_eq!(encode_dto(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord, ByteOrder::Motorola, 0x0102, 1, &[]), vec![1, 0x01, 0x02]);
+        let dto = encode_dto(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned, ByteOrder::Intel, 0x0102, 0, &[0xAA, 0xBB]);
+        assert_eq!(dto, vec![0, 0, 0x02, 0x01, 0xAA, 0xBB]);
+
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned, ByteOrder::Intel);
+        decoder.add_list(0x0102, 0, vec![vec![2]], false);
+        match decoder.decode(&dto, std::time::Instant::now()).unwrap() {
+            Dto::Sample(sample) => assert_eq!(sample.values, vec![vec![0xAA, 0xBB]]),
+            other => panic!("expected a sample, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn stim_session_write() {
+        use xcp::daq::DaqListBuilder;
+        use xcp::error::XcpError;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.get_daq_processor_info().expect("get_daq_processor_info failed");
+        let list = DaqListBuilder::new(info.min_daq as u16).add(0x2000_0000, 0, 2).event(0, 1, 0);
+
+        let mut session = master.configure_stim(&[list]).expect("configure_stim failed");
+        session.write(info.min_daq as u16, 0, &[0x34, 0x12]).expect("write failed");
+        match session.write(info.min_daq as u16, 0, &[0x34]) {
+            Err(XcpError::DtoLengthMismatch { length: 1, expected: 2, .. }) => {}
+            other => panic!("expected a length mismatch, got {:?}", other),
+        }
+        session.stop().expect("stop failed");
+    }
+
+    #[test]
+    #[serial]
+    fn bypass_loop() {
+        use std::time::Duration;
+        use xcp::bypass::{BypassLoop, BypassOutput};
+        use xcp::daq::DaqListBuilder;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.get_daq_processor_info().expect("get_daq_processor_info failed");
+        let input = DaqListBuilder::new(info.min_daq as u16).add(0x2000_0000, 0, 2).event(0, 1, 0);
+        let output = DaqListBuilder::new(info.min_daq as u16 + 1).add(0x2000_0010, 0, 2).event(0, 1, 0);
+
+        let mut bypass = BypassLoop::new(&mut master, &[input], &[output]).expect("bypass setup failed");
+        bypass.run(Duration::from_millis(200), |sample, _deadline| {
+            let value = u16::from_le_bytes([sample.values[0][0], sample.values[0][1]]).wrapping_add(1);
+            vec![BypassOutput { daq_list: info.min_daq as u16 + 1, odt: 0, payload: value.to_le_bytes().to_vec() }]
+        }).expect("bypass failed");
+        assert_eq!(bypass.stats().outputs, bypass.stats().cycles);
+        bypass.stop().expect("stop failed");
+    }
+
+    #[test]
+    #[serial]
+    fn resume_daq_session() {
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::error::XcpError;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.get_daq_processor_info().expect("get_daq_processor_info failed");
+        let list = DaqListBuilder::new(info.min_daq as u16)
+            .add(0x2000_0000, 0, 4)
+            .event(0, 1, 0)
+            .resume(true);
+        let lists = [list];
+
+        let mut session = master.configure_daq(&lists).expect("configure_daq failed");
+        session.master().store_daq_for_resume(0x1234, Duration::from_secs(5)).expect("store_daq_for_resume failed");
+        drop(session);
+
+        master.connect(ConnectMode::Normal).expect("reconnect failed");
+        match master.resume_daq(&lists, 0x4321) {
+            Err(XcpError::SessionConfigurationMismatch { expected: 0x4321, actual: Some(0x1234) }) => {}
+            Err(e) => panic!("expected a session configuration mismatch, got {}", e),
+            Ok(_) => panic!("resumed with the wrong session configuration id"),
+        }
+
+        let mut session = master.resume_daq(&lists, 0x1234).expect("resume_daq failed");
+        let sample = session.samples(Duration::from_millis(500)).next().expect("no sample received");
+        assert_eq!(sample.expect("receiving samples failed").values[0].len(), 4);
+        session.stop().expect("stop failed");
+    }
+
+    #[test]
+    fn dto_decoder_list_stats() {
+        use std::time::{Duration, Instant};
+        use xcp::daq::DtoDecoder;
+        use xcp::frame::ByteOrder;
+        use xcp::xcp_command::DaqIdentificationFieldType;
+
+        let now = Instant::now();
+        let mut decoder = DtoDecoder::new(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel);
+        decoder.add_list(0, 0, vec![vec![2], vec![1]], false);
+        decoder.add_list(1, 2, vec![vec![1]], false);
+
+        for (ms, dto) in [(0, &[0x00, 1, 2][..]), (0, &[0x01, 3]), (10, &[0x00, 1, 2]), (10, &[0x01, 3]), (20, &[0x00, 1, 2])] {
+            decoder.decode(dto, now + Duration::from_millis(ms)).unwrap();
+        }
+        assert!(decoder.decode(&[0x02, 1, 2], now).is_err());
+        decoder.decode(&[0x07], now).unwrap();
+
+        let stats = decoder.stats();
+        assert_eq!((stats.samples, stats.length_errors, stats.unknown), (5, 1, 1));
+        let list = stats.lists[0];
+        assert_eq!((list.daq_list, list.frames, list.bytes, list.samples, list.decode_errors), (0, 5, 13, 5, 0));
+        assert!((list.event_rate.unwrap() - 100.0).abs() < 1e-6);
+        let list = stats.lists[1];
+        assert_eq!((list.daq_list, list.frames, list.samples, list.decode_errors, list.event_rate), (1, 1, 0, 1, None));
+
+        decoder.reset_stats();
+        let stats = decoder.stats();
+        assert_eq!((stats.samples, stats.lists[0].frames, stats.lists[0].event_rate), (0, 0, None));
+        assert_eq!(stats.lists[1].daq_list, 1);
+    }
+
+    #[test]
+    fn estimate_daq_bus_load() {
+        use xcp::daq::{estimate_bus_load, CanBitrate, DaqListLoad};
+
+        let lists = [
+            DaqListLoad { daq_list: 0, events_per_second: Some(1000.0), dto_lengths: vec![8, 8], extended_id: false },
+            DaqListLoad { daq_list: 1, events_per_second: None, dto_lengths: vec![8], extended_id: false },
+        ];
+        let load = estimate_bus_load(&lists, CanBitrate::Classic(500_000));
+        assert_eq!((load.frames_per_second, load.bits_per_second), (2000.0, 270_000.0));
+        assert!((load.load - 0.54).abs() < 1e-9);
+        assert_eq!(load.acyclic_lists, vec![1]);
+
+        // A 10 byte DTO is sent in a 12 byte CAN FD frame.
+        let lists = [DaqListLoad { daq_list: 0, events_per_second: Some(1000.0), dto_lengths: vec![10], extended_id: false }];
+        let load = estimate_bus_load(&lists, CanBitrate::Fd { nominal: 500_000, data: 2_000_000 });
+        assert_eq!(load.bits_per_second, 187_000.0);
+        assert!((load.load - 0.1445).abs() < 1e-9);
+    }
+
+    #[test]
+    fn poll_schedule_earliest_deadline_first() {
+        use std::time::{Duration, Instant};
+        use xcp::polling::PollSchedule;
+
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut schedule = PollSchedule::new(vec![ms(10), ms(25)], start);
+        assert_eq!(schedule.take_due(start), Some((0, 0)));
+        assert_eq!(schedule.take_due(start), Some((1, 0)));
+        assert_eq!(schedule.take_due(start), None);
+        assert_eq!(schedule.next_due(), Some(start + ms(10)));
+
+        assert_eq!(schedule.take_due(start + ms(12)), Some((0, 0)));
+        // Both entries are late; the one due earlier goes first and skips the
+        // periods it missed.
+        assert_eq!(schedule.take_due(start + ms(55)), Some((0, 3)));
+        assert_eq!(schedule.next_due(), Some(start + ms(25)));
+        assert_eq!(schedule.take_due(start + ms(55)), Some((1, 1)));
+        assert_eq!(schedule.next_due(), Some(start + ms(60)));
+    }
+
+    #[test]
+    #[serial]
+    fn poller_short_upload() {
+        use std::time::Duration;
+        use xcp::polling::{PollEntry, Poller};
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let entries = vec![
+            PollEntry { address: 0x2000_0000, address_extension: 0, size: 4, period: Duration::from_millis(100) },
+            PollEntry { address: 0x2000_0100, address_extension: 0, size: 32, period: Duration::from_millis(250) },
+        ];
+        let mut poller = Poller::new(&mut master, entries);
+        for sample in poller.samples().take(6) {
+            let sample = sample.expect("polling failed");
+            let size = if sample.first_entry == 0 { 4 } else { 32 };
+            assert_eq!(sample.values[0].len(), size);
+        }
+        poller.master().get_status().expect("get_status between polls failed");
+        assert_eq!(poller.stats().uploads, 6);
+    }
+
+    #[test]
+    fn program_commands_encode() {
+        use xcp::frame::{AddressGranularity, ByteOrder, XcpCommand};
+        use xcp::xcp_command::{ProgramClearCommand, ProgramClearMode, ProgramNextCommand};
+
+        let clear = ProgramClearCommand { mode: ProgramClearMode::Absolute, clear_range: 0x1000, byte_order: ByteOrder::Intel };
+        assert_eq!(clear.to_can_frame().unwrap().as_slice(), &[0xD1, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00]);
+
+        let data: Vec<u8> = (1..=10).collect();
+        let (program, next) = ProgramNextCommand::block(&data, AddressGranularity::Byte, 8);
+        assert_eq!(program.to_can_frame().unwrap().as_slice(), &[0xD0, 10, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].to_can_frame().unwrap().as_slice(), &[0xCA, 4, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn program_codec_across_blocks() {
+        use xcp::flash::{IdentityCodec, ProgramCodec};
+        use xcp::frame::{AddressGranularity, XcpCommand};
+        use xcp::xcp_command::{ProgramFormatCommand, ProgramNextCommand};
+
+        /// Stream cipher stand-in: the key advances with every byte, so encoding
+        /// the data in pieces would give a different stream than encoding it at once.
+        struct RollingXorCodec { key: u8 }
+
+        impl ProgramCodec for RollingXorCodec {
+            fn encode(&mut self, data: &[u8]) -> Vec<u8> {
+                data.iter().map(|byte| {
+                    self.key = self.key.wrapping_add(1);
+                    byte ^ self.key
+                }).collect()
+            }
+
+            fn encryption_method(&self) -> u8 { 0x01 }
+        }
+
+        assert!(!IdentityCodec.needs_format());
+        let codec = RollingXorCodec { key: 0x5A };
+        assert!(codec.needs_format());
+        let format = ProgramFormatCommand { encryption_method: codec.encryption_method(), ..ProgramFormatCommand::default() };
+        assert_eq!(format.to_can_frame().unwrap().as_slice(), &[0xCB, 0x00, 0x01, 0x00, 0x00]);
+
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let encoded = RollingXorCodec { key: 0x5A }.encode(&data);
+
+        // Blocks of 4 frames with 6 bytes each, as in master block mode with MAX_BS_PGM 4.
+        let mut sent = Vec::new();
+        for block in encoded.chunks(24) {
+            let (program, next) = ProgramNextCommand::block(block, AddressGranularity::Byte, 8);
+            sent.extend_from_slice(&program.data);
+            for command in next {
+                sent.extend_from_slice(&command.data);
+            }
+        }
+        assert_eq!(sent, encoded);
+        assert_eq!(RollingXorCodec { key: 0x5A }.encode(&sent), data);
+
+        let per_block: Vec<u8> = data.chunks(24).flat_map(|block| RollingXorCodec { key: 0x5A }.encode(block)).collect();
+        assert_ne!(per_block, encoded);
+    }
+
+    #[test]
+    fn parse_ihex_regions() {
+        use std::io::ErrorKind;
+        use xcp::flash::{parse_ihex, HexRegion};
+
+        let hex = ":020000040800F2\n\
+                   :0400000001020304F2\n\
+                   :020004000506EF\n\
+                   :0100100007E8\n\
+                   :00000001FF\n";
+        assert_eq!(parse_ihex(hex.as_bytes()).unwrap(), vec![
+            HexRegion { address: 0x0800_0000, data: vec![1, 2, 3, 4, 5, 6] },
+            HexRegion { address: 0x0800_0010, data: vec![7] },
+        ]);
+
+        let bad_checksum = ":0400000001020304F3\n:00000001FF\n";
+        let e = parse_ihex(bad_checksum.as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("line 1"));
+        assert!(parse_ihex(":0400000001020304F2\n".as_bytes()).is_err());
+        assert!(parse_ihex(":0400000001020304F2\n:0100020007F6\n:00000001FF\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn flash_ihex_image() {
+        use xcp::error::XcpError;
+        use xcp::flash::FlashOptions;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let hex = ":020000040800F2\n:0400000001020304F2\n:00000001FF\n";
+        let mut calls = Vec::new();
+        let mut progress = |done: usize, total: usize| calls.push((done, total));
+        match master.flash_ihex(hex.as_bytes(), FlashOptions::default(), Some(&mut progress)) {
+            Ok(report) => {
+                assert_eq!(calls.last(), Some(&(4, 4)));
+                assert_eq!(report.regions.len(), 1);
+                assert!(report.is_verified());
+                assert!(report.reset);
+            }
+            Err(XcpError::FlashFailed { source, .. }) if matches!(*source, XcpError::PgmLocked) => {}
+            Err(e) => panic!("flash_ihex failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn parse_srec_regions() {
+        use std::io::ErrorKind;
+        use xcp::flash::{parse_srec, HexRegion};
+
+        let srec = "S0060000686472BB\n\
+                    S107100001020304DE\n\
+                    S2060110040506D9\n\
+                    S3060800000007EA\n\
+                    S5030003F9\n\
+                    S9030000FC\n";
+        assert_eq!(parse_srec(srec.as_bytes()).unwrap(), vec![
+            HexRegion { address: 0x1000, data: vec![1, 2, 3, 4] },
+            HexRegion { address: 0x01_1004, data: vec![5, 6] },
+            HexRegion { address: 0x0800_0000, data: vec![7] },
+        ]);
+
+        let e = parse_srec("S107100001020304DE\nS2060110040506D8\n".as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "line 2 (S2): checksum mismatch");
+        let e = parse_srec("S107100001020304DE\nS5030002FA\n".as_bytes()).unwrap_err();
+        assert!(e.to_string().starts_with("line 2 (S5)"));
+        assert!(parse_srec("S107100001020304DE\nS104100209E0\n".as_bytes()).is_err());
+        assert!(parse_srec("S4030000FC\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_elf_segments() {
+        use xcp::flash::{parse_elf, AddressMapping, ElfOptions, HexRegion};
+
+        // ELF32 little endian header followed by three program headers and the data
+        let mut elf = vec![0x7F, b'E', b'L', b'F', 1, 1, 1];
+        elf.resize(52, 0);
+        elf[0x1C] = 52;
+        elf[0x2A] = 32;
+        elf[0x2C] = 3;
+        let segments: [[u32; 8]; 3] = [
+            [1, 148, 0x2000_0000, 0x0800_1000, 4, 8, 5, 4],
+            [4, 0, 0, 0, 4, 4, 4, 4],
+            [1, 152, 0x1000, 0x1000, 4, 4, 5, 4],
+        ];
+        for field in segments.iter().flatten() {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+        elf.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert_eq!(parse_elf(&elf[..], &ElfOptions::default()).unwrap(), vec![
+            HexRegion { address: 0x1000, data: vec![5, 6, 7, 8] },
+            HexRegion { address: 0x0800_1000, data: vec![1, 2, 3, 4] },
+        ]);
+
+        let mapped = ElfOptions {
+            mappings: vec![AddressMapping { virtual_address: 0x2000_0000, length: 0x100, physical_address: 0x0900_0000 }],
+            ..ElfOptions::default()
+        };
+        assert_eq!(parse_elf(&elf[..], &mapped).unwrap()[1], HexRegion { address: 0x0900_0000, data: vec![1, 2, 3, 4] });
+
+        let range = 0x0800_1002..0x0800_2000;
+        let filtered = ElfOptions { ranges: vec![range], ..ElfOptions::default() };
+        assert_eq!(parse_elf(&elf[..], &filtered).unwrap(), vec![HexRegion { address: 0x0800_1002, data: vec![3, 4] }]);
+
+        assert!(parse_elf(&elf[..100], &ElfOptions::default()).is_err());
+        assert!(parse_elf(&b"not an elf file at all"[..], &ElfOptions::default()).is_err());
+    }
+
+    #[test]
+    fn validate_flash_regions() {
+        use xcp::flash::{validate_regions, FlashSector, FlashViolation, HexRegion};
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::GetSectorInfoResponse;
+
+        let info = GetSectorInfoResponse::from_can_frame(&[0xFF, 1, 2, 0, 0x00, 0x20, 0x00, 0x00]);
+        assert_eq!((info.clear_sequence_number, info.program_sequence_number, info.sector_info), (1, 2, 0x2000));
+
+        let sector = |sector: u8, address: u32| FlashSector {
+            sector, address, length: 0x1000, clear_sequence_number: sector, program_sequence_number: sector, programming_method: 0,
+        };
+        let sectors = [sector(2, 0x4000), sector(0, 0x1000), sector(1, 0x2000)];
+        let regions = [
+            HexRegion { address: 0x1000, data: vec![0; 0x2000] },
+            HexRegion { address: 0x2800, data: vec![0; 0x1000] },
+        ];
+        let validation = validate_regions(&regions, &sectors, 1);
+        assert_eq!(validation[0].sectors, vec![0, 1]);
+        assert!(validation[0].violations.is_empty());
+        assert_eq!(validation[1].sectors, vec![1]);
+        assert_eq!(validation[1].violations, vec![
+            FlashViolation::PartialSector { sector: 1 },
+            FlashViolation::OutsideSectors { address: 0x3000, length: 0x800 },
+        ]);
+
+        let validation = validate_regions(&[HexRegion { address: 0x4000, data: vec![0; 3] }], &sectors, 2);
+        assert_eq!(validation[0].violations, vec![
+            FlashViolation::UnalignedLength { length: 3, granularity: 2 },
+            FlashViolation::PartialSector { sector: 2 },
+        ]);
+    }
+
+    #[test]
+    #[serial]
+    fn unlock_resource() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let protection = master.unlock_resource(XcpResource::Pgm, &|seed: &[u8]| seed.to_vec()).expect("unlock failed");
+        assert!(!protection.pgm);
+    }
+
+    #[test]
+    fn unlock_all_resources() {
+        use std::cell::RefCell;
+        use xcp::error::XcpError;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        // CAL/PAG, DAQ and PGM; STIM is not protected
+        slave.borrow_mut().protection = 0x15;
+        slave.borrow_mut().seed = (1..=10).collect();
+        let seeded = RefCell::new(Vec::new());
+        let mut transport = MockTransport::new(mock_ids(), |cmd| {
+            if cmd[0] == 0xF8 && cmd[1] == 0x00 { seeded.borrow_mut().push(cmd[2]) }
+            slave.borrow_mut().respond(cmd)
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let summary = master.unlock_all(&MockKey, false).expect("GET_STATUS failed");
+        assert!(summary.is_unlocked());
+        assert_eq!(summary.resources.iter().map(|unlock| unlock.resource).collect::<Vec<_>>(),
+                   vec![XcpResource::CalPage, XcpResource::Daq, XcpResource::Pgm]);
+        assert!(summary.protection.resources().is_empty());
+        // one resource at a time, the 10 byte seed and key in two frames each
+        assert_eq!(*seeded.borrow(), vec![0x01, 0x04, 0x10]);
+        assert_eq!(slave.borrow().log[2..], [0xF8, 0xF8, 0xF7, 0xF7].repeat(3));
+        assert_eq!(slave.borrow().protection, 0x00);
+
+        // nothing protected any more, only GET_STATUS is sent
+        let summary = master.unlock_all(&MockKey, false).expect("GET_STATUS failed");
+        assert!(summary.resources.is_empty());
+        assert_eq!(slave.borrow().log.len(), 15);
+        assert_eq!(slave.borrow().log.last(), Some(&0xFD));
+
+        // ERR_GENERIC for the GET_SEED of DAQ: PGM is unlocked all the same, unless failing fast
+        for fail_fast in [false, true] {
+            let mut fresh = MockSlave::new();
+            fresh.protection = 0x15;
+            fresh.seed = vec![0x5A; 4];
+            fresh.fail(0xF8, 2, 0x31);
+            *slave.borrow_mut() = fresh;
+            let summary = master.unlock_all(&MockKey, fail_fast).expect("GET_STATUS failed");
+            assert!(!summary.is_unlocked());
+            let results: Vec<_> = summary.resources.iter().map(|unlock| (unlock.resource, unlock.result.is_ok())).collect();
+            if fail_fast {
+                assert_eq!(results, vec![(XcpResource::CalPage, true), (XcpResource::Daq, false)]);
+                assert_eq!(slave.borrow().protection, 0x14);
+            } else {
+                assert_eq!(results, vec![(XcpResource::CalPage, true), (XcpResource::Daq, false), (XcpResource::Pgm, true)]);
+                assert_eq!(slave.borrow().protection, 0x04);
+                assert!(summary.protection.daq && !summary.protection.pgm);
+            }
+            assert!(matches!(summary.resources[1].result, Err(XcpError::NegativeResponse(_))));
+        }
+    }
+
+    #[test]
+    fn cached_protection() {
+        use std::cell::RefCell;
+        use xcp::frame::XcpErrorCode;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().protection = 0x10;
+        slave.borrow_mut().seed = vec![0x11, 0x22, 0x33, 0x44];
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        assert!(master.protection().is_none());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        assert!(master.protection().is_some());
+        assert!(master.get_status().expect("get_status failed").protection.pgm);
+
+        let protection = master.unlock_resource(XcpResource::Pgm, &MockKey).expect("unlock failed");
+        assert!(!protection.pgm);
+        assert_eq!(master.protection(), Some(protection));
+        // PGM is known to be unlocked now, so this is answered from the cache
+        assert!(!master.unlock_resource(XcpResource::Pgm, &|_: &[u8]| -> Vec<u8> { panic!("seed requested") }).unwrap().pgm);
+        assert_eq!(slave.borrow().log, vec![0xFF, 0xFD, 0xF8, 0xF7]);
+
+        // locked again behind the master's back, the cache is wrong until the slave refuses a command
+        slave.borrow_mut().protection = 0x10;
+        let err = master.program_start().expect_err("PROGRAM_START of a locked slave");
+        assert_eq!(err.error_code(), Some(XcpErrorCode::ErrAccessLocked));
+        assert!(master.protection().is_none());
+
+        // refreshed with GET_STATUS before the seed and key are exchanged again
+        assert!(!master.unlock_resource(XcpResource::Pgm, &MockKey).expect("unlock failed").pgm);
+        assert_eq!(slave.borrow().log[4..], [0xD2, 0xFD, 0xF8, 0xF7]);
+        master.program_start().expect("program_start failed");
+
+        master.disconnect().expect("disconnect failed");
+        assert!(master.protection().is_none());
+    }
+
+    #[test]
+    fn lazy_unlock() {
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use xcp::frame::{XcpCommandCode, XcpErrorCode};
+        use xcp::master::SeedKeyAlgorithm;
+        use xcp::session::SessionEvent;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().protection = 0x01;
+        slave.borrow_mut().seed = vec![0xA0, 0xA1, 0xA2, 0xA3];
+        slave.borrow_mut().segments = vec![(0x8000, 0x100, 0x00, vec![(0x00, 0x00)])];
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        let events = master.subscribe_events();
+        let mut providers: HashMap<XcpResource, Box<dyn SeedKeyAlgorithm>> = HashMap::new();
+        providers.insert(XcpResource::CalPage, Box::new(MockKey));
+        master.set_seed_key_provider(providers);
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        // idempotent: one seed/key exchange, then sent once more
+        master.get_pag_processor_info().expect("GET_PAG_PROCESSOR_INFO failed after unlocking");
+        assert_eq!(slave.borrow().log, vec![0xFF, 0xE9, 0xFD, 0xF8, 0xF7, 0xE9]);
+        assert_eq!(slave.borrow().protection, 0x00);
+        let unlocks: Vec<_> = events.try_iter().filter_map(|e| match e.event {
+            SessionEvent::AutoUnlock { command, resource } => Some((command, resource)),
+            _ => None,
+        }).collect();
+        assert_eq!(unlocks, vec![(XcpCommandCode::GetPagProcessorInfo, XcpResource::CalPage)]);
+
+        // DOWNLOAD advances the MTA, so it is not retried unless allowed
+        slave.borrow_mut().protection = 0x01;
+        master.set_mta(0x8000, 0).expect("set_mta failed");
+        let err = master.download(&[1, 2, 3, 4]).expect_err("DOWNLOAD of a locked slave");
+        assert_eq!(err.error_code(), Some(XcpErrorCode::ErrAccessLocked));
+        assert_eq!(slave.borrow().log[6..], [0xF6, 0xF0]);
+        assert!(events.try_iter().all(|e| !matches!(e.event, SessionEvent::AutoUnlock { .. })));
+
+        master.allow_unlock_retry(XcpCommandCode::Download);
+        master.set_mta(0x8000, 0).expect("set_mta failed");
+        master.download(&[1, 2, 3, 4]).expect("DOWNLOAD failed after unlocking");
+        assert_eq!(slave.borrow().log[8..], [0xF6, 0xF0, 0xFD, 0xF8, 0xF7, 0xF0]);
+        assert_eq!(events.try_iter().filter(|e| matches!(e.event, SessionEvent::AutoUnlock { .. })).count(), 1);
+        drop(master);
+        assert_eq!(slave.borrow().read(0x8000, 4), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn get_version_encode() {
+        use xcp::frame::{XcpCommand, XcpResponse, XcpVersion};
+        use xcp::xcp_command::{GetVersionCommand, GetVersionResponse};
+
+        assert_eq!(GetVersionCommand.to_can_frame().unwrap().as_slice(), &[0xC0, 0x00]);
+        let version = GetVersionResponse::from_can_frame(&[0xFF, 0x00, 0x01, 0x04, 0x01, 0x02]);
+        assert_eq!(version.protocol_layer, XcpVersion::new(1, 4));
+        assert_eq!(version.transport_layer, XcpVersion::new(1, 2));
+    }
+
+    #[test]
+    #[serial]
+    fn slave_info() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let info = master.slave_info().expect("slave_info failed");
+        println!("{:#?}", info);
+        assert!(info.protocol_version.is_some());
+        assert_eq!(master.slave_info().expect("slave_info failed").max_cto, info.max_cto);
+    }
+
+    #[test]
+    fn epk_padding() {
+        use xcp::epk::epk_string;
+
+        assert_eq!(epk_string(b"EPK_1.2.3\0\0"), "EPK_1.2.3");
+        assert_eq!(epk_string(b"EPK_1.2.3\xFF\xFF\xFF"), "EPK_1.2.3");
+        assert_eq!(epk_string(b"EPK 1\0\xFF\0"), "EPK 1");
+        assert_eq!(epk_string(b"\xFF\xFF"), "");
+        assert_eq!(epk_string(b"A\0B"), "A\0B");
+    }
+
+    #[test]
+    #[serial]
+    fn dump_memory_map() {
+        use xcp::dump::{MemoryMap, MemoryRegion, RegionDumpStatus};
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let map = MemoryMap {
+            regions: vec![
+                MemoryRegion { name: "first".to_string(), address: 0x0000_0000, address_extension: 0, length: 100 },
+                MemoryRegion { name: "second".to_string(), address: 0x0000_1000, address_extension: 0, length: 33 },
+            ],
+            block_size: 64,
+            ..MemoryMap::default()
+        };
+        let result = master.dump_map(&map);
+
+        assert_eq!(result.regions.len(), 2);
+        for (region, dump) in map.regions.iter().zip(&result.regions) {
+            assert_eq!(dump.name, region.name);
+            match &dump.status {
+                RegionDumpStatus::Failed { offset, error } => panic!("{} failed at {}: {}", dump.name, offset, error),
+                _ => assert_eq!(dump.data.len(), region.length),
+            }
+        }
+    }
+
+    #[test]
+    fn cal_diff_ranges() {
+        use xcp::cal::{diff, Characteristic};
+        use xcp::snapshot::{CalSnapshot, SnapshotRegion};
+
+        let region = |address: u32, data: Vec<u8>| SnapshotRegion { address_extension: 0, address, data };
+        let mut data: Vec<u8> = (0..64).collect();
+        let a = CalSnapshot { id: Vec::new(), regions: vec![region(0x1000, data.clone()), region(0x2000, vec![0; 4])] };
+        data[10] = 0xAA;
+        data[11] = 0xBB;
+        data[13] = 0xCC;
+        data.push(0xDD);
+        let b = CalSnapshot { id: Vec::new(), regions: vec![region(0x1000, data), region(0x3000, vec![0; 4])] };
+
+        let mut result = diff(&a, &b);
+        assert_eq!(result.only_in_a, vec![(0, 0x2000)]);
+        assert_eq!(result.only_in_b, vec![(0, 0x3000)]);
+        assert_eq!(result.segments.len(), 1);
+        let ranges = &result.segments[0].ranges;
+        assert_eq!(ranges.iter().map(|r| (r.offset, r.len())).collect::<Vec<_>>(), vec![(10, 2), (13, 1), (64, 1)]);
+        assert_eq!(ranges[0].a, vec![10, 11]);
+        assert_eq!(ranges[0].b, vec![0xAA, 0xBB]);
+        assert!(ranges[2].a.is_empty());
+
+        // a 4 x 4 map of u16 at 0x1008 covers offsets 8..40
+        result.resolve(&[
+            Characteristic { name: "KF_IGNITION_MAP".to_string(), address: 0x1008, address_extension: 0, element_size: 2, dimensions: vec![4, 4] },
+            Characteristic { name: "K_LIMIT".to_string(), address: 0x1040, address_extension: 0, element_size: 1, dimensions: vec![] },
+        ]);
+        let names = |i: usize| result.segments[0].ranges[i].changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(names(0), vec!["KF_IGNITION_MAP[0][1]"]);
+        assert_eq!(names(1), vec!["KF_IGNITION_MAP[0][2]"]);
+        assert_eq!(names(2), vec!["K_LIMIT"]);
+
+        let text = result.to_string();
+        assert!(text.contains("+0xA, 2 byte(s): 0A 0B -> AA BB"));
+        assert!(text.contains("KF_IGNITION_MAP[0][1] changed"));
+    }
+
+    #[test]
+    fn typed_values_from_bytes() {
+        use xcp::frame::ByteOrder;
+        use xcp::value::FromXcpBytes;
+
+        assert_eq!(u16::from_xcp_bytes(&[0x34, 0x12], ByteOrder::Intel), 0x1234);
+        assert_eq!(u16::from_xcp_bytes(&[0x12, 0x34], ByteOrder::Motorola), 0x1234);
+        assert_eq!(i32::from_xcp_bytes(&[0xFF, 0xFF, 0xFF, 0xFE], ByteOrder::Motorola), -2);
+        assert_eq!(f32::from_xcp_bytes(&1.5f32.to_le_bytes(), ByteOrder::Intel), 1.5);
+        assert_eq!(f64::from_xcp_bytes(&(-0.25f64).to_be_bytes(), ByteOrder::Motorola), -0.25);
+        assert_eq!(<[u16; 3]>::SIZE, 6);
+        assert_eq!(<[u16; 3]>::from_xcp_bytes(&[0, 1, 0, 2, 0, 3], ByteOrder::Motorola), [1, 2, 3]);
+    }
+
+    #[test]
+    #[serial]
+    fn read_typed_values() {
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let word = master.read_u32(0x0000_0000, 0).expect("read_u32 failed");
+        let bytes: [u8; 4] = master.read_value(0x0000_0000, 0).expect("read_value failed");
+        assert_eq!(word, master.comm_mode_basic.endianness().read_u32(&bytes));
+        // larger than one response, read with SET_MTA and UPLOAD
+        let _: [u64; 4] = master.read_value(0x0000_0000, 0).expect("read_value failed");
+    }
+
+    #[test]
+    fn typed_values_to_bytes() {
+        use xcp::frame::ByteOrder;
+        use xcp::value::{FromXcpBytes, ToXcpBytes};
+
+        assert_eq!(0x1234u16.to_xcp_bytes(ByteOrder::Intel), [0x34, 0x12]);
+        assert_eq!(0x1234u16.to_xcp_bytes(ByteOrder::Motorola), [0x12, 0x34]);
+        assert_eq!((-2i32).to_xcp_bytes(ByteOrder::Motorola), [0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_eq!([1u16, 2, 3].to_xcp_bytes(ByteOrder::Motorola), [0, 1, 0, 2, 0, 3]);
+        for order in [ByteOrder::Intel, ByteOrder::Motorola] {
+            assert_eq!(f64::from_xcp_bytes(&(-0.25f64).to_xcp_bytes(order), order), -0.25);
+            assert_eq!(u64::from_xcp_bytes(&u64::MAX.to_xcp_bytes(order), order), u64::MAX);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn write_typed_values() {
+        use xcp::master::WriteVerification;
+
+        let iface = "can0";
+        let mut sock: CanSocket = CanSocket::open(iface).expect("Failed to open socket on interface");
+
+        let mut master = xcp::master::XcpMaster::new(&mut sock, CanIdPair::from_raw(XCP_REQUEST_ID, XCP_RESPONSE_ID).unwrap());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        master.write_u32(0x0000_0000, 0, 0xDEAD_BEEF).expect("write_u32 failed");
+        assert_eq!(master.read_u32(0x0000_0000, 0).expect("read_u32 failed"), 0xDEAD_BEEF);
+        // larger than one command, written with SET_MTA and DOWNLOAD
+        master.write_value_verified(0x0000_0000, 0, [1.5f64, -2.0, 0.25], WriteVerification::ReadBack)
+            .expect("write_value_verified failed");
+    }
+
+    #[test]
+    fn flash_checkpoint_roundtrip() {
+        use std::path::PathBuf;
+        use xcp::checkpoint::{FlashCheckpoint, FlashImage};
+        use xcp::epk::ExpectedEpk;
+        use xcp::flash::{AddressMapping, ElfOptions, FlashOptions, HexRegion};
+
+        let regions = vec![
+            HexRegion { address: 0x8000_0000, data: vec![0xAA; 0x100] },
+            HexRegion { address: 0x8001_0000, data: vec![0x55; 0x40] },
+        ];
+        let elf = ElfOptions {
+            ranges: vec![0x8000_0000..0x8002_0000, 0xA000_0000..0xA000_1000],
+            mappings: vec![AddressMapping { virtual_address: 0x1000, length: 0x100, physical_address: 0x8000_0000 }],
+        };
+        let options = FlashOptions {
+            stop_daq: true,
+            epk: Some(ExpectedEpk { address: 0x8000_0400, address_extension: 0, expected: "ECU_SW_1.0".to_string() }),
+            checkpoint: Some(PathBuf::from("/tmp/flash.state")),
+            ..FlashOptions::default()
+        };
+        let mut checkpoint = FlashCheckpoint::new(b"ECU".to_vec(), FlashImage::Elf(PathBuf::from("/images/app.elf"), elf), &options, &regions);
+        checkpoint.regions[0].programmed = 0x100;
+        checkpoint.regions[1].programmed = 0x20;
+        assert_eq!(checkpoint.options.checkpoint, None);
+
+        let mut file = Vec::new();
+        checkpoint.write_to(&mut file).unwrap();
+        let read = FlashCheckpoint::read_from(file.as_slice()).unwrap();
+        assert_eq!(read, checkpoint);
+        assert!(read.matches(&regions));
+
+        let mut modified = regions.clone();
+        modified[1].data[0x3F] = 0x00;
+        assert!(!read.matches(&modified));
+        assert!(!read.matches(&regions[..1]));
+        assert!(FlashCheckpoint::read_from(&b"XCPCAL01"[..]).is_err());
+    }
+
+    #[test]
+    fn response_timeout() {
+        use std::time::{Duration, Instant};
+        use xcp::error::XcpError;
+        use xcp::frame::XcpCommandCode;
+        use xcp::master::{TimeoutClass, TimeoutConfig};
+        use xcp::transport::MockTransport;
+
+        // a slave that never answers
+        let mut transport = MockTransport::new(mock_ids(), |_| vec![]);
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.timeouts.connect = Duration::from_millis(50);
+        let start = Instant::now();
+        match master.connect(ConnectMode::Normal) {
+            Err(XcpError::Timeout { command: XcpCommandCode::Connect, class: TimeoutClass::Connect, timeout, elapsed }) => {
+                assert_eq!(timeout, Duration::from_millis(50));
+                assert!(elapsed >= timeout);
+            }
+            other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(master.statistics().response_timeouts, 1);
+
+        let slow = TimeoutConfig { connect: Duration::from_millis(200), ..master.timeouts };
+        match master.with_timeouts(slow, |master| master.connect(ConnectMode::Normal)) {
+            Err(XcpError::Timeout { timeout, .. }) => assert_eq!(timeout, Duration::from_millis(200)),
+            other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(master.timeouts.connect, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn timeout_classes() {
+        use std::time::Duration;
+        use xcp::frame::XcpCommandCode;
+        use xcp::master::{TimeoutClass, TimeoutConfig};
+
+        assert_eq!(TimeoutClass::of(XcpCommandCode::Connect), TimeoutClass::Connect);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::GetStatus), TimeoutClass::Standard);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::ShortUpload), TimeoutClass::Memory);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::BuildChecksum), TimeoutClass::Memory);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::SetCalPage), TimeoutClass::PageSwitch);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::ProgramClear), TimeoutClass::Program);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::ProgramReset), TimeoutClass::Program);
+        assert_eq!(TimeoutClass::of(XcpCommandCode::StartStopSynch), TimeoutClass::Daq);
+
+        let mut timeouts = TimeoutConfig::default();
+        assert!(timeouts.get(TimeoutClass::Connect) < timeouts.get(TimeoutClass::Standard));
+        assert!(timeouts.get(TimeoutClass::Standard) < timeouts.get(TimeoutClass::Program));
+        timeouts.set(TimeoutClass::Program, Duration::from_secs(120));
+        assert_eq!(timeouts.program, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn responses_decode_at_min_length() {
+        use xcp::frame::XcpResponse;
+        use xcp::xcp_command::*;
+
+        fn decode<R: XcpResponse>() {
+            let frame = [0xFFu8; 8];
+            R::from_can_frame(&frame[..R::MIN_LENGTH]);
+            R::from_can_frame_with_mode(&frame[..R::MIN_LENGTH], XcpCommModeBasic(0xFF));
+        }
+
+        decode::<ConnectResponse>();
+        decode::<GetSeedResponse>();
+        decode::<UnlockResponse>();
+        decode::<EmptyResponse>();
+        decode::<GetCommModeInfoResponse>();
+        decode::<GetVersionResponse>();
+        decode::<GetIdResponse>();
+        decode::<UploadResponse>();
+        decode::<BuildChecksumResponse>();
+        decode::<GetCalPageResponse>();
+        decode::<GetPagProcessorInfoResponse>();
+        decode::<SegmentBasicInfoResponse>();
+        decode::<SegmentStandardInfoResponse>();
+        decode::<SegmentMappingInfoResponse>();
+        decode::<GetPageInfoResponse>();
+        decode::<GetSegmentModeResponse>();
+        decode::<GetDaqListModeResponse>();
+        decode::<StartStopDaqListResponse>();
+        decode::<GetDaqClockResponse>();
+        decode::<ReadDaqResponse>();
+        decode::<GetDaqProcessorInfoResponse>();
+        decode::<GetDaqResolutionInfoResponse>();
+        decode::<GetDaqListInfoResponse>();
+        decode::<GetDaqEventInfoResponse>();
+        decode::<GetStatusResponse>();
+        decode::<NegativeResponse>();
+        decode::<ProgramStartResponse>();
+        decode::<GetPgmProcessorInfoResponse>();
+        decode::<GetSectorInfoResponse>();
+    }
+
+    /// Identifiers the simulated slave of the mock transport tests answers on.
+    fn mock_ids() -> CanIdPair {
+        CanIdPair::from_raw(0x7E0, 0x7E8).unwrap()
+    }
+
+    /// CONNECT response of an unprotected classic CAN slave implementing protocol
+    /// layer version 1.
+    fn mock_connect_response() -> Vec<u8> {
+        vec![0xFF, 0x00, 0x00, 0x08, 0x08, 0x01, 0x01]
+    }
+
+    #[test]
+    fn throttled_transmits_are_not_dropped() {
+        use std::cell::RefCell;
+        use xcp::transport::MockTransport;
+
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let memory = RefCell::new(Vec::new());
+        let mut remaining = 0;
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            // MASTER_BLOCK_MODE, MAX_BS 32, MIN_ST 0
+            0xFB => vec![vec![0xFF, 0x00, 0x01, 0x00, 0x20, 0x00, 0x00, 0x01]],
+            0xF0 | 0xEF => {
+                if cmd[0] == 0xF0 { remaining = cmd[1] as usize }
+                let n = usize::min(remaining, cmd.len() - 2);
+                memory.borrow_mut().extend_from_slice(&cmd[2..2 + n]);
+                remaining -= n;
+                if remaining == 0 { vec![vec![0xFF]] } else { vec![] }
+            }
+            // STIM DTOs are not answered
+            pid if pid < 0xC0 => vec![],
+            _ => vec![vec![0xFF]],
+        });
+        transport.throttle(3, 2);
+
+        let tx_retries = {
+            let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+            master.connect(ConnectMode::Normal).expect("connect failed");
+            master.get_comm_mode_info().expect("get_comm_mode_info failed");
+            master.download_block(&data).expect("download_block failed");
+            for i in 0..100u8 {
+                master.send_dto(&[0x10, i]).expect("send_dto failed");
+            }
+            master.statistics().tx_retries
+        };
+
+        assert_eq!(*memory.borrow(), data);
+        let dtos: Vec<Vec<u8>> = transport.sent_payloads().into_iter().filter(|frame| frame[0] == 0x10).collect();
+        assert_eq!(dtos, (0..100u8).map(|i| vec![0x10, i]).collect::<Vec<_>>());
+        assert!(transport.rejected > 0);
+        assert_eq!(tx_retries, transport.rejected as u64);
+    }
+
+    #[test]
+    fn protocol_version_refined() {
+        use xcp::error::XcpError;
+        use xcp::frame::{XcpCommand, XcpCommandCode, XcpCommandFrame, XcpFrameBuf, XcpResponseFrame, XcpVersion};
+        use socketcan::{CanFrame, EmbeddedFrame};
+        use xcp::xcp_command::EmptyResponse;
+        use xcp::session::SessionEvent;
+        use xcp::transport::MockTransport;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            // GET_VERSION: protocol layer 1.4, transport layer 1.2
+            0xC0 => vec![vec![0xFF, 0x00, 0x01, 0x04, 0x01, 0x02]],
+            _ => vec![vec![0xFF]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.strict = true;
+        let events = master.subscribe_events();
+
+        // CONNECT only reports the major version, which does not rule out a 1.1 command
+        #[derive(Debug)]
+        struct TimeCorrelationProperties;
+
+        impl XcpCommand for TimeCorrelationProperties {
+            fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> { XcpFrameBuf::from_slice(&[0xC6, 0, 0, 0, 0, 0]) }
+            fn get_code(&self) -> XcpCommandCode { XcpCommandCode::TimeCorrelationProperties }
+        }
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        master.get_version().expect("get_version failed");
+        assert_eq!(master.protocol_version, Some(XcpVersion::new(1, 4)));
+
+        master.set_protocol_version(XcpVersion::new(1, 2));
+        let mut properties = XcpCommandFrame { data: TimeCorrelationProperties };
+        let empty = |frame: CanFrame| XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data());
+        match master.send_recv_one_blocking(&mut properties, empty) {
+            Err(XcpError::UnsupportedByProtocolVersion { command: XcpCommandCode::TimeCorrelationProperties, .. }) => {}
+            other => panic!("expected the command to be refused, got {:?}", other.map(|_| ())),
+        }
+
+        master.strict = false;
+        master.send_recv_one_blocking(&mut properties, empty).expect("command failed");
+        assert!(events.try_iter().any(|e| matches!(e.event,
+            SessionEvent::UnsupportedCommand { command: XcpCommandCode::TimeCorrelationProperties, required, negotiated }
+                if required == XcpVersion::new(1, 3) && negotiated == XcpVersion::new(1, 2))));
+    }
+
+    #[test]
+    fn get_version_unknown() {
+        use xcp::frame::XcpVersion;
+        use xcp::info::Capability;
+        use xcp::transport::MockTransport;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            0xFD => vec![vec![0xFF, 0x00, 0x00, 0x00, 0x00, 0x00]],
+            // every optional command, GET_VERSION included, is unknown
+            _ => vec![vec![0xFE, 0x20]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.strict = true;
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let info = master.slave_info().expect("slave_info failed");
+        assert_eq!(info.version, Capability::Unsupported);
+        assert_eq!(info.protocol_version, Some(XcpVersion::new(1, 0)));
+
+        // the version is known exactly now, but GET_VERSION is still sent
+        assert!(master.get_version().is_err());
+        assert_eq!(master.statistics().response_timeouts, 0);
+    }
+
+    #[test]
+    fn dto_queue_bounded() {
+        use xcp::master::DTO_QUEUE_LIMIT;
+        use xcp::transport::MockTransport;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            _ => vec![vec![0xFF]],
+        });
+        // DTOs of a DAQ list nobody reads, ahead of the next response
+        for i in 0..DTO_QUEUE_LIMIT + 10 {
+            transport.push_response(&[0x00, i as u8]);
+        }
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        assert_eq!(master.statistics().dtos_dropped, 10);
+        assert_eq!(master.recv_dto(std::time::Duration::ZERO).unwrap(), Some(vec![0x00, 0x00]));
+    }
+
+    /// Address, length, address extension and per page the properties and init
+    /// segment of a calibration segment.
+    type MockSegment = (u32, u32, u8, Vec<(u8, u8)>);
+
+    /// The seed/key algorithm of `MockSlave`: every seed byte XORed with the resource bit.
+    struct MockKey;
+
+    impl xcp::master::SeedKeyAlgorithm for MockKey {
+        fn compute_key(&self, resource: XcpResourceFlags, seed: &[u8]) -> Vec<u8> {
+            seed.iter().map(|b| b ^ u8::from(resource)).collect()
+        }
+    }
+
+    /// A signal the DAQ engine of `MockSlave` samples at an address, as a function of
+    /// the number of the sample.
+    #[derive(Clone, Copy)]
+    enum MockSignal {
+        /// 0, 1, 2, ..., wrapping at the size of the entry.
+        Counter,
+        /// Rises by `step` per sample, starting again from 0 every `period` samples.
+        Sawtooth { period: u32, step: u32 },
+        /// `offset + amplitude * sin(2π n / period + phase)`, rounded.
+        Sine { offset: f64, amplitude: f64, period: u32, phase: f64 },
+        Constant(u32),
+    }
+
+    impl MockSignal {
+        fn value(&self, n: u32) -> u32 {
+            match *self {
+                MockSignal::Counter => n,
+                MockSignal::Sawtooth { period, step } => n % period * step,
+                MockSignal::Sine { offset, amplitude, period, phase } => {
+                    let angle = 2.0 * std::f64::consts::PI * n as f64 / period as f64 + phase;
+                    (offset + amplitude * angle.sin()).round() as i64 as u32
+                }
+                MockSignal::Constant(value) => value,
+            }
+        }
+    }
+
+    /// A DAQ list allocated in `MockSlave`.
+    #[derive(Clone, Default)]
+    struct MockDaqList {
+        /// Address and size of every entry, per ODT.
+        odts: Vec<Vec<(u32, u8)>>,
+        event_channel: u16,
+        prescaler: u8,
+        timestamp: bool,
+        selected: bool,
+        running: bool,
+        /// Occurrences of the event since the list started, and samples sent.
+        events: u32,
+        samples: u32,
+    }
+
+    impl MockDaqList {
+        fn start(&mut self) {
+            self.running = true;
+            self.events = 0;
+            self.samples = 0;
+        }
+    }
+
+    /// A simulated slave for the mock transport tests: a sparse memory behind the
+    /// MTA, the PGM commands, dynamic DAQ lists, and errors injected into chosen
+    /// commands.
+    struct MockSlave {
+        memory: std::collections::HashMap<u32, u8>,
+        mta: u32,
+        /// Elements still expected in the current block transfer.
+        block_remaining: usize,
+        /// `(command, n, error)`: the `n`th command with that code, counted from 1,
+        /// is answered with ERR `error`.
+        faults: Vec<(u8, usize, u8)>,
+        counts: std::collections::HashMap<u8, usize>,
+        /// Codes of the commands received, oldest first.
+        log: Vec<u8>,
+        /// COMM_MODE_PGM, MAX_CTO_PGM and MAX_BS_PGM reported by PROGRAM_START.
+        /// Longer PROGRAM frames are refused, and PROGRAM_NEXT without master block mode.
+        comm_mode_pgm: u8,
+        max_cto_pgm: u8,
+        max_bs_pgm: u8,
+        checksum_type: u8,
+        /// Computes the checksum reported as user-defined (0xFF).
+        user_checksum: Option<fn(&[u8]) -> u32>,
+        /// ASCII identification uploaded after GET_ID; GET_ID is refused if empty.
+        identity: Vec<u8>,
+        /// Between PROGRAM_START and PROGRAM_RESET.
+        pgm_active: bool,
+        /// Dynamic DAQ lists with absolute PIDs, and the entry WRITE_DAQ writes next
+        /// as list, ODT and entry.
+        daq_lists: Vec<MockDaqList>,
+        daq_ptr: (usize, usize, usize),
+        /// ODTs that can be allocated over all lists, at most the 0xFC absolute PIDs.
+        max_odt: usize,
+        /// Cycle of every event channel in clock ticks.
+        event_periods: Vec<u64>,
+        /// Size of the DTO timestamps in bytes, counting clock ticks of 1 ms. DAQ
+        /// has no timestamps, and GET_DAQ_RESOLUTION_INFO is refused, if 0.
+        timestamp_size: u8,
+        /// Signals sampled by the ODT entries at their address; other entries
+        /// sample the memory.
+        signals: std::collections::HashMap<u32, MockSignal>,
+        /// The test clock in ticks, only advanced by `tick`.
+        clock: u64,
+        /// Calibration segments; paging is refused if empty.
+        segments: Vec<MockSegment>,
+        /// Flash sectors as address and length; programming info is refused if empty.
+        sectors: Vec<(u32, u32)>,
+        /// Resource protection bits; the commands of a protected resource are refused
+        /// with ERR_ACCESS_LOCKED. GET_SEED of a protected resource returns `seed`,
+        /// and UNLOCK with the key `MockKey` computes for it clears the bit.
+        protection: u8,
+        seed: Vec<u8>,
+        /// Resource of the current seed, or 0, and how many of its bytes were sent.
+        seed_resource: u8,
+        seed_sent: usize,
+        /// Key bytes received so far.
+        key: Vec<u8>,
+    }
+
+    impl MockSlave {
+        fn new() -> MockSlave {
+            MockSlave {
+                memory: std::collections::HashMap::new(),
+                mta: 0,
+                block_remaining: 0,
+                faults: Vec::new(),
+                counts: std::collections::HashMap::new(),
+                log: Vec::new(),
+                comm_mode_pgm: 0,
+                max_cto_pgm: 8,
+                max_bs_pgm: 0,
+                // CRC-32
+                checksum_type: 0x09,
+                user_checksum: None,
+                identity: Vec::new(),
+                pgm_active: false,
+                daq_lists: Vec::new(),
+                daq_ptr: (0, 0, 0),
+                max_odt: 0xFC,
+                event_periods: vec![10],
+                timestamp_size: 0,
+                signals: std::collections::HashMap::new(),
+                clock: 0,
+                segments: Vec::new(),
+                sectors: Vec::new(),
+                protection: 0,
+                seed: Vec::new(),
+                seed_resource: 0,
+                seed_sent: 0,
+                key: Vec::new(),
+            }
+        }
+
+        fn fail(&mut self, command: u8, n: usize, error: u8) {
+            self.faults.push((command, n, error));
+        }
+
+        fn read(&self, address: u32, length: usize) -> Vec<u8> {
+            (address..address + length as u32).map(|a| self.memory.get(&a).copied().unwrap_or(0xFF)).collect()
+        }
+
+        fn daq_running(&self) -> bool {
+            self.daq_lists.iter().any(|list| list.running)
+        }
+
+        /// Samples `size` bytes at `address` for sample `n`.
+        fn sample(&self, address: u32, size: u8, n: u32) -> Vec<u8> {
+            match self.signals.get(&address) {
+                Some(signal) => {
+                    let mut value = signal.value(n).to_le_bytes().to_vec();
+                    value.resize(size as usize, 0x00);
+                    value
+                }
+                None => self.read(address, size as usize),
+            }
+        }
+
+        /// Advances the clock by one tick and returns the DTOs of the running lists
+        /// whose event occurred on it, as often as their prescaler says.
+        fn tick(&mut self) -> Vec<Vec<u8>> {
+            use xcp::daq::encode_dto;
+            use xcp::frame::ByteOrder;
+            use xcp::xcp_command::DaqIdentificationFieldType;
+
+            self.clock += 1;
+            let timestamp = &self.clock.to_le_bytes()[..self.timestamp_size as usize];
+            let mut dtos = Vec::new();
+            let mut first_pid = 0;
+            for index in 0..self.daq_lists.len() {
+                let pid = first_pid;
+                first_pid += self.daq_lists[index].odts.len();
+
+                let list = &mut self.daq_lists[index];
+                let period = self.event_periods[list.event_channel as usize];
+                if !list.running || !self.clock.is_multiple_of(period) { continue }
+                list.events += 1;
+                if !(list.events - 1).is_multiple_of(list.prescaler.max(1) as u32) { continue }
+                let n = list.samples;
+                list.samples += 1;
+
+                let list = &self.daq_lists[index];
+                for (odt, entries) in list.odts.iter().enumerate() {
+                    let mut payload = if odt == 0 && list.timestamp { timestamp.to_vec() } else { Vec::new() };
+                    for &(address, size) in entries {
+                        payload.extend(self.sample(address, size, n));
+                    }
+                    let pid = (pid + odt) as u8;
+                    dtos.push(encode_dto(DaqIdentificationFieldType::AbsolutePid, ByteOrder::Intel, index as u16, pid, &payload));
+                }
+            }
+            dtos
+        }
+
+        /// Advances the clock to the next tick on which a running list sends DTOs,
+        /// and returns them; nothing if no list is running.
+        fn next_dtos(&mut self) -> Vec<Vec<u8>> {
+            while self.daq_running() {
+                let dtos = self.tick();
+                if !dtos.is_empty() { return dtos }
+            }
+            Vec::new()
+        }
+
+        fn respond(&mut self, cmd: &[u8]) -> Vec<Vec<u8>> {
+            use xcp::checksum;
+            use xcp::frame::ByteOrder;
+            use xcp::xcp_command::ChecksumType;
+            use xcp::master::SeedKeyAlgorithm;
+
+            let code = cmd[0];
+            // STIM DTOs are not answered
+            if code < 0xC0 { return vec![] }
+            self.log.push(code);
+            let count = self.counts.entry(code).or_default();
+            *count += 1;
+            let count = *count;
+            if let Some(&(_, _, error)) = self.faults.iter().find(|&&(c, n, _)| c == code && n == count) {
+                self.block_remaining = 0;
+                return vec![vec![0xFE, error]];
+            }
+            // the CAL/PAG, DAQ and PGM commands of a protected resource are refused
+            let resource = match code {
+                0xE4..=0xF0 => 0x01,
+                0xD3..=0xE3 | 0xC7 => 0x04,
+                0xC8..=0xD2 => 0x10,
+                _ => 0x00,
+            };
+            if self.protection & resource != 0 {
+                self.block_remaining = 0;
+                return vec![vec![0xFE, 0x25]];
+            }
+
+            let u32_at = |offset: usize| u32::from_le_bytes(cmd[offset..offset + 4].try_into().unwrap());
+            let u16_at = |offset: usize| u16::from_le_bytes([cmd[offset], cmd[offset + 1]]);
+            match code {
+                0xFF => vec![mock_connect_response()],
+                0xFD => vec![vec![0xFF, if self.daq_running() { 0x40 } else { 0x00 }, self.protection, 0x00, 0x00, 0x00]],
+                0xF6 => { self.mta = u32_at(4); vec![vec![0xFF]] }
+                0xF5 => {
+                    let data = self.read(self.mta, cmd[1] as usize);
+                    self.mta += cmd[1] as u32;
+                    vec![[vec![0xFF], data].concat()]
+                }
+                0xF4 => {
+                    self.mta = u32_at(4);
+                    let data = self.read(self.mta, cmd[1] as usize);
+                    self.mta += cmd[1] as u32;
+                    vec![[vec![0xFF], data].concat()]
+                }
+                0xF3 => {
+                    let data = self.read(self.mta, u32_at(4) as usize);
+                    self.mta += data.len() as u32;
+                    let sum = match (self.checksum_type, self.user_checksum) {
+                        (0xFF, Some(user_checksum)) => user_checksum(&data),
+                        (code, _) => checksum::compute(ChecksumType::from_code(code), &data, ByteOrder::Intel).unwrap_or(0),
+                    };
+                    vec![[vec![0xFF, self.checksum_type, 0x00, 0x00], sum.to_le_bytes().to_vec()].concat()]
+                }
+                0xF8 => {
+                    if cmd[1] == 0x00 {
+                        self.seed_resource = self.protection & cmd[2];
+                        self.seed_sent = 0;
+                        self.key.clear();
+                    } else if self.seed_resource == 0 {
+                        return vec![vec![0xFE, 0x29]];
+                    }
+                    let seed = if self.seed_resource == 0 { &[][..] } else { &self.seed[self.seed_sent..] };
+                    let length = usize::min(seed.len(), 6);
+                    let mut response = [vec![0xFF, seed.len() as u8], seed[..length].to_vec()].concat();
+                    // padded to the full CAN frame
+                    response.resize(8, 0xCC);
+                    self.seed_sent += length;
+                    vec![response]
+                }
+                0xF7 if self.seed_resource == 0 => vec![vec![0xFE, 0x29]],
+                0xF7 => {
+                    let length = usize::min(cmd[1] as usize, cmd.len() - 2);
+                    self.key.extend_from_slice(&cmd[2..2 + length]);
+                    if (cmd[1] as usize) > length { return vec![vec![0xFF, self.protection]] }
+                    let resource = std::mem::take(&mut self.seed_resource);
+                    if std::mem::take(&mut self.key) != MockKey.compute_key(resource.into(), &self.seed) {
+                        return vec![vec![0xFE, 0x25]];
+                    }
+                    self.protection &= !resource;
+                    vec![vec![0xFF, self.protection]]
+                }
+                0xFA if !self.identity.is_empty() => {
+                    // uploaded from the MTA, from outside the flash of the tests
+                    self.mta = 0xFFFF_0000;
+                    self.memory.extend(self.identity.iter().enumerate().map(|(i, &b)| (self.mta + i as u32, b)));
+                    vec![[vec![0xFF, 0x00, 0x00, 0x00], (self.identity.len() as u32).to_le_bytes().to_vec()].concat()]
+                }
+                0xDD => {
+                    for list in &mut self.daq_lists {
+                        match cmd[1] {
+                            0x00 => list.running = false,
+                            0x01 if list.selected => list.start(),
+                            0x02 if list.selected => list.running = false,
+                            _ => {}
+                        }
+                        list.selected = false;
+                    }
+                    vec![vec![0xFF]]
+                }
+                0xD2 if self.pgm_active => vec![vec![0xFE, 0x12]],
+                0xD2 => {
+                    self.pgm_active = true;
+                    vec![vec![0xFF, 0x00, self.comm_mode_pgm, self.max_cto_pgm, self.max_bs_pgm, 0x00, 0x00]]
+                }
+                0xCF => { self.pgm_active = false; vec![vec![0xFF]] }
+                // dynamic DAQ, 4 lists, absolute PIDs, timestamps if they have a size
+                0xDA => {
+                    let properties = if self.timestamp_size > 0 { 0x11 } else { 0x01 };
+                    vec![vec![0xFF, properties, 0x04, 0x00, self.event_periods.len() as u8, 0x00, 0x00, 0x00]]
+                }
+                // ticks of 1 ms
+                0xD9 if self.timestamp_size > 0 => vec![vec![0xFF, 0x01, 0xFF, 0x01, 0xFF, 0x60 | self.timestamp_size, 0x01, 0x00]],
+                0xD6 => { self.daq_lists.clear(); vec![vec![0xFF]] }
+                0xD5 => { self.daq_lists = vec![MockDaqList::default(); u16_at(2) as usize]; vec![vec![0xFF]] }
+                0xD4 => {
+                    let allocated: usize = self.daq_lists.iter().map(|list| list.odts.len()).sum();
+                    if allocated + cmd[4] as usize > self.max_odt { return vec![vec![0xFE, 0x30]] }
+                    match self.daq_lists.get_mut(u16_at(2) as usize) {
+                        Some(list) => { list.odts.resize(list.odts.len() + cmd[4] as usize, Vec::new()); vec![vec![0xFF]] }
+                        None => vec![vec![0xFE, 0x22]],
+                    }
+                }
+                0xD3 => match self.daq_lists.get_mut(u16_at(2) as usize).and_then(|list| list.odts.get_mut(cmd[4] as usize)) {
+                    Some(entries) => { *entries = vec![(0, 0); cmd[5] as usize]; vec![vec![0xFF]] }
+                    None => vec![vec![0xFE, 0x22]],
+                },
+                0xE2 => { self.daq_ptr = (u16_at(2) as usize, cmd[4] as usize, cmd[5] as usize); vec![vec![0xFF]] }
+                0xE1 => {
+                    let (list, odt, entry) = self.daq_ptr;
+                    let Some(slot) = self.daq_lists.get_mut(list)
+                        .and_then(|list| list.odts.get_mut(odt))
+                        .and_then(|entries| entries.get_mut(entry)) else { return vec![vec![0xFE, 0x22]] };
+                    *slot = (u32_at(4), cmd[2]);
+                    self.daq_ptr.2 += 1;
+                    vec![vec![0xFF]]
+                }
+                0xE0 if u16_at(4) as usize >= self.event_periods.len() => vec![vec![0xFE, 0x22]],
+                0xE0 => match self.daq_lists.get_mut(u16_at(2) as usize) {
+                    Some(list) => {
+                        list.timestamp = cmd[1] & 0x10 != 0 && self.timestamp_size > 0;
+                        list.event_channel = u16_at(4);
+                        list.prescaler = cmd[6];
+                        vec![vec![0xFF]]
+                    }
+                    None => vec![vec![0xFE, 0x22]],
+                },
+                0xDE if u16_at(2) as usize >= self.daq_lists.len() => vec![vec![0xFE, 0x22]],
+                0xDE => {
+                    let index = u16_at(2) as usize;
+                    let first_pid: usize = self.daq_lists[..index].iter().map(|list| list.odts.len()).sum();
+                    let list = &mut self.daq_lists[index];
+                    match cmd[1] {
+                        0x00 => list.running = false,
+                        0x01 => list.start(),
+                        _ => list.selected = true,
+                    }
+                    vec![vec![0xFF, first_pid as u8]]
+                }
+                // DAQ configuration commands
+                0xE3 | 0xC7 => vec![vec![0xFF]],
+                0xD1 => {
+                    for address in self.mta..self.mta + u32_at(4) {
+                        self.memory.remove(&address);
+                    }
+                    vec![vec![0xFF]]
+                }
+                0xD0 | 0xCA if cmd.len() > self.max_cto_pgm as usize => vec![vec![0xFE, 0x21]],
+                0xCA if self.comm_mode_pgm & 0x01 == 0 => vec![vec![0xFE, 0x20]],
+                // PROGRAM/DOWNLOAD and their block continuations
+                0xD0 | 0xF0 | 0xCA | 0xEF => {
+                    if code == 0xD0 && cmd[1] == 0 { return vec![vec![0xFF]] }
+                    if code == 0xD0 || code == 0xF0 {
+                        self.block_remaining = cmd[1] as usize;
+                    } else if self.block_remaining == 0 {
+                        return vec![];
+                    }
+                    let length = usize::min(self.block_remaining, cmd.len() - 2);
+                    for &b in &cmd[2..2 + length] {
+                        self.memory.insert(self.mta, b);
+                        self.mta += 1;
+                    }
+                    self.block_remaining -= length;
+                    if self.block_remaining == 0 { vec![vec![0xFF]] } else { vec![] }
+                }
+                0xE9 if !self.segments.is_empty() => vec![vec![0xFF, self.segments.len() as u8, 0x00]],
+                0xE8 if (cmd[2] as usize) < self.segments.len() => {
+                    let (address, length, address_extension, ref pages) = self.segments[cmd[2] as usize];
+                    match (cmd[1], cmd[3]) {
+                        (0x00, 0x00) => vec![[vec![0xFF, 0x00, 0x00, 0x00], address.to_le_bytes().to_vec()].concat()],
+                        (0x00, 0x01) => vec![[vec![0xFF, 0x00, 0x00, 0x00], length.to_le_bytes().to_vec()].concat()],
+                        (0x01, _) => vec![vec![0xFF, pages.len() as u8, address_extension, 0x00, 0x00, 0x00]],
+                        _ => vec![vec![0xFE, 0x22]],
+                    }
+                }
+                0xE7 => match self.segments.get(cmd[2] as usize).and_then(|segment| segment.3.get(cmd[3] as usize)) {
+                    Some(&(properties, init_segment)) => vec![vec![0xFF, properties, init_segment]],
+                    None => vec![vec![0xFE, 0x22]],
+                },
+                // absolute access mode
+                0xCE if !self.sectors.is_empty() => vec![vec![0xFF, 0x01, self.sectors.len() as u8]],
+                0xCD => match self.sectors.get(cmd[2] as usize) {
+                    Some(&(address, length)) => {
+                        let info = if cmd[1] == 0x00 { address } else { length };
+                        let sector = cmd[2];
+                        vec![[vec![0xFF, sector, sector + 1, 0x00], info.to_le_bytes().to_vec()].concat()]
+                    }
+                    None => vec![vec![0xFE, 0x22]],
+                },
+                0xFE => vec![vec![0xFF]],
+                _ => vec![vec![0xFE, 0x20]],
+            }
+        }
+    }
+
+    #[test]
+    fn flash_recovery_retries_block() {
+        use std::cell::RefCell;
+        use xcp::flash::{FlashOptions, FlashRecovery, HexRegion};
+        use xcp::frame::XcpCommandCode;
+        use xcp::session::SessionEvent;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        // ERR_DAQ_ACTIVE for the third PROGRAM, after 12 bytes were programmed
+        slave.borrow_mut().fail(0xD0, 3, 0x11);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        let events = master.subscribe_events();
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let data: Vec<u8> = (0..40).collect();
+        let regions = vec![HexRegion { address: 0x1000, data: data.clone() }];
+        let options = FlashOptions { stop_daq: true, reset: false, ..FlashOptions::default() };
+        let mut reported = Vec::new();
+        let mut progress = |programmed: usize, total: usize| reported.push((programmed, total));
+        let report = master.run_flash(&regions, &options, None, Some(&mut progress)).expect("flash failed");
+
+        assert_eq!(report.recoveries, vec![FlashRecovery::DaqStopped { command: XcpCommandCode::Program }]);
+        assert!(report.is_verified());
+        assert!(reported.iter().all(|&(programmed, total)| programmed <= total));
+        assert_eq!(reported.last(), Some(&(40, 40)));
+        assert!(events.try_iter().any(|e| matches!(e.event, SessionEvent::FlashRecovery { .. })));
+        drop(master);
+        assert_eq!(slave.borrow().read(0x1000, 40), data);
+    }
+
+    #[test]
+    fn service_request_event() {
+        use xcp::session::SessionEvent;
+        use xcp::transport::MockTransport;
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| match cmd[0] {
+            0xFF => vec![mock_connect_response()],
+            // SERV_TEXT ahead of the response
+            0xFD => vec![vec![0xFC, 0x01, b'h', b'i'], vec![0xFF, 0x00, 0x00, 0x00, 0x00, 0x00]],
+            _ => vec![vec![0xFF]],
+        });
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        let events = master.subscribe_events();
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        master.get_status().expect("get status failed");
+
+        let requests: Vec<Vec<u8>> = events.try_iter().filter_map(|e| match e.event {
+            SessionEvent::ServiceRequest { data } => Some(data),
+            _ => None,
+        }).collect();
+        assert_eq!(requests, vec![vec![0xFC, 0x01, b'h', b'i']]);
+    }
+
+    #[test]
+    fn user_defined_checksum() {
+        use std::cell::RefCell;
+        use xcp::checksum;
+        use xcp::error::XcpError;
+        use xcp::transport::MockTransport;
+        use xcp::xcp_command::ChecksumType;
+
+        // a proprietary checksum: bytes rotated into a dword
+        fn proprietary(data: &[u8]) -> u32 {
+            data.iter().fold(0x5A5A_5A5A, |sum: u32, &b| sum.rotate_left(5) ^ b as u32)
+        }
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().memory.extend((0..16).map(|i| (0x2000 + i, i as u8)));
+        slave.borrow_mut().checksum_type = 0xFF;
+        slave.borrow_mut().user_checksum = Some(proprietary);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let data: Vec<u8> = (0..16).collect();
+
+        match master.verify_block(0x2000, 0, &data) {
+            Err(e @ XcpError::UnsupportedChecksumType(ChecksumType::UserDefined)) => {
+                assert!(e.to_string().contains("register an implementation"));
+            }
+            other => panic!("expected UnsupportedChecksumType, got {:?}", other),
+        }
+
+        master.register_checksum_algorithm(0xFF, proprietary);
+        master.verify_block(0x2000, 0, &data).expect("verify failed");
+        match master.verify_block(0x2000, 0, &[0u8; 16]) {
+            Err(XcpError::ChecksumMismatch { checksum_type: ChecksumType::UserDefined, .. }) => {}
+            other => panic!("expected ChecksumMismatch, got {:?}", other),
+        }
+
+        // the slave reports CRC-16-CCITT as user-defined
+        slave.borrow_mut().user_checksum = Some(|data| checksum::crc16_ccitt(data) as u32);
+        assert!(matches!(master.register_builtin_checksum(0xFF, "XCP_CRC_42"), Err(XcpError::UnknownChecksumAlgorithm(_))));
+        master.register_builtin_checksum(0xFF, "XCP_CRC_16_CITT").expect("register failed");
+        master.verify_block(0x2000, 0, &data).expect("verify failed");
+    }
+
+    #[test]
+    fn flash_report_matches_slave() {
+        use std::cell::RefCell;
+        use xcp::error::XcpError;
+        use xcp::flash::{FlashOptions, FlashRecovery, HexRegion, RegionVerification};
+        use xcp::frame::XcpCommandCode;
+        use xcp::transport::MockTransport;
+
+        let regions = vec![
+            HexRegion { address: 0x1000, data: (0..20).collect() },
+            HexRegion { address: 0x2000, data: (100..130).collect() },
+        ];
+        let options = FlashOptions { stop_daq: true, ..FlashOptions::default() };
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().identity = b"ECU-SIM 1.0".to_vec();
+        // ERR_DAQ_ACTIVE for the second PROGRAM of the first region
+        slave.borrow_mut().fail(0xD0, 2, 0x11);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let report = master.run_flash(&regions, &options, None, None).expect("flash failed");
+
+        assert_eq!(report.identity.as_deref(), Some("ECU-SIM 1.0"));
+        assert_eq!(report.regions.iter().map(|region| (region.address, region.length)).collect::<Vec<_>>(),
+                   vec![(0x1000, 20), (0x2000, 30)]);
+        assert_eq!(report.programmed(), 50);
+        assert_eq!(report.regions.iter().map(|region| region.retries).collect::<Vec<_>>(), vec![1, 0]);
+        assert_eq!(report.recoveries, vec![FlashRecovery::DaqStopped { command: XcpCommandCode::Program }]);
+        assert!(report.regions.iter().all(|region| region.verification == RegionVerification::Verified));
+        assert!(report.reset);
+        assert!(report.duration >= report.regions.iter().map(|region| region.duration + region.verify_duration).sum());
+        drop(master);
+        for region in &regions {
+            assert_eq!(slave.borrow().read(region.address, region.data.len()), region.data);
+        }
+
+        // ERR_GENERIC while programming the second region
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().fail(0xD0, 6, 0x31);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        match master.run_flash(&regions, &options, None, None) {
+            Err(XcpError::FlashFailed { report, source, .. }) => {
+                assert!(matches!(*source, XcpError::NegativeResponse(_)));
+                assert_eq!(report.identity, None);
+                assert_eq!(report.regions.iter().map(|region| region.length).collect::<Vec<_>>(), vec![20, 0]);
+                assert!(!report.reset);
+            }
+            other => panic!("expected FlashFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_helpers_clean_up() {
+        use std::cell::RefCell;
+        use xcp::daq::DaqListBuilder;
+        use xcp::error::{Cleanup, XcpError};
+        use xcp::flash::{FlashOptions, HexRegion};
+        use xcp::frame::XcpCommandCode;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        // ERR_GENERIC for the third PROGRAM and the first WRITE_DAQ
+        slave.borrow_mut().fail(0xD0, 3, 0x31);
+        slave.borrow_mut().fail(0xE1, 1, 0x31);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let regions = vec![HexRegion { address: 0x1000, data: (0..40).collect() }];
+        match master.run_flash(&regions, &FlashOptions::default(), None, None) {
+            Err(XcpError::FlashFailed { cleanup: Cleanup::Done { command: XcpCommandCode::ProgramReset }, .. }) => {}
+            other => panic!("expected FlashFailed after PROGRAM_RESET, got {:?}", other),
+        }
+        // without the PROGRAM_RESET, PROGRAM_START would fail with ERR_PGM_ACTIVE
+        assert!(!slave.borrow().pgm_active);
+        master.run_flash(&regions, &FlashOptions::default(), None, None).expect("second flash failed");
+
+        let list = DaqListBuilder::new(0).add(0x2000, 0, 4).add(0x2004, 0, 2);
+        match master.configure_daq(std::slice::from_ref(&list)) {
+            Err(XcpError::DaqConfigFailed { cleanup: Cleanup::Done { command: XcpCommandCode::FreeDaq }, .. }) => {}
+            Err(e) => panic!("expected DaqConfigFailed after FREE_DAQ, got {:?}", e),
+            Ok(_) => panic!("expected DaqConfigFailed after FREE_DAQ"),
+        }
+        assert_eq!(slave.borrow().log.iter().rev().find(|&&code| code != 0xE1), Some(&0xD6));
+        master.configure_daq(&[list]).expect("second configure_daq failed");
+    }
+
+    #[test]
+    fn quick_session_disconnects() {
+        use std::cell::RefCell;
+        use xcp::error::XcpError;
+        use xcp::quick;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        quick::session(&mut transport, mock_ids(), |master| master.write_memory(0x3000, 0, &[1, 2, 3, 4]))
+            .expect("write failed");
+        let data = quick::session(&mut transport, mock_ids(), |master| master.read_memory(0x3000, 0, 4))
+            .expect("read failed");
+        assert_eq!(data, vec![1, 2, 3, 4]);
+        assert_eq!(slave.borrow().log.iter().filter(|&&code| code == 0xFE).count(), 2);
+
+        // the session still ends when the operation fails
+        slave.borrow_mut().log.clear();
+        slave.borrow_mut().fail(0xF5, 2, 0x31);
+        let result = quick::session(&mut transport, mock_ids(), |master| master.read_memory(0x3000, 0, 4));
+        assert!(matches!(result, Err(ref e) if e.error_code() == Some(xcp::frame::XcpErrorCode::ErrGeneric)));
+        assert_eq!(slave.borrow().log.last(), Some(&0xFE));
+
+        // and a failed DISCONNECT is reported after a successful operation
+        slave.borrow_mut().fail(0xFE, 4, 0x31);
+        let result = quick::session(&mut transport, mock_ids(), |master| master.read_memory(0x3000, 0, 4));
+        assert!(matches!(result, Err(ref e) if e.error_code() == Some(xcp::frame::XcpErrorCode::ErrGeneric)));
+
+        // IDs are checked before the interface is opened
+        assert!(matches!(quick::read("can0", 0x7E0, 0xFFFF_FFFF, 0x3000, 4), Err(XcpError::InvalidCanId(_))));
+    }
+
+    #[test]
+    fn program_block_and_standard_mode() {
+        use std::cell::{Cell, RefCell};
+        use xcp::flash::{FlashOptions, HexRegion};
+        use xcp::transport::MockTransport;
+
+        let data: Vec<u8> = (0..60).map(|i| i * 3).collect();
+        let regions = vec![HexRegion { address: 0x4000, data: data.clone() }];
+        let options = FlashOptions { reset: false, ..FlashOptions::default() };
+
+        // PROGRAM and PROGRAM_NEXT frames sent, and how many of them were answered
+        let flash = |comm_mode_pgm: u8, max_cto_pgm: u8, max_bs_pgm: u8| {
+            let slave = RefCell::new(MockSlave::new());
+            slave.borrow_mut().comm_mode_pgm = comm_mode_pgm;
+            slave.borrow_mut().max_cto_pgm = max_cto_pgm;
+            slave.borrow_mut().max_bs_pgm = max_bs_pgm;
+            let frames = RefCell::new(Vec::new());
+            let answered = Cell::new(0);
+            let mut transport = MockTransport::new(mock_ids(), |cmd| {
+                let responses = slave.borrow_mut().respond(cmd);
+                if cmd[0] == 0xD0 || cmd[0] == 0xCA {
+                    frames.borrow_mut().push(cmd.to_vec());
+                    answered.set(answered.get() + responses.len());
+                }
+                responses
+            });
+            let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+            master.connect(ConnectMode::Normal).expect("connect failed");
+            let report = master.run_flash(&regions, &options, None, None).expect("flash failed");
+            assert!(report.is_verified());
+            drop(master);
+            let memory = slave.borrow().read(0x4000, 60);
+            (memory, frames.take(), answered.get())
+        };
+
+        // master block mode: blocks of 8 frames, one response per block
+        let (block_memory, frames, answered) = flash(0x01, 8, 8);
+        assert_eq!(frames.iter().filter(|frame| frame[0] == 0xCA).count(), 8);
+        // two blocks and the segment end
+        assert_eq!(frames.len(), 11);
+        assert_eq!(answered, 3);
+
+        // standard mode with MAX_CTO_PGM below MAX_CTO: every frame is answered
+        let (standard_memory, frames, answered) = flash(0x00, 6, 0);
+        assert!(frames.iter().all(|frame| frame[0] == 0xD0 && frame.len() <= 6));
+        assert_eq!(frames.len(), 16);
+        assert_eq!(answered, frames.len());
+
+        assert_eq!(block_memory, data);
+        assert_eq!(standard_memory, block_memory);
+    }
+
+    #[test]
+    fn memory_layout_matches_slave() {
+        use std::cell::RefCell;
+        use xcp::info::{Capability, PageLayout};
+        use xcp::master::XcpMaster;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().segments = vec![
+            // working page: ECU and XCP read/write; reference page: XCP read only
+            (0x8000_0000, 0x1000, 0x00, vec![(0x3F, 0x00), (0x0C, 0x00)]),
+            (0x8000_4000, 0x0200, 0x01, vec![(0x0F, 0x01)]),
+        ];
+        slave.borrow_mut().sectors = vec![(0x0000_0000, 0x4000), (0x0000_4000, 0x1_0000)];
+
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).unwrap();
+        let layout = master.build_memory_map().unwrap();
+
+        let segments = layout.segments.supported().expect("paging supported");
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].address, segments[0].length, segments[0].address_extension), (0x8000_0000, 0x1000, 0));
+        assert_eq!((segments[1].address, segments[1].length, segments[1].address_extension), (0x8000_4000, 0x200, 1));
+        assert_eq!(segments[0].pages, Capability::Supported(vec![
+            PageLayout { page: 0, ecu_access: true, xcp_read_access: true, xcp_write_access: true, init_segment: 0 },
+            PageLayout { page: 1, ecu_access: false, xcp_read_access: true, xcp_write_access: false, init_segment: 0 },
+        ]));
+        let sectors = layout.sectors.supported().expect("programming supported");
+        assert_eq!(sectors.iter().map(|sector| (sector.address, sector.length)).collect::<Vec<_>>(),
+            vec![(0x0000_0000, 0x4000), (0x0000_4000, 0x1_0000)]);
+        assert_eq!((sectors[1].clear_sequence_number, sectors[1].program_sequence_number), (1, 2));
+
+        let table = layout.to_string();
+        assert!(table.contains("0x80000000 0x00001000 0    yes  yes  yes   0"), "{}", table);
+        assert!(table.contains("0x00004000 0x00010000 1     2       0"), "{}", table);
+
+        // without paging and programming both are reported as unsupported
+        drop(master);
+        drop(transport);
+        let slave = RefCell::new(MockSlave::new());
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).unwrap();
+        let layout = master.build_memory_map().unwrap();
+        assert_eq!((layout.segments, layout.sectors), (Capability::Unsupported, Capability::Unsupported));
+    }
+
+    #[test]
+    fn padded_seed_spans_frames() {
+        use std::cell::RefCell;
+        use xcp::transport::MockTransport;
+
+        let seed: Vec<u8> = (1..=15).collect();
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().protection = 0x10;
+        slave.borrow_mut().seed = seed.clone();
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        // 6 + 6 + 3 bytes, the last frame padded with 3 bytes
+        assert_eq!(master.get_seed(XcpResource::Pgm.into()).expect("get_seed failed"), seed);
+        assert_eq!(slave.borrow().counts[&0xF8], 3);
+
+        // a seed filling the frame exactly, and one of an unprotected resource
+        slave.borrow_mut().seed = seed[..6].to_vec();
+        assert_eq!(master.get_seed(XcpResource::Pgm.into()).expect("get_seed failed"), &seed[..6]);
+        assert_eq!(master.get_seed(XcpResource::CalPage.into()).expect("get_seed failed"), Vec::<u8>::new());
+        assert_eq!(slave.borrow().counts[&0xF8], 5);
+    }
+
+    #[test]
+    fn flash_report_sectors() {
+        use std::cell::RefCell;
+        use xcp::checksum;
+        use xcp::flash::{FlashOptions, HexRegion, RegionVerification};
+        use xcp::frame::{ByteOrder, XcpCommandCode};
+        use xcp::transport::MockTransport;
+        use xcp::xcp_command::ChecksumType;
+
+        let regions = vec![
+            // sectors 0 and 1, and the start of 2
+            HexRegion { address: 0x1000, data: (0..40).collect() },
+            // the rest of sector 2
+            HexRegion { address: 0x1030, data: vec![0xA5; 8] },
+            // outside the sector map
+            HexRegion { address: 0x2000, data: vec![1, 2, 3, 4] },
+        ];
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().sectors = vec![(0x1000, 0x10), (0x1010, 0x10), (0x1020, 0x20)];
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        // a bit of sector 1 flips before verification
+        master.add_pre_hook(XcpCommandCode::BuildChecksum, |_| { slave.borrow_mut().memory.insert(0x1012, 0x00); });
+        master.connect(ConnectMode::Normal).expect("connect failed");
+        let options = FlashOptions { reset: false, ..FlashOptions::default() };
+        let report = master.run_flash(&regions, &options, None, None).expect("flash failed");
+
+        let sectors: Vec<_> = report.sectors.iter().map(|sector| (sector.sector, sector.address, sector.length)).collect();
+        assert_eq!(sectors, vec![(Some(0), 0x1000, 16), (Some(1), 0x1010, 16), (Some(2), 0x1020, 16), (None, 0x2000, 4)]);
+        assert_eq!(report.sectors.iter().map(|sector| sector.checksums.len()).collect::<Vec<_>>(), vec![1, 1, 2, 1]);
+        drop(master);
+        for sector in &report.sectors {
+            for block in &sector.checksums {
+                let memory = slave.borrow().read(block.address, block.length);
+                assert_eq!(block.checksum_type, ChecksumType::Crc32);
+                assert_eq!(block.actual, checksum::compute(ChecksumType::Crc32, &memory, ByteOrder::Intel).unwrap());
+            }
+        }
+        let expected = checksum::compute(ChecksumType::Crc32, &regions[0].data[16..32], ByteOrder::Intel).unwrap();
+        let mismatch = &report.sectors[1];
+        assert_eq!(mismatch.checksums[0].expected, expected);
+        assert_ne!(mismatch.checksums[0].actual, expected);
+        assert!(matches!(mismatch.verification, RegionVerification::Mismatch { offset: 0, .. }));
+        assert!(report.sectors.iter().enumerate().all(|(i, sector)| i == 1 || sector.verification == RegionVerification::Verified));
+        assert!(matches!(report.regions[0].verification, RegionVerification::Mismatch { offset: 16, .. }));
+        assert_eq!(report.regions[1].verification, RegionVerification::Verified);
+        assert!(!report.is_verified() && !report.reset);
+
+        let text = report.to_string();
+        assert_eq!(text.lines().count(), 6);
+        assert!(text.lines().nth(2).unwrap().contains("MISMATCH"));
+    }
+
+    #[test]
+    fn mock_daq_signals() {
+        use std::cell::RefCell;
+        use std::f64::consts::FRAC_PI_2;
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        {
+            let mut slave = slave.borrow_mut();
+            slave.timestamp_size = 2;
+            slave.signals.insert(0x1000, MockSignal::Counter);
+            slave.signals.insert(0x1002, MockSignal::Sawtooth { period: 3, step: 10 });
+            slave.signals.insert(0x1003, MockSignal::Sine { offset: 128.0, amplitude: 100.0, period: 4, phase: FRAC_PI_2 });
+            slave.signals.insert(0x1004, MockSignal::Constant(0xDEAD_BEEF));
+        }
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        transport.on_idle(|| slave.borrow_mut().next_dtos());
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        // every second cycle of the 10 ms event
+        let list = DaqListBuilder::new(0).add(0x1000, 0, 2).add(0x1002, 0, 1).add(0x1003, 0, 1).add(0x1004, 0, 4)
+            .event(0, 2, 0)
+            .timestamp(true);
+        let mut session = master.configure_daq(&[list]).expect("configure_daq failed");
+        let samples = session.samples(Duration::from_millis(10)).take(12).collect::<Result<Vec<_>, _>>().unwrap();
+        session.stop().expect("stop failed");
+
+        let sine = [228, 128, 28, 128, 228, 128];
+        for (n, event) in samples.chunks(2).enumerate() {
+            // the constant no longer fits next to the timestamp
+            assert_eq!((event[0].odt, event[1].odt, event[1].first_entry), (0, 1, 3));
+            assert_eq!(event[0].values, vec![(n as u16).to_le_bytes().to_vec(), vec![n as u8 % 3 * 10], vec![sine[n]]]);
+            assert_eq!(event[1].values, vec![vec![0xEF, 0xBE, 0xAD, 0xDE]]);
+            let timestamp = event[0].timestamp.unwrap();
+            assert_eq!((timestamp.ticks, timestamp.elapsed), (10 + 20 * n as u32, 20 * n as u64));
+            assert_eq!(timestamp.time, Some(Duration::from_millis(20 * n as u64)));
+            assert_eq!(event[1].timestamp, Some(timestamp));
+        }
+        assert!(!slave.borrow().daq_running());
+    }
+
+    #[test]
+    fn mock_daq_timestamp_rollover() {
+        use std::cell::RefCell;
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().timestamp_size = 1;
+        slave.borrow_mut().event_periods = vec![100];
+        slave.borrow_mut().signals.insert(0x1000, MockSignal::Counter);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        transport.on_idle(|| slave.borrow_mut().next_dtos());
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let list = DaqListBuilder::new(0).add(0x1000, 0, 1).timestamp(true);
+        let mut session = master.configure_daq(&[list]).expect("configure_daq failed");
+        let samples = session.samples(Duration::from_millis(10)).take(6).collect::<Result<Vec<_>, _>>().unwrap();
+
+        // the one byte tick counter wraps after the second and the fifth sample
+        let ticks: Vec<u32> = samples.iter().map(|sample| sample.timestamp.unwrap().ticks).collect();
+        assert_eq!(ticks, [100, 200, 44, 144, 244, 88]);
+        for (n, sample) in samples.iter().enumerate() {
+            assert_eq!(sample.values, vec![vec![n as u8]]);
+            assert_eq!(sample.timestamp.unwrap().elapsed, 100 * n as u64);
+            assert_eq!(sample.timestamp.unwrap().time, Some(Duration::from_millis(100 * n as u64)));
+        }
+    }
+
+    #[test]
+    fn mock_daq_one_entry_odt() {
+        use std::cell::RefCell;
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        slave.borrow_mut().signals.insert(0x1000, MockSignal::Counter);
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        transport.on_idle(|| slave.borrow_mut().next_dtos());
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        let mut session = master.configure_daq(&[DaqListBuilder::new(0).add(0x1000, 0, 1)]).expect("configure_daq failed");
+        let samples = session.samples(Duration::from_millis(10)).take(258).collect::<Result<Vec<_>, _>>().unwrap();
+
+        // the counter wraps at the size of the entry
+        for (n, sample) in samples.iter().enumerate() {
+            assert_eq!((sample.odt, sample.first_entry, sample.timestamp), (0, 0, None));
+            assert_eq!(sample.values, vec![vec![n as u8]]);
+        }
+        // PID and value only
+        assert_eq!(session.stats().lists[0].bytes, 2 * 258);
+    }
+
+    #[test]
+    fn mock_daq_max_size_odt() {
+        use std::cell::RefCell;
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        {
+            let mut slave = slave.borrow_mut();
+            slave.timestamp_size = 2;
+            slave.signals.insert(0x2000, MockSignal::Constant(0x0403_0201));
+            slave.signals.insert(0x2004, MockSignal::Counter);
+            slave.signals.insert(0x2008, MockSignal::Sawtooth { period: 2, step: 0x0100_0000 });
+            slave.signals.insert(0x200E, MockSignal::Constant(0x7F));
+            slave.memory.extend([(0x200C, 0xAA), (0x200D, 0xBB)]);
+        }
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        transport.on_idle(|| slave.borrow_mut().next_dtos());
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        // 2 + 4 + 1 and 4 + 2 + 1 bytes: both ODTs fill a DTO behind the PID
+        let list = DaqListBuilder::new(0).add(0x2000, 0, 4).add(0x2004, 0, 1)
+            .add(0x2008, 0, 4).add(0x200C, 0, 2).add(0x200E, 0, 1)
+            .timestamp(true);
+        let mut session = master.configure_daq(&[list]).expect("configure_daq failed");
+        let samples = session.samples(Duration::from_millis(10)).take(6).collect::<Result<Vec<_>, _>>().unwrap();
+
+        for (n, event) in samples.chunks(2).enumerate() {
+            assert_eq!(event[0].values, vec![vec![0x01, 0x02, 0x03, 0x04], vec![n as u8]]);
+            let sawtooth = if n % 2 == 0 { 0x00 } else { 0x01 };
+            assert_eq!((event[1].odt, event[1].first_entry), (1, 2));
+            assert_eq!(event[1].values, vec![vec![0x00, 0x00, 0x00, sawtooth], vec![0xAA, 0xBB], vec![0x7F]]);
+            assert_eq!(event[0].timestamp.unwrap().elapsed, 10 * n as u64);
+        }
+        let stats = session.stats().lists[0];
+        assert_eq!((stats.frames, stats.bytes, stats.decode_errors), (6, 6 * 8, 0));
+    }
+
+    #[test]
+    fn mock_daq_odt_limit() {
+        use std::cell::RefCell;
+        use std::time::Duration;
+        use xcp::daq::DaqListBuilder;
+        use xcp::error::{Cleanup, XcpError};
+        use xcp::frame::{XcpCommandCode, XcpErrorCode};
+        use xcp::transport::MockTransport;
+
+        let slave = RefCell::new(MockSlave::new());
+        {
+            let mut slave = slave.borrow_mut();
+            slave.max_odt = 4;
+            slave.signals.insert(0x1000, MockSignal::Counter);
+            slave.signals.insert(0x2000, MockSignal::Constant(0x1111_1111));
+            slave.signals.insert(0x2004, MockSignal::Sawtooth { period: 3, step: 1 });
+            slave.signals.insert(0x2008, MockSignal::Constant(0x3333_3333));
+        }
+        let mut transport = MockTransport::new(mock_ids(), |cmd| slave.borrow_mut().respond(cmd));
+        transport.on_idle(|| slave.borrow_mut().next_dtos());
+        let mut master = xcp::master::XcpMaster::new(&mut transport, mock_ids());
+        master.connect(ConnectMode::Normal).expect("connect failed");
+
+        // list 1 takes the last three of the four ODTs, PIDs 1 to 3
+        let list0 = DaqListBuilder::new(0).add(0x1000, 0, 1);
+        let list1 = DaqListBuilder::new(1).add(0x2000, 0, 4).add(0x2004, 0, 4).add(0x2008, 0, 4);
+        let mut session = master.configure_daq(&[list0, list1.clone()]).expect("configure_daq failed");
+        let samples = session.samples(Duration::from_millis(10)).take(8).collect::<Result<Vec<_>, _>>().unwrap();
+        session.stop().expect("stop failed");
+
+        for (n, event) in samples.chunks(4).enumerate() {
+            let ids: Vec<(u16, u8)> = event.iter().map(|sample| (sample.daq_list, sample.odt)).collect();
+            assert_eq!(ids, [(0, 0), (1, 0), (1, 1), (1, 2)]);
+            assert_eq!(event[0].values, vec![vec![n as u8]]);
+            assert_eq!(event[1].values, vec![vec![0x11; 4]]);
+            assert_eq!(event[2].values, vec![vec![n as u8, 0x00, 0x00, 0x00]]);
+            assert_eq!(event[3].values, vec![vec![0x33; 4]]);
+        }
+
+        // a fifth ODT is refused by ALLOC_ODT and the allocation undone
+        let list0 = DaqListBuilder::new(0).add(0x1000, 0, 1);
+        match master.configure_daq(&[list0, list1.add(0x200C, 0, 4)]) {
+            Err(e @ XcpError::DaqConfigFailed { cleanup: Cleanup::Done { command: XcpCommandCode::FreeDaq }, .. }) => {
+                assert_eq!(e.error_code(), Some(XcpErrorCode::ErrMemoryOverflow));
+            }
+            Err(e) => panic!("expected DaqConfigFailed after FREE_DAQ, got {:?}", e),
+            Ok(_) => panic!("expected DaqConfigFailed after FREE_DAQ"),
+        }
+        assert!(slave.borrow().daq_lists.is_empty());
+    }
 }
@@ -0,0 +1,129 @@
+//! Bypassing: computing values from DAQ samples and stimulating them back into the
+//! slave within the same event cycle.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::xcp::daq::{DaqDirection, DaqListBuilder, DaqSample, DaqSession, Dto, StimEncoder};
+use crate::xcp::error::XcpError;
+use crate::xcp::master::XcpMaster;
+
+/// Data for one ODT of a STIM list, returned by the closure of `BypassLoop::run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BypassOutput {
+    pub daq_list: u16,
+    pub odt: u8,
+    /// The values of all entries of the ODT, in order.
+    pub payload: Vec<u8>,
+}
+
+/// Counters of a `BypassLoop`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BypassStats {
+    /// Samples passed to the closure.
+    pub cycles: u64,
+    /// STIM DTOs sent.
+    pub outputs: u64,
+    /// Samples whose outputs were sent later than one event cycle after the sample
+    /// was received.
+    pub missed_deadlines: u64,
+}
+
+/// Reads DAQ lists, computes and stimulates the results back via STIM lists.
+///
+/// The lists keep running until `stop` is called, even if the loop is dropped.
+pub struct BypassLoop<'m, 'a> {
+    session: DaqSession<'m, 'a>,
+    encoder: StimEncoder,
+    /// Cycle time of the event of every cyclic input list.
+    cycles: HashMap<u16, Duration>,
+    stats: BypassStats,
+}
+
+impl<'m, 'a> BypassLoop<'m, 'a> {
+    /// Configures `inputs` for DAQ and `outputs` for STIM, and starts all of them
+    /// together.
+    ///
+    /// # Returns
+    /// `XcpError::EventDirectionUnsupported` if GET_DAQ_EVENT_INFO says the event
+    /// channel of a list cannot be used in its direction, before anything is written.
+    pub fn new(master: &'m mut XcpMaster<'a>, inputs: &[DaqListBuilder], outputs: &[DaqListBuilder]) -> Result<BypassLoop<'m, 'a>, XcpError> {
+        let mut cycles = HashMap::new();
+        for list in inputs {
+            let info = master.get_daq_event_info(list.event_channel())?;
+            if !info.properties.daq() {
+                return Err(XcpError::EventDirectionUnsupported { event_channel: list.event_channel(), direction: DaqDirection::Daq });
+            }
+            if let Some(cycle) = info.cycle_seconds() {
+                cycles.insert(list.daq_list(), Duration::from_secs_f64(cycle));
+            }
+        }
+        for list in outputs {
+            let info = master.get_daq_event_info(list.event_channel())?;
+            if !info.properties.stim() {
+                return Err(XcpError::EventDirectionUnsupported { event_channel: list.event_channel(), direction: DaqDirection::Stim });
+            }
+        }
+
+        let (mut decoder, daq_layouts) = master.prepare_daq(inputs)?;
+        let (stim_lists, stim_layouts) = master.prepare_stim(outputs)?;
+        let lists: Vec<DaqListBuilder> = inputs.iter().cloned().chain(stim_lists).collect();
+        let layouts: Vec<_> = daq_layouts.iter().chain(&stim_layouts).cloned().collect();
+
+        let first_pids = master.start_layouts(&lists, &layouts)?;
+        let (daq_pids, stim_pids) = first_pids.split_at(inputs.len());
+        master.register_daq(&mut decoder, inputs, &daq_layouts, daq_pids);
+        let encoder = master.stim_encoder(&stim_layouts, stim_pids);
+
+        let lists = lists.iter().map(|list| list.daq_list()).collect();
+        Ok(BypassLoop {
+            session: DaqSession { master, lists, decoder },
+            encoder,
+            cycles,
+            stats: BypassStats::default(),
+        })
+    }
+
+    /// The master running the loop, e.g. to send other commands meanwhile.
+    pub fn master(&mut self) -> &mut XcpMaster<'a> {
+        self.session.master()
+    }
+
+    pub fn stats(&self) -> BypassStats {
+        self.stats
+    }
+
+    /// Calls `compute` for every DAQ sample until no DTO arrived within `timeout`,
+    /// and sends the outputs it returns as STIM DTOs.
+    ///
+    /// `compute` gets the sample and its deadline: one event cycle after the sample
+    /// was received, `None` for lists on non-cyclic events.
+    pub fn run<F>(&mut self, timeout: Duration, mut compute: F) -> Result<(), XcpError>
+    where
+        F: FnMut(&DaqSample, Option<Instant>) -> Vec<BypassOutput>,
+    {
+        loop {
+            let Some(dto) = self.session.master.recv_dto(timeout)? else { return Ok(()) };
+            let sample = match self.session.decoder.decode(&dto, Instant::now())? {
+                Dto::Sample(sample) => sample,
+                Dto::Unknown(_) => continue,
+            };
+
+            let deadline = self.cycles.get(&sample.daq_list).map(|&cycle| sample.received + cycle);
+            self.stats.cycles += 1;
+            for output in compute(&sample, deadline) {
+                let dto = self.encoder.encode(output.daq_list, output.odt, &output.payload)?;
+                self.session.master.send_dto(&dto)?;
+                self.stats.outputs += 1;
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                self.stats.missed_deadlines += 1;
+            }
+        }
+    }
+
+    /// Stops the DAQ and STIM lists at the same time.
+    pub fn stop(self) -> Result<(), XcpError> {
+        self.session.stop()
+    }
+}
@@ -0,0 +1,251 @@
+//! Comparing calibration data, e.g. two snapshots saved by `XcpMaster::save_cal_page`.
+//!
+//! Addresses are taken as byte addresses, so offsets into the region data and
+//! differences of addresses are the same.
+
+use std::fmt;
+use crate::xcp::dump::DumpResult;
+use crate::xcp::snapshot::{CalSnapshot, SnapshotRegion};
+
+/// Bytes shown per side of a range by the text rendering of a `CalDiff`.
+const RENDERED_BYTES: usize = 16;
+
+/// A run of adjacent differing bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffRange {
+    /// Offset from the segment base address.
+    pub offset: usize,
+    /// The bytes in the first snapshot; shorter than `b` if the segment ends earlier there.
+    pub a: Vec<u8>,
+    /// The bytes in the second snapshot.
+    pub b: Vec<u8>,
+    /// Characteristic elements in the range, filled in by `CalDiff::resolve`.
+    pub changes: Vec<ElementChange>,
+}
+
+impl DiffRange {
+    /// Number of bytes the range covers.
+    pub fn len(&self) -> usize {
+        usize::max(self.a.len(), self.b.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The differences within one segment, present in both snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentDiff {
+    pub address_extension: u8,
+    pub address: u32,
+    pub ranges: Vec<DiffRange>,
+}
+
+/// Result of `diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CalDiff {
+    /// Segments with differences, in the order of the first snapshot.
+    pub segments: Vec<SegmentDiff>,
+    /// Address extension and address of segments only the first snapshot contains.
+    pub only_in_a: Vec<(u8, u32)>,
+    /// Address extension and address of segments only the second snapshot contains.
+    pub only_in_b: Vec<(u8, u32)>,
+}
+
+/// Layout of a calibration object, e.g. a CHARACTERISTIC of an A2L file, used to
+/// name the differences of a `CalDiff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Characteristic {
+    pub name: String,
+    pub address: u32,
+    pub address_extension: u8,
+    /// Size of one element in bytes.
+    pub element_size: usize,
+    /// Number of elements per dimension, e.g. `[8, 16]` for a map of 8 rows; empty
+    /// for a scalar. Elements are stored row by row, the last index varying fastest.
+    pub dimensions: Vec<usize>,
+}
+
+impl Characteristic {
+    fn elements(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+
+    fn index(&self, element: usize) -> Vec<usize> {
+        let mut index = vec![0; self.dimensions.len()];
+        let mut rest = element;
+        for (i, &dimension) in self.dimensions.iter().enumerate().rev() {
+            index[i] = rest % dimension;
+            rest /= dimension;
+        }
+        index
+    }
+}
+
+/// A characteristic element covered by a `DiffRange`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementChange {
+    pub name: String,
+    /// Index of the element, empty for a scalar.
+    pub index: Vec<usize>,
+}
+
+/// Shows the element as e.g. `KF_IGNITION_MAP[3][7]`.
+impl fmt::Display for ElementChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for i in &self.index {
+            write!(f, "[{}]", i)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the segments of two snapshots, matched by address extension and address.
+pub fn diff(a: &CalSnapshot, b: &CalSnapshot) -> CalDiff {
+    let find = |snapshot: &'_ CalSnapshot, region: &SnapshotRegion| {
+        snapshot.regions.iter().position(|other| {
+            other.address_extension == region.address_extension && other.address == region.address
+        })
+    };
+
+    let mut result = CalDiff::default();
+    for region in &a.regions {
+        match find(b, region) {
+            Some(index) => {
+                let ranges = diff_bytes(&region.data, &b.regions[index].data);
+                if !ranges.is_empty() {
+                    result.segments.push(SegmentDiff {
+                        address_extension: region.address_extension,
+                        address: region.address,
+                        ranges,
+                    });
+                }
+            }
+            None => result.only_in_a.push((region.address_extension, region.address)),
+        }
+    }
+    for region in &b.regions {
+        if find(a, region).is_none() {
+            result.only_in_b.push((region.address_extension, region.address));
+        }
+    }
+    result
+}
+
+/// Coalesces the differing bytes of `a` and `b` into ranges; bytes only one of
+/// them has count as differing.
+fn diff_bytes(a: &[u8], b: &[u8]) -> Vec<DiffRange> {
+    let length = usize::max(a.len(), b.len());
+    let differs = |offset: usize| a.get(offset) != b.get(offset);
+
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < length {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < length && differs(offset) {
+            offset += 1;
+        }
+        let slice = |data: &[u8]| data[usize::min(start, data.len())..usize::min(offset, data.len())].to_vec();
+        ranges.push(DiffRange { offset: start, a: slice(a), b: slice(b), changes: Vec::new() });
+    }
+    ranges
+}
+
+impl CalDiff {
+    /// Names the characteristic elements every range covers.
+    pub fn resolve(&mut self, characteristics: &[Characteristic]) {
+        for segment in &mut self.segments {
+            for range in &mut segment.ranges {
+                let start = segment.address as u64 + range.offset as u64;
+                let end = start + range.len() as u64;
+                range.changes.clear();
+
+                for characteristic in characteristics {
+                    if characteristic.address_extension != segment.address_extension || characteristic.element_size == 0 {
+                        continue;
+                    }
+                    let base = characteristic.address as u64;
+                    let size = characteristic.element_size as u64;
+                    let limit = base + characteristic.elements() as u64 * size;
+                    if end <= base || start >= limit { continue }
+
+                    let first = (u64::max(start, base) - base) / size;
+                    let last = (u64::min(end, limit) - base - 1) / size;
+                    for element in first..=last {
+                        range.changes.push(ElementChange {
+                            name: characteristic.name.clone(),
+                            index: characteristic.index(element as usize),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty() && self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
+}
+
+fn write_bytes(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    for (i, byte) in data.iter().take(RENDERED_BYTES).enumerate() {
+        if i > 0 { write!(f, " ")? }
+        write!(f, "{:02X}", byte)?;
+    }
+    if data.len() > RENDERED_BYTES { write!(f, " ...")? }
+    if data.is_empty() { write!(f, "(none)")? }
+    Ok(())
+}
+
+/// Renders the differences as text: one line per range with its offset from the
+/// segment base and the bytes before and after, followed by the characteristic
+/// elements it changes, if resolved.
+impl fmt::Display for CalDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        for segment in &self.segments {
+            writeln!(f, "segment 0x{:08X} (extension {}): {} range(s) differ",
+                segment.address, segment.address_extension, segment.ranges.len())?;
+            for range in &segment.ranges {
+                write!(f, "  +0x{:X}, {} byte(s): ", range.offset, range.len())?;
+                write_bytes(f, &range.a)?;
+                write!(f, " -> ")?;
+                write_bytes(f, &range.b)?;
+                writeln!(f)?;
+                for change in &range.changes {
+                    writeln!(f, "    {} changed", change)?;
+                }
+            }
+        }
+        for &(address_extension, address) in &self.only_in_a {
+            writeln!(f, "segment 0x{:08X} (extension {}) only in the first snapshot", address, address_extension)?;
+        }
+        for &(address_extension, address) in &self.only_in_b {
+            writeln!(f, "segment 0x{:08X} (extension {}) only in the second snapshot", address, address_extension)?;
+        }
+        Ok(())
+    }
+}
+
+/// Takes the regions of a dump as a snapshot without identification, to compare
+/// raw dumps; regions that failed keep the data read before the failure.
+impl From<&DumpResult> for CalSnapshot {
+    fn from(dump: &DumpResult) -> CalSnapshot {
+        CalSnapshot {
+            id: Vec::new(),
+            regions: dump.regions.iter().map(|region| SnapshotRegion {
+                address_extension: region.address_extension,
+                address: region.address,
+                data: region.data.clone(),
+            }).collect(),
+        }
+    }
+}
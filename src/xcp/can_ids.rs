@@ -0,0 +1,194 @@
+//! CAN identifiers used by the XCP on CAN transport layer.
+//!
+//! XCP on CAN uses one identifier for master-to-slave traffic (CMD/STIM) and one for
+//! slave-to-master traffic (RES/ERR/EV/SERV/DAQ). This module provides a validated
+//! `CanId` type covering both 11 bit and 29 bit identifiers, the `CanIdPair` used by
+//! `XcpMaster`, and textual parsing so IDs given on a command line or in a config file
+//! are interpreted the same way everywhere.
+
+use std::fmt;
+use std::str::FromStr;
+use socketcan::{ExtendedId, Id, StandardId};
+
+/// Highest identifier representable in an 11 bit (standard) CAN ID.
+pub const MAX_STANDARD_ID: u32 = 0x7FF;
+
+/// Highest identifier representable in a 29 bit (extended) CAN ID.
+pub const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+
+/// Flag in the most significant bit of a 32 bit identifier marking it as extended,
+/// as used for CAN_ID_MASTER/CAN_ID_SLAVE/CAN_ID_BROADCAST in A2L files.
+pub const CAN_ID_EXTENDED_FLAG: u32 = 0x8000_0000;
+
+/// CAN_ID_BROADCAST of the example configuration in the XCP on CAN specification,
+/// the identifier slaves listen on for GET_SLAVE_ID unless configured otherwise.
+pub const DEFAULT_BROADCAST_ID: CanId = CanId { raw: 0x100, extended: false };
+
+/// CAN_ID_MASTER and CAN_ID_SLAVE of the example configuration in the XCP on CAN
+/// specification, which many slaves ship with.
+pub const DEFAULT_IDS: CanIdPair = CanIdPair {
+    cmd: CanId { raw: 0x200, extended: false },
+    res: CanId { raw: 0x300, extended: false },
+};
+
+/// Errors produced when constructing or parsing a `CanId`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CanIdError {
+    /// The identifier does not fit into an 11 bit standard ID.
+    StandardOutOfRange(u32),
+    /// The identifier does not fit into a 29 bit extended ID.
+    ExtendedOutOfRange(u32),
+    /// The string could not be parsed as an identifier.
+    Parse(String),
+}
+
+impl fmt::Display for CanIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CanIdError::StandardOutOfRange(id) => write!(f, "{:#X} exceeds the 11 bit CAN ID range", id),
+            CanIdError::ExtendedOutOfRange(id) => write!(f, "{:#X} exceeds the 29 bit CAN ID range", id),
+            CanIdError::Parse(s) => write!(f, "cannot parse \"{}\" as a CAN ID", s),
+        }
+    }
+}
+
+impl std::error::Error for CanIdError {}
+
+/// A validated 11 bit or 29 bit CAN identifier.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct CanId {
+    raw: u32,
+    extended: bool,
+}
+
+impl CanId {
+    /// Creates an 11 bit identifier.
+    pub fn standard(id: u16) -> Result<CanId, CanIdError> {
+        if id as u32 > MAX_STANDARD_ID {
+            return Err(CanIdError::StandardOutOfRange(id as u32));
+        }
+        Ok(CanId { raw: id as u32, extended: false })
+    }
+
+    /// Creates a 29 bit identifier, even if the value would fit into 11 bits.
+    pub fn extended(id: u32) -> Result<CanId, CanIdError> {
+        if id > MAX_EXTENDED_ID {
+            return Err(CanIdError::ExtendedOutOfRange(id));
+        }
+        Ok(CanId { raw: id, extended: true })
+    }
+
+    /// Interprets a raw 32 bit identifier.
+    ///
+    /// Values with `CAN_ID_EXTENDED_FLAG` set, and values above `MAX_STANDARD_ID`,
+    /// become extended identifiers; everything else is a standard identifier.
+    pub fn from_raw(raw: u32) -> Result<CanId, CanIdError> {
+        if raw & CAN_ID_EXTENDED_FLAG != 0 {
+            CanId::extended(raw & !CAN_ID_EXTENDED_FLAG)
+        } else if raw > MAX_STANDARD_ID {
+            CanId::extended(raw)
+        } else {
+            CanId::standard(raw as u16)
+        }
+    }
+
+    /// The identifier without any flags.
+    pub fn raw(&self) -> u32 {
+        self.raw
+    }
+
+    /// Whether this is a 29 bit identifier.
+    pub fn is_extended(&self) -> bool {
+        self.extended
+    }
+}
+
+impl From<CanId> for Id {
+    fn from(id: CanId) -> Id {
+        // the range was validated on construction
+        if id.extended {
+            Id::Extended(ExtendedId::new(id.raw).unwrap())
+        } else {
+            Id::Standard(StandardId::new(id.raw as u16).unwrap())
+        }
+    }
+}
+
+impl From<Id> for CanId {
+    fn from(id: Id) -> CanId {
+        match id {
+            Id::Standard(id) => CanId { raw: id.as_raw() as u32, extended: false },
+            Id::Extended(id) => CanId { raw: id.as_raw(), extended: true },
+        }
+    }
+}
+
+/// Standard IDs are shown with 3 hex digits ("0x7E0"), extended IDs always with 8
+/// ("0x18DB33F1", "0x00000100"), so the output parses back to the same kind of ID.
+impl fmt::Display for CanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.extended {
+            write!(f, "0x{:08X}", self.raw)
+        } else {
+            write!(f, "0x{:03X}", self.raw)
+        }
+    }
+}
+
+/// Accepts decimal or `0x` prefixed hexadecimal values, interpreted like `CanId::from_raw`.
+/// A hexadecimal value written with 8 digits is always an extended ID.
+impl FromStr for CanId {
+    type Err = CanIdError;
+
+    fn from_str(s: &str) -> Result<CanId, CanIdError> {
+        let s = s.trim();
+        let parse_err = || CanIdError::Parse(s.to_string());
+
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            let raw = u32::from_str_radix(hex, 16).map_err(|_| parse_err())?;
+            if hex.len() == 8 {
+                CanId::extended(raw & !CAN_ID_EXTENDED_FLAG)
+            } else {
+                CanId::from_raw(raw)
+            }
+        } else {
+            CanId::from_raw(s.parse::<u32>().map_err(|_| parse_err())?)
+        }
+    }
+}
+
+/// The pair of identifiers an XCP on CAN session uses.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct CanIdPair {
+    /// Identifier the master transmits commands (and STIM data) on.
+    pub cmd: CanId,
+    /// Identifier the slave transmits responses, events and DAQ data on.
+    pub res: CanId,
+}
+
+impl CanIdPair {
+    pub fn new(cmd: CanId, res: CanId) -> CanIdPair {
+        CanIdPair { cmd, res }
+    }
+
+    /// Builds a pair from raw identifiers, see `CanId::from_raw`.
+    pub fn from_raw(cmd: u32, res: u32) -> Result<CanIdPair, CanIdError> {
+        Ok(CanIdPair { cmd: CanId::from_raw(cmd)?, res: CanId::from_raw(res)? })
+    }
+}
+
+/// Formatted as `<cmd>:<res>`, e.g. "0x7E0:0x7E8".
+impl fmt::Display for CanIdPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.cmd, self.res)
+    }
+}
+
+impl FromStr for CanIdPair {
+    type Err = CanIdError;
+
+    fn from_str(s: &str) -> Result<CanIdPair, CanIdError> {
+        let (cmd, res) = s.split_once(':').ok_or_else(|| CanIdError::Parse(s.trim().to_string()))?;
+        Ok(CanIdPair { cmd: cmd.parse()?, res: res.parse()? })
+    }
+}
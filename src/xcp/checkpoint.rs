@@ -0,0 +1,307 @@
+//! Checkpoints of a flash session, to continue programming after an interruption
+//! instead of clearing and programming everything again, see
+//! `FlashOptions::checkpoint` and `XcpMaster::resume_flash`.
+//!
+//! The state file starts with the magic `XCPFLS01`, followed by the slave
+//! identification (u16 length and the bytes of GET_ID type 0), the EPK to check
+//! (u8 present flag, address extension u8, address u32, u16 length and the
+//! string), the options (address extension u8, fill u8 and a u8 of flags: verify,
+//! reset, reset on mismatch, stop DAQ, reset active PGM from bit 0 on) and the
+//! image (u8 format: 0 Intel HEX, 1 S-record, 2 ELF, u16 length and the UTF-8
+//! path; for ELF the u32 count of ranges, each start and end u32, and the u32
+//! count of mappings, each virtual address, length and physical address u32).
+//! Then the number of regions (u32) and per region the address (u32), length
+//! (u32), CRC-32 of the padded data (u32) and the bytes programmed so far (u32).
+//! All integers are little endian.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::xcp::checksum;
+use crate::xcp::epk::ExpectedEpk;
+use crate::xcp::error::XcpError;
+use crate::xcp::flash::{parse_elf, parse_ihex, parse_srec, pad_regions, AddressMapping, ElfOptions, FlashOptions,
+    FlashReport, HexRegion};
+use crate::xcp::master::XcpMaster;
+use crate::xcp::xcp_command::{ConnectMode, IdType};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"XCPFLS01";
+
+/// The image file a flash session programs, to read it again when resuming.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlashImage {
+    Ihex(PathBuf),
+    Srec(PathBuf),
+    Elf(PathBuf, ElfOptions),
+}
+
+impl FlashImage {
+    pub fn path(&self) -> &Path {
+        match self {
+            FlashImage::Ihex(path) | FlashImage::Srec(path) | FlashImage::Elf(path, _) => path,
+        }
+    }
+
+    /// Reads and parses the image file.
+    pub fn parse(&self) -> io::Result<Vec<HexRegion>> {
+        let file = BufReader::new(File::open(self.path())?);
+        match self {
+            FlashImage::Ihex(_) => parse_ihex(file),
+            FlashImage::Srec(_) => parse_srec(file),
+            FlashImage::Elf(_, options) => parse_elf(file, options),
+        }
+    }
+
+    /// The same image with an absolute path, so a session can be resumed from
+    /// another working directory.
+    pub(crate) fn canonicalize(self) -> io::Result<FlashImage> {
+        Ok(match self {
+            FlashImage::Ihex(path) => FlashImage::Ihex(fs::canonicalize(path)?),
+            FlashImage::Srec(path) => FlashImage::Srec(fs::canonicalize(path)?),
+            FlashImage::Elf(path, options) => FlashImage::Elf(fs::canonicalize(path)?, options),
+        })
+    }
+}
+
+/// Progress of one image region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointRegion {
+    pub address: u32,
+    /// Length in bytes, including fill bytes.
+    pub length: usize,
+    /// CRC-32 of the data, to detect a modified image.
+    pub crc: u32,
+    /// Bytes from the start of the region that were programmed and acknowledged.
+    pub programmed: usize,
+}
+
+/// State of a flash session, written while programming.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashCheckpoint {
+    /// Identification of the slave being programmed.
+    pub id: Vec<u8>,
+    pub image: FlashImage,
+    /// The options of the session; `checkpoint` is not stored.
+    pub options: FlashOptions,
+    pub regions: Vec<CheckpointRegion>,
+}
+
+impl FlashCheckpoint {
+    /// A checkpoint before anything of the padded `regions` was programmed.
+    pub fn new(id: Vec<u8>, image: FlashImage, options: &FlashOptions, regions: &[HexRegion]) -> FlashCheckpoint {
+        FlashCheckpoint {
+            id,
+            image,
+            options: FlashOptions { checkpoint: None, ..options.clone() },
+            regions: regions.iter().map(|region| CheckpointRegion {
+                address: region.address,
+                length: region.data.len(),
+                crc: checksum::crc32(&region.data),
+                programmed: 0,
+            }).collect(),
+        }
+    }
+
+    /// The padded `regions` are the ones the checkpoint was taken for.
+    pub fn matches(&self, regions: &[HexRegion]) -> bool {
+        self.regions.len() == regions.len() && self.regions.iter().zip(regions).all(|(recorded, region)| {
+            recorded.address == region.address && recorded.length == region.data.len()
+                && recorded.crc == checksum::crc32(&region.data)
+        })
+    }
+
+    /// Replaces the file at `path`, writing a temporary file next to it first so an
+    /// interruption never leaves a truncated state behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        self.write_to(BufWriter::new(File::create(&temporary)?))?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        write_bytes(&mut writer, &self.id)?;
+
+        match &self.options.epk {
+            Some(epk) => {
+                writer.write_all(&[1, epk.address_extension])?;
+                writer.write_all(&epk.address.to_le_bytes())?;
+                write_bytes(&mut writer, epk.expected.as_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        let options = &self.options;
+        let flags = [options.verify, options.reset, options.reset_on_mismatch, options.stop_daq, options.reset_active_pgm]
+            .iter().enumerate().fold(0u8, |flags, (bit, &set)| flags | (set as u8) << bit);
+        writer.write_all(&[options.address_extension, options.fill, flags])?;
+
+        let format = match &self.image {
+            FlashImage::Ihex(_) => 0,
+            FlashImage::Srec(_) => 1,
+            FlashImage::Elf(..) => 2,
+        };
+        writer.write_all(&[format])?;
+        let path = self.image.path().to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "image path is not valid UTF-8"))?;
+        write_bytes(&mut writer, path.as_bytes())?;
+        if let FlashImage::Elf(_, elf) = &self.image {
+            writer.write_all(&(elf.ranges.len() as u32).to_le_bytes())?;
+            for range in &elf.ranges {
+                writer.write_all(&range.start.to_le_bytes())?;
+                writer.write_all(&range.end.to_le_bytes())?;
+            }
+            writer.write_all(&(elf.mappings.len() as u32).to_le_bytes())?;
+            for mapping in &elf.mappings {
+                writer.write_all(&mapping.virtual_address.to_le_bytes())?;
+                writer.write_all(&mapping.length.to_le_bytes())?;
+                writer.write_all(&mapping.physical_address.to_le_bytes())?;
+            }
+        }
+
+        writer.write_all(&(self.regions.len() as u32).to_le_bytes())?;
+        for region in &self.regions {
+            writer.write_all(&region.address.to_le_bytes())?;
+            writer.write_all(&(region.length as u32).to_le_bytes())?;
+            writer.write_all(&region.crc.to_le_bytes())?;
+            writer.write_all(&(region.programmed as u32).to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<FlashCheckpoint> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid("not a flash checkpoint"));
+        }
+        let id = read_bytes(&mut reader)?;
+
+        let epk = match read_u8(&mut reader)? {
+            0 => None,
+            _ => {
+                let address_extension = read_u8(&mut reader)?;
+                let address = read_u32(&mut reader)?;
+                let expected = String::from_utf8(read_bytes(&mut reader)?).map_err(|_| invalid("EPK is not valid UTF-8"))?;
+                Some(ExpectedEpk { address, address_extension, expected })
+            }
+        };
+        let address_extension = read_u8(&mut reader)?;
+        let fill = read_u8(&mut reader)?;
+        let flags = read_u8(&mut reader)?;
+        let flag = |bit: u8| flags & (1 << bit) != 0;
+        let options = FlashOptions {
+            address_extension,
+            fill,
+            verify: flag(0),
+            reset: flag(1),
+            reset_on_mismatch: flag(2),
+            stop_daq: flag(3),
+            reset_active_pgm: flag(4),
+            epk,
+            checkpoint: None,
+        };
+
+        let format = read_u8(&mut reader)?;
+        let path = PathBuf::from(String::from_utf8(read_bytes(&mut reader)?).map_err(|_| invalid("image path is not valid UTF-8"))?);
+        let image = match format {
+            0 => FlashImage::Ihex(path),
+            1 => FlashImage::Srec(path),
+            2 => {
+                let mut elf = ElfOptions::default();
+                for _ in 0..read_u32(&mut reader)? {
+                    elf.ranges.push(read_u32(&mut reader)?..read_u32(&mut reader)?);
+                }
+                for _ in 0..read_u32(&mut reader)? {
+                    elf.mappings.push(AddressMapping {
+                        virtual_address: read_u32(&mut reader)?,
+                        length: read_u32(&mut reader)?,
+                        physical_address: read_u32(&mut reader)?,
+                    });
+                }
+                FlashImage::Elf(path, elf)
+            }
+            _ => return Err(invalid("unknown image format")),
+        };
+
+        let count = read_u32(&mut reader)?;
+        let mut regions = Vec::new();
+        for _ in 0..count {
+            let address = read_u32(&mut reader)?;
+            let length = read_u32(&mut reader)? as usize;
+            let crc = read_u32(&mut reader)?;
+            let programmed = read_u32(&mut reader)? as usize;
+            if programmed > length {
+                return Err(invalid("region programmed beyond its length"));
+            }
+            regions.push(CheckpointRegion { address, length, crc, programmed });
+        }
+
+        Ok(FlashCheckpoint { id, image, options, regions })
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u16).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut raw = [0u8; 2];
+    reader.read_exact(&mut raw)?;
+    let mut bytes = vec![0u8; u16::from_le_bytes(raw) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut raw = [0u8; 1];
+    reader.read_exact(&mut raw)?;
+    Ok(raw[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut raw = [0u8; 4];
+    reader.read_exact(&mut raw)?;
+    Ok(u32::from_le_bytes(raw))
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Continues a flash session interrupted after it wrote the checkpoint at
+    /// `state_path`, see `FlashOptions::checkpoint`.
+    ///
+    /// Connects, reads the image file again and enters programming mode with
+    /// PROGRAM_START. The bytes already programmed are verified with
+    /// BUILD_CHECKSUM; regions that verify continue after them, the others are
+    /// cleared and programmed from the start. The session then ends like
+    /// `flash_ihex` with the options it was started with.
+    ///
+    /// # Returns
+    /// `XcpError::ResumeImageChanged` if the image file no longer holds the data the
+    /// checkpoint was taken for, and `XcpError::ResumeSlaveMismatch` if the slave
+    /// identifies differently. Both are checked before PROGRAM_START.
+    pub fn resume_flash<P: AsRef<Path>>(&mut self, state_path: P, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let state_path = state_path.as_ref();
+        let checkpoint = FlashCheckpoint::read_from(BufReader::new(File::open(state_path)?))?;
+        self.connect(ConnectMode::Normal)?;
+
+        let mut regions = checkpoint.image.parse()?;
+        pad_regions(&mut regions, checkpoint.options.fill, self.comm_mode_basic.granularity().size());
+        if !checkpoint.matches(&regions) {
+            return Err(XcpError::ResumeImageChanged { path: checkpoint.image.path().to_path_buf() });
+        }
+
+        let id = self.get_id(IdType::AsciiText)?;
+        if id != checkpoint.id {
+            return Err(XcpError::ResumeSlaveMismatch {
+                recorded: String::from_utf8_lossy(&checkpoint.id).into_owned(),
+                actual: String::from_utf8_lossy(&id).into_owned(),
+            });
+        }
+
+        let options = FlashOptions { checkpoint: Some(state_path.to_path_buf()), ..checkpoint.options.clone() };
+        self.run_flash(&regions, &options, Some(checkpoint), progress)
+    }
+}
@@ -0,0 +1,84 @@
+//! Local implementations of the checksum algorithms BUILD_CHECKSUM may report.
+//!
+//! Computing the same checksum over the data that was written lets the master verify
+//! memory contents without uploading them again.
+
+use crate::xcp::frame::ByteOrder;
+use crate::xcp::xcp_command::ChecksumType;
+
+/// Computes the checksum of `data` with the given algorithm.
+///
+/// Words and dwords of the ADD_2x/ADD_4x algorithms are read in `byte_order`; a
+/// trailing partial word or dword is padded with zeros. Returns `None` for
+/// user-defined and unknown algorithms.
+pub fn compute(checksum_type: ChecksumType, data: &[u8], byte_order: ByteOrder) -> Option<u32> {
+    let checksum = match checksum_type {
+        ChecksumType::Add11 => add_bytes(data) & 0xFF,
+        ChecksumType::Add12 => add_bytes(data) & 0xFFFF,
+        ChecksumType::Add14 => add_bytes(data),
+        ChecksumType::Add22 => add_words(data, byte_order) & 0xFFFF,
+        ChecksumType::Add24 => add_words(data, byte_order),
+        ChecksumType::Add44 => add_dwords(data, byte_order),
+        ChecksumType::Crc16 => crc16(data) as u32,
+        ChecksumType::Crc16Ccitt => crc16_ccitt(data) as u32,
+        ChecksumType::Crc32 => crc32(data),
+        ChecksumType::UserDefined | ChecksumType::Unknown(_) => return None,
+    };
+    Some(checksum)
+}
+
+fn add_bytes(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, &b| sum.wrapping_add(b as u32))
+}
+
+fn add_words(data: &[u8], byte_order: ByteOrder) -> u32 {
+    data.chunks(2).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 2];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(byte_order.read_u16(&word) as u32)
+    })
+}
+
+fn add_dwords(data: &[u8], byte_order: ByteOrder) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut dword = [0u8; 4];
+        dword[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(byte_order.read_u32(&dword))
+    })
+}
+
+/// CRC-16 as used by XCP (CRC-16/ARC): polynomial 0x8005, reflected, initial value 0.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0x0000;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 0x0001 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16-CCITT as used by XCP (CRC-16/CCITT-FALSE): polynomial 0x1021, initial value 0xFFFF.
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-32 as used by XCP (the IEEE 802.3 CRC): polynomial 0x04C11DB7, reflected.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 0x0000_0001 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
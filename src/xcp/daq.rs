@@ -0,0 +1,1782 @@
+//! DAQ measurements: writing complete DAQ list configurations, on slaves with static
+//! as well as dynamic DAQ configuration, and running them.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use socketcan::{CanSocket, EmbeddedFrame, Socket};
+use crate::xcp::can_ids::CanId;
+use crate::xcp::error::{Cleanup, XcpError};
+use crate::xcp::frame::{ByteOrder, XcpCommandCode, XcpEventCode, XcpResponseCode};
+use crate::xcp::master::{TimeoutClass, XcpMaster};
+use crate::xcp::session::SessionEvent;
+use crate::xcp::xcp_command::{DaqConfigType, DaqEntry, DaqIdentificationFieldType, DaqListModeConfig, DaqOptimisationType, DaqOverloadIndication, GetDaqResolutionInfoResponse, XcpSetRequestMode, XcpTimeUnit, StartStopMode, StartStopSynchMode};
+
+/// Direction of a DAQ list: the slave sends DAQ lists and receives STIM lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaqDirection {
+    Daq,
+    Stim,
+}
+
+/// The ODT entries of one DAQ list, grouped by ODT.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DaqListLayout {
+    pub daq_list: u16,
+    pub odts: Vec<Vec<DaqEntry>>,
+}
+
+/// Declares one DAQ list of a measurement, see `XcpMaster::configure_daq`.
+///
+/// Entries are packed into ODTs in the order they are added.
+#[derive(Debug, Clone)]
+pub struct DaqListBuilder {
+    daq_list: u16,
+    entries: Vec<DaqEntry>,
+    mode: DaqListModeConfig,
+    overload_indication_required: bool,
+}
+
+impl DaqListBuilder {
+    /// Starts a list with number `daq_list`, sampled on event channel 0 with
+    /// prescaler 1 and priority 0.
+    ///
+    /// With dynamic DAQ configuration, lists are numbered from MIN_DAQ onwards.
+    pub fn new(daq_list: u16) -> DaqListBuilder {
+        DaqListBuilder { daq_list, entries: Vec::new(), mode: DaqListModeConfig::default(), overload_indication_required: false }
+    }
+
+    /// Samples `size` elements at `address`.
+    pub fn add(mut self, address: u32, address_extension: u8, size: u8) -> DaqListBuilder {
+        self.entries.push(DaqEntry { bit_offset: None, size, address_extension, address });
+        self
+    }
+
+    /// Samples the list on every `prescaler`th occurrence of `event_channel`.
+    pub fn event(mut self, event_channel: u16, prescaler: u8, priority: u8) -> DaqListBuilder {
+        self.mode.event_channel = event_channel;
+        self.mode.prescaler = prescaler;
+        self.mode.priority = priority;
+        self
+    }
+
+    pub fn daq_list(&self) -> u16 {
+        self.daq_list
+    }
+
+    pub fn event_channel(&self) -> u16 {
+        self.mode.event_channel
+    }
+
+    /// Puts the list in RESUME mode, so that once stored with
+    /// `XcpMaster::store_daq_for_resume` it starts on its own when the slave powers up.
+    pub fn resume(mut self, enabled: bool) -> DaqListBuilder {
+        self.mode.mode.set_resume(enabled);
+        self
+    }
+
+    /// Has the slave timestamp the first ODT of every sample.
+    pub fn timestamp(mut self, enabled: bool) -> DaqListBuilder {
+        self.mode.mode.set_timestamp(enabled);
+        self
+    }
+
+    /// Relies on the slave indicating overloads of the list, see
+    /// `DaqSample::overrun`. `configure_daq` then fails with
+    /// `XcpError::OverloadIndicationUnsupported` on slaves that advertise no
+    /// overload indication in DAQ_PROPERTIES.
+    pub fn require_overload_indication(mut self, required: bool) -> DaqListBuilder {
+        self.overload_indication_required = required;
+        self
+    }
+
+    pub fn entries(&self) -> &[DaqEntry] {
+        &self.entries
+    }
+
+    /// Whether the slave is asked to timestamp the list, see `timestamp`.
+    pub fn is_timestamped(&self) -> bool {
+        self.mode.mode.timestamp()
+    }
+
+    /// Packs the entries into ODTs carrying at most `odt_len` bytes each, with
+    /// `ag` bytes per element. The first ODT leaves room for a `timestamp_len`
+    /// byte timestamp.
+    pub fn layout(&self, odt_len: usize, ag: usize, timestamp_len: usize) -> Result<DaqListLayout, XcpError> {
+        self.optimised_layout(odt_len, ag, timestamp_len, DaqOptimisationType::Default)
+    }
+
+    /// Like `layout`, for a slave optimised for `optimisation` (DAQ_KEY_BYTE).
+    ///
+    /// If the slave prefers ODTs of entries of one size, e.g. OM_ODT_TYPE_32,
+    /// entries of that size and other entries are not mixed in one ODT. Entries
+    /// keep their order, so adding the entries of the preferred size next to
+    /// each other saves DTOs.
+    pub fn optimised_layout(&self, odt_len: usize, ag: usize, timestamp_len: usize, optimisation: DaqOptimisationType)
+        -> Result<DaqListLayout, XcpError> {
+        let preferred = optimisation.preferred_entry_size();
+        let mut odts: Vec<Vec<DaqEntry>> = Vec::new();
+        let mut capacity = 0;
+        let mut used = 0;
+        let mut preferred_odt = false;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let size = entry.size as usize * ag;
+            let preferred_entry = preferred == Some(size);
+            let split = preferred.is_some() && used > 0 && preferred_entry != preferred_odt;
+            preferred_odt = preferred_entry;
+            if odts.is_empty() || split || used + size > capacity {
+                capacity = if odts.is_empty() { odt_len.saturating_sub(timestamp_len) } else { odt_len };
+                if size > capacity {
+                    return Err(XcpError::DaqEntryTooLarge { daq_list: self.daq_list, entry: index, size, max: capacity });
+                }
+                odts.push(Vec::new());
+                used = 0;
+            }
+            odts.last_mut().unwrap().push(*entry);
+            used += size;
+        }
+
+        Ok(DaqListLayout { daq_list: self.daq_list, odts })
+    }
+}
+
+/// The values of one ODT as received in a DTO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaqSample {
+    pub daq_list: u16,
+    pub odt: u8,
+    /// Index, among all entries of the list, of the first entry of the ODT.
+    pub first_entry: usize,
+    /// Raw bytes of every entry of the ODT, in the order they were added.
+    pub values: Vec<Vec<u8>>,
+    /// Slave timestamp of the sample, if the list is timestamped. Later ODTs of a
+    /// sample carry the timestamp of its first ODT.
+    pub timestamp: Option<DaqTimestamp>,
+    /// When the master received the DTO.
+    pub received: Instant,
+    /// The slave indicated an overload before this DTO, or DTOs of the list were
+    /// lost since the previous one.
+    pub overrun: bool,
+}
+
+/// An overload or lost DTOs detected by `DtoDecoder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaqOverrun {
+    /// The slave indicated an overload, for `daq_list` if known from the PID.
+    Overload { daq_list: Option<u16> },
+    /// `missing` DTOs of `daq_list` were lost: `expected` was the next ODT, but
+    /// `received` arrived.
+    MissingOdts { daq_list: u16, expected: u8, received: u8, missing: usize },
+}
+
+/// DTO counters of a `DtoDecoder`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DaqStats {
+    /// DTOs decoded into samples.
+    pub samples: u64,
+    /// Overloads indicated by the slave.
+    pub overloads: u64,
+    /// DTOs lost according to the ODT sequence of their lists.
+    pub missing_odts: u64,
+    /// DTOs rejected for not matching the size of their ODT.
+    pub length_errors: u64,
+    /// DTOs matching no configured ODT.
+    pub unknown: u64,
+    /// Counters of every registered list.
+    pub lists: Vec<DaqListStats>,
+}
+
+/// DTO counters of one DAQ list, see `DaqStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DaqListStats {
+    pub daq_list: u16,
+    /// DTOs identified as belonging to the list.
+    pub frames: u64,
+    /// Bytes of these DTOs, identification field included.
+    pub bytes: u64,
+    /// DTOs decoded into samples.
+    pub samples: u64,
+    /// DTOs rejected for not matching the size of their ODT.
+    pub decode_errors: u64,
+    /// Samples marked as overrun.
+    pub overruns: u64,
+    /// Events per second, from the first ODTs received. Measured with the slave
+    /// timestamps if the list has them, otherwise with the time of reception.
+    /// `None` until two have arrived.
+    pub event_rate: Option<f64>,
+}
+
+/// Event rate estimate of one list.
+#[derive(Debug, Clone, Copy, Default)]
+struct RateState {
+    events: u64,
+    first_received: Option<Instant>,
+    first_time: Option<Duration>,
+}
+
+impl RateState {
+    /// Records the first ODT of an event and returns the rate so far.
+    fn track(&mut self, received: Instant, time: Option<Duration>) -> Option<f64> {
+        self.events += 1;
+        let elapsed = match time {
+            Some(time) => time - *self.first_time.get_or_insert(time),
+            None => received - *self.first_received.get_or_insert(received),
+        };
+        (self.events > 1 && !elapsed.is_zero()).then(|| (self.events - 1) as f64 / elapsed.as_secs_f64())
+    }
+}
+
+/// A slave timestamp of a DAQ sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DaqTimestamp {
+    /// The tick count as sent by the slave.
+    pub ticks: u32,
+    /// Ticks since the first sample of the list, continuing across wrap-arounds
+    /// of the tick counter.
+    pub elapsed: u64,
+    /// Time since the first sample of the list, continuing across wrap-arounds of
+    /// the tick counter. `None` if the slave uses an unknown time unit.
+    pub time: Option<Duration>,
+}
+
+/// What the time of recorded samples is based on, see `DtoDecoder::time_base`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBase {
+    /// The slave sends no timestamps; samples are timed by when they were received.
+    Host,
+    /// Slave timestamps, with ticks of `tick_seconds`.
+    Slave { tick_seconds: f64 },
+    /// Slave timestamps in ticks of a unit the slave does not report.
+    Ticks,
+}
+
+impl TimeBase {
+    /// Slave timestamps with ticks of `ticks` times `unit`, e.g. to correct a
+    /// slave that reports the wrong unit in GET_DAQ_RESOLUTION_INFO.
+    pub fn from_unit(unit: XcpTimeUnit, ticks: u16) -> TimeBase {
+        match unit.seconds() {
+            Some(seconds) => TimeBase::Slave { tick_seconds: seconds * ticks as f64 },
+            None => TimeBase::Ticks,
+        }
+    }
+
+    /// The slave time of `timestamp`, if the unit is known.
+    pub(crate) fn slave_time(&self, timestamp: &DaqTimestamp) -> Option<Duration> {
+        match self {
+            TimeBase::Slave { tick_seconds } => Some(Duration::from_secs_f64(timestamp.elapsed as f64 * tick_seconds)),
+            _ => None,
+        }
+    }
+}
+
+/// Size and resolution of DTO timestamps, from GET_DAQ_RESOLUTION_INFO.
+#[derive(Debug, Clone, Copy)]
+struct TimestampFormat {
+    size: usize,
+    /// Timestamps are sent whether or not the list's TIMESTAMP mode bit is set.
+    fixed: bool,
+    tick_seconds: Option<f64>,
+}
+
+/// Roll-over tracking of the timestamps of one list.
+#[derive(Debug, Clone, Copy, Default)]
+struct TimestampState {
+    first: Option<u32>,
+    last: Option<u32>,
+    rollovers: u64,
+    current: Option<DaqTimestamp>,
+}
+
+impl TimestampState {
+    /// Records `ticks` from a `size` byte counter as the timestamp of the current sample.
+    fn track(&mut self, ticks: u32, size: usize, tick_seconds: Option<f64>) {
+        if self.last.is_some_and(|last| ticks < last) {
+            self.rollovers += 1;
+        }
+        self.last = Some(ticks);
+        let first = *self.first.get_or_insert(ticks);
+
+        let range = 1u64 << (8 * size.min(4));
+        let elapsed = (self.rollovers * range + ticks as u64) - first as u64;
+        let time = tick_seconds.map(|tick| Duration::from_secs_f64(elapsed as f64 * tick));
+        self.current = Some(DaqTimestamp { ticks, elapsed, time });
+    }
+}
+
+/// ODT layout of a running list, used to decode its DTOs.
+#[derive(Debug, Clone)]
+struct DecoderList {
+    daq_list: u16,
+    first_pid: u8,
+    /// Byte size of every entry, per ODT.
+    odts: Vec<Vec<usize>>,
+    timestamped: bool,
+    timestamps: TimestampState,
+    /// ODT whose DTO should arrive next, once the first DTO was seen.
+    next_odt: Option<u8>,
+    /// Mark the next sample, after an overload event.
+    overrun: bool,
+    stats: DaqListStats,
+    rate: RateState,
+}
+
+/// A DTO decoded by `DtoDecoder::decode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dto {
+    /// A DTO of a configured ODT.
+    Sample(DaqSample),
+    /// A DTO whose identification field matches no configured ODT, as received.
+    Unknown(Vec<u8>),
+}
+
+/// Callback registered with `DtoDecoder::on_overrun`.
+pub type OverrunCallback = Box<dyn FnMut(&DaqOverrun) + Send>;
+
+/// Maps DTOs back to the ODT entries they carry.
+///
+/// The identification field type comes from the DAQ_KEY_BYTE of
+/// GET_DAQ_PROCESSOR_INFO; ODT numbers are counted from the FIRST_PID that
+/// START_STOP_DAQ_LIST returned for the list.
+///
+/// Within a list, ODTs are expected in order; a skipped ODT counts as lost.
+pub struct DtoDecoder {
+    id_type: DaqIdentificationFieldType,
+    byte_order: ByteOrder,
+    padded_len: Option<usize>,
+    timestamp_format: Option<TimestampFormat>,
+    overload_indication: DaqOverloadIndication,
+    lists: Vec<DecoderList>,
+    stats: DaqStats,
+    on_overrun: Option<OverrunCallback>,
+}
+
+impl DtoDecoder {
+    pub fn new(id_type: DaqIdentificationFieldType, byte_order: ByteOrder) -> DtoDecoder {
+        DtoDecoder {
+            id_type,
+            byte_order,
+            padded_len: None,
+            timestamp_format: None,
+            overload_indication: DaqOverloadIndication::NoIndication,
+            lists: Vec::new(),
+            stats: DaqStats::default(),
+            on_overrun: None,
+        }
+    }
+
+    /// Detects overloads the way DAQ_PROPERTIES advertises: by the MSB of the PID
+    /// or by EV_DAQ_OVERLOAD packets passed to `decode`.
+    pub fn with_overload_indication(mut self, indication: DaqOverloadIndication) -> DtoDecoder {
+        self.overload_indication = indication;
+        self
+    }
+
+    /// Calls `callback` for every overload or loss of DTOs detected.
+    pub fn on_overrun<F: FnMut(&DaqOverrun) + Send + 'static>(&mut self, callback: F) {
+        self.on_overrun = Some(Box::new(callback));
+    }
+
+    pub fn stats(&self) -> DaqStats {
+        let mut stats = DaqStats::default();
+        self.copy_stats(&mut stats);
+        stats
+    }
+
+    /// Overwrites `stats` with the current counters, reusing its list buffer.
+    fn copy_stats(&self, stats: &mut DaqStats) {
+        let mut lists = std::mem::take(&mut stats.lists);
+        lists.clear();
+        lists.extend(self.lists.iter().map(|list| list.stats));
+        *stats = DaqStats { lists, ..self.stats };
+    }
+
+    /// Sets all counters back to zero, e.g. to compare before and after a change.
+    pub fn reset_stats(&mut self) {
+        self.stats = DaqStats::default();
+        for list in &mut self.lists {
+            list.stats = DaqListStats { daq_list: list.daq_list, ..DaqListStats::default() };
+            list.rate = RateState::default();
+        }
+    }
+
+    /// Decodes the timestamps of timestamped lists with the size and unit from
+    /// GET_DAQ_RESOLUTION_INFO.
+    pub fn with_timestamps(mut self, info: &GetDaqResolutionInfoResponse) -> DtoDecoder {
+        self.timestamp_format = Some(TimestampFormat {
+            size: info.timestamp_mode.size() as usize,
+            fixed: info.timestamp_mode.fixed(),
+            tick_seconds: info.tick_seconds(),
+        });
+        self
+    }
+
+    /// What the timestamps of decoded samples are based on: the unit from
+    /// GET_DAQ_RESOLUTION_INFO if set with `with_timestamps`, otherwise none.
+    pub fn time_base(&self) -> TimeBase {
+        match self.timestamp_format {
+            Some(TimestampFormat { tick_seconds: Some(tick_seconds), .. }) => TimeBase::Slave { tick_seconds },
+            Some(_) => TimeBase::Ticks,
+            None => TimeBase::Host,
+        }
+    }
+
+    /// Size of the timestamp in the first ODT of a list, 0 if it has none.
+    pub fn timestamp_len(&self, timestamped: bool) -> usize {
+        match self.timestamp_format {
+            Some(format) if timestamped || format.fixed => format.size,
+            _ => 0,
+        }
+    }
+
+    /// Also accepts DTOs padded to exactly `frame_len` bytes, as sent by slaves
+    /// that always transmit full CAN frames.
+    pub fn allow_padding(mut self, frame_len: usize) -> DtoDecoder {
+        self.padded_len = Some(frame_len);
+        self
+    }
+
+    /// Registers a started list; `odts` holds the byte size of every entry, per ODT,
+    /// and `timestamped` whether the TIMESTAMP mode bit is set.
+    pub fn add_list(&mut self, daq_list: u16, first_pid: u8, odts: Vec<Vec<usize>>, timestamped: bool) {
+        self.lists.retain(|list| list.daq_list != daq_list);
+        self.lists.push(DecoderList {
+            daq_list,
+            first_pid,
+            odts,
+            timestamped,
+            timestamps: TimestampState::default(),
+            next_odt: None,
+            overrun: false,
+            stats: DaqListStats { daq_list, ..DaqListStats::default() },
+            rate: RateState::default(),
+        });
+    }
+
+    /// Splits `dto` into DAQ list, ODT and payload.
+    ///
+    /// Returns `None` if the identification field matches no registered ODT.
+    pub fn identify<'d>(&self, dto: &'d [u8]) -> Option<(u16, u8, &'d [u8])> {
+        let id_len = self.id_type.size();
+        if dto.len() < id_len { return None }
+
+        let pid = dto[0];
+        let list = match self.id_type {
+            DaqIdentificationFieldType::AbsolutePid => self.lists.iter().find(|list| {
+                pid >= list.first_pid && ((pid - list.first_pid) as usize) < list.odts.len()
+            })?,
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte => {
+                self.lists.iter().find(|list| list.daq_list == dto[1] as u16)?
+            }
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord => {
+                let daq_list = self.byte_order.read_u16(&dto[1..3]);
+                self.lists.iter().find(|list| list.daq_list == daq_list)?
+            }
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned => {
+                let daq_list = self.byte_order.read_u16(&dto[2..4]);
+                self.lists.iter().find(|list| list.daq_list == daq_list)?
+            }
+        };
+
+        let odt = pid.checked_sub(list.first_pid)?;
+        if odt as usize >= list.odts.len() { return None }
+
+        Some((list.daq_list, odt, &dto[id_len..]))
+    }
+
+    /// Decodes `dto`, received at `received`, into the values of its ODT entries.
+    ///
+    /// The first ODT of a timestamped list starts with the timestamp. A tick count
+    /// lower than the previous one is taken as one wrap-around of the counter, so
+    /// samples must be decoded in the order they were received.
+    ///
+    /// Event packets are returned as `Dto::Unknown`, after noting EV_DAQ_OVERLOAD.
+    ///
+    /// # Returns
+    /// `XcpError::DtoLengthMismatch` if the payload does not have the size of the
+    /// configured ODT.
+    pub fn decode(&mut self, dto: &[u8], received: Instant) -> Result<Dto, XcpError> {
+        if dto.len() >= 2 && XcpResponseCode::from_code(dto[0]) == XcpResponseCode::Event {
+            if self.overload_indication == DaqOverloadIndication::EventPacket
+                && XcpEventCode::from_code(dto[1]) == XcpEventCode::DaqOverload {
+                self.stats.overloads += 1;
+                self.lists.iter_mut().for_each(|list| list.overrun = true);
+                self.report(DaqOverrun::Overload { daq_list: None });
+            }
+            return Ok(Dto::Unknown(dto.to_vec()));
+        }
+
+        let overloaded = self.overload_indication == DaqOverloadIndication::PidMsb
+            && dto.first().is_some_and(|&pid| pid & 0x80 != 0);
+        let unmarked;
+        let dto = if overloaded {
+            unmarked = [&[dto[0] & 0x7F], &dto[1..]].concat();
+            &unmarked[..]
+        } else {
+            dto
+        };
+
+        let Some((daq_list, odt, _)) = self.identify(dto) else {
+            self.stats.unknown += 1;
+            return Ok(Dto::Unknown(dto.to_vec()));
+        };
+
+        let mut overruns = Vec::new();
+        if overloaded {
+            self.stats.overloads += 1;
+            overruns.push(DaqOverrun::Overload { daq_list: Some(daq_list) });
+        }
+
+        let timestamp_len = if odt == 0 {
+            let list = self.lists.iter().find(|list| list.daq_list == daq_list).unwrap();
+            self.timestamp_len(list.timestamped)
+        } else {
+            0
+        };
+        let tick_seconds = self.timestamp_format.and_then(|format| format.tick_seconds);
+        let byte_order = self.byte_order;
+        let padded_len = self.padded_len;
+        let mut payload = &dto[self.id_type.size()..];
+        let list = self.lists.iter_mut().find(|list| list.daq_list == daq_list).unwrap();
+        list.stats.frames += 1;
+        list.stats.bytes += dto.len() as u64;
+
+        let sizes = &list.odts[odt as usize];
+        let expected = timestamp_len + sizes.iter().sum::<usize>();
+        let padded = padded_len == Some(dto.len()) && payload.len() >= expected;
+        if payload.len() != expected && !padded {
+            self.stats.length_errors += 1;
+            list.stats.decode_errors += 1;
+            return Err(XcpError::DtoLengthMismatch { daq_list, odt, length: payload.len(), expected });
+        }
+
+        let odt_count = list.odts.len();
+        if let Some(next) = list.next_odt.filter(|&next| next != odt) {
+            let missing = (odt as usize + odt_count - next as usize) % odt_count;
+            self.stats.missing_odts += missing as u64;
+            overruns.push(DaqOverrun::MissingOdts { daq_list, expected: next, received: odt, missing });
+        }
+        list.next_odt = Some(((odt as usize + 1) % odt_count) as u8);
+        let overrun = overloaded || !overruns.is_empty() || list.overrun;
+        list.overrun = false;
+
+        if timestamp_len > 0 {
+            let ticks = match timestamp_len {
+                1 => payload[0] as u32,
+                2 => byte_order.read_u16(payload) as u32,
+                _ => byte_order.read_u32(payload),
+            };
+            list.timestamps.track(ticks, timestamp_len, tick_seconds);
+            payload = &payload[timestamp_len..];
+        }
+
+        let mut values = Vec::with_capacity(sizes.len());
+        for &size in sizes {
+            values.push(payload[..size].to_vec());
+            payload = &payload[size..];
+        }
+
+        let first_entry = list.odts[..odt as usize].iter().map(Vec::len).sum();
+        let timestamp = list.timestamps.current;
+        if odt == 0 {
+            let time = if timestamp_len > 0 { timestamp.and_then(|timestamp| timestamp.time) } else { None };
+            list.stats.event_rate = list.rate.track(received, time);
+        }
+        list.stats.samples += 1;
+        list.stats.overruns += overrun as u64;
+        self.stats.samples += 1;
+        for overrun in overruns {
+            self.report(overrun);
+        }
+        Ok(Dto::Sample(DaqSample { daq_list, odt, first_entry, values, timestamp, received, overrun }))
+    }
+
+    fn report(&mut self, overrun: DaqOverrun) {
+        if let Some(callback) = self.on_overrun.as_mut() {
+            callback(&overrun);
+        }
+    }
+}
+
+/// A running measurement, see `XcpMaster::configure_daq`.
+///
+/// The lists keep running until `stop` is called, even if the session is dropped.
+pub struct DaqSession<'m, 'a> {
+    pub(crate) master: &'m mut XcpMaster<'a>,
+    /// All lists started with the session, stopped together by `stop`.
+    pub(crate) lists: Vec<u16>,
+    pub(crate) decoder: DtoDecoder,
+}
+
+impl<'m, 'a> DaqSession<'m, 'a> {
+    /// The master running the session, e.g. to send other commands meanwhile.
+    pub fn master(&mut self) -> &mut XcpMaster<'a> {
+        self.master
+    }
+
+    /// The decoder set up for the lists of the session.
+    pub fn decoder(&self) -> &DtoDecoder {
+        &self.decoder
+    }
+
+    /// DTO counters, in total and per list, including detected overloads and lost DTOs.
+    pub fn stats(&self) -> DaqStats {
+        self.decoder.stats()
+    }
+
+    /// Sets all counters of `stats` back to zero.
+    pub fn reset_stats(&mut self) {
+        self.decoder.reset_stats();
+    }
+
+    /// Calls `callback` for every overload or loss of DTOs detected.
+    pub fn on_overrun<F: FnMut(&DaqOverrun) + Send + 'static>(&mut self, callback: F) {
+        self.decoder.on_overrun(callback);
+    }
+
+    /// Iterates over the samples as they arrive, skipping DTOs of other lists. The
+    /// iterator ends when no DTO arrived within `timeout`, or after a receive error.
+    pub fn samples(&mut self, timeout: Duration) -> DaqSamples<'_, 'm, 'a> {
+        DaqSamples { session: self, timeout, failed: false }
+    }
+
+    /// Stops all lists of the session at the same time.
+    pub fn stop(self) -> Result<(), XcpError> {
+        stop_lists(self.master, &self.lists)
+    }
+
+    /// Moves reception of the DTOs onto a thread reading from `socket`, a second
+    /// socket on the same interface, and delivers the decoded samples over a
+    /// channel holding up to `capacity` of them.
+    ///
+    /// While the thread runs, the master discards DTOs arriving on its own socket.
+    pub fn spawn_receiver(self, socket: CanSocket, capacity: usize, backpressure: Backpressure) -> DaqReceiver<'m, 'a> {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let rx = Arc::new(Mutex::new(rx));
+        let stop = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let stats = Arc::new(Mutex::new(self.decoder.stats()));
+        let reset_stats = Arc::new(AtomicBool::new(false));
+
+        let receiver = ReceiverThread {
+            socket,
+            res: self.master.ids.res,
+            decoder: self.decoder,
+            tx,
+            rx: rx.clone(),
+            backpressure,
+            stop: stop.clone(),
+            dropped: dropped.clone(),
+            stats: stats.clone(),
+            reset_stats: reset_stats.clone(),
+            undelivered: Vec::new(),
+        };
+        self.master.queue_dtos = false;
+        let thread = thread::spawn(move || receiver.run());
+
+        DaqReceiver { master: self.master, lists: self.lists, rx, stop, dropped, stats, reset_stats, thread: Some(thread) }
+    }
+}
+
+/// Stops `lists` at the same time.
+fn stop_lists(master: &mut XcpMaster, lists: &[u16]) -> Result<(), XcpError> {
+    for &daq_list in lists {
+        master.start_stop_daq_list(StartStopMode::Select, daq_list)?;
+    }
+    master.start_stop_synch(StartStopSynchMode::StopSelected)
+}
+
+/// Samples of a running measurement, see `DaqSession::samples`.
+pub struct DaqSamples<'s, 'm, 'a> {
+    session: &'s mut DaqSession<'m, 'a>,
+    timeout: Duration,
+    failed: bool,
+}
+
+impl Iterator for DaqSamples<'_, '_, '_> {
+    type Item = Result<DaqSample, XcpError>;
+
+    fn next(&mut self) -> Option<Result<DaqSample, XcpError>> {
+        if self.failed { return None }
+
+        loop {
+            match self.session.master.recv_dto(self.timeout) {
+                Ok(Some(dto)) => match self.session.decoder.decode(&dto, Instant::now()) {
+                    Ok(Dto::Sample(sample)) => return Some(Ok(sample)),
+                    Ok(Dto::Unknown(_)) => {}
+                    Err(e) => return Some(Err(e)),
+                },
+                Ok(None) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// What the receiver thread of `DaqSession::spawn_receiver` does when the channel
+/// is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the application takes a sample. DTOs queue up in the socket
+    /// meanwhile and are lost if its buffer overflows.
+    Block,
+    /// Discard the oldest sample in the channel, counted in `DaqReceiver::dropped`.
+    DropOldest,
+}
+
+/// How long the receiver thread waits for a DTO before checking whether to stop.
+const RECEIVER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the receiver thread keeps reading DTOs still in flight after the lists
+/// were stopped.
+const RECEIVER_DRAIN_TIMEOUT: Duration = Duration::from_millis(20);
+
+type SampleResult = Result<DaqSample, XcpError>;
+
+/// How often `XcpMaster::stop_all_daq` polls GET_STATUS.
+const DAQ_STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A running measurement whose DTOs are received on a background thread, see
+/// `DaqSession::spawn_receiver`.
+///
+/// Dropping the receiver ends the thread but leaves the lists running; use `stop`.
+pub struct DaqReceiver<'m, 'a> {
+    master: &'m mut XcpMaster<'a>,
+    lists: Vec<u16>,
+    rx: Arc<Mutex<Receiver<SampleResult>>>,
+    stop: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    stats: Arc<Mutex<DaqStats>>,
+    reset_stats: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Vec<SampleResult>>>,
+}
+
+impl<'a> DaqReceiver<'_, 'a> {
+    /// The master running the measurement, e.g. to send other commands meanwhile.
+    pub fn master(&mut self) -> &mut XcpMaster<'a> {
+        self.master
+    }
+
+    /// Waits up to `timeout` for the next sample.
+    ///
+    /// Returns `None` on timeout, and once the thread has ended after a receive error
+    /// and all samples have been taken.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<SampleResult> {
+        self.rx.lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    /// DTO counters of the receiver thread, in total and per list, including detected
+    /// overloads and lost DTOs.
+    pub fn stats(&self) -> DaqStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Sets all counters of `stats` back to zero. The receiver thread starts counting
+    /// again with the next DTO.
+    pub fn reset_stats(&self) {
+        let mut stats = self.stats.lock().unwrap();
+        let lists = stats.lists.iter().map(|list| DaqListStats { daq_list: list.daq_list, ..DaqListStats::default() }).collect();
+        *stats = DaqStats { lists, ..DaqStats::default() };
+        self.reset_stats.store(true, Ordering::Release);
+    }
+
+    /// Number of samples discarded under `Backpressure::DropOldest`.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stops the lists, waits for the DTOs still in flight and ends the thread.
+    ///
+    /// # Returns
+    /// All samples received but not yet taken, in order.
+    pub fn stop(mut self) -> Result<Vec<SampleResult>, XcpError> {
+        stop_lists(self.master, &self.lists)?;
+        Ok(self.join())
+    }
+
+    /// Ends the thread and collects the samples it has not delivered.
+    fn join(&mut self) -> Vec<SampleResult> {
+        let Some(thread) = self.thread.take() else { return Vec::new() };
+        self.stop.store(true, Ordering::Release);
+        let undelivered = thread.join().unwrap_or_default();
+        self.master.queue_dtos = true;
+
+        let mut samples: Vec<SampleResult> = self.rx.lock().unwrap().try_iter().collect();
+        samples.extend(undelivered);
+        samples
+    }
+}
+
+impl Drop for DaqReceiver<'_, '_> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// State of the thread started by `DaqSession::spawn_receiver`.
+struct ReceiverThread {
+    socket: CanSocket,
+    res: CanId,
+    decoder: DtoDecoder,
+    tx: SyncSender<SampleResult>,
+    /// The receiving end, to discard the oldest sample under `Backpressure::DropOldest`.
+    rx: Arc<Mutex<Receiver<SampleResult>>>,
+    backpressure: Backpressure,
+    stop: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    stats: Arc<Mutex<DaqStats>>,
+    /// Set by `DaqReceiver::reset_stats`.
+    reset_stats: Arc<AtomicBool>,
+    /// Samples that did not fit into the channel after `stop` was set.
+    undelivered: Vec<SampleResult>,
+}
+
+impl ReceiverThread {
+    /// Receives and delivers DTOs until stopped and no more arrive, or the socket fails.
+    fn run(mut self) -> Vec<SampleResult> {
+        loop {
+            let stopping = self.stop.load(Ordering::Acquire);
+            let timeout = if stopping { RECEIVER_DRAIN_TIMEOUT } else { RECEIVER_POLL_INTERVAL };
+
+            let frame = match self.socket.read_frame_timeout(timeout) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    if stopping { break }
+                    continue;
+                }
+                Err(e) => {
+                    self.deliver(Err(XcpError::Io(e)));
+                    break;
+                }
+            };
+
+            let data = frame.data();
+            if CanId::from(frame.id()) != self.res || data.is_empty() { continue }
+            match XcpResponseCode::from_code(data[0]) {
+                XcpResponseCode::UnknownResponse | XcpResponseCode::Event => {}
+                _ => continue,
+            }
+
+            if self.reset_stats.swap(false, Ordering::AcqRel) {
+                self.decoder.reset_stats();
+            }
+            let decoded = self.decoder.decode(data, Instant::now());
+            self.decoder.copy_stats(&mut self.stats.lock().unwrap());
+            match decoded {
+                Ok(Dto::Sample(sample)) => self.deliver(Ok(sample)),
+                Ok(Dto::Unknown(_)) => {}
+                Err(e) => self.deliver(Err(e)),
+            }
+        }
+
+        self.undelivered
+    }
+
+    /// Puts `sample` into the channel, applying the backpressure policy if it is full.
+    fn deliver(&mut self, mut sample: SampleResult) {
+        if !self.undelivered.is_empty() {
+            self.undelivered.push(sample);
+            return;
+        }
+
+        loop {
+            match self.tx.try_send(sample) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(rejected)) => sample = rejected,
+            }
+
+            match self.backpressure {
+                Backpressure::DropOldest => {
+                    if self.rx.lock().unwrap().try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Backpressure::Block if self.stop.load(Ordering::Acquire) => {
+                    self.undelivered.push(sample);
+                    return;
+                }
+                Backpressure::Block => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+}
+
+/// ODT sizes of a list configured for STIM.
+#[derive(Debug, Clone)]
+struct StimList {
+    daq_list: u16,
+    first_pid: u8,
+    /// Payload size of every ODT in bytes.
+    odts: Vec<usize>,
+}
+
+/// Builds a DTO for the ODT identified by `pid` of `daq_list`, with the
+/// identification field of `id_type` followed by `payload`.
+pub fn encode_dto(id_type: DaqIdentificationFieldType, byte_order: ByteOrder, daq_list: u16, pid: u8, payload: &[u8]) -> Vec<u8> {
+    let mut dto = Vec::with_capacity(id_type.size() + payload.len());
+    dto.push(pid);
+    match id_type {
+        DaqIdentificationFieldType::AbsolutePid => {}
+        DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte => dto.push(daq_list as u8),
+        DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord => dto.extend_from_slice(&byte_order.u16_bytes(daq_list)),
+        DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned => {
+            dto.push(0x00); // fill byte
+            dto.extend_from_slice(&byte_order.u16_bytes(daq_list));
+        }
+    }
+    dto.extend_from_slice(payload);
+    dto
+}
+
+/// Builds the STIM DTOs of started lists.
+#[derive(Debug, Clone)]
+pub(crate) struct StimEncoder {
+    lists: Vec<StimList>,
+    id_type: DaqIdentificationFieldType,
+    byte_order: ByteOrder,
+}
+
+impl StimEncoder {
+    pub(crate) fn daq_lists(&self) -> Vec<u16> {
+        self.lists.iter().map(|list| list.daq_list).collect()
+    }
+
+    pub(crate) fn odt_len(&self, daq_list: u16, odt: u8) -> Option<usize> {
+        let list = self.lists.iter().find(|list| list.daq_list == daq_list)?;
+        list.odts.get(odt as usize).copied()
+    }
+
+    /// The DTO carrying `payload` for `odt` of `daq_list`.
+    pub(crate) fn encode(&self, daq_list: u16, odt: u8, payload: &[u8]) -> Result<Vec<u8>, XcpError> {
+        let Some(list) = self.lists.iter().find(|list| list.daq_list == daq_list) else {
+            return Err(XcpError::OdtNotValid { daq_list, odt });
+        };
+        let Some(&expected) = list.odts.get(odt as usize) else {
+            return Err(XcpError::OdtNotValid { daq_list, odt });
+        };
+        if payload.len() != expected {
+            return Err(XcpError::DtoLengthMismatch { daq_list, odt, length: payload.len(), expected });
+        }
+
+        Ok(encode_dto(self.id_type, self.byte_order, daq_list, list.first_pid + odt, payload))
+    }
+}
+
+/// Running STIM lists, see `XcpMaster::configure_stim`.
+///
+/// The lists keep running until `stop` is called, even if the session is dropped.
+pub struct StimSession<'m, 'a> {
+    master: &'m mut XcpMaster<'a>,
+    encoder: StimEncoder,
+}
+
+impl<'a> StimSession<'_, 'a> {
+    /// The master running the session, e.g. to send other commands meanwhile.
+    pub fn master(&mut self) -> &mut XcpMaster<'a> {
+        self.master
+    }
+
+    /// Payload size in bytes of `odt` of `daq_list`, `None` if not configured.
+    pub fn odt_len(&self, daq_list: u16, odt: u8) -> Option<usize> {
+        self.encoder.odt_len(daq_list, odt)
+    }
+
+    /// Sends `payload`, the values of all entries of `odt` of `daq_list` in order,
+    /// as a STIM DTO on the command CAN ID.
+    ///
+    /// # Returns
+    /// `XcpError::OdtNotValid` if the list has no such ODT, and
+    /// `XcpError::DtoLengthMismatch` if `payload` does not have the size of the ODT.
+    pub fn write(&mut self, daq_list: u16, odt: u8, payload: &[u8]) -> Result<(), XcpError> {
+        let dto = self.encoder.encode(daq_list, odt, payload)?;
+        self.master.send_dto(&dto)
+    }
+
+    /// Stops all lists of the session at the same time.
+    pub fn stop(self) -> Result<(), XcpError> {
+        stop_lists(self.master, &self.encoder.daq_lists())
+    }
+}
+
+/// Bit rate of the CAN bus DTOs are sent on, for `estimate_bus_load`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CanBitrate {
+    /// Classic CAN at the given bits per second.
+    Classic(u32),
+    /// CAN FD, with the arbitration phase at `nominal` and the data phase at `data`
+    /// bits per second. Without bit rate switching both are the same.
+    Fd { nominal: u32, data: u32 },
+}
+
+/// The DTOs one list sends per event, for `estimate_bus_load`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaqListLoad {
+    pub daq_list: u16,
+    /// Events per second the list is sent on, after its prescaler. `None` if the
+    /// event is not cyclic.
+    pub events_per_second: Option<f64>,
+    /// Length of the DTO of every ODT, identification field and timestamp included.
+    pub dto_lengths: Vec<usize>,
+    /// The DTOs are sent with a 29 bit identifier.
+    pub extended_id: bool,
+}
+
+/// Bus load expected from a DAQ configuration, see `estimate_bus_load`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BusLoad {
+    pub frames_per_second: f64,
+    /// Bits on the wire per second, framing, stuff bits and interframe space included.
+    pub bits_per_second: f64,
+    /// Fraction of the bus time taken by the DTOs; above 1.0 they cannot all be sent.
+    pub load: f64,
+    /// Lists left out of the estimate because their event is not cyclic.
+    pub acyclic_lists: Vec<u16>,
+}
+
+/// When `XcpMaster::configure_daq` checks the expected bus load, see
+/// `XcpMaster::bus_load_limit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BusLoadLimit {
+    pub bitrate: CanBitrate,
+    /// Largest acceptable `BusLoad::load`, e.g. 0.8.
+    pub max_load: f64,
+    /// Fail with `XcpError::BusLoadExceeded` instead of only publishing
+    /// `SessionEvent::BusLoadExceeded`.
+    pub strict: bool,
+}
+
+/// Payload sizes a CAN FD frame can have.
+const CAN_FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Bits of a frame carrying `len` data bytes, sent at the nominal and the data bit
+/// rate, with the worst case number of stuff bits.
+fn frame_bits(len: usize, extended_id: bool, fd: bool) -> (usize, usize) {
+    if !fd {
+        let len = len.min(8);
+        let (fixed, stuffed) = if extended_id { (67, 54) } else { (47, 34) };
+        return (fixed + 8 * len + (stuffed + 8 * len - 1) / 4, 0);
+    }
+
+    let len = CAN_FD_LENGTHS.iter().copied().find(|&size| size >= len).unwrap_or(64);
+    // SOF to BRS, stuffed, then CRC delimiter, ACK, EOF and interframe space.
+    let arbitration = if extended_id { 36 } else { 17 };
+    let nominal = arbitration + (arbitration - 1) / 4 + 13;
+    // ESI, DLC and data, stuffed, then stuff count, CRC and its fixed stuff bits.
+    let crc = if len > 16 { 21 + 7 } else { 17 + 6 };
+    let data = 5 + 8 * len + (5 + 8 * len - 1) / 4 + 4 + crc;
+    (nominal, data)
+}
+
+/// Estimates the load the DTOs of `lists` put on a CAN bus running at `bitrate`.
+///
+/// Frames are counted with the worst case number of stuff bits and, on CAN FD,
+/// with their payload rounded up to the next valid frame length, so the estimate
+/// errs on the high side.
+pub fn estimate_bus_load(lists: &[DaqListLoad], bitrate: CanBitrate) -> BusLoad {
+    let (nominal, data, fd) = match bitrate {
+        CanBitrate::Classic(bitrate) => (bitrate as f64, bitrate as f64, false),
+        CanBitrate::Fd { nominal, data } => (nominal as f64, data as f64, true),
+    };
+
+    let mut load = BusLoad::default();
+    for list in lists {
+        let Some(events) = list.events_per_second else {
+            load.acyclic_lists.push(list.daq_list);
+            continue;
+        };
+        for &len in &list.dto_lengths {
+            let (nominal_bits, data_bits) = frame_bits(len, list.extended_id, fd);
+            load.frames_per_second += events;
+            load.bits_per_second += events * (nominal_bits + data_bits) as f64;
+            load.load += events * (nominal_bits as f64 / nominal + data_bits as f64 / data);
+        }
+    }
+    load
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Configures and starts a measurement of `lists`.
+    ///
+    /// Writes the entries with `write_daq_layout`, sets the mode of every list, then
+    /// selects them and starts them together with START_STOP_SYNCH.
+    ///
+    /// # Returns
+    /// The running session. Errors once lists are written come as
+    /// `XcpError::DaqConfigFailed` after the configuration was undone; they name
+    /// the list, and where known the ODT and entry, that could not be configured.
+    pub fn configure_daq(&mut self, lists: &[DaqListBuilder]) -> Result<DaqSession<'_, 'a>, XcpError> {
+        let (mut decoder, layouts) = self.prepare_daq(lists)?;
+        if let Some(limit) = self.bus_load_limit {
+            let loads = self.daq_list_loads(lists, &layouts, &decoder)?;
+            let load = estimate_bus_load(&loads, limit.bitrate).load;
+            if load > limit.max_load {
+                if limit.strict { return Err(XcpError::BusLoadExceeded { load, max_load: limit.max_load }) }
+                self.publish_event(SessionEvent::BusLoadExceeded { load, max_load: limit.max_load });
+            }
+        }
+        let first_pids = self.start_layouts(lists, &layouts)?;
+        self.register_daq(&mut decoder, lists, &layouts, &first_pids);
+
+        let lists = lists.iter().map(|list| list.daq_list).collect();
+        Ok(DaqSession { master: self, lists, decoder })
+    }
+
+    /// Estimates the bus load `lists` would cause once started, from the cycle
+    /// times GET_DAQ_EVENT_INFO reports for their events. Nothing is written to
+    /// the slave.
+    pub fn estimate_daq_bus_load(&mut self, lists: &[DaqListBuilder], bitrate: CanBitrate) -> Result<BusLoad, XcpError> {
+        let (decoder, layouts) = self.prepare_daq(lists)?;
+        let loads = self.daq_list_loads(lists, &layouts, &decoder)?;
+        Ok(estimate_bus_load(&loads, bitrate))
+    }
+
+    /// The DTOs `lists`, packed into `layouts`, send per event and their event rate.
+    fn daq_list_loads(&mut self, lists: &[DaqListBuilder], layouts: &[DaqListLayout], decoder: &DtoDecoder)
+        -> Result<Vec<DaqListLoad>, XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let id_len = decoder.id_type.size();
+        let mut cycles = HashMap::new();
+        let mut loads = Vec::with_capacity(lists.len());
+
+        for (list, layout) in lists.iter().zip(layouts) {
+            let channel = list.event_channel();
+            let cycle = match cycles.get(&channel) {
+                Some(&cycle) => cycle,
+                None => {
+                    let cycle = self.get_daq_event_info(channel)?.cycle_seconds();
+                    cycles.insert(channel, cycle);
+                    cycle
+                }
+            };
+            let timestamp_len = decoder.timestamp_len(list.mode.mode.timestamp());
+            let dto_lengths = layout.odts.iter().enumerate()
+                .map(|(odt, entries)| {
+                    let payload: usize = entries.iter().map(|entry| entry.size as usize * ag).sum();
+                    id_len + payload + if odt == 0 { timestamp_len } else { 0 }
+                })
+                .collect();
+
+            loads.push(DaqListLoad {
+                daq_list: list.daq_list,
+                events_per_second: cycle.map(|cycle| 1.0 / (cycle * list.mode.prescaler.max(1) as f64)),
+                dto_lengths,
+                extended_id: self.ids.res.is_extended(),
+            });
+        }
+        Ok(loads)
+    }
+
+    /// Packs `lists` into ODTs for DAQ and sets up a decoder for them.
+    pub(crate) fn prepare_daq(&mut self, lists: &[DaqListBuilder]) -> Result<(DtoDecoder, Vec<DaqListLayout>), XcpError> {
+        let info = match self.daq_processor_info {
+            Some(info) => info,
+            None => self.get_daq_processor_info()?,
+        };
+
+        let id_len = info.key_byte.identification_field_type().size();
+        let odt_len = self.max_dto.saturating_sub(id_len);
+        let ag = self.comm_mode_basic.granularity().size();
+        let overload_indication = info.properties.overload_indication();
+        if let Some(list) = lists.iter().find(|list| list.overload_indication_required) {
+            if matches!(overload_indication, None | Some(DaqOverloadIndication::NoIndication)) {
+                return Err(XcpError::OverloadIndicationUnsupported { daq_list: list.daq_list });
+            }
+        }
+        let mut decoder = DtoDecoder::new(info.key_byte.identification_field_type(), self.comm_mode_basic.endianness())
+            .allow_padding(self.max_dto)
+            .with_overload_indication(overload_indication.unwrap_or_default());
+        if info.properties.timestamp_supported() {
+            let resolution = self.get_daq_resolution_info()?;
+            decoder = decoder.with_timestamps(&resolution);
+        }
+
+        let layouts = lists.iter()
+            .map(|list| list.optimised_layout(odt_len, ag, decoder.timestamp_len(list.mode.mode.timestamp()),
+                info.key_byte.optimisation_type()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((decoder, layouts))
+    }
+
+    /// Registers the started DAQ `lists` with `decoder`.
+    pub(crate) fn register_daq(&self, decoder: &mut DtoDecoder, lists: &[DaqListBuilder], layouts: &[DaqListLayout], first_pids: &[u8]) {
+        let ag = self.comm_mode_basic.granularity().size();
+        for ((list, layout), &first_pid) in lists.iter().zip(layouts).zip(first_pids) {
+            let odts = layout.odts.iter()
+                .map(|entries| entries.iter().map(|entry| entry.size as usize * ag).collect())
+                .collect();
+            decoder.add_list(list.daq_list, first_pid, odts, list.mode.mode.timestamp());
+        }
+    }
+
+    /// Stops all DAQ lists with START_STOP_SYNCH and waits up to `timeout` for
+    /// GET_STATUS to confirm that no list is running anymore.
+    ///
+    /// # Returns
+    /// `XcpError::Timeout` if DAQ is still running after `timeout`.
+    pub fn stop_all_daq(&mut self, timeout: Duration) -> Result<(), XcpError> {
+        let start = Instant::now();
+        self.start_stop_synch(StartStopSynchMode::StopAll)?;
+        while self.get_status()?.session_status.daq_running() {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                let command = XcpCommandCode::StartStopSynch;
+                return Err(XcpError::Timeout { command, class: TimeoutClass::of(command), timeout, elapsed });
+            }
+            thread::sleep(DAQ_STOP_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// Stores the configuration of the lists in RESUME mode in the slave under
+    /// `session_configuration_id`, with SET_REQUEST STORE_DAQ_REQ, and waits up to
+    /// `timeout` for the slave to finish.
+    pub fn store_daq_for_resume(&mut self, session_configuration_id: u16, timeout: Duration) -> Result<(), XcpError> {
+        let mut store = XcpSetRequestMode(0);
+        store.set_store_daq_req(true);
+        self.set_request_and_wait(store, session_configuration_id, timeout)
+    }
+
+    /// Takes over DAQ lists the slave started on its own in RESUME mode.
+    ///
+    /// `lists` must be the lists stored under `session_configuration_id`, as they were
+    /// passed to `configure_daq`; they are only used to decode the DTOs and are not
+    /// written again. The FIRST_PID of every list is learned again by selecting it.
+    ///
+    /// # Returns
+    /// `XcpError::ResumeNotActive` if GET_STATUS reports neither RESUME nor running
+    /// DAQ, `XcpError::SessionConfigurationMismatch` if the slave runs a different
+    /// stored configuration, and `XcpError::DaqListNotRunning` for a list that is
+    /// not running.
+    pub fn resume_daq(&mut self, lists: &[DaqListBuilder], session_configuration_id: u16) -> Result<DaqSession<'_, 'a>, XcpError> {
+        let status = self.get_status()?;
+        if !status.session_status.resume() && !status.session_status.daq_running() {
+            return Err(XcpError::ResumeNotActive);
+        }
+        if status.session_configuration_id != Some(session_configuration_id) {
+            return Err(XcpError::SessionConfigurationMismatch {
+                expected: session_configuration_id,
+                actual: status.session_configuration_id,
+            });
+        }
+
+        let (mut decoder, layouts) = self.prepare_daq(lists)?;
+        let mut first_pids = Vec::with_capacity(lists.len());
+        for list in lists {
+            let mode = self.get_daq_list_mode(list.daq_list)
+                .map_err(|e| list_failed(list.daq_list, None, e))?;
+            if !mode.mode.running() {
+                return Err(XcpError::DaqListNotRunning { daq_list: list.daq_list });
+            }
+            let first_pid = self.start_stop_daq_list(StartStopMode::Select, list.daq_list)
+                .map_err(|e| list_failed(list.daq_list, None, e))?;
+            first_pids.push(first_pid);
+        }
+        self.register_daq(&mut decoder, lists, &layouts, &first_pids);
+
+        let lists = lists.iter().map(|list| list.daq_list).collect();
+        Ok(DaqSession { master: self, lists, decoder })
+    }
+
+    /// Configures `lists` for stimulation and starts them.
+    ///
+    /// Like `configure_daq`, but sets the STIM direction bit of every list and checks
+    /// the entries against the STIM granularity and maximum entry size from
+    /// GET_DAQ_RESOLUTION_INFO. The event channel of a list is the one whose
+    /// occurrence makes the slave apply the received data.
+    ///
+    /// # Returns
+    /// The session sending the STIM DTOs. `XcpError::DaqEntrySizeNotSupported` if an
+    /// entry does not fit the STIM resolution, before anything is written.
+    pub fn configure_stim(&mut self, lists: &[DaqListBuilder]) -> Result<StimSession<'_, 'a>, XcpError> {
+        let (lists, layouts) = self.prepare_stim(lists)?;
+        let first_pids = self.start_layouts(&lists, &layouts)?;
+        let encoder = self.stim_encoder(&layouts, &first_pids);
+        Ok(StimSession { master: self, encoder })
+    }
+
+    /// Sets the STIM direction bit of `lists`, checks them against the STIM
+    /// resolution and packs them into ODTs.
+    pub(crate) fn prepare_stim(&mut self, lists: &[DaqListBuilder]) -> Result<(Vec<DaqListBuilder>, Vec<DaqListLayout>), XcpError> {
+        let info = match self.daq_processor_info {
+            Some(info) => info,
+            None => self.get_daq_processor_info()?,
+        };
+        let resolution = self.get_daq_resolution_info()?;
+
+        let lists: Vec<DaqListBuilder> = lists.iter()
+            .map(|list| {
+                let mut list = list.clone();
+                list.mode.mode.set_stim(true);
+                list
+            })
+            .collect();
+
+        let granularity = resolution.granularity_odt_entry_size_stim.max(1);
+        let max = resolution.max_odt_entry_size_stim;
+        for list in &lists {
+            for (index, entry) in list.entries.iter().enumerate() {
+                if entry.size % granularity != 0 || entry.size > max {
+                    return Err(XcpError::DaqEntrySizeNotSupported {
+                        daq_list: list.daq_list, entry: index, size: entry.size, granularity, max,
+                    });
+                }
+            }
+        }
+
+        let id_type = info.key_byte.identification_field_type();
+        let odt_len = self.max_dto.saturating_sub(id_type.size());
+        let ag = self.comm_mode_basic.granularity().size();
+        let layouts = lists.iter()
+            .map(|list| list.optimised_layout(odt_len, ag, 0, info.key_byte.optimisation_type()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((lists, layouts))
+    }
+
+    /// The encoder for the started STIM lists of `layouts`.
+    pub(crate) fn stim_encoder(&self, layouts: &[DaqListLayout], first_pids: &[u8]) -> StimEncoder {
+        let ag = self.comm_mode_basic.granularity().size();
+        let lists = layouts.iter().zip(first_pids)
+            .map(|(layout, &first_pid)| StimList {
+                daq_list: layout.daq_list,
+                first_pid,
+                odts: layout.odts.iter()
+                    .map(|entries| entries.iter().map(|entry| entry.size as usize * ag).sum())
+                    .collect(),
+            })
+            .collect();
+
+        // cached by prepare_stim
+        let id_type = self.daq_processor_info
+            .map_or(DaqIdentificationFieldType::AbsolutePid, |info| info.key_byte.identification_field_type());
+        StimEncoder { lists, id_type, byte_order: self.comm_mode_basic.endianness() }
+    }
+
+    /// Writes `layouts`, sets the mode of every list in `lists`, then selects and
+    /// starts them together.
+    ///
+    /// # Returns
+    /// The FIRST_PID of every list, in the order of `lists`. Errors are returned as
+    /// `XcpError::DaqConfigFailed` after undoing the configuration with
+    /// `roll_back_layouts`, so that configuring can be retried.
+    pub(crate) fn start_layouts(&mut self, lists: &[DaqListBuilder], layouts: &[DaqListLayout]) -> Result<Vec<u8>, XcpError> {
+        self.write_and_start_layouts(lists, layouts).map_err(|source| {
+            let cleanup = self.roll_back_layouts(layouts);
+            XcpError::DaqConfigFailed { source: Box::new(source), cleanup }
+        })
+    }
+
+    /// The steps of `start_layouts`.
+    fn write_and_start_layouts(&mut self, lists: &[DaqListBuilder], layouts: &[DaqListLayout]) -> Result<Vec<u8>, XcpError> {
+        self.write_daq_layout(layouts)?;
+
+        for list in lists {
+            self.set_daq_list_mode(list.daq_list, list.mode)
+                .map_err(|e| list_failed(list.daq_list, None, e))?;
+        }
+
+        let mut first_pids = Vec::with_capacity(lists.len());
+        for list in lists {
+            let first_pid = self.start_stop_daq_list(StartStopMode::Select, list.daq_list)
+                .map_err(|e| list_failed(list.daq_list, None, e))?;
+            first_pids.push(first_pid);
+        }
+        self.start_stop_synch(StartStopSynchMode::StartSelected)?;
+
+        Ok(first_pids)
+    }
+
+    /// Undoes a partly written configuration of `layouts`: frees the dynamic DAQ
+    /// lists with FREE_DAQ, and clears static and predefined lists with CLEAR_DAQ_LIST.
+    fn roll_back_layouts(&mut self, layouts: &[DaqListLayout]) -> Cleanup {
+        let Some(info) = self.daq_processor_info else { return Cleanup::NotNeeded };
+        let dynamic_config = info.properties.config_type() == DaqConfigType::Dynamic;
+        let is_dynamic = |layout: &&DaqListLayout| dynamic_config && layout.daq_list >= info.min_daq as u16;
+
+        let mut cleanup = Cleanup::NotNeeded;
+        if layouts.iter().any(|layout| is_dynamic(&layout)) {
+            cleanup = Cleanup::of(XcpCommandCode::FreeDaq, self.free_daq());
+        }
+        for layout in layouts.iter().filter(|layout| !is_dynamic(layout)) {
+            if !cleanup.succeeded() { break }
+            cleanup = Cleanup::of(XcpCommandCode::ClearDaqList, self.clear_daq_list(layout.daq_list));
+        }
+        cleanup
+    }
+
+    /// Writes the ODT entries of `layouts` to the slave.
+    ///
+    /// The DAQ configuration type comes from GET_DAQ_PROCESSOR_INFO (queried first
+    /// unless already cached). With dynamic configuration, the lists from MIN_DAQ up
+    /// to the highest one in `layouts` are allocated with FREE_DAQ, ALLOC_DAQ,
+    /// ALLOC_ODT and ALLOC_ODT_ENTRY. Static lists, and predefined ones below MIN_DAQ,
+    /// cannot be allocated: their layout is checked against MAX_ODT and
+    /// MAX_ODT_ENTRIES from GET_DAQ_LIST_INFO and the list is cleared instead.
+    ///
+    /// # Returns
+    /// `XcpError::DaqCapacityExceeded` naming the list and limit if a static list is
+    /// too small, before anything is written. Other failures are reported as
+    /// `XcpError::DaqListFailed` naming the list and ODT.
+    pub fn write_daq_layout(&mut self, layouts: &[DaqListLayout]) -> Result<(), XcpError> {
+        let info = match self.daq_processor_info {
+            Some(info) => info,
+            None => self.get_daq_processor_info()?,
+        };
+
+        let mut layouts: Vec<&DaqListLayout> = layouts.iter().collect();
+        layouts.sort_by_key(|layout| layout.daq_list);
+
+        let dynamic_config = info.properties.config_type() == DaqConfigType::Dynamic;
+        let (dynamic, fixed): (Vec<&DaqListLayout>, Vec<&DaqListLayout>) = layouts.iter()
+            .copied()
+            .partition(|layout| dynamic_config && layout.daq_list >= info.min_daq as u16);
+
+        for layout in &fixed {
+            self.check_daq_capacity(layout)?;
+        }
+
+        if let Some(last) = dynamic.last() {
+            self.free_daq()?;
+            self.alloc_daq(last.daq_list - info.min_daq as u16 + 1)?;
+            for layout in &dynamic {
+                self.alloc_odt(layout.daq_list, layout.odts.len() as u8)
+                    .map_err(|e| list_failed(layout.daq_list, None, e))?;
+            }
+            for layout in &dynamic {
+                for (odt, entries) in layout.odts.iter().enumerate() {
+                    self.alloc_odt_entry(layout.daq_list, odt as u8, entries.len() as u8)
+                        .map_err(|e| list_failed(layout.daq_list, Some(odt as u8), e))?;
+                }
+            }
+        }
+
+        for layout in &fixed {
+            self.clear_daq_list(layout.daq_list)
+                .map_err(|e| list_failed(layout.daq_list, None, e))?;
+        }
+
+        for layout in &layouts {
+            for (odt, entries) in layout.odts.iter().enumerate() {
+                if entries.is_empty() {
+                    continue;
+                }
+                self.set_daq_ptr(layout.daq_list, odt as u8, 0)
+                    .and_then(|_| self.write_daq_multiple(entries))
+                    .map_err(|e| list_failed(layout.daq_list, Some(odt as u8), e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a list the master cannot allocate is large enough for `layout`.
+    fn check_daq_capacity(&mut self, layout: &DaqListLayout) -> Result<(), XcpError> {
+        let list_info = self.get_daq_list_info(layout.daq_list)?;
+
+        if layout.odts.len() > list_info.max_odt as usize {
+            return Err(XcpError::DaqCapacityExceeded {
+                daq_list: layout.daq_list,
+                odt: None,
+                requested: layout.odts.len(),
+                limit: list_info.max_odt,
+            });
+        }
+
+        for (odt, entries) in layout.odts.iter().enumerate() {
+            if entries.len() > list_info.max_odt_entries as usize {
+                return Err(XcpError::DaqCapacityExceeded {
+                    daq_list: layout.daq_list,
+                    odt: Some(odt as u8),
+                    requested: entries.len(),
+                    limit: list_info.max_odt_entries,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wraps `e` in `XcpError::DaqListFailed` for `daq_list` and `odt`.
+fn list_failed(daq_list: u16, odt: Option<u8>, e: XcpError) -> XcpError {
+    XcpError::DaqListFailed { daq_list, odt, source: Box::new(e) }
+}
+
+/// How the bytes of an entry are interpreted when recording them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueFormat {
+    /// The raw bytes in transmission order, as lowercase hex digits.
+    #[default]
+    Hex,
+    /// An unsigned integer in the slave's byte order.
+    Unsigned,
+    /// A two's complement integer in the slave's byte order.
+    Signed,
+    /// An IEEE 754 float in the slave's byte order.
+    Float,
+}
+
+impl ValueFormat {
+    /// Formats `bytes`. Sizes an integer or float cannot have are written as hex.
+    pub fn format(&self, bytes: &[u8], byte_order: ByteOrder) -> String {
+        let mut raw = [0u8; 8];
+        let len = bytes.len();
+        let int_size = matches!(len, 1 | 2 | 4 | 8);
+        if int_size {
+            match byte_order {
+                ByteOrder::Intel => raw[..len].copy_from_slice(bytes),
+                ByteOrder::Motorola => raw[..len].iter_mut().zip(bytes.iter().rev()).for_each(|(r, b)| *r = *b),
+            }
+        }
+        let unsigned = u64::from_le_bytes(raw);
+
+        match self {
+            ValueFormat::Unsigned if int_size => unsigned.to_string(),
+            ValueFormat::Signed if int_size => {
+                let shift = 64 - 8 * len as u32;
+                (((unsigned << shift) as i64) >> shift).to_string()
+            }
+            ValueFormat::Float if len == 4 => f32::from_bits(unsigned as u32).to_string(),
+            ValueFormat::Float if len == 8 => f64::from_bits(unsigned).to_string(),
+            _ => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+/// A column of a `CsvRecorder`.
+#[derive(Debug, Clone)]
+struct CsvColumn {
+    daq_list: u16,
+    entry: usize,
+    label: String,
+    format: ValueFormat,
+}
+
+/// The row of a list being assembled from its ODTs.
+#[derive(Debug, Default)]
+struct CsvRow {
+    /// Time since the first recorded DTO was received, and the slave timestamp.
+    received: Option<Duration>,
+    timestamp: Option<DaqTimestamp>,
+    values: Vec<Option<String>>,
+    filled: usize,
+    overrun: bool,
+}
+
+/// Writes DAQ samples as CSV, one row per sample of a list.
+///
+/// The columns start with the time of the sample as set by `time_base`:
+/// `host_time`, the seconds since the first recorded DTO was received, preceded
+/// by `time`, the seconds since the first sample of the list from the slave
+/// timestamps, or by `ticks`, the timestamp ticks since then if their unit is
+/// unknown. The slave columns are empty for lists without timestamps. Then follow
+/// `daq_list`, one column per entry of every list, and finally `overrun`. A row
+/// has values only in the columns of its own list. `overrun` is 1 for rows with
+/// missing values and rows following an overload or lost DTOs (see
+/// `DaqSample::overrun`).
+///
+/// Rows are written as soon as all ODTs of a sample arrived (or, if some were lost,
+/// when the next sample starts) and flushed every `flush_interval`, so memory use
+/// does not grow with the length of a recording.
+pub struct CsvRecorder<W: Write> {
+    writer: W,
+    byte_order: ByteOrder,
+    columns: Vec<CsvColumn>,
+    rows: HashMap<u16, CsvRow>,
+    time_base: TimeBase,
+    header_written: bool,
+    started: Option<Instant>,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl<W: Write> CsvRecorder<W> {
+    /// Records the entries of `lists`, labelled `addr:size` (as in `0x20000000:4`)
+    /// and written as hex until set otherwise. `byte_order` is that of the slave.
+    pub fn new(writer: W, byte_order: ByteOrder, lists: &[DaqListBuilder]) -> CsvRecorder<W> {
+        let columns = lists.iter()
+            .flat_map(|list| list.entries().iter().enumerate().map(|(entry, daq_entry)| CsvColumn {
+                daq_list: list.daq_list(),
+                entry,
+                label: format!("{:#x}:{}", daq_entry.address, daq_entry.size),
+                format: ValueFormat::default(),
+            }))
+            .collect();
+
+        CsvRecorder {
+            writer,
+            byte_order,
+            columns,
+            rows: HashMap::new(),
+            time_base: TimeBase::Host,
+            header_written: false,
+            started: None,
+            flush_interval: Duration::from_secs(1),
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Names the column of the `entry`th entry of `daq_list`.
+    pub fn label(mut self, daq_list: u16, entry: usize, label: &str) -> CsvRecorder<W> {
+        if let Some(column) = self.column_mut(daq_list, entry) {
+            column.label = label.to_string();
+        }
+        self
+    }
+
+    /// Sets how the value of the `entry`th entry of `daq_list` is written.
+    pub fn format(mut self, daq_list: u16, entry: usize, format: ValueFormat) -> CsvRecorder<W> {
+        if let Some(column) = self.column_mut(daq_list, entry) {
+            column.format = format;
+        }
+        self
+    }
+
+    /// Flushes written rows to `writer` at least this often (default 1 s).
+    pub fn flush_interval(mut self, interval: Duration) -> CsvRecorder<W> {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Writes the slave timestamps as `time_base` describes them, usually that of
+    /// the session's decoder (`DaqSession::decoder`). The default `TimeBase::Host`
+    /// only records when DTOs were received.
+    pub fn time_base(mut self, time_base: TimeBase) -> CsvRecorder<W> {
+        self.time_base = time_base;
+        self
+    }
+
+    fn column_mut(&mut self, daq_list: u16, entry: usize) -> Option<&mut CsvColumn> {
+        self.columns.iter_mut().find(|column| column.daq_list == daq_list && column.entry == entry)
+    }
+
+    /// Adds the values of `sample` to the row of its list.
+    pub fn record(&mut self, sample: &DaqSample) -> io::Result<()> {
+        if !self.header_written {
+            let mut header = String::from(match self.time_base {
+                TimeBase::Host => "host_time,daq_list",
+                TimeBase::Slave { .. } => "time,host_time,daq_list",
+                TimeBase::Ticks => "ticks,host_time,daq_list",
+            });
+            for column in &self.columns {
+                header.push(',');
+                header.push_str(&csv_escape(&column.label));
+            }
+            header.push_str(",overrun");
+            writeln!(self.writer, "{}", header)?;
+            self.header_written = true;
+        }
+
+        let started = *self.started.get_or_insert(sample.received);
+        let received = sample.received.saturating_duration_since(started);
+
+        if sample.odt == 0 {
+            if let Some(row) = self.rows.remove(&sample.daq_list) {
+                self.write_row(sample.daq_list, row)?;
+            }
+        }
+
+        let entries = self.columns.iter().filter(|column| column.daq_list == sample.daq_list).count();
+        let row = self.rows.entry(sample.daq_list).or_insert_with(|| CsvRow {
+            received: None,
+            timestamp: None,
+            values: vec![None; entries],
+            filled: 0,
+            overrun: false,
+        });
+        if row.received.is_none() {
+            row.received = Some(received);
+            row.timestamp = sample.timestamp;
+        }
+        row.overrun |= sample.overrun;
+
+        for (index, value) in sample.values.iter().enumerate() {
+            let entry = sample.first_entry + index;
+            let Some(column) = self.columns.iter().find(|c| c.daq_list == sample.daq_list && c.entry == entry) else {
+                continue;
+            };
+            if let Some(slot) = row.values.get_mut(entry) {
+                if slot.is_none() { row.filled += 1 }
+                *slot = Some(column.format.format(value, self.byte_order));
+            }
+        }
+
+        if row.filled >= entries {
+            let row = self.rows.remove(&sample.daq_list).unwrap();
+            self.write_row(sample.daq_list, row)?;
+        }
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Records every sample of `samples`, e.g. `DaqSession::samples`, until it ends
+    /// or yields an error.
+    pub fn record_all<I: IntoIterator<Item = Result<DaqSample, XcpError>>>(&mut self, samples: I) -> Result<(), XcpError> {
+        for sample in samples {
+            self.record(&sample?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the incomplete rows still pending, flushes and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut pending: Vec<(u16, CsvRow)> = self.rows.drain().collect();
+        pending.sort_by_key(|(_, row)| row.received);
+        for (daq_list, row) in pending {
+            self.write_row(daq_list, row)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_row(&mut self, daq_list: u16, row: CsvRow) -> io::Result<()> {
+        let mut line = String::new();
+        match (self.time_base, row.timestamp) {
+            (TimeBase::Host, _) => {}
+            (TimeBase::Ticks, Some(timestamp)) => line.push_str(&format!("{},", timestamp.elapsed)),
+            (time_base, timestamp) => {
+                if let Some(time) = timestamp.and_then(|timestamp| time_base.slave_time(&timestamp)) {
+                    line.push_str(&csv_seconds(time));
+                }
+                line.push(',');
+            }
+        }
+        line.push_str(&format!("{},{}", csv_seconds(row.received.unwrap_or_default()), daq_list));
+        for column in &self.columns {
+            line.push(',');
+            if column.daq_list == daq_list {
+                if let Some(Some(value)) = row.values.get(column.entry) {
+                    line.push_str(value);
+                }
+            }
+        }
+        let overrun = row.overrun || row.filled < row.values.len();
+        line.push_str(if overrun { ",1" } else { ",0" });
+        writeln!(self.writer, "{}", line)
+    }
+}
+
+/// Writes `time` as seconds with nanosecond digits.
+fn csv_seconds(time: Duration) -> String {
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+/// Quotes `field` if it contains a separator, quote or line break.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
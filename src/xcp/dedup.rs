@@ -0,0 +1,53 @@
+//! Suppression of duplicated response frames.
+//!
+//! Some CAN gateways occasionally deliver the same frame twice. A duplicated RES/ERR
+//! frame would otherwise be taken as the response to the next command and shift every
+//! following response by one.
+
+use std::time::{Duration, Instant};
+use crate::xcp::frame::XcpFrameBuf;
+
+/// Remembers the last delivered response and flags an identical copy arriving
+/// within a short window.
+#[derive(Debug, Default)]
+pub struct DuplicateFilter {
+    window: Option<Duration>,
+    last: Option<(XcpFrameBuf, Instant)>,
+}
+
+impl DuplicateFilter {
+    /// Creates a filter treating identical frames within `window` as duplicates.
+    /// `None` disables the filter.
+    pub fn new(window: Option<Duration>) -> DuplicateFilter {
+        DuplicateFilter { window, last: None }
+    }
+
+    pub fn window(&self) -> Option<Duration> {
+        self.window
+    }
+
+    /// Returns true if `data`, received at `now`, repeats the previously delivered frame
+    /// within the window. Otherwise `data` becomes the frame later copies are compared to.
+    pub fn is_duplicate(&mut self, data: &[u8], now: Instant) -> bool {
+        let Some(window) = self.window else { return false };
+
+        if let Some((last_data, last_time)) = &self.last {
+            if last_data.as_slice() == data && now.saturating_duration_since(*last_time) <= window {
+                return true;
+            }
+        }
+        self.last = Some((XcpFrameBuf::from_prefix(data), now));
+        false
+    }
+
+    /// Whether a response was delivered that later frames may repeat.
+    pub fn is_armed(&self) -> bool {
+        self.window.is_some() && self.last.is_some()
+    }
+
+    /// Forgets the last delivered response, e.g. once the next command is sent and an
+    /// identical frame can be a genuine answer to it.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
@@ -0,0 +1,242 @@
+//! Dumping slave memory to files for offline analysis.
+
+use std::io::{self, Write};
+use crate::xcp::error::XcpError;
+use crate::xcp::frame::XcpErrorCode;
+use crate::xcp::master::XcpMaster;
+
+/// Number of data bytes per Intel HEX data record.
+pub const IHEX_RECORD_LEN: usize = 16;
+
+/// Streams data to a writer as Intel HEX records.
+///
+/// Extended linear address records are emitted whenever the upper 16 bits of the
+/// address change, so data anywhere in the 32-bit address space can be written.
+/// `finish` must be called to terminate the file with an EOF record.
+pub struct IhexWriter<W: Write> {
+    writer: W,
+    upper: Option<u16>,
+}
+
+impl<W: Write> IhexWriter<W> {
+    pub fn new(writer: W) -> IhexWriter<W> {
+        IhexWriter { writer, upper: None }
+    }
+
+    /// Writes `data` located at `address`.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> io::Result<()> {
+        let mut address = address;
+        let mut data = data;
+
+        while !data.is_empty() {
+            let upper = (address >> 16) as u16;
+            if self.upper != Some(upper) {
+                self.record(0x0000, 0x04, &upper.to_be_bytes())?;
+                self.upper = Some(upper);
+            }
+
+            // records must not cross a 64 KiB boundary
+            let lower = (address & 0xFFFF) as usize;
+            let len = usize::min(usize::min(IHEX_RECORD_LEN, 0x1_0000 - lower), data.len());
+            self.record(lower as u16, 0x00, &data[..len])?;
+
+            address = address.wrapping_add(len as u32);
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Writes the EOF record and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.record(0x0000, 0x01, &[])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn record(&mut self, offset: u16, record_type: u8, data: &[u8]) -> io::Result<()> {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&offset.to_be_bytes());
+        bytes.push(record_type);
+        bytes.extend_from_slice(data);
+        let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+
+        write!(self.writer, ":")?;
+        for b in bytes {
+            write!(self.writer, "{:02X}", b)?;
+        }
+        writeln!(self.writer, "{:02X}", checksum)
+    }
+}
+
+/// A named memory region of a `MemoryMap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryRegion {
+    pub name: String,
+    pub address: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub address_extension: u8,
+    /// Length in bytes.
+    pub length: usize,
+}
+
+/// The regions `XcpMaster::dump_map` reads, and how.
+///
+/// With the `serde` feature, a map can be shared as e.g. JSON:
+/// `{"retries": 3, "regions": [{"name": "cal", "address": 2147483648, "length": 4096}]}`.
+/// Omitted settings take their default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MemoryMap {
+    pub regions: Vec<MemoryRegion>,
+    /// Bytes read and verified at once; rounded down to whole elements.
+    pub block_size: usize,
+    /// How often a block that could not be read or verified is read again.
+    pub retries: u32,
+}
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        MemoryMap { regions: Vec::new(), block_size: 0x1000, retries: 2 }
+    }
+}
+
+/// How dumping a region of a `MemoryMap` ended.
+#[derive(Debug)]
+pub enum RegionDumpStatus {
+    /// Every block matched the slave's BUILD_CHECKSUM at the first attempt.
+    Verified,
+    /// Every block matched, after `retries` reads in total had to be repeated.
+    Retried { retries: u32 },
+    /// The data was read, but the slave cannot build a checksum the master can
+    /// compute, so it is not verified.
+    Unverified,
+    /// The block at `offset` still failed after all retries; the data ends there.
+    Failed { offset: usize, error: XcpError },
+}
+
+/// Data and status of one region, see `DumpResult`.
+#[derive(Debug)]
+pub struct RegionDump {
+    pub name: String,
+    pub address: u32,
+    pub address_extension: u8,
+    pub data: Vec<u8>,
+    pub status: RegionDumpStatus,
+}
+
+/// Result of `XcpMaster::dump_map`, with the regions in the order of the map.
+#[derive(Debug, Default)]
+pub struct DumpResult {
+    pub regions: Vec<RegionDump>,
+}
+
+impl DumpResult {
+    /// Whether every region was read completely and verified.
+    pub fn is_verified(&self) -> bool {
+        self.regions.iter().all(|region| matches!(region.status, RegionDumpStatus::Verified | RegionDumpStatus::Retried { .. }))
+    }
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Reads every region of `map`, block by block, and verifies each block against
+    /// a BUILD_CHECKSUM of the slave.
+    ///
+    /// A block whose upload fails or whose checksum differs is read again, up to
+    /// `map.retries` times. Blocks larger than the slave's maximum checksum block size
+    /// are made smaller for the rest of the region. A failing region does not stop
+    /// the following ones.
+    pub fn dump_map(&mut self, map: &MemoryMap) -> DumpResult {
+        let mut result = DumpResult::default();
+        for region in &map.regions {
+            let (data, status) = self.dump_region(region, map);
+            result.regions.push(RegionDump {
+                name: region.name.clone(),
+                address: region.address,
+                address_extension: region.address_extension,
+                data,
+                status,
+            });
+        }
+        result
+    }
+
+    fn dump_region(&mut self, region: &MemoryRegion, map: &MemoryMap) -> (Vec<u8>, RegionDumpStatus) {
+        let ag = self.comm_mode_basic.granularity().size();
+        let mut block_size = usize::max(map.block_size / ag, 1) * ag;
+        let mut data = Vec::with_capacity(region.length);
+        let mut verify = true;
+        let mut retries = 0;
+        let mut attempt = 0;
+
+        while data.len() < region.length {
+            let offset = data.len();
+            let address = region.address.wrapping_add((offset / ag) as u32);
+            let length = usize::min(block_size, region.length - offset);
+
+            let mut outcome = self.read_memory(address, region.address_extension, length);
+            if let (true, Ok(block)) = (verify, &outcome) {
+                // a trailing partial element cannot be included in the checksum
+                let whole = &block[..block.len() / ag * ag];
+                match self.verify_block(address, region.address_extension, whole) {
+                    Ok(()) => {}
+                    Err(XcpError::BlockSizeOutOfRange { max, .. }) if max > 0 && (max as usize) * ag < length => {
+                        block_size = max as usize * ag;
+                        continue;
+                    }
+                    Err(XcpError::UnsupportedChecksumType(_)) => verify = false,
+                    Err(e) if e.error_code() == Some(XcpErrorCode::ErrCmdUnknown) => verify = false,
+                    Err(e) => outcome = Err(e),
+                }
+            }
+
+            match outcome {
+                Ok(block) => {
+                    data.extend_from_slice(&block);
+                    attempt = 0;
+                }
+                Err(_) if attempt < map.retries => {
+                    attempt += 1;
+                    retries += 1;
+                }
+                Err(error) => return (data, RegionDumpStatus::Failed { offset, error }),
+            }
+        }
+
+        let status = match (verify, retries) {
+            (false, _) => RegionDumpStatus::Unverified,
+            (true, 0) => RegionDumpStatus::Verified,
+            (true, retries) => RegionDumpStatus::Retried { retries },
+        };
+        (data, status)
+    }
+
+    /// Reads the `(address, length)` regions and writes them to `writer` as Intel HEX.
+    ///
+    /// Every uploaded chunk is written out immediately, so large regions are not
+    /// buffered. Regions are read with address extension 0, and HEX addresses are the
+    /// region address plus the byte offset.
+    ///
+    /// # Returns
+    /// `XcpError::DumpIncomplete` with the first address that could not be read if a
+    /// command fails; everything before it has already been written.
+    pub fn dump_to_ihex<W: Write>(&mut self, regions: &[(u32, usize)], writer: W) -> Result<W, XcpError> {
+        let mut ihex = IhexWriter::new(writer);
+
+        for &(address, length) in regions {
+            let mut offset = 0;
+            let failed = |offset: usize, e: XcpError| {
+                XcpError::DumpIncomplete { address: address.wrapping_add(offset as u32), source: Box::new(e) }
+            };
+
+            for chunk in self.upload_stream(address, 0, length).map_err(|e| failed(offset, e))? {
+                let chunk = chunk.map_err(|e| failed(offset, e))?;
+                ihex.write(address.wrapping_add(offset as u32), &chunk)?;
+                offset += chunk.len();
+            }
+        }
+
+        Ok(ihex.finish()?)
+    }
+}
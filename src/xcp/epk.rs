@@ -0,0 +1,49 @@
+//! The EPK, the identifier of the software and calibration data version an ECU
+//! carries in memory, checked before writing to memory that belongs to a version.
+
+use crate::xcp::error::XcpError;
+use crate::xcp::master::XcpMaster;
+
+/// Where the EPK is located and the value it must have, usually ADDR_EPK and EPK
+/// from the MOD_PAR of the A2L file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedEpk {
+    pub address: u32,
+    pub address_extension: u8,
+    pub expected: String,
+}
+
+/// The EPK in `raw` without the trailing NUL or 0xFF padding.
+pub fn epk_string(raw: &[u8]) -> String {
+    let length = raw.iter().rposition(|&byte| byte != 0x00 && byte != 0xFF).map_or(0, |last| last + 1);
+    String::from_utf8_lossy(&raw[..length]).into_owned()
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Uploads the `length` bytes long EPK at `address`, see `epk_string`.
+    pub fn read_epk(&mut self, address: u32, address_extension: u8, length: usize) -> Result<String, XcpError> {
+        let raw = self.read_memory(address, address_extension, length)?;
+        Ok(epk_string(&raw))
+    }
+
+    /// Checks that the EPK at `address` is `expected`, reading as many bytes as
+    /// `expected` is long.
+    ///
+    /// # Returns
+    /// `XcpError::EpkMismatch` with both identifiers if the EPK differs.
+    pub fn verify_epk(&mut self, address: u32, address_extension: u8, expected: &str) -> Result<(), XcpError> {
+        let actual = self.read_epk(address, address_extension, expected.len())?;
+        if actual != epk_string(expected.as_bytes()) {
+            return Err(XcpError::EpkMismatch { expected: expected.to_string(), actual });
+        }
+        Ok(())
+    }
+
+    /// Runs `verify_epk` for `epk`, if any.
+    pub(crate) fn check_expected_epk(&mut self, epk: Option<&ExpectedEpk>) -> Result<(), XcpError> {
+        match epk {
+            Some(epk) => self.verify_epk(epk.address, epk.address_extension, &epk.expected),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Error type returned by `XcpMaster` operations.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::xcp::frame::{XcpCommandCode, XcpErrorCode, XcpResponseFrame, XcpVersion, XCP_MAX_FRAME_LEN};
+use crate::xcp::can_ids::CanIdError;
+use crate::xcp::daq::DaqDirection;
+use crate::xcp::flash::FlashReport;
+use crate::xcp::master::{DaqAllocPhase, DaqPtr, TimeoutClass};
+use crate::xcp::xcp_command::{CalPageAccessFlags, ChecksumType, NegativeResponse};
+
+#[derive(Debug)]
+pub enum XcpError {
+    /// The slave answered with a negative response (ERR packet).
+    NegativeResponse(XcpResponseFrame<NegativeResponse>),
+
+    /// The command was not sent because the slave's protocol version predates it.
+    UnsupportedByProtocolVersion {
+        command: XcpCommandCode,
+        required: XcpVersion,
+        negotiated: XcpVersion,
+    },
+
+    /// The slave answered with a positive response where the protocol calls for
+    /// a different one.
+    UnexpectedResponse { command: XcpCommandCode },
+
+    /// The slave did not complete `command` within `timeout`, usually the timeout
+    /// of its `class`, and the master gave up after `elapsed`.
+    Timeout { command: XcpCommandCode, class: TimeoutClass, timeout: Duration, elapsed: Duration },
+
+    /// The command would transfer more data than fits into a single CTO.
+    PayloadTooLarge { command: XcpCommandCode, requested: usize, max: usize },
+
+    /// A frame of `requested` bytes was built, more than `XCP_MAX_FRAME_LEN`.
+    FrameOverflow { requested: usize },
+
+    /// BUILD_CHECKSUM was refused because the block exceeds what the slave supports.
+    BlockSizeOutOfRange { requested: u32, max: u32 },
+
+    /// The checksum the slave built differs from the one computed locally.
+    ChecksumMismatch { checksum_type: ChecksumType, expected: u32, actual: u32 },
+
+    /// The slave uses a checksum algorithm that cannot be computed locally, see
+    /// `XcpMaster::register_checksum_algorithm`.
+    UnsupportedChecksumType(ChecksumType),
+
+    /// `XcpMaster::register_builtin_checksum` was given a name that is not a
+    /// built-in checksum algorithm.
+    UnknownChecksumAlgorithm(String),
+
+    /// The response to `command` has `length` bytes, fewer than the `expected` its
+    /// layout needs.
+    ResponseTooShort { command: XcpCommandCode, length: usize, expected: usize },
+
+    /// The command requires exactly `expected` bytes of data.
+    InvalidPayloadLength { command: XcpCommandCode, length: usize, expected: usize },
+
+    /// The data length is not a whole number of elements of the address granularity.
+    UnalignedLength { length: usize, granularity: usize },
+
+    /// The slave lost track of a block transfer (ERR_SEQUENCE). It expects
+    /// `expected_elements` more elements, which start at byte `offset` of the data.
+    DownloadSequence { expected_elements: u8, offset: usize },
+
+    /// The memory at `address` is write protected (ERR_WRITE_PROTECTED); unlocking
+    /// does not help.
+    WriteProtected { address: u32 },
+
+    /// The memory at `address` is protected by seed & key (ERR_ACCESS_LOCKED); unlock
+    /// CAL/PAG and retry.
+    AccessLocked { address: u32 },
+
+    /// Programming is protected by seed & key (ERR_ACCESS_LOCKED); unlock PGM and retry.
+    PgmLocked,
+
+    /// Memory read back after a write differs from what was written, first at `offset`.
+    VerificationFailed { offset: usize, expected: u8, actual: u8 },
+
+    /// The page does not exist in the segment (ERR_PAGE_NOT_VALID).
+    PageNotValid { segment: u8, page: u8 },
+
+    /// The segment does not exist (ERR_SEGMENT_NOT_VALID).
+    SegmentNotValid { segment: u8 },
+
+    /// The page cannot be used with the requested access mode (ERR_MODE_NOT_VALID).
+    ModeNotValid { mode: CalPageAccessFlags },
+
+    /// After switching to `page`, these segments still have a different page active.
+    PageSwitchFailed { page: u8, segments: Vec<u8> },
+
+    /// The command was not sent because `feature` needs a newer protocol version
+    /// than the slave reported (`None` if not connected).
+    FeatureUnsupported { feature: &'static str, required: XcpVersion, negotiated: Option<XcpVersion> },
+
+    /// The DAQ list does not exist (ERR_OUT_OF_RANGE).
+    DaqListNotValid { daq_list: u16 },
+
+    /// An ODT entry must have a non-zero size and a bit offset of at most 31.
+    InvalidDaqEntry { bit_offset: Option<u8>, size: u8 },
+
+    /// The slave rejected the entry at `ptr` as not fitting its DAQ configuration
+    /// (ERR_DAQ_CONFIG), e.g. an unsupported element size.
+    DaqConfigInvalid { ptr: Option<DaqPtr> },
+
+    /// The entry at `ptr` does not exist (ERR_OUT_OF_RANGE), usually because all
+    /// allocated ODT entries are used up.
+    DaqEntryOutOfRange { ptr: Option<DaqPtr> },
+
+    /// `command` was not sent because dynamic DAQ allocation must follow the order
+    /// FREE_DAQ, ALLOC_DAQ, ALLOC_ODT, ALLOC_ODT_ENTRY, and the master is in `phase`.
+    DaqAllocationSequence { command: XcpCommandCode, phase: DaqAllocPhase },
+
+    /// A DAQ list the master cannot allocate has fewer ODTs (`odt` is `None`), or
+    /// an ODT fewer entries, than requested.
+    DaqCapacityExceeded { daq_list: u16, odt: Option<u8>, requested: usize, limit: u8 },
+
+    /// An entry of a DAQ list is larger than the payload of a DTO.
+    DaqEntryTooLarge { daq_list: u16, entry: usize, size: usize, max: usize },
+
+    /// The entry of a STIM list does not fit the STIM resolution of the slave: its
+    /// `size` must be a multiple of `granularity` and at most `max`.
+    DaqEntrySizeNotSupported { daq_list: u16, entry: usize, size: u8, granularity: u8, max: u8 },
+
+    /// `daq_list` requires overload indication, but DAQ_PROPERTIES advertises none,
+    /// or both OVERLOAD_MSB and OVERLOAD_EVENT.
+    OverloadIndicationUnsupported { daq_list: u16 },
+
+    /// Lists in `direction` cannot be bound to `event_channel` (GET_DAQ_EVENT_INFO).
+    EventDirectionUnsupported { event_channel: u16, direction: DaqDirection },
+
+    /// The DAQ lists to configure would take `load` of the bus, more than the
+    /// configured `max_load`.
+    BusLoadExceeded { load: f64, max_load: f64 },
+
+    /// `odt` is not part of the configuration of `daq_list`.
+    OdtNotValid { daq_list: u16, odt: u8 },
+
+    /// A DTO of `odt` of `daq_list` carries `length` payload bytes, but the ODT is
+    /// configured for `expected`.
+    DtoLengthMismatch { daq_list: u16, odt: u8, length: usize, expected: usize },
+
+    /// Configuring `odt` (or, if `None`, the whole list) of `daq_list` failed.
+    DaqListFailed { daq_list: u16, odt: Option<u8>, source: Box<XcpError> },
+
+    /// The slave is neither in RESUME mode nor running DAQ lists.
+    ResumeNotActive,
+
+    /// The slave runs the stored DAQ configuration `actual` (`None` if it does not
+    /// report one), not `expected`.
+    SessionConfigurationMismatch { expected: u16, actual: Option<u16> },
+
+    /// The DAQ list was expected to be running but is not.
+    DaqListNotRunning { daq_list: u16 },
+
+    /// The paging processor of the slave does not support FREEZE mode.
+    FreezeNotSupported,
+
+    /// Setting FREEZE mode failed for `segment`; the segments in `frozen` are
+    /// already in FREEZE mode.
+    FreezeFailed { segment: u8, frozen: Vec<u8>, source: Box<XcpError> },
+
+    /// A calibration snapshot was taken from a slave with a different identification.
+    SnapshotMismatch { recorded: String, actual: String },
+
+    /// The EPK in the slave's memory is not the one expected.
+    EpkMismatch { expected: String, actual: String },
+
+    /// A flash session cannot be resumed because the image file at `path` changed
+    /// since the checkpoint was written.
+    ResumeImageChanged { path: PathBuf },
+
+    /// A flash session cannot be resumed on a slave with a different identification.
+    ResumeSlaveMismatch { recorded: String, actual: String },
+
+    /// A multi-command transfer failed after `offset` bytes; `data` holds what was
+    /// transferred up to then.
+    PartialTransfer { offset: usize, data: Vec<u8>, source: Box<XcpError> },
+
+    /// Flashing failed with `source`; `report` tells how far it got, and `cleanup`
+    /// whether the PGM sequence was ended with PROGRAM_RESET.
+    FlashFailed { report: Box<FlashReport>, source: Box<XcpError>, cleanup: Cleanup },
+
+    /// Configuring DAQ or STIM lists failed with `source` after the slave's DAQ
+    /// configuration was changed; `cleanup` tells whether it was undone.
+    DaqConfigFailed { source: Box<XcpError>, cleanup: Cleanup },
+
+    /// A memory dump stopped at `address` because reading it failed.
+    DumpIncomplete { address: u32, source: Box<XcpError> },
+
+    /// Transmitting or receiving on the socket failed.
+    Io(io::Error),
+
+    /// A raw CAN ID passed in is not valid.
+    InvalidCanId(CanIdError),
+
+    /// A raw flag value has bits set that the specification reserves.
+    ReservedBitsSet { value: u8, reserved: u8 },
+}
+
+impl XcpError {
+    /// The XCP error code, if the slave answered with a negative response.
+    pub fn error_code(&self) -> Option<XcpErrorCode> {
+        match self {
+            XcpError::NegativeResponse(resp) => Some(resp.data.error_code),
+            XcpError::WriteProtected { .. } => Some(XcpErrorCode::ErrWriteProtected),
+            XcpError::AccessLocked { .. } => Some(XcpErrorCode::ErrAccessLocked),
+            XcpError::PgmLocked => Some(XcpErrorCode::ErrAccessLocked),
+            XcpError::PageNotValid { .. } => Some(XcpErrorCode::ErrPageNotValid),
+            XcpError::SegmentNotValid { .. } => Some(XcpErrorCode::ErrSegmentNotValid),
+            XcpError::ModeNotValid { .. } => Some(XcpErrorCode::ErrModeNotValid),
+            XcpError::DaqListNotValid { .. } => Some(XcpErrorCode::ErrOutOfRange),
+            XcpError::DaqConfigInvalid { .. } => Some(XcpErrorCode::ErrDaqConfig),
+            XcpError::DaqEntryOutOfRange { .. } => Some(XcpErrorCode::ErrOutOfRange),
+            XcpError::PartialTransfer { source, .. }
+            | XcpError::DumpIncomplete { source, .. }
+            | XcpError::FlashFailed { source, .. }
+            | XcpError::DaqConfigFailed { source, .. }
+            | XcpError::FreezeFailed { source, .. }
+            | XcpError::DaqListFailed { source, .. } => source.error_code(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for XcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XcpError::NegativeResponse(resp) => {
+                write!(f, "negative response: {:?}", resp.data.error_code)
+            }
+            XcpError::UnsupportedByProtocolVersion { command, required, negotiated } => {
+                write!(f, "{:?} requires protocol version {}, slave implements {}", command, required, negotiated)
+            }
+            XcpError::UnexpectedResponse { command } => {
+                write!(f, "unexpected positive response to {:?}", command)
+            }
+            XcpError::Timeout { command, class, timeout, elapsed } => {
+                write!(f, "{:?} did not complete after {:?} ({:?} timeout of {:?})", command, elapsed, class, timeout)
+            }
+            XcpError::PayloadTooLarge { command, requested, max } => {
+                write!(f, "{:?} of {} bytes exceeds the {} bytes a CTO can carry", command, requested, max)
+            }
+            XcpError::FrameOverflow { requested } => {
+                write!(f, "frame of {} bytes exceeds the {} bytes of an XCP frame", requested, XCP_MAX_FRAME_LEN)
+            }
+            XcpError::BlockSizeOutOfRange { requested, max } => {
+                write!(f, "checksum block size {:#x} exceeds the maximum of {:#x}", requested, max)
+            }
+            XcpError::ChecksumMismatch { checksum_type, expected, actual } => {
+                write!(f, "{:?} checksum mismatch: expected {:#x}, slave built {:#x}", checksum_type, expected, actual)
+            }
+            XcpError::UnsupportedChecksumType(ChecksumType::UserDefined) => {
+                write!(f, "slave uses user-defined checksum; register an implementation with register_checksum_algorithm")
+            }
+            XcpError::UnsupportedChecksumType(checksum_type) => {
+                write!(f, "checksum type {:?} cannot be computed locally", checksum_type)
+            }
+            XcpError::UnknownChecksumAlgorithm(name) => write!(f, "unknown checksum algorithm {:?}", name),
+            XcpError::ResponseTooShort { command, length, expected } => {
+                write!(f, "response to {:?} has {} bytes, at least {} expected", command, length, expected)
+            }
+            XcpError::InvalidPayloadLength { command, length, expected } => {
+                write!(f, "{:?} requires {} bytes of data, got {}", command, expected, length)
+            }
+            XcpError::UnalignedLength { length, granularity } => {
+                write!(f, "{} bytes is not a multiple of the {} byte address granularity", length, granularity)
+            }
+            XcpError::DownloadSequence { expected_elements, offset } => {
+                write!(f, "block transfer out of sequence, slave expects {} elements from offset {}", expected_elements, offset)
+            }
+            XcpError::WriteProtected { address } => write!(f, "memory at {:#010x} is write protected", address),
+            XcpError::PgmLocked => write!(f, "programming is locked, unlock PGM first"),
+            XcpError::AccessLocked { address } => write!(f, "memory at {:#010x} is locked, unlock CAL/PAG first", address),
+            XcpError::VerificationFailed { offset, expected, actual } => {
+                write!(f, "verification failed at offset {}: wrote {:#04x}, read back {:#04x}", offset, expected, actual)
+            }
+            XcpError::PageNotValid { segment, page } => write!(f, "segment {} has no page {}", segment, page),
+            XcpError::SegmentNotValid { segment } => write!(f, "segment {} does not exist", segment),
+            XcpError::ModeNotValid { mode } => write!(f, "access mode {} is not valid for this page", mode),
+            XcpError::PageSwitchFailed { page, segments } => {
+                write!(f, "segments {:?} did not switch to page {}", segments, page)
+            }
+            XcpError::FeatureUnsupported { feature, required, negotiated: Some(negotiated) } => {
+                write!(f, "{} requires protocol version {}, slave implements {}", feature, required, negotiated)
+            }
+            XcpError::FeatureUnsupported { feature, required, negotiated: None } => {
+                write!(f, "{} requires protocol version {}, slave version unknown", feature, required)
+            }
+            XcpError::DaqListNotValid { daq_list } => write!(f, "DAQ list {} does not exist", daq_list),
+            XcpError::InvalidDaqEntry { bit_offset, size } => {
+                write!(f, "invalid ODT entry: size {}, bit offset {:?}", size, bit_offset)
+            }
+            XcpError::DaqConfigInvalid { ptr } => write!(f, "ODT entry {:?} rejected by the DAQ configuration", ptr),
+            XcpError::DaqEntryOutOfRange { ptr } => write!(f, "ODT entry {:?} does not exist", ptr),
+            XcpError::DaqAllocationSequence { command, phase } => {
+                write!(f, "{:?} is not allowed in DAQ allocation phase {:?}; the order is \
+                           FREE_DAQ, ALLOC_DAQ, ALLOC_ODT, ALLOC_ODT_ENTRY", command, phase)
+            }
+            XcpError::DaqCapacityExceeded { daq_list, odt: None, requested, limit } => {
+                write!(f, "DAQ list {} has {} ODTs, {} requested", daq_list, limit, requested)
+            }
+            XcpError::DaqCapacityExceeded { daq_list, odt: Some(odt), requested, limit } => {
+                write!(f, "ODT {} of DAQ list {} has {} entries, {} requested", odt, daq_list, limit, requested)
+            }
+            XcpError::DaqEntryTooLarge { daq_list, entry, size, max } => {
+                write!(f, "entry {} of DAQ list {} has {} bytes, a DTO carries {}", entry, daq_list, size, max)
+            }
+            XcpError::OverloadIndicationUnsupported { daq_list } => {
+                write!(f, "DAQ list {} requires overload indication, which the slave does not support", daq_list)
+            }
+            XcpError::DaqEntrySizeNotSupported { daq_list, entry, size, granularity, max } => {
+                write!(f, "entry {} of DAQ list {} has size {}, STIM requires a multiple of {} up to {}",
+                       entry, daq_list, size, granularity, max)
+            }
+            XcpError::EventDirectionUnsupported { event_channel, direction } => {
+                let direction = match direction { DaqDirection::Daq => "DAQ", DaqDirection::Stim => "STIM" };
+                write!(f, "event channel {} does not support {} lists", event_channel, direction)
+            }
+            XcpError::OdtNotValid { daq_list, odt } => write!(f, "DAQ list {} has no ODT {}", daq_list, odt),
+            XcpError::DtoLengthMismatch { daq_list, odt, length, expected } => {
+                write!(f, "DTO of DAQ list {}, ODT {} has {} bytes, {} expected", daq_list, odt, length, expected)
+            }
+            XcpError::DaqListFailed { daq_list, odt: None, source } => write!(f, "DAQ list {}: {}", daq_list, source),
+            XcpError::DaqListFailed { daq_list, odt: Some(odt), source } => {
+                write!(f, "DAQ list {}, ODT {}: {}", daq_list, odt, source)
+            }
+            XcpError::ResumeNotActive => write!(f, "the slave is not in RESUME mode"),
+            XcpError::SessionConfigurationMismatch { expected, actual: Some(actual) } => {
+                write!(f, "slave runs session configuration {}, expected {}", actual, expected)
+            }
+            XcpError::SessionConfigurationMismatch { expected, actual: None } => {
+                write!(f, "slave reports no session configuration, expected {}", expected)
+            }
+            XcpError::BusLoadExceeded { load, max_load } => {
+                write!(f, "estimated DAQ bus load of {:.1}% exceeds the limit of {:.1}%", load * 100.0, max_load * 100.0)
+            }
+            XcpError::DaqListNotRunning { daq_list } => write!(f, "DAQ list {} is not running", daq_list),
+            XcpError::FreezeNotSupported => write!(f, "the slave does not support FREEZE mode"),
+            XcpError::FreezeFailed { segment, frozen, source } => {
+                write!(f, "freezing segment {} failed (segments {:?} frozen): {}", segment, frozen, source)
+            }
+            XcpError::SnapshotMismatch { recorded, actual } => {
+                write!(f, "snapshot was taken from \"{}\", slave identifies as \"{}\"", recorded, actual)
+            }
+            XcpError::EpkMismatch { expected, actual } => {
+                write!(f, "EPK mismatch: expected \"{}\", slave has \"{}\"", expected, actual)
+            }
+            XcpError::ResumeImageChanged { path } => {
+                write!(f, "{} changed since the flash checkpoint was written", path.display())
+            }
+            XcpError::ResumeSlaveMismatch { recorded, actual } => {
+                write!(f, "flash session was started on \"{}\", slave identifies as \"{}\"", recorded, actual)
+            }
+            XcpError::PartialTransfer { offset, source, .. } => {
+                write!(f, "transfer failed at offset {}: {}", offset, source)
+            }
+            XcpError::DumpIncomplete { address, source } => {
+                write!(f, "dump stopped at {:#010x}: {}", address, source)
+            }
+            XcpError::FlashFailed { report, source, cleanup } => {
+                write!(f, "flashing failed after {} bytes: {} ({})", report.programmed(), source, cleanup)
+            }
+            XcpError::DaqConfigFailed { source, cleanup } => {
+                write!(f, "DAQ configuration failed: {} ({})", source, cleanup)
+            }
+            XcpError::Io(e) => write!(f, "I/O error: {}", e),
+            XcpError::InvalidCanId(e) => write!(f, "invalid CAN ID: {}", e),
+            XcpError::ReservedBitsSet { value, reserved } => {
+                write!(f, "{:#04x} sets reserved bits {:#04x}", value, value & reserved)
+            }
+        }
+    }
+}
+
+impl std::error::Error for XcpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XcpError::PartialTransfer { source, .. }
+            | XcpError::DumpIncomplete { source, .. }
+            | XcpError::FlashFailed { source, .. }
+            | XcpError::DaqConfigFailed { source, .. }
+            | XcpError::FreezeFailed { source, .. }
+            | XcpError::DaqListFailed { source, .. } => Some(source.as_ref()),
+            XcpError::Io(e) => Some(e),
+            XcpError::InvalidCanId(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// How a helper that failed midway undid its changes to the slave, so that it can
+/// be called again, see `XcpError::FlashFailed` and `XcpError::DaqConfigFailed`.
+#[derive(Debug)]
+pub enum Cleanup {
+    /// Nothing had been changed yet.
+    NotNeeded,
+    /// `command` undid the changes.
+    Done { command: XcpCommandCode },
+    /// `command` failed with `error` as well; the slave may keep the state until
+    /// it is reset.
+    Failed { command: XcpCommandCode, error: Box<XcpError> },
+}
+
+impl Cleanup {
+    /// The outcome of undoing the changes with `command`.
+    pub(crate) fn of(command: XcpCommandCode, result: Result<(), XcpError>) -> Cleanup {
+        match result {
+            Ok(()) => Cleanup::Done { command },
+            Err(error) => Cleanup::Failed { command, error: Box::new(error) },
+        }
+    }
+
+    /// The slave is back in the state before the helper ran.
+    pub fn succeeded(&self) -> bool {
+        !matches!(self, Cleanup::Failed { .. })
+    }
+}
+
+impl fmt::Display for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cleanup::NotNeeded => write!(f, "nothing to undo"),
+            Cleanup::Done { command } => write!(f, "undone with {:?}", command),
+            Cleanup::Failed { command, error } => write!(f, "undoing with {:?} failed: {}", command, error),
+        }
+    }
+}
+
+impl From<io::Error> for XcpError {
+    fn from(e: io::Error) -> XcpError {
+        XcpError::Io(e)
+    }
+}
+
+impl From<CanIdError> for XcpError {
+    fn from(e: CanIdError) -> XcpError {
+        XcpError::InvalidCanId(e)
+    }
+}
+
+impl From<XcpResponseFrame<NegativeResponse>> for XcpError {
+    fn from(resp: XcpResponseFrame<NegativeResponse>) -> XcpError {
+        XcpError::NegativeResponse(resp)
+    }
+}
@@ -0,0 +1,1050 @@
+//! Flash programming: Intel HEX, Motorola S-record and ELF parsing and the PGM
+//! command sequence writing an image to the slave.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::xcp::checkpoint::{FlashCheckpoint, FlashImage};
+use crate::xcp::epk::ExpectedEpk;
+use crate::xcp::error::{Cleanup, XcpError};
+use crate::xcp::frame::{XcpCommandCode, XcpErrorCode};
+use crate::xcp::master::XcpMaster;
+use crate::xcp::session::{FlashPhase, SessionEvent};
+use crate::xcp::xcp_command::{ChecksumType, IdType, ProgramClearMode, SectorInfoMode, XcpPgmProperties};
+
+/// Contiguous data of an Intel HEX or S-record file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexRegion {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Parses an Intel HEX file into contiguous regions, sorted by address.
+///
+/// Data records are placed using extended segment (02) and extended linear (04)
+/// address records; start address records are ignored.
+///
+/// # Returns
+/// An `io::ErrorKind::InvalidData` error naming the line for malformed records,
+/// bad checksums, overlapping data or a missing end of file record.
+pub fn parse_ihex<R: BufRead>(reader: R) -> io::Result<Vec<HexRegion>> {
+    let mut records: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut base = 0u32;
+    let mut complete = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() { continue }
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, reason));
+        if complete {
+            return Err(invalid("data after the end of file record"));
+        }
+
+        let hex = line.strip_prefix(':').ok_or_else(|| invalid("record does not start with ':'"))?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(invalid("record too short"));
+        }
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("not a hexadecimal number"))?;
+
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(invalid("record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..4 + length];
+        match bytes[3] {
+            0x00 => records.push((base.wrapping_add(offset), data.to_vec())),
+            0x01 => complete = true,
+            0x02 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if length == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            0x03 | 0x05 => {}
+            _ => return Err(invalid("unsupported record type")),
+        }
+    }
+    if !complete {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing end of file record"));
+    }
+    merge_records(records)
+}
+
+/// Parses a Motorola S-record file (.s19, .s28, .s37, .srec) into contiguous
+/// regions, sorted by address.
+///
+/// Data records may use 16 (S1), 24 (S2) or 32 bit (S3) addresses. The header
+/// (S0) and termination records (S7 to S9) are ignored, record counts (S5, S6)
+/// are checked against the data records before them.
+///
+/// # Returns
+/// An `io::ErrorKind::InvalidData` error naming the line and record type for
+/// malformed records, bad checksums and wrong counts, and one for overlapping data.
+pub fn parse_srec<R: BufRead>(reader: R) -> io::Result<Vec<HexRegion>> {
+    let mut records: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() { continue }
+        let record_type = line.get(..2).unwrap_or(line);
+        let invalid = |reason: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {} ({}): {}", index + 1, record_type, reason))
+        };
+
+        let hex = line.strip_prefix('S').ok_or_else(|| invalid("record does not start with 'S'"))?;
+        if hex.len() < 1 + 2 || hex.len() % 2 != 1 {
+            return Err(invalid("record too short"));
+        }
+        let address_len = match &hex[..1] {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(invalid("unsupported record type")),
+        };
+        let bytes = (1..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("not a hexadecimal number"))?;
+
+        let count = bytes[0] as usize;
+        if bytes.len() != count + 1 {
+            return Err(invalid("record length does not match its byte count"));
+        }
+        if count < address_len + 1 {
+            return Err(invalid("record too short"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xFF {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let address = bytes[1..1 + address_len].iter().fold(0u32, |address, &b| address << 8 | b as u32);
+        let data = &bytes[1 + address_len..count];
+        match &hex[..1] {
+            "1" | "2" | "3" => records.push((address, data.to_vec())),
+            "5" | "6" if address as usize != records.len() => {
+                return Err(invalid(&format!("record count {} does not match the {} data records", address, records.len())));
+            }
+            _ => {}
+        }
+    }
+    merge_records(records)
+}
+
+/// Maps a range of virtual addresses to physical ones, see `ElfOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressMapping {
+    pub virtual_address: u32,
+    pub length: u32,
+    pub physical_address: u32,
+}
+
+/// Selects and places the segments of an ELF file, see `parse_elf`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElfOptions {
+    /// Physical address ranges to program; data outside them is dropped. Empty
+    /// (the default) keeps everything.
+    pub ranges: Vec<Range<u32>>,
+    /// Segments whose virtual address falls into one of these mappings are placed
+    /// by it instead of by their physical address.
+    pub mappings: Vec<AddressMapping>,
+}
+
+/// Program header type of loadable segments.
+const PT_LOAD: u32 = 1;
+
+/// Reads the PT_LOAD segments of an ELF file (32 or 64 bit, either byte order)
+/// into contiguous regions, sorted by address.
+///
+/// Segments are placed at their physical address unless `options` maps their
+/// virtual address, and clipped to `options.ranges`. Only the bytes stored in the
+/// file are programmed, not the zero-filled rest of a segment.
+///
+/// # Returns
+/// An `io::ErrorKind::InvalidData` error for files that are not ELF, truncated
+/// headers or segments, addresses beyond 32 bits and overlapping data.
+pub fn parse_elf<R: Read>(mut reader: R, options: &ElfOptions) -> io::Result<Vec<HexRegion>> {
+    let mut file = Vec::new();
+    reader.read_to_end(&mut file)?;
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let truncated = || invalid("truncated ELF file".to_string());
+
+    if !file.starts_with(b"\x7fELF") || file.len() < 0x34 {
+        return Err(invalid("not an ELF file".to_string()));
+    }
+    let wide = match file[4] {
+        1 => false,
+        2 => true,
+        class => return Err(invalid(format!("unknown ELF class {}", class))),
+    };
+    let big_endian = match file[5] {
+        1 => false,
+        2 => true,
+        encoding => return Err(invalid(format!("unknown ELF data encoding {}", encoding))),
+    };
+    let read = |offset: usize, size: usize| -> io::Result<u64> {
+        let bytes = file.get(offset..offset + size).ok_or_else(truncated)?;
+        let fold = |value: u64, &b: &u8| value << 8 | b as u64;
+        Ok(if big_endian { bytes.iter().fold(0, fold) } else { bytes.iter().rev().fold(0, fold) })
+    };
+    let word = if wide { 8 } else { 4 };
+
+    let (phoff, phentsize, phnum) = if wide {
+        (read(0x20, 8)?, read(0x36, 2)?, read(0x38, 2)?)
+    } else {
+        (read(0x1C, 4)?, read(0x2A, 2)?, read(0x2C, 2)?)
+    };
+
+    let mut records = Vec::new();
+    for index in 0..phnum as usize {
+        let header = phoff as usize + index * phentsize as usize;
+        if read(header, 4)? as u32 != PT_LOAD { continue }
+
+        // p_offset, p_vaddr, p_paddr and p_filesz follow p_type (and p_flags on 64 bit)
+        let fields = header + if wide { 8 } else { 4 };
+        let offset = read(fields, word)? as usize;
+        let virtual_address = read(fields + word, word)?;
+        let physical_address = read(fields + 2 * word, word)?;
+        let size = read(fields + 3 * word, word)? as usize;
+        if size == 0 { continue }
+
+        let data = file.get(offset..offset + size).ok_or_else(truncated)?;
+        let address = match options.mappings.iter().find(|mapping| {
+            (mapping.virtual_address as u64..mapping.virtual_address as u64 + mapping.length as u64).contains(&virtual_address)
+        }) {
+            Some(mapping) => virtual_address - mapping.virtual_address as u64 + mapping.physical_address as u64,
+            None => physical_address,
+        };
+        if address + size as u64 > 1 << 32 {
+            return Err(invalid(format!("segment at {:#x} lies beyond the 32 bit address space", address)));
+        }
+        let address = address as u32;
+
+        if options.ranges.is_empty() {
+            records.push((address, data.to_vec()));
+            continue;
+        }
+        for range in &options.ranges {
+            let start = u32::max(address, range.start);
+            let end = u64::min(address as u64 + size as u64, range.end as u64) as u32;
+            if start < end {
+                records.push((start, data[(start - address) as usize..(end - address) as usize].to_vec()));
+            }
+        }
+    }
+    merge_records(records)
+}
+
+/// Pads `regions` with `fill` to whole elements of `ag` bytes.
+pub(crate) fn pad_regions(regions: &mut [HexRegion], fill: u8, ag: usize) {
+    for region in regions {
+        let padded = region.data.len().div_ceil(ag) * ag;
+        region.data.resize(padded, fill);
+    }
+}
+
+/// Sorts data `records` by address and joins adjacent ones into regions.
+fn merge_records(mut records: Vec<(u32, Vec<u8>)>) -> io::Result<Vec<HexRegion>> {
+    records.sort_by_key(|(address, _)| *address);
+    let mut regions: Vec<HexRegion> = Vec::new();
+    for (address, data) in records {
+        if let Some(last) = regions.last_mut() {
+            let end = last.address as u64 + last.data.len() as u64;
+            if (address as u64) < end {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("overlapping data at {:#010x}", address)));
+            }
+            if address as u64 == end {
+                last.data.extend_from_slice(&data);
+                continue;
+            }
+        }
+        regions.push(HexRegion { address, data });
+    }
+    Ok(regions)
+}
+
+/// Encoding of the data stream sent with PROGRAM and PROGRAM_NEXT, e.g. the
+/// compression or encryption a bootloader expects, see `XcpMaster::set_program_codec`.
+///
+/// `encode` is called once per programmed region with all of its data, before the
+/// data is split into commands, so the encoding does not depend on the transfer
+/// sizes. The methods announced with PROGRAM_FORMAT default to 0, the unencoded
+/// format.
+pub trait ProgramCodec {
+    fn encode(&mut self, data: &[u8]) -> Vec<u8>;
+
+    fn compression_method(&self) -> u8 { 0 }
+
+    fn encryption_method(&self) -> u8 { 0 }
+
+    fn programming_method(&self) -> u8 { 0 }
+
+    fn access_method(&self) -> u8 { 0 }
+
+    /// Whether the codec uses a format other than the default, which then has to be
+    /// announced with PROGRAM_FORMAT.
+    fn needs_format(&self) -> bool {
+        self.compression_method() != 0 || self.encryption_method() != 0
+            || self.programming_method() != 0 || self.access_method() != 0
+    }
+}
+
+/// Codec sending data unchanged, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityCodec;
+
+impl ProgramCodec for IdentityCodec {
+    fn encode(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+}
+
+/// Settings of `XcpMaster::flash_ihex`, `flash_srec` and `flash_elf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashOptions {
+    /// Address extension of all regions.
+    pub address_extension: u8,
+    /// Byte appended to regions that do not end on an element boundary.
+    pub fill: u8,
+    /// Compare a BUILD_CHECKSUM of every region with the file data after programming.
+    pub verify: bool,
+    /// Send PROGRAM_RESET after programming.
+    pub reset: bool,
+    /// Send PROGRAM_RESET even if a region failed verification.
+    pub reset_on_mismatch: bool,
+    /// If a command fails with ERR_DAQ_ACTIVE, stop all DAQ lists and retry it once.
+    pub stop_daq: bool,
+    /// If PROGRAM_START fails with ERR_PGM_ACTIVE, end the programming sequence left
+    /// open with PROGRAM_RESET and retry it once.
+    pub reset_active_pgm: bool,
+    /// Check the EPK before programming anything.
+    pub epk: Option<ExpectedEpk>,
+    /// Record the progress in a state file at this path, from which
+    /// `XcpMaster::resume_flash` continues if the session is interrupted. Only the
+    /// functions reading the image from a file support it; the state file is
+    /// removed once the image is programmed and verified.
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for FlashOptions {
+    fn default() -> FlashOptions {
+        FlashOptions {
+            address_extension: 0,
+            fill: 0xFF,
+            verify: true,
+            reset: true,
+            reset_on_mismatch: false,
+            stop_daq: false,
+            reset_active_pgm: false,
+            epk: None,
+            checkpoint: None,
+        }
+    }
+}
+
+/// Outcome of verifying a programmed region, see `FlashOptions::verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionVerification {
+    /// Verification was disabled.
+    NotVerified,
+    Verified,
+    /// The checksum of the block starting at byte `offset` of the region differs.
+    Mismatch { offset: usize, checksum_type: ChecksumType, expected: u32, actual: u32 },
+    /// The slave builds a checksum that cannot be computed locally.
+    Unsupported(ChecksumType),
+}
+
+/// A region written by `XcpMaster::flash_ihex` and the like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlashedRegion {
+    pub address: u32,
+    /// Bytes programmed, including fill bytes. Short of the region only in the
+    /// report of a failed run, see `XcpError::FlashFailed`.
+    pub length: usize,
+    pub verification: RegionVerification,
+    /// Time spent clearing and programming the region.
+    pub duration: Duration,
+    /// Time spent verifying the region.
+    pub verify_duration: Duration,
+    /// Commands of the region retried after a recovery, see `FlashReport::recoveries`.
+    pub retries: usize,
+}
+
+/// A BUILD_CHECKSUM compared with the checksum of the image data, see `FlashedSector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockChecksum {
+    pub address: u32,
+    /// Bytes covered.
+    pub length: usize,
+    pub checksum_type: ChecksumType,
+    /// Checksum of the image data.
+    pub expected: u32,
+    /// Checksum the slave built.
+    pub actual: u32,
+}
+
+/// A flash sector written by `XcpMaster::flash_ihex` and the like, from the sector
+/// map the slave reports with GET_SECTOR_INFO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlashedSector {
+    /// `None` for the bytes of a region outside all sectors, e.g. if the slave
+    /// reports no sector map; they get an entry per region.
+    pub sector: Option<u8>,
+    /// Address of the first byte written to the sector.
+    pub address: u32,
+    /// Bytes programmed into the sector, including fill bytes.
+    pub length: usize,
+    /// Like `FlashedRegion::verification`, with the offset of a mismatch counted
+    /// from `address`.
+    pub verification: RegionVerification,
+    /// The checksums compared, one per block BUILD_CHECKSUM accepts, up to the
+    /// first mismatch. Empty if verification was disabled.
+    pub checksums: Vec<BlockChecksum>,
+    /// Time spent programming the sector. Data a codec encodes as a whole is timed
+    /// as a whole, and the time split between its sectors by their length.
+    pub duration: Duration,
+    /// Time spent verifying the sector.
+    pub verify_duration: Duration,
+}
+
+/// A recovery `XcpMaster::flash_ihex` and the like performed on their own, see
+/// `FlashOptions::stop_daq` and `FlashOptions::reset_active_pgm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FlashRecovery {
+    /// `command` failed with ERR_DAQ_ACTIVE and was retried after stopping all DAQ lists.
+    DaqStopped { command: XcpCommandCode },
+    /// PROGRAM_START failed with ERR_PGM_ACTIVE and was retried after PROGRAM_RESET.
+    ProgramReset,
+    /// When resuming, the bytes recorded as programmed in the region at `address`
+    /// did not verify, so it was cleared and programmed from the start.
+    RegionRestarted { address: u32 },
+}
+
+/// How long to wait for GET_STATUS to report DAQ stopped after STOP_ALL.
+const DAQ_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bytes programmed between two checkpoints, see `FlashOptions::checkpoint`; a
+/// multiple of every address granularity.
+const CHECKPOINT_BLOCK: usize = 0x4000;
+
+/// Result of `XcpMaster::flash_ihex` and the like.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlashReport {
+    /// ASCII identification of the slave from GET_ID, `None` if it refused GET_ID.
+    pub identity: Option<String>,
+    /// Regions started, in order.
+    pub regions: Vec<FlashedRegion>,
+    /// Sectors touched, in the order they were first written to.
+    pub sectors: Vec<FlashedSector>,
+    /// Recoveries performed, in order.
+    pub recoveries: Vec<FlashRecovery>,
+    /// PROGRAM_RESET was sent.
+    pub reset: bool,
+    /// Time from PROGRAM_START, or the EPK check, until the end of the run.
+    pub duration: Duration,
+}
+
+impl FlashReport {
+    /// No region failed verification. Also true if verification was disabled.
+    pub fn is_verified(&self) -> bool {
+        !self.regions.iter().any(|region| matches!(region.verification, RegionVerification::Mismatch { .. }))
+    }
+
+    /// Bytes programmed over all regions.
+    pub fn programmed(&self) -> usize {
+        self.regions.iter().map(|region| region.length).sum()
+    }
+}
+
+/// One line per sector with the checksums compared, then the totals.
+impl fmt::Display for FlashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "slave: {}", self.identity.as_deref().unwrap_or("unidentified"))?;
+        for sector in &self.sectors {
+            match sector.sector {
+                Some(number) => write!(f, "sector {:>3}", number)?,
+                None => write!(f, "no sector ")?,
+            }
+            write!(f, " at 0x{:08X}: {} bytes in {:.1?}, ", sector.address, sector.length, sector.duration)?;
+            match sector.verification {
+                RegionVerification::NotVerified => write!(f, "not verified")?,
+                RegionVerification::Verified => write!(f, "verified")?,
+                RegionVerification::Mismatch { offset, .. } => write!(f, "MISMATCH at +0x{:X}", offset)?,
+                RegionVerification::Unsupported(checksum_type) => write!(f, "{:?} not supported", checksum_type)?,
+            }
+            for checksum in &sector.checksums {
+                write!(f, ", {:?} 0x{:08X} expected 0x{:08X} read", checksum.checksum_type, checksum.expected, checksum.actual)?;
+            }
+            writeln!(f)?;
+        }
+        for recovery in &self.recoveries {
+            writeln!(f, "recovered: {:?}", recovery)?;
+        }
+        write!(f, "{} bytes programmed in {:.1?}", self.programmed(), self.duration)?;
+        if self.reset {
+            write!(f, ", reset")?;
+        }
+        Ok(())
+    }
+}
+
+/// A sector of the slave's flash, from GET_SECTOR_INFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlashSector {
+    pub sector: u8,
+    pub address: u32,
+    pub length: u32,
+    pub clear_sequence_number: u8,
+    pub program_sequence_number: u8,
+    pub programming_method: u8,
+}
+
+/// A problem `XcpMaster::validate_flash_image` found with an image region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashViolation {
+    /// `length` elements starting at `address` lie in no sector.
+    OutsideSectors { address: u32, length: u32 },
+    /// The region covers `sector` only partly, so clearing it erases memory the
+    /// image does not program again.
+    PartialSector { sector: u8 },
+    /// The region is not a whole number of elements and would be padded.
+    UnalignedLength { length: usize, granularity: usize },
+}
+
+/// The sectors an image region touches and its violations, see `FlashValidation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionValidation {
+    pub address: u32,
+    /// Length in bytes.
+    pub length: usize,
+    pub sectors: Vec<u8>,
+    pub violations: Vec<FlashViolation>,
+}
+
+/// Result of `XcpMaster::validate_flash_image`.
+#[derive(Debug, Clone)]
+pub struct FlashValidation {
+    pub properties: XcpPgmProperties,
+    pub sectors: Vec<FlashSector>,
+    pub regions: Vec<RegionValidation>,
+}
+
+impl FlashValidation {
+    /// The slave supports absolute clearing, as the flash functions use it, and
+    /// no region has violations.
+    pub fn is_valid(&self) -> bool {
+        self.properties.absolute_mode() && self.regions.iter().all(|region| region.violations.is_empty())
+    }
+}
+
+/// Checks that `regions` consist of whole elements of `ag` bytes and lie in whole
+/// `sectors`. Addresses and sector bounds are in elements.
+pub fn validate_regions(regions: &[HexRegion], sectors: &[FlashSector], ag: usize) -> Vec<RegionValidation> {
+    let mut sorted = sectors.to_vec();
+    sorted.sort_by_key(|sector| sector.address);
+
+    regions.iter().map(|region| {
+        let start = region.address as u64;
+        let end = start + region.data.len().div_ceil(ag) as u64;
+        let mut validation = RegionValidation {
+            address: region.address,
+            length: region.data.len(),
+            sectors: Vec::new(),
+            violations: Vec::new(),
+        };
+        if region.data.len() % ag != 0 {
+            validation.violations.push(FlashViolation::UnalignedLength { length: region.data.len(), granularity: ag });
+        }
+
+        let mut covered = start;
+        for sector in &sorted {
+            let sector_start = sector.address as u64;
+            let sector_end = sector_start + sector.length as u64;
+            if sector_end <= start || sector_start >= end { continue }
+
+            validation.sectors.push(sector.sector);
+            if sector_start > covered {
+                validation.violations.push(FlashViolation::OutsideSectors {
+                    address: covered as u32,
+                    length: (sector_start - covered) as u32,
+                });
+            }
+            if sector_start < start || sector_end > end {
+                validation.violations.push(FlashViolation::PartialSector { sector: sector.sector });
+            }
+            covered = u64::max(covered, sector_end);
+        }
+        if covered < end {
+            validation.violations.push(FlashViolation::OutsideSectors { address: covered as u32, length: (end - covered) as u32 });
+        }
+        validation
+    }).collect()
+}
+
+/// Splits the data of `region` at the bounds of `sectors`, as byte ranges with the
+/// sector they lie in. Addresses and sector bounds are in elements of `ag` bytes.
+fn sector_ranges(region: &HexRegion, sectors: &[FlashSector], ag: usize) -> Vec<(Option<u8>, Range<usize>)> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < region.data.len() {
+        let address = region.address as u64 + (offset / ag) as u64;
+        let containing = sectors.iter()
+            .find(|sector| sector.address as u64 <= address && address < sector.address as u64 + sector.length as u64);
+        let (sector, end) = match containing {
+            Some(sector) => (Some(sector.sector), sector.address as u64 + sector.length as u64),
+            // up to the next sector
+            None => (None, sectors.iter().map(|sector| sector.address as u64).filter(|&start| start > address).min().unwrap_or(u64::MAX)),
+        };
+        let length = usize::min(region.data.len() - offset, ((end - address) as usize).saturating_mul(ag));
+        ranges.push((sector, offset..offset + length));
+        offset += length;
+    }
+    ranges
+}
+
+/// Adds the sectors `region` touches to `sectors`, the first `programmed` bytes
+/// as already written.
+///
+/// # Returns
+/// The byte ranges of the region with the index of their entry in `sectors`.
+fn track_sectors(region: &HexRegion, map: &[FlashSector], ag: usize, programmed: usize, sectors: &mut Vec<FlashedSector>)
+    -> Vec<(usize, Range<usize>)> {
+    sector_ranges(region, map, ag).into_iter().map(|(sector, range)| {
+        let written = usize::min(range.end, programmed).saturating_sub(range.start);
+        let index = match sectors.iter().position(|flashed| sector.is_some() && flashed.sector == sector) {
+            Some(index) => index,
+            None => {
+                sectors.push(FlashedSector {
+                    sector,
+                    address: region.address + (range.start / ag) as u32,
+                    length: 0,
+                    verification: RegionVerification::NotVerified,
+                    checksums: Vec::new(),
+                    duration: Duration::ZERO,
+                    verify_duration: Duration::ZERO,
+                });
+                sectors.len() - 1
+            }
+        };
+        sectors[index].length += written;
+        (index, range)
+    }).collect()
+}
+
+/// Adds the verification of a further part of a region or sector, starting at
+/// byte `offset` of it; the first failure is kept.
+fn merge_verification(verification: RegionVerification, part: RegionVerification, offset: usize) -> RegionVerification {
+    match (verification, part) {
+        (RegionVerification::Mismatch { .. } | RegionVerification::Unsupported(_), _) => verification,
+        (_, RegionVerification::Mismatch { offset: part_offset, checksum_type, expected, actual }) => {
+            RegionVerification::Mismatch { offset: offset + part_offset, checksum_type, expected, actual }
+        }
+        (_, part) => part,
+    }
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Reads the layout of the slave's flash with GET_PGM_PROCESSOR_INFO and
+    /// GET_SECTOR_INFO.
+    pub fn read_sector_map(&mut self) -> Result<Vec<FlashSector>, XcpError> {
+        let info = self.get_pgm_processor_info()?;
+        let mut sectors = Vec::with_capacity(info.max_sector as usize);
+        for sector in 0..info.max_sector {
+            let start = self.get_sector_info(SectorInfoMode::StartAddress, sector)?;
+            let length = self.get_sector_info(SectorInfoMode::Length, sector)?;
+            sectors.push(FlashSector {
+                sector,
+                address: start.sector_info,
+                length: length.sector_info,
+                clear_sequence_number: start.clear_sequence_number,
+                program_sequence_number: start.program_sequence_number,
+                programming_method: start.programming_method,
+            });
+        }
+        Ok(sectors)
+    }
+
+    /// Checks an image against the slave's sector map without programming anything:
+    /// only GET_PGM_PROCESSOR_INFO and GET_SECTOR_INFO are sent.
+    ///
+    /// # Returns
+    /// The sector map and, per region, the sectors it touches and its violations.
+    pub fn validate_flash_image(&mut self, regions: &[HexRegion]) -> Result<FlashValidation, XcpError> {
+        let sectors = self.read_sector_map()?;
+        let properties = self.pgm_processor_info.map(|info| info.properties).unwrap_or_default();
+        let ag = self.comm_mode_basic.granularity().size();
+        let regions = validate_regions(regions, &sectors, ag);
+        Ok(FlashValidation { properties, sectors, regions })
+    }
+
+    /// Programs the Intel HEX image read from `reader` into the slave's flash.
+    ///
+    /// Runs PROGRAM_START, then for every contiguous region SET_MTA, PROGRAM_CLEAR
+    /// and the data with PROGRAM (and PROGRAM_NEXT in block mode) followed by an
+    /// empty PROGRAM ending the segment, and finally PROGRAM_RESET unless disabled
+    /// in `options`. The data is encoded by the master's `ProgramCodec`, preceded by
+    /// PROGRAM_FORMAT if the codec uses a format other than the default.
+    /// `progress` is called with `(programmed, total)` encoded bytes after every
+    /// acknowledged transfer.
+    ///
+    /// ERR_DAQ_ACTIVE and ERR_PGM_ACTIVE can be recovered from automatically, see
+    /// `FlashOptions`; the recoveries are listed in the report. If `options.epk` is
+    /// set, the EPK is checked before PROGRAM_START and nothing is programmed if it
+    /// differs. The `_file` variants can save checkpoints for `resume_flash`, see
+    /// `options.checkpoint`.
+    ///
+    /// Unless disabled, every region is then verified with BUILD_CHECKSUM, split
+    /// into smaller blocks if the slave reports a maximum block size. A mismatch
+    /// suppresses PROGRAM_RESET unless `options.reset_on_mismatch` is set.
+    ///
+    /// # Returns
+    /// The programmed regions and their verification status. Once programming
+    /// started, errors are returned as `XcpError::FlashFailed` with the report so
+    /// far, e.g. wrapping `XcpError::PgmLocked` if PGM must be unlocked first.
+    /// `XcpError::Io` if the file is not valid Intel HEX.
+    pub fn flash_ihex<R: BufRead>(&mut self, reader: R, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_ihex(reader)?;
+        self.flash_regions(regions, options, None, progress)
+    }
+
+    /// Like `flash_ihex`, reading the image from the file at `path`.
+    pub fn flash_ihex_file<P: AsRef<Path>>(&mut self, path: P, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_ihex(BufReader::new(File::open(&path)?))?;
+        self.flash_regions(regions, options, Some(FlashImage::Ihex(path.as_ref().to_path_buf())), progress)
+    }
+
+    /// Programs the Motorola S-record image read from `reader`, like `flash_ihex`.
+    /// The whole file is parsed before the first command is sent.
+    pub fn flash_srec<R: BufRead>(&mut self, reader: R, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_srec(reader)?;
+        self.flash_regions(regions, options, None, progress)
+    }
+
+    /// Like `flash_srec`, reading the image from the file at `path`.
+    pub fn flash_srec_file<P: AsRef<Path>>(&mut self, path: P, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_srec(BufReader::new(File::open(&path)?))?;
+        self.flash_regions(regions, options, Some(FlashImage::Srec(path.as_ref().to_path_buf())), progress)
+    }
+
+    /// Programs the loadable segments of the ELF image read from `reader`, placed
+    /// and filtered according to `elf`, like `flash_ihex`.
+    pub fn flash_elf<R: Read>(&mut self, reader: R, elf: &ElfOptions, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_elf(reader, elf)?;
+        self.flash_regions(regions, options, None, progress)
+    }
+
+    /// Like `flash_elf`, reading the image from the file at `path`.
+    pub fn flash_elf_file<P: AsRef<Path>>(&mut self, path: P, elf: &ElfOptions, options: FlashOptions, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<FlashReport, XcpError> {
+        let regions = parse_elf(File::open(&path)?, elf)?;
+        self.flash_regions(regions, options, Some(FlashImage::Elf(path.as_ref().to_path_buf(), elf.clone())), progress)
+    }
+
+    /// Runs the PGM sequence of `flash_ihex` for `regions`, read from `image` if
+    /// they come from a file.
+    fn flash_regions(&mut self, mut regions: Vec<HexRegion>, options: FlashOptions, image: Option<FlashImage>,
+        progress: Option<&mut dyn FnMut(usize, usize)>) -> Result<FlashReport, XcpError> {
+        pad_regions(&mut regions, options.fill, self.comm_mode_basic.granularity().size());
+
+        let checkpoint = match (&options.checkpoint, image) {
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "checkpoints need an image read from a file").into());
+            }
+            (Some(_), Some(image)) => {
+                let id = self.get_id(IdType::AsciiText)?;
+                Some(FlashCheckpoint::new(id, image.canonicalize()?, &options, &regions))
+            }
+        };
+        self.run_flash(&regions, &options, checkpoint, progress)
+    }
+
+    /// Programs the padded `regions`, continuing after the bytes `checkpoint`
+    /// records as programmed, and saves the checkpoint after every block.
+    ///
+    /// # Returns
+    /// `XcpError::FlashFailed` with the report so far if a step fails, after ending
+    /// a started PGM sequence with PROGRAM_RESET so that flashing can be retried.
+    /// Calibration pages are not touched by flashing and need no cleanup.
+    pub(crate) fn run_flash(&mut self, regions: &[HexRegion], options: &FlashOptions, checkpoint: Option<FlashCheckpoint>,
+        progress: Option<&mut dyn FnMut(usize, usize)>) -> Result<FlashReport, XcpError> {
+        let started = Instant::now();
+        let mut report = FlashReport::default();
+        let result = self.flash_sequence(regions, options, checkpoint, progress, &mut report);
+        report.duration = started.elapsed();
+        match result {
+            Ok(()) => Ok(report),
+            Err(source) => {
+                let cleanup = match self.pgm_info {
+                    Some(_) => Cleanup::of(XcpCommandCode::ProgramReset, self.program_reset()),
+                    None => Cleanup::NotNeeded,
+                };
+                Err(XcpError::FlashFailed { report: Box::new(report), source: Box::new(source), cleanup })
+            }
+        }
+    }
+
+    /// The steps of `run_flash`, recording them in `report` as they complete.
+    fn flash_sequence(&mut self, regions: &[HexRegion], options: &FlashOptions, mut checkpoint: Option<FlashCheckpoint>,
+        mut progress: Option<&mut dyn FnMut(usize, usize)>, report: &mut FlashReport) -> Result<(), XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let encoded: Vec<Vec<u8>> = regions.iter().map(|region| self.program_codec().encode(&region.data)).collect();
+        let total = encoded.iter().map(Vec::len).sum();
+        let mut programmed = 0;
+
+        self.check_expected_epk(options.epk.as_ref())?;
+        let save = |checkpoint: &Option<FlashCheckpoint>| match (checkpoint, &options.checkpoint) {
+            (Some(checkpoint), Some(path)) => checkpoint.save(path),
+            _ => Ok(()),
+        };
+        save(&checkpoint)?;
+
+        report.identity = self.flash_identity()?;
+        let sector_map = self.flash_sector_map()?;
+        let mut region_sectors = Vec::with_capacity(regions.len());
+        self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Start });
+        self.recover(XcpCommandCode::ProgramStart, options, &mut report.recoveries, |master| master.program_start())?;
+        if let Some(checkpoint) = checkpoint.as_mut() {
+            self.verify_checkpoint(regions, options.address_extension, checkpoint, &mut report.recoveries)?;
+        }
+
+        for (index, (region, data)) in regions.iter().zip(&encoded).enumerate() {
+            // the encoded data can only be continued midway if it maps byte by byte
+            // to the region, otherwise the region is one block
+            let sliceable = data.len() == region.data.len() && !self.program_codec().needs_format();
+            let block = if checkpoint.is_some() && sliceable { CHECKPOINT_BLOCK } else { data.len() };
+            let mut start = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.regions[index].programmed);
+            if start != region.data.len() && !sliceable {
+                start = 0;
+            }
+            let region_started = Instant::now();
+            let recoveries_before = report.recoveries.len();
+            let ranges = track_sectors(region, &sector_map, ag, start, &mut report.sectors);
+            report.regions.push(FlashedRegion {
+                address: region.address,
+                length: start,
+                verification: RegionVerification::NotVerified,
+                duration: Duration::ZERO,
+                verify_duration: Duration::ZERO,
+                retries: 0,
+            });
+
+            if start > 0 && start == region.data.len() {
+                programmed += data.len();
+            } else {
+                if start == 0 {
+                    self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Clear { address: region.address } });
+                    self.recover(XcpCommandCode::ProgramClear, options, &mut report.recoveries, |master| {
+                        master.set_mta(region.address, options.address_extension)?;
+                        master.program_clear(ProgramClearMode::Absolute, (region.data.len() / ag) as u32)
+                    })?;
+                }
+                programmed += start;
+                self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Program { address: region.address } });
+                if self.program_codec().needs_format() {
+                    self.program_format()?;
+                }
+
+                let mut offset = start;
+                while offset < data.len() {
+                    let mut end = usize::min(offset + block, data.len());
+                    // blocks end at sector bounds to time the sectors
+                    let sector = ranges.iter().find(|(_, range)| sliceable && range.contains(&offset))
+                        .map(|(index, range)| (*index, range.end));
+                    if let Some((_, sector_end)) = sector {
+                        end = usize::min(end, sector_end);
+                    }
+                    let block_started = Instant::now();
+                    let address = region.address + (offset / ag) as u32;
+                    let programmed_before = programmed;
+                    self.recover(XcpCommandCode::Program, options, &mut report.recoveries, |master| {
+                        // a retry programs the block again from its start
+                        programmed = programmed_before;
+                        master.set_mta(address, options.address_extension)?;
+                        master.program_with_progress(&data[offset..end], &mut |length| {
+                            programmed += length;
+                            if let Some(progress) = progress.as_mut() {
+                                progress(programmed, total);
+                            }
+                        })
+                    })?;
+                    let elapsed = block_started.elapsed();
+                    match sector {
+                        Some((index, _)) => {
+                            report.sectors[index].length += end - offset;
+                            report.sectors[index].duration += elapsed;
+                        }
+                        None => for (index, range) in &ranges {
+                            report.sectors[*index].length += range.len();
+                            report.sectors[*index].duration += elapsed.mul_f64(range.len() as f64 / region.data.len() as f64);
+                        },
+                    }
+                    offset = end;
+                    let flashed = report.regions.last_mut().unwrap();
+                    flashed.length = if sliceable { end } else { region.data.len() };
+                    flashed.duration = region_started.elapsed();
+                    flashed.retries = report.recoveries.len() - recoveries_before;
+                    if let Some(checkpoint) = checkpoint.as_mut() {
+                        checkpoint.regions[index].programmed = flashed.length;
+                    }
+                    save(&checkpoint)?;
+                }
+                self.program_segment_end()?;
+            }
+            let flashed = report.regions.last_mut().unwrap();
+            flashed.length = region.data.len();
+            flashed.duration = region_started.elapsed();
+            flashed.retries = report.recoveries.len() - recoveries_before;
+            region_sectors.push(ranges);
+        }
+
+        if options.verify {
+            self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Verify });
+            for ((region, flashed), ranges) in regions.iter().zip(&mut report.regions).zip(&region_sectors) {
+                let verify_started = Instant::now();
+                for (index, range) in ranges {
+                    let sector_started = Instant::now();
+                    let address = region.address + (range.start / ag) as u32;
+                    let (verification, checksums) = self.verify_blocks(address, options.address_extension, &region.data[range.clone()])?;
+                    flashed.verification = merge_verification(flashed.verification, verification, range.start);
+                    let sector = &mut report.sectors[*index];
+                    let offset = address.saturating_sub(sector.address) as usize * ag;
+                    sector.verification = merge_verification(sector.verification, verification, offset);
+                    sector.checksums.extend(checksums);
+                    sector.verify_duration += sector_started.elapsed();
+                }
+                flashed.verify_duration = verify_started.elapsed();
+            }
+        }
+
+        if options.reset && (report.is_verified() || options.reset_on_mismatch) {
+            self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Reset });
+            self.program_reset()?;
+            report.reset = true;
+        }
+        if let (Some(path), true) = (&options.checkpoint, report.is_verified()) {
+            fs::remove_file(path)?;
+        }
+        self.publish_event(SessionEvent::FlashPhase { phase: FlashPhase::Finished });
+        Ok(())
+    }
+
+    /// The ASCII identification of the slave for the report, `None` if it refuses GET_ID.
+    fn flash_identity(&mut self) -> Result<Option<String>, XcpError> {
+        match self.get_id_string(IdType::AsciiText) {
+            Ok(identity) => Ok(Some(identity)),
+            Err(e) if e.error_code().is_some() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The sector map for the report, empty if the slave refuses to report one.
+    fn flash_sector_map(&mut self) -> Result<Vec<FlashSector>, XcpError> {
+        match self.read_sector_map() {
+            Ok(sectors) => Ok(sectors),
+            Err(e) if e.error_code().is_some() => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Verifies the bytes `checkpoint` records as programmed. Regions that do not
+    /// verify are marked as not programmed at all, to be cleared again.
+    fn verify_checkpoint(&mut self, regions: &[HexRegion], address_extension: u8, checkpoint: &mut FlashCheckpoint,
+        recoveries: &mut Vec<FlashRecovery>) -> Result<(), XcpError> {
+        for (region, recorded) in regions.iter().zip(&mut checkpoint.regions) {
+            if recorded.programmed == 0 { continue }
+
+            let programmed = HexRegion { address: region.address, data: region.data[..recorded.programmed].to_vec() };
+            let verification = self.verify_region(&programmed, address_extension)?;
+            if verification != RegionVerification::Verified {
+                let recovery = FlashRecovery::RegionRestarted { address: region.address };
+                self.publish_event(SessionEvent::FlashRecovery { recovery });
+                recorded.programmed = 0;
+                recoveries.push(recovery);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `operation`, and once more after recovering from ERR_DAQ_ACTIVE or, for
+    /// PROGRAM_START, ERR_PGM_ACTIVE if `options` allow it.
+    fn recover<T>(&mut self, command: XcpCommandCode, options: &FlashOptions, recoveries: &mut Vec<FlashRecovery>,
+        mut operation: impl FnMut(&mut XcpMaster<'a>) -> Result<T, XcpError>) -> Result<T, XcpError> {
+        match operation(self) {
+            Err(e) if options.stop_daq && e.error_code() == Some(XcpErrorCode::ErrDaqActive) => {
+                let recovery = FlashRecovery::DaqStopped { command };
+                self.publish_event(SessionEvent::FlashRecovery { recovery });
+                self.stop_all_daq(DAQ_STOP_TIMEOUT)?;
+                recoveries.push(recovery);
+            }
+            Err(e) if options.reset_active_pgm && command == XcpCommandCode::ProgramStart
+                && e.error_code() == Some(XcpErrorCode::ErrPgmActive) => {
+                let recovery = FlashRecovery::ProgramReset;
+                self.publish_event(SessionEvent::FlashRecovery { recovery });
+                self.program_reset()?;
+                recoveries.push(recovery);
+            }
+            result => return result,
+        }
+        operation(self)
+    }
+
+    /// Compares BUILD_CHECKSUMs over `region` with checksums of its data, in blocks
+    /// as large as the slave accepts.
+    fn verify_region(&mut self, region: &HexRegion, address_extension: u8) -> Result<RegionVerification, XcpError> {
+        self.verify_blocks(region.address, address_extension, &region.data).map(|(verification, _)| verification)
+    }
+
+    /// Like `verify_region` for `data` at `address`, also returning the checksums
+    /// compared up to the first mismatch.
+    fn verify_blocks(&mut self, address: u32, address_extension: u8, data: &[u8])
+        -> Result<(RegionVerification, Vec<BlockChecksum>), XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let mut block_len = data.len();
+        let mut offset = 0;
+        let mut checksums = Vec::new();
+
+        while offset < data.len() {
+            let len = usize::min(block_len, data.len() - offset);
+            let block_address = address + (offset / ag) as u32;
+            match self.compare_checksum(block_address, address_extension, &data[offset..offset + len]) {
+                Ok((checksum_type, expected, actual)) => {
+                    checksums.push(BlockChecksum { address: block_address, length: len, checksum_type, expected, actual });
+                    if expected != actual {
+                        return Ok((RegionVerification::Mismatch { offset, checksum_type, expected, actual }, checksums));
+                    }
+                    offset += len;
+                }
+                Err(XcpError::BlockSizeOutOfRange { max, .. }) if max > 0 && (max as usize) * ag < len => {
+                    block_len = max as usize * ag;
+                }
+                Err(XcpError::UnsupportedChecksumType(checksum_type)) => {
+                    return Ok((RegionVerification::Unsupported(checksum_type), checksums));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((RegionVerification::Verified, checksums))
+    }
+}
@@ -1,5 +1,9 @@
+use crate::xcp::error::XcpError;
+use crate::xcp::xcp_command::XcpCommModeBasic;
+
 /// Enumeration of XCP command codes based on the XCP Protocol specification.
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(dead_code)]
 pub enum XcpCommandCode {
     Connect = 0xFF,
@@ -58,6 +62,9 @@ pub enum XcpCommandCode {
     ProgramNext = 0xCA,
     ProgramMax = 0xC9,
     ProgramVerify = 0xC8,
+    WriteDaqMultiple = 0xC7,
+    TimeCorrelationProperties = 0xC6,
+    Level1Command = 0xC0,
 
     #[default]
     Unknown = 0x00,
@@ -67,6 +74,7 @@ impl XcpCommandCode {
     /// Convert a raw command code to an `XcpCommandCode` enum variant.
     pub fn from_code(code: u8) -> XcpCommandCode {
         match code {
+            0xFF => XcpCommandCode::Connect,
             0xFE => XcpCommandCode::Disconnect,
             0xFD => XcpCommandCode::GetStatus,
             0xFC => XcpCommandCode::Synch,
@@ -122,6 +130,9 @@ impl XcpCommandCode {
             0xCA => XcpCommandCode::ProgramNext,
             0xC9 => XcpCommandCode::ProgramMax,
             0xC8 => XcpCommandCode::ProgramVerify,
+            0xC7 => XcpCommandCode::WriteDaqMultiple,
+            0xC6 => XcpCommandCode::TimeCorrelationProperties,
+            0xC0 => XcpCommandCode::Level1Command,
             _ => XcpCommandCode::Unknown,
         }
     }
@@ -130,6 +141,51 @@ impl XcpCommandCode {
     pub fn to_code(&self) -> u8 {
         *self as u8
     }
+
+    /// Minimum protocol layer version a slave must implement to know this command.
+    ///
+    /// Slaves predating a command may do anything from answering ERR_CMD_UNKNOWN to
+    /// resetting, so the master can refuse to send it (see `XcpMaster::strict`).
+    pub fn min_protocol_version(&self) -> XcpVersion {
+        match self {
+            XcpCommandCode::Level1Command => XcpVersion::new(1, 1),
+            XcpCommandCode::WriteDaqMultiple
+            | XcpCommandCode::TimeCorrelationProperties => XcpVersion::new(1, 3),
+            _ => XcpVersion::new(1, 0),
+        }
+    }
+}
+
+/// XCP protocol layer version as major.minor.
+///
+/// CONNECT only reports the major version; the minor version is 0 unless learned
+/// otherwise (e.g. from GET_VERSION or the A2L file).
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XcpVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl XcpVersion {
+    pub fn new(major: u8, minor: u8) -> XcpVersion {
+        XcpVersion { major, minor }
+    }
+
+    /// Checks whether a slave implementing this version knows `command`.
+    pub fn require(&self, command: XcpCommandCode) -> Result<(), XcpError> {
+        let required = command.min_protocol_version();
+        if *self < required {
+            return Err(XcpError::UnsupportedByProtocolVersion { command, required, negotiated: *self });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for XcpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
 }
 
 /// Enumeration of XCP response codes.
@@ -138,6 +194,8 @@ impl XcpCommandCode {
 pub enum XcpResponseCode {
     PositiveResponse = 0xFF,
     NegativeResponse = 0xFE,
+    Event = 0xFD,
+    ServiceRequest = 0xFC,
     #[default]
     UnknownResponse = 0x00,
 }
@@ -148,6 +206,8 @@ impl XcpResponseCode {
         match code {
             0xFF => XcpResponseCode::PositiveResponse,
             0xFE => XcpResponseCode::NegativeResponse,
+            0xFD => XcpResponseCode::Event,
+            0xFC => XcpResponseCode::ServiceRequest,
             _ => XcpResponseCode::UnknownResponse,
         }
     }
@@ -158,6 +218,67 @@ impl XcpResponseCode {
     }
 }
 
+/// Event codes carried in the second byte of an EV packet.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XcpEventCode {
+    ResumeMode,
+    ClearDaq,
+    StoreDaq,
+    StoreCal,
+    CmdPending,
+    DaqOverload,
+    SessionTerminated,
+    TimeSync,
+    StimTimeout,
+    Sleep,
+    WakeUp,
+    User,
+    Transport,
+    Unknown(u8),
+}
+
+impl XcpEventCode {
+    /// Convert a raw event code to an `XcpEventCode` enum variant.
+    pub fn from_code(code: u8) -> XcpEventCode {
+        match code {
+            0x00 => XcpEventCode::ResumeMode,
+            0x01 => XcpEventCode::ClearDaq,
+            0x02 => XcpEventCode::StoreDaq,
+            0x03 => XcpEventCode::StoreCal,
+            0x05 => XcpEventCode::CmdPending,
+            0x06 => XcpEventCode::DaqOverload,
+            0x07 => XcpEventCode::SessionTerminated,
+            0x08 => XcpEventCode::TimeSync,
+            0x09 => XcpEventCode::StimTimeout,
+            0x0A => XcpEventCode::Sleep,
+            0x0B => XcpEventCode::WakeUp,
+            0xFE => XcpEventCode::User,
+            0xFF => XcpEventCode::Transport,
+            code => XcpEventCode::Unknown(code),
+        }
+    }
+
+    /// Convert an `XcpEventCode` enum variant to its raw event code.
+    pub fn to_code(&self) -> u8 {
+        match self {
+            XcpEventCode::ResumeMode => 0x00,
+            XcpEventCode::ClearDaq => 0x01,
+            XcpEventCode::StoreDaq => 0x02,
+            XcpEventCode::StoreCal => 0x03,
+            XcpEventCode::CmdPending => 0x05,
+            XcpEventCode::DaqOverload => 0x06,
+            XcpEventCode::SessionTerminated => 0x07,
+            XcpEventCode::TimeSync => 0x08,
+            XcpEventCode::StimTimeout => 0x09,
+            XcpEventCode::Sleep => 0x0A,
+            XcpEventCode::WakeUp => 0x0B,
+            XcpEventCode::User => 0xFE,
+            XcpEventCode::Transport => 0xFF,
+            XcpEventCode::Unknown(code) => *code,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
 pub enum XcpErrorCode {
     /// Command processor synchronization.
@@ -260,10 +381,197 @@ impl XcpErrorCode {
 }
 
 
+/// Byte order of multi-byte values exchanged with the slave (COMM_MODE_BASIC bit 0).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteOrder {
+    /// Little endian.
+    #[default]
+    Intel,
+    /// Big endian.
+    Motorola,
+}
+
+impl ByteOrder {
+    /// Reads a u16 from the first two bytes of `bytes`.
+    pub fn read_u16(&self, bytes: &[u8]) -> u16 {
+        let raw = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::Intel => u16::from_le_bytes(raw),
+            ByteOrder::Motorola => u16::from_be_bytes(raw),
+        }
+    }
+
+    /// Reads a u32 from the first four bytes of `bytes`.
+    pub fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let raw = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::Intel => u32::from_le_bytes(raw),
+            ByteOrder::Motorola => u32::from_be_bytes(raw),
+        }
+    }
+
+    pub fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Intel => value.to_le_bytes(),
+            ByteOrder::Motorola => value.to_be_bytes(),
+        }
+    }
+
+    pub fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Intel => value.to_le_bytes(),
+            ByteOrder::Motorola => value.to_be_bytes(),
+        }
+    }
+}
+
+/// Size of the smallest addressable memory element (COMM_MODE_BASIC bits 1 and 2).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressGranularity {
+    #[default]
+    Byte,
+    Word,
+    DWord,
+}
+
+impl AddressGranularity {
+    /// Size of one element in bytes.
+    ///
+    /// This is also the offset of the first data byte in UPLOAD style responses, and
+    /// of the first data byte in DOWNLOAD_MAX style commands, as the payload is aligned
+    /// to the element size.
+    pub fn size(&self) -> usize {
+        match self {
+            AddressGranularity::Byte => 1,
+            AddressGranularity::Word => 2,
+            AddressGranularity::DWord => 4,
+        }
+    }
+    /// Offset of the first data byte in DOWNLOAD style commands, which carry the
+    /// element count in byte 1 before the aligned payload.
+    pub fn download_offset(&self) -> usize {
+        usize::max(2, self.size())
+    }
+}
+
+/// Largest frame any supported transport carries (CAN FD).
+pub const XCP_MAX_FRAME_LEN: usize = 64;
+
+/// Fixed capacity buffer holding the payload of a single XCP frame.
+///
+/// Used instead of `Vec<u8>` when encoding commands and for the variable payload of
+/// responses, so that building or decoding a frame never allocates and the 64 byte
+/// bound is part of the type.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct XcpFrameBuf {
+    data: [u8; XCP_MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl XcpFrameBuf {
+    pub fn new() -> XcpFrameBuf {
+        XcpFrameBuf { data: [0; XCP_MAX_FRAME_LEN], len: 0 }
+    }
+
+    /// Creates a buffer holding a copy of `bytes`.
+    ///
+    /// # Returns
+    /// `XcpError::FrameOverflow` if `bytes` is longer than `XCP_MAX_FRAME_LEN`.
+    pub fn from_slice(bytes: &[u8]) -> Result<XcpFrameBuf, XcpError> {
+        let mut buf = XcpFrameBuf::new();
+        buf.extend_from_slice(bytes)?;
+        Ok(buf)
+    }
+
+    /// Creates a buffer holding a copy of the first `XCP_MAX_FRAME_LEN` bytes of
+    /// `bytes`, e.g. the payload of a received frame.
+    pub fn from_prefix(bytes: &[u8]) -> XcpFrameBuf {
+        let len = usize::min(bytes.len(), XCP_MAX_FRAME_LEN);
+        let mut buf = XcpFrameBuf::new();
+        buf.data[..len].copy_from_slice(&bytes[..len]);
+        buf.len = len;
+        buf
+    }
+
+    /// Appends a byte.
+    ///
+    /// # Returns
+    /// `XcpError::FrameOverflow` if the buffer is full; it is left unchanged then.
+    pub fn push(&mut self, byte: u8) -> Result<(), XcpError> {
+        self.extend_from_slice(&[byte])
+    }
+
+    /// Appends `bytes`.
+    ///
+    /// # Returns
+    /// `XcpError::FrameOverflow` if the bytes do not fit into the remaining
+    /// capacity; the buffer is left unchanged then.
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) -> Result<(), XcpError> {
+        let end = self.len + bytes.len();
+        if end > XCP_MAX_FRAME_LEN {
+            return Err(XcpError::FrameOverflow { requested: end });
+        }
+        self.data[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    /// Shortens the buffer to `len` bytes; does nothing if it is not longer.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = usize::min(self.len, len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for XcpFrameBuf {
+    fn default() -> XcpFrameBuf {
+        XcpFrameBuf::new()
+    }
+}
+
+impl std::ops::Deref for XcpFrameBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<XcpFrameBuf> for Vec<u8> {
+    fn from(buf: XcpFrameBuf) -> Vec<u8> {
+        buf.as_slice().to_vec()
+    }
+}
+
+/// Renders the payload as hex, e.g. `[ff, 00]`.
+impl std::fmt::Debug for XcpFrameBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02x?}", self.as_slice())
+    }
+}
+
 /// Trait for XCP commands, providing a method to encode commands into CAN frames.
 pub trait XcpCommand {
-    fn to_can_frame(&self) -> Vec<u8>;
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError>;
     fn get_code(&self) -> XcpCommandCode;
+
+    /// Lowest protocol layer version a slave must implement to know the command.
+    fn min_protocol_version(&self) -> XcpVersion {
+        self.get_code().min_protocol_version()
+    }
 }
 
 /// Generic structure representing an XCP command frame.
@@ -274,14 +582,25 @@ pub struct XcpCommandFrame<T: XcpCommand> {
 
 impl<T: XcpCommand> XcpCommandFrame<T> {
     /// Convert the command frame into a CAN frame data vector.
-    pub fn to_can_frame(&self) -> Vec<u8> {
+    pub fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
         self.data.to_can_frame()
     }
 }
 
 /// Trait for XCP responses, providing a method to decode responses from CAN frames.
-pub trait XcpResponse {
+pub trait XcpResponse: Sized {
+    /// Length of the shortest frame the decoder accepts, including the PID. The
+    /// master rejects shorter responses before decoding them.
+    const MIN_LENGTH: usize = 1;
+
     fn from_can_frame(frame: &[u8]) -> Self;
+
+    /// Decode a response whose layout depends on the byte order or address granularity
+    /// the slave reported in COMM_MODE_BASIC. Responses without such fields need not
+    /// override this.
+    fn from_can_frame_with_mode(frame: &[u8], _mode: XcpCommModeBasic) -> Self {
+        Self::from_can_frame(frame)
+    }
 }
 
 /// Generic structure representing an XCP response frame.
@@ -295,5 +614,10 @@ impl<T: XcpResponse> XcpResponseFrame<T> {
     pub fn from_can_frame(frame: &[u8]) -> Self {
             XcpResponseFrame { data: T::from_can_frame(frame) }
     }
+
+    /// Decode a CAN frame into an XCP response frame using the slave's COMM_MODE_BASIC.
+    pub fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> Self {
+            XcpResponseFrame { data: T::from_can_frame_with_mode(frame, mode) }
+    }
 }
 
@@ -0,0 +1,357 @@
+//! Everything the slave tells about itself in one place, see `XcpMaster::slave_info`
+//! and `XcpMaster::build_memory_map`.
+
+use std::fmt;
+use crate::xcp::error::XcpError;
+use crate::xcp::flash::FlashSector;
+use crate::xcp::frame::{AddressGranularity, ByteOrder, XcpCommandCode, XcpErrorCode, XcpVersion};
+use crate::xcp::master::XcpMaster;
+use crate::xcp::xcp_command::{
+    GetCommModeInfoResponse, GetDaqProcessorInfoResponse, GetPagProcessorInfoResponse, GetStatusResponse,
+    GetVersionResponse, IdType, SegmentInfo, SegmentInfoMode, XcpResourceFlags,
+};
+
+/// The result of an optional command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Capability<T> {
+    Supported(T),
+    /// The slave answered ERR_CMD_UNKNOWN, or the protocol version predates the command.
+    Unsupported,
+    /// The slave answered ERR_ACCESS_LOCKED; the resource must be unlocked first.
+    Locked,
+}
+
+impl<T> Capability<T> {
+    pub fn supported(&self) -> Option<&T> {
+        match self {
+            Capability::Supported(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Capability::Supported(_))
+    }
+
+    /// Maps the errors optional commands may fail with to the capability they imply.
+    fn from_result(result: Result<T, XcpError>) -> Result<Capability<T>, XcpError> {
+        match result {
+            Ok(value) => Ok(Capability::Supported(value)),
+            Err(XcpError::UnsupportedByProtocolVersion { .. }) => Ok(Capability::Unsupported),
+            Err(e) => match e.error_code() {
+                Some(XcpErrorCode::ErrCmdUnknown) => Ok(Capability::Unsupported),
+                Some(XcpErrorCode::ErrAccessLocked) => Ok(Capability::Locked),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// Session state from GET_STATUS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlaveStatus {
+    pub store_cal_pending: bool,
+    pub store_daq_pending: bool,
+    pub clear_daq_pending: bool,
+    pub daq_running: bool,
+    pub resume: bool,
+    /// Resources still protected by seed & key.
+    pub protection: XcpResourceFlags,
+    pub state_number: Option<u8>,
+    pub session_configuration_id: Option<u16>,
+}
+
+impl From<&GetStatusResponse> for SlaveStatus {
+    fn from(status: &GetStatusResponse) -> SlaveStatus {
+        SlaveStatus {
+            store_cal_pending: status.session_status.store_cal_req(),
+            store_daq_pending: status.session_status.store_daq_req(),
+            clear_daq_pending: status.session_status.clear_daq_req(),
+            daq_running: status.session_status.daq_running(),
+            resume: status.session_status.resume(),
+            protection: status.protection,
+            state_number: status.state_number,
+            session_configuration_id: status.session_configuration_id,
+        }
+    }
+}
+
+/// Optional communication modes from GET_COMM_MODE_INFO.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommModeInfo {
+    pub master_block_mode: bool,
+    pub interleaved_mode: bool,
+    pub max_bs: u8,
+    /// In units of 100 µs.
+    pub min_st: u8,
+    pub queue_size: u8,
+    pub driver_version: u8,
+}
+
+impl From<GetCommModeInfoResponse> for CommModeInfo {
+    fn from(info: GetCommModeInfoResponse) -> CommModeInfo {
+        CommModeInfo {
+            master_block_mode: info.comm_mode_optional.master_block_mode(),
+            interleaved_mode: info.comm_mode_optional.interleaved_mode(),
+            max_bs: info.max_bs,
+            min_st: info.min_st,
+            queue_size: info.queue_size,
+            driver_version: info.driver_version,
+        }
+    }
+}
+
+/// Layer versions from GET_VERSION.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VersionInfo {
+    pub protocol_layer: XcpVersion,
+    pub transport_layer: XcpVersion,
+}
+
+impl From<GetVersionResponse> for VersionInfo {
+    fn from(version: GetVersionResponse) -> VersionInfo {
+        VersionInfo { protocol_layer: version.protocol_layer, transport_layer: version.transport_layer }
+    }
+}
+
+/// DAQ capabilities from GET_DAQ_PROCESSOR_INFO.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DaqProcessorInfo {
+    pub dynamic: bool,
+    pub prescaler_supported: bool,
+    pub resume_supported: bool,
+    pub bit_stim_supported: bool,
+    pub timestamp_supported: bool,
+    pub pid_off_supported: bool,
+    pub max_daq: u16,
+    pub max_event_channel: u16,
+    pub min_daq: u8,
+}
+
+impl From<GetDaqProcessorInfoResponse> for DaqProcessorInfo {
+    fn from(info: GetDaqProcessorInfoResponse) -> DaqProcessorInfo {
+        DaqProcessorInfo {
+            dynamic: info.properties.dynamic(),
+            prescaler_supported: info.properties.prescaler_supported(),
+            resume_supported: info.properties.resume_supported(),
+            bit_stim_supported: info.properties.bit_stim_supported(),
+            timestamp_supported: info.properties.timestamp_supported(),
+            pid_off_supported: info.properties.pid_off_supported(),
+            max_daq: info.max_daq,
+            max_event_channel: info.max_event_channel,
+            min_daq: info.min_daq,
+        }
+    }
+}
+
+/// Paging capabilities from GET_PAG_PROCESSOR_INFO.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PagingInfo {
+    pub max_segments: u8,
+    pub freeze_supported: bool,
+}
+
+impl From<GetPagProcessorInfoResponse> for PagingInfo {
+    fn from(info: GetPagProcessorInfoResponse) -> PagingInfo {
+        PagingInfo { max_segments: info.max_segments, freeze_supported: info.properties.freeze_supported() }
+    }
+}
+
+/// Result of `XcpMaster::slave_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlaveInfo {
+    /// From CONNECT, with the minor version from GET_VERSION if supported.
+    pub protocol_version: Option<XcpVersion>,
+    pub byte_order: ByteOrder,
+    pub address_granularity: AddressGranularity,
+    pub max_cto: usize,
+    pub max_dto: usize,
+    pub status: SlaveStatus,
+    pub comm_mode: Capability<CommModeInfo>,
+    /// The ASCII identification from GET_ID.
+    pub id: Capability<String>,
+    pub version: Capability<VersionInfo>,
+    pub daq: Capability<DaqProcessorInfo>,
+    pub paging: Capability<PagingInfo>,
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Collects what the connected slave reports about itself: the CONNECT results
+    /// kept by the master, GET_STATUS, and the optional GET_COMM_MODE_INFO, GET_ID,
+    /// GET_VERSION, GET_DAQ_PROCESSOR_INFO and GET_PAG_PROCESSOR_INFO.
+    ///
+    /// Optional commands the slave does not know are recorded as unsupported, and
+    /// ones refused for a protected resource as locked. Only queries are sent, so
+    /// this can be repeated at any time; note that GET_ID may move the MTA.
+    ///
+    /// # Returns
+    /// The first error other than ERR_CMD_UNKNOWN and ERR_ACCESS_LOCKED.
+    pub fn slave_info(&mut self) -> Result<SlaveInfo, XcpError> {
+        let status = SlaveStatus::from(&self.get_status()?);
+        let comm_mode = Capability::from_result(self.get_comm_mode_info().map(CommModeInfo::from))?;
+        let id = Capability::from_result(self.get_id_string(IdType::AsciiText))?;
+        let version = Capability::from_result(self.get_version().map(VersionInfo::from))?;
+        let daq = Capability::from_result(self.get_daq_processor_info().map(DaqProcessorInfo::from))?;
+        let paging = Capability::from_result(self.get_pag_processor_info().map(PagingInfo::from))?;
+
+        Ok(SlaveInfo {
+            protocol_version: self.protocol_version,
+            byte_order: self.comm_mode_basic.endianness(),
+            address_granularity: self.comm_mode_basic.granularity(),
+            max_cto: self.max_cto,
+            max_dto: self.max_dto,
+            status,
+            comm_mode,
+            id,
+            version,
+            daq,
+            paging,
+        })
+    }
+}
+
+/// A page of a calibration segment, from GET_PAGE_INFO.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageLayout {
+    pub page: u8,
+    pub ecu_access: bool,
+    pub xcp_read_access: bool,
+    pub xcp_write_access: bool,
+    /// Segment holding the initial data of the page.
+    pub init_segment: u8,
+}
+
+/// A calibration segment, from GET_SEGMENT_INFO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentLayout {
+    pub segment: u8,
+    pub address: u32,
+    pub address_extension: u8,
+    pub length: u32,
+    pub pages: Capability<Vec<PageLayout>>,
+}
+
+/// Result of `XcpMaster::build_memory_map`: the memory the slave exposes for
+/// calibration and for programming.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryLayout {
+    pub segments: Capability<Vec<SegmentLayout>>,
+    pub sectors: Capability<Vec<FlashSector>>,
+}
+
+fn write_unavailable<T>(f: &mut fmt::Formatter<'_>, what: &str, capability: &Capability<T>) -> fmt::Result {
+    match capability {
+        Capability::Locked => writeln!(f, "{}: locked", what),
+        _ => writeln!(f, "{}: not supported", what),
+    }
+}
+
+/// Renders the layout as two tables, segments with one line per page and
+/// sectors, with addresses in hex.
+impl fmt::Display for MemoryLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.segments {
+            Capability::Supported(segments) => {
+                writeln!(f, "{:<8} {:<4} {:<10} {:<10} {:<4} {:<4} {:<4} {:<5} init", "segment", "ext", "address",
+                    "length", "page", "ecu", "read", "write")?;
+                for segment in segments {
+                    write!(f, "{:<8} {:<4} 0x{:08X} 0x{:08X}", segment.segment, segment.address_extension,
+                        segment.address, segment.length)?;
+                    match &segment.pages {
+                        Capability::Supported(pages) if !pages.is_empty() => {
+                            let flag = |set: bool| if set { "yes" } else { "no" };
+                            for (index, page) in pages.iter().enumerate() {
+                                if index > 0 {
+                                    write!(f, "{:<35}", "")?;
+                                }
+                                writeln!(f, " {:<4} {:<4} {:<4} {:<5} {}", page.page, flag(page.ecu_access),
+                                    flag(page.xcp_read_access), flag(page.xcp_write_access), page.init_segment)?;
+                            }
+                        }
+                        Capability::Locked => writeln!(f, " pages locked")?,
+                        _ => writeln!(f, " pages unknown")?,
+                    }
+                }
+            }
+            other => write_unavailable(f, "segments", other)?,
+        }
+        match &self.sectors {
+            Capability::Supported(sectors) => {
+                writeln!(f, "{:<8} {:<10} {:<10} {:<5} {:<7} method", "sector", "address", "length", "clear",
+                    "program")?;
+                for sector in sectors {
+                    writeln!(f, "{:<8} 0x{:08X} 0x{:08X} {:<5} {:<7} {}", sector.sector, sector.address,
+                        sector.length, sector.clear_sequence_number, sector.program_sequence_number,
+                        sector.programming_method)?;
+                }
+            }
+            other => write_unavailable(f, "sectors", other)?,
+        }
+        Ok(())
+    }
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Describes what memory the slave exposes: the calibration segments with
+    /// their pages from GET_PAG_PROCESSOR_INFO, GET_SEGMENT_INFO and
+    /// GET_PAGE_INFO, and the flash sectors from GET_PGM_PROCESSOR_INFO and
+    /// GET_SECTOR_INFO, see `read_sector_map`.
+    ///
+    /// Paging, page information and programming are optional; like in
+    /// `slave_info` they are recorded as unsupported or locked if the slave
+    /// refuses them. Only queries are sent.
+    ///
+    /// # Returns
+    /// The first error other than ERR_CMD_UNKNOWN and ERR_ACCESS_LOCKED.
+    pub fn build_memory_map(&mut self) -> Result<MemoryLayout, XcpError> {
+        let segments = match Capability::from_result(self.get_pag_processor_info())? {
+            Capability::Supported(info) => {
+                let mut segments = Vec::with_capacity(info.max_segments as usize);
+                for segment in 0..info.max_segments {
+                    segments.push(self.segment_layout(segment)?);
+                }
+                Capability::Supported(segments)
+            }
+            Capability::Unsupported => Capability::Unsupported,
+            Capability::Locked => Capability::Locked,
+        };
+        let sectors = Capability::from_result(self.read_sector_map())?;
+        Ok(MemoryLayout { segments, sectors })
+    }
+
+    fn segment_layout(&mut self, segment: u8) -> Result<SegmentLayout, XcpError> {
+        let basic = |info: SegmentInfo| match info {
+            SegmentInfo::Basic(basic) => Ok(basic.basic_info),
+            _ => Err(XcpError::UnexpectedResponse { command: XcpCommandCode::GetSegmentInfo }),
+        };
+        let address = basic(self.get_segment_info(SegmentInfoMode::BasicAddress, segment, 0, 0)?)?;
+        let length = basic(self.get_segment_info(SegmentInfoMode::BasicAddress, segment, 1, 0)?)?;
+        let standard = match self.get_segment_info(SegmentInfoMode::Standard, segment, 0, 0)? {
+            SegmentInfo::Standard(standard) => standard,
+            _ => return Err(XcpError::UnexpectedResponse { command: XcpCommandCode::GetSegmentInfo }),
+        };
+
+        let pages = Capability::from_result((0..standard.max_pages).map(|page| {
+            let info = self.get_page_info(segment, page)?;
+            Ok(PageLayout {
+                page,
+                ecu_access: info.properties.ecu_access(),
+                xcp_read_access: info.properties.xcp_read_access(),
+                xcp_write_access: info.properties.xcp_write_access(),
+                init_segment: info.init_segment,
+            })
+        }).collect::<Result<Vec<_>, XcpError>>())?;
+
+        Ok(SegmentLayout { segment, address, address_extension: standard.address_extension, length, pages })
+    }
+}
@@ -1,49 +1,2097 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use crate::xcp::xcp_command::{
     ConnectCommand, ConnectResponse, ConnectMode, 
     GetSeedCommand, GetSeedResponse, GetSeedMode,
     UnlockCommand, UnlockResponse,
+    UserCmdCommand, EmptyResponse,
+    GetStatusCommand, GetStatusResponse,
+    SynchCommand,
+    SetRequestCommand, XcpSetRequestMode,
+    GetCommModeInfoCommand, GetCommModeInfoResponse,
+    GetIdCommand, GetIdResponse, IdType,
+    SetMtaCommand, BuildChecksumCommand, BuildChecksumResponse, ChecksumType,
+    UploadCommand, UploadResponse, ShortUploadCommand, ShortUploadResponse,
+    DownloadCommand, DownloadNextCommand, DownloadMaxCommand, ShortDownloadCommand,
+    ModifyBitsCommand,
+    SetCalPageCommand, GetCalPageCommand, GetCalPageResponse, CalPageAccessFlags, PageAccess,
+    GetPagProcessorInfoCommand, GetPagProcessorInfoResponse,
+    GetSegmentInfoCommand, SegmentInfoMode, SegmentInfo,
+    GetPageInfoCommand, GetPageInfoResponse,
+    SetSegmentModeCommand, GetSegmentModeCommand, GetSegmentModeResponse, XcpSegmentMode,
+    CopyCalPageCommand,
+    ClearDaqListCommand, SetDaqPtrCommand, WriteDaqCommand, WriteDaqMultipleCommand, DaqEntry,
+    SetDaqListModeCommand, DaqListModeConfig, GetDaqListModeCommand, GetDaqListModeResponse,
+    StartStopDaqListCommand, StartStopDaqListResponse, StartStopMode,
+    StartStopSynchCommand, StartStopSynchMode,
+    GetDaqClockCommand, GetDaqClockResponse,
+    ReadDaqCommand, ReadDaqResponse,
+    GetDaqProcessorInfoCommand, GetDaqProcessorInfoResponse,
+    GetDaqResolutionInfoCommand, GetDaqResolutionInfoResponse,
+    GetDaqListInfoCommand, GetDaqListInfoResponse,
+    GetDaqEventInfoCommand, GetDaqEventInfoResponse,
+    FreeDaqCommand, AllocDaqCommand, AllocOdtCommand, AllocOdtEntryCommand,
+    SegmentBasicInfoResponse, SegmentStandardInfoResponse, SegmentMappingInfoResponse,
+    XcpCommModeBasic, XcpSessionStatus,
+    ProgramStartCommand, ProgramStartResponse, ProgramClearCommand, ProgramClearMode,
+    ProgramCommand, ProgramNextCommand, ProgramResetCommand, ProgramFormatCommand,
+    GetPgmProcessorInfoCommand, GetPgmProcessorInfoResponse, GetSectorInfoCommand, GetSectorInfoResponse, SectorInfoMode,
     NegativeResponse,
-    XcpResourceFlags
+    XcpResource, XcpResourceFlags, DisconnectCommand, GetVersionCommand, GetVersionResponse
 };
-use crate::xcp::frame::{XcpCommandFrame, XcpCommand, XcpResponseFrame, XcpResponse, XcpResponseCode };
-use socketcan::{CanSocket, CanFrame, Socket, EmbeddedFrame, StandardId, BlockingCan, Id};
+use crate::xcp::frame::{XcpCommandFrame, XcpCommand, XcpCommandCode, XcpResponseFrame, XcpResponse, XcpResponseCode, XcpErrorCode, XcpEventCode, XcpFrameBuf, XcpVersion, AddressGranularity };
+use crate::xcp::error::XcpError;
+use crate::xcp::dedup::DuplicateFilter;
+use crate::xcp::checksum;
+use crate::xcp::flash::{IdentityCodec, ProgramCodec};
+use std::time::{Duration, Instant};
+use crate::xcp::can_ids::{CanId, CanIdPair};
+use crate::xcp::daq::BusLoadLimit;
+use crate::xcp::session::{SessionEvent, SessionEventBus, TimedSessionEvent, SESSION_EVENT_BUFFER};
+use std::sync::mpsc::Receiver;
+use socketcan::{CanFrame, EmbeddedFrame, Id};
+use crate::xcp::transport::XcpTransport;
+use std::io;
+use std::thread;
+
+/// Hook run before a command of a given code is transmitted.
+pub type XcpPreHook<'a> = Box<dyn FnMut(&mut XcpMaster<'a>) + 'a>;
+
+/// Hook run after the response (positive or negative) to a command of a given code arrived.
+/// The second argument is the raw response frame data.
+pub type XcpPostHook<'a> = Box<dyn FnMut(&mut XcpMaster<'a>, &[u8]) + 'a>;
+
+/// Computes a checksum the way the slave does for a checksum type it reports, see
+/// `XcpMaster::register_checksum_algorithm`.
+pub type ChecksumAlgorithm<'a> = Box<dyn Fn(&[u8]) -> u32 + 'a>;
+
+/// How the master computes a checksum type registered with it.
+enum LocalChecksum<'a> {
+    /// A built-in algorithm, for a slave reporting it under another code.
+    Builtin(ChecksumType),
+    Custom(ChecksumAlgorithm<'a>),
+}
+
+/// Computes the UNLOCK key for a GET_SEED seed, see `XcpMaster::unlock_resource`.
+///
+/// Implemented for closures `Fn(&[u8]) -> Vec<u8>`, the form `unlock` takes.
+pub trait SeedKeyAlgorithm {
+    /// The key unlocking `resource` for `seed`.
+    fn compute_key(&self, resource: XcpResourceFlags, seed: &[u8]) -> Vec<u8>;
+}
+
+impl<F: Fn(&[u8]) -> Vec<u8>> SeedKeyAlgorithm for F {
+    fn compute_key(&self, _resource: XcpResourceFlags, seed: &[u8]) -> Vec<u8> {
+        self(seed)
+    }
+}
+
+/// Outcome of unlocking one resource in `XcpMaster::unlock_all`.
+#[derive(Debug)]
+pub struct ResourceUnlock {
+    pub resource: XcpResource,
+    pub result: Result<(), XcpError>,
+}
+
+impl ResourceUnlock {
+    /// The negative response code the slave rejected the exchange with, if any.
+    pub fn error_code(&self) -> Option<XcpErrorCode> {
+        self.result.as_ref().err().and_then(XcpError::error_code)
+    }
+}
+
+/// Result of `XcpMaster::unlock_all`.
+#[derive(Debug, Default)]
+pub struct UnlockSummary {
+    /// The protected resources in the order they were unlocked. With `fail_fast`,
+    /// the ones after the first failure are missing.
+    pub resources: Vec<ResourceUnlock>,
+    /// The resource protection status after the last exchange.
+    pub protection: XcpResourceFlags,
+}
+
+impl UnlockSummary {
+    /// Whether every protected resource was unlocked.
+    pub fn is_unlocked(&self) -> bool {
+        self.resources.iter().all(|unlock| unlock.result.is_ok())
+    }
+}
+
+/// The resource whose protection keeps the slave from executing `code`.
+fn protecting_resource(code: XcpCommandCode) -> Option<XcpResource> {
+    use XcpCommandCode::*;
+    match code {
+        Download | DownloadNext | DownloadMax | ShortDownload | ModifyBits | SetCalPage | GetCalPage
+        | GetPagProcessorInfo | GetSegmentInfo | GetPageInfo | SetSegmentMode | GetSegmentMode
+        | CopyCalPage => Some(XcpResource::CalPage),
+        ClearDaqList | SetDaqPtr | WriteDaq | SetDaqListMode | GetDaqListMode | StartStopDaqList
+        | StartStopSynch | GetDaqClock | ReadDaq | GetDaqProcessorInfo | GetDaqResolutionInfo
+        | GetDaqListInfo | GetDaqEventInfo | FreeDaq | AllocDaq | AllocOdt | AllocOdtEntry
+        | WriteDaqMultiple => Some(XcpResource::Daq),
+        ProgramStart | ProgramClear | Program | ProgramReset | GetPgmProcessorInfo | GetSectorInfo
+        | ProgramPrepare | ProgramFormat | ProgramNext | ProgramMax | ProgramVerify => Some(XcpResource::Pgm),
+        _ => None,
+    }
+}
+
+/// Whether sending `code` a second time after it was rejected has the effect of
+/// sending it once: it neither relies on nor advances the MTA or DAQ pointer and
+/// is not part of a sequence.
+fn is_idempotent(code: XcpCommandCode) -> bool {
+    use XcpCommandCode::*;
+    matches!(code, ShortDownload | SetCalPage | GetCalPage | GetPagProcessorInfo | GetSegmentInfo
+        | GetPageInfo | SetSegmentMode | GetSegmentMode | CopyCalPage | ClearDaqList | SetDaqListMode
+        | GetDaqListMode | StartStopDaqList | StartStopSynch | GetDaqClock | GetDaqProcessorInfo
+        | GetDaqResolutionInfo | GetDaqListInfo | GetDaqEventInfo | ProgramStart | GetPgmProcessorInfo
+        | GetSectorInfo)
+}
+
+/// How often a frame is retried while the socket's TX queue is full.
+const TX_RETRY_LIMIT: u32 = 8;
+
+/// Delay before the first retry of a frame; doubled for every further retry.
+const TX_RETRY_BACKOFF: Duration = Duration::from_micros(200);
+
+/// Most DTOs kept for `recv_dto` while waiting for responses; further ones are
+/// dropped and counted in `XcpStatistics::dtos_dropped`.
+pub const DTO_QUEUE_LIMIT: usize = 4096;
+
+/// How long `drop_duplicates` waits for frames, short enough to only see the ones
+/// that already arrived.
+const DUPLICATE_POLL: Duration = Duration::from_micros(1);
+
+/// ENOBUFS on Linux, returned by SocketCAN when the interface TX queue is full.
+pub(crate) const ENOBUFS: i32 = 105;
+
+/// Whether a transmit error only means the TX queue is momentarily full.
+pub(crate) fn is_tx_backpressure(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.raw_os_error() == Some(ENOBUFS)
+}
+
+/// The groups of commands that share a response timeout, see `TimeoutConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeoutClass {
+    /// CONNECT.
+    Connect,
+    /// Everything not in another class.
+    Standard,
+    /// UPLOAD, DOWNLOAD and their variants, MODIFY_BITS and BUILD_CHECKSUM.
+    Memory,
+    /// SET_CAL_PAGE, COPY_CAL_PAGE and SET_SEGMENT_MODE.
+    PageSwitch,
+    /// PROGRAM_START, PROGRAM_CLEAR, PROGRAM and the other PGM commands that change
+    /// the flash.
+    Program,
+    /// START_STOP_DAQ_LIST and START_STOP_SYNCH.
+    Daq,
+}
+
+impl TimeoutClass {
+    /// The class `code` belongs to.
+    pub fn of(code: XcpCommandCode) -> TimeoutClass {
+        match code {
+            XcpCommandCode::Connect => TimeoutClass::Connect,
+            XcpCommandCode::Upload | XcpCommandCode::ShortUpload | XcpCommandCode::Download
+            | XcpCommandCode::DownloadNext | XcpCommandCode::DownloadMax | XcpCommandCode::ShortDownload
+            | XcpCommandCode::ModifyBits | XcpCommandCode::BuildChecksum => TimeoutClass::Memory,
+            XcpCommandCode::SetCalPage | XcpCommandCode::CopyCalPage | XcpCommandCode::SetSegmentMode => {
+                TimeoutClass::PageSwitch
+            }
+            XcpCommandCode::ProgramStart | XcpCommandCode::ProgramClear | XcpCommandCode::Program
+            | XcpCommandCode::ProgramReset | XcpCommandCode::ProgramPrepare | XcpCommandCode::ProgramFormat
+            | XcpCommandCode::ProgramNext | XcpCommandCode::ProgramMax | XcpCommandCode::ProgramVerify => {
+                TimeoutClass::Program
+            }
+            XcpCommandCode::StartStopDaqList | XcpCommandCode::StartStopSynch => TimeoutClass::Daq,
+            _ => TimeoutClass::Standard,
+        }
+    }
+}
+
+/// How long the master waits for the response to a command, per `TimeoutClass`.
+///
+/// The defaults follow the split of the timeouts T1 to T7 of the specification,
+/// with room for slow slaves: milliseconds for CONNECT, seconds for ordinary
+/// commands, and tens of seconds for clearing flash sectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// Kept short so that checking whether a slave is there fails fast.
+    pub connect: Duration,
+    pub standard: Duration,
+    pub memory: Duration,
+    pub page_switch: Duration,
+    pub program: Duration,
+    pub daq: Duration,
+}
+
+impl TimeoutConfig {
+    pub fn get(&self, class: TimeoutClass) -> Duration {
+        match class {
+            TimeoutClass::Connect => self.connect,
+            TimeoutClass::Standard => self.standard,
+            TimeoutClass::Memory => self.memory,
+            TimeoutClass::PageSwitch => self.page_switch,
+            TimeoutClass::Program => self.program,
+            TimeoutClass::Daq => self.daq,
+        }
+    }
+
+    pub fn set(&mut self, class: TimeoutClass, timeout: Duration) {
+        match class {
+            TimeoutClass::Connect => self.connect = timeout,
+            TimeoutClass::Standard => self.standard = timeout,
+            TimeoutClass::Memory => self.memory = timeout,
+            TimeoutClass::PageSwitch => self.page_switch = timeout,
+            TimeoutClass::Program => self.program = timeout,
+            TimeoutClass::Daq => self.daq = timeout,
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> TimeoutConfig {
+        TimeoutConfig {
+            connect: Duration::from_millis(100),
+            standard: Duration::from_secs(1),
+            memory: Duration::from_secs(2),
+            page_switch: Duration::from_secs(2),
+            program: Duration::from_secs(30),
+            daq: Duration::from_secs(2),
+        }
+    }
+}
+
+/// How long `set_request_and_wait` listens for an event before polling GET_STATUS again.
+const STORE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Decodes the ERR packet `data` answering `command`.
+fn negative_response(command: XcpCommandCode, data: &[u8]) -> XcpError {
+    if data.len() < NegativeResponse::MIN_LENGTH {
+        return XcpError::ResponseTooShort { command, length: data.len(), expected: NegativeResponse::MIN_LENGTH };
+    }
+    XcpError::NegativeResponse(XcpResponseFrame::<NegativeResponse> { data: NegativeResponse::from_can_frame(data) })
+}
+
+/// Turns ERR_SEGMENT_NOT_VALID into `XcpError::SegmentNotValid` for `segment`.
+fn segment_error(e: XcpError, segment: u8) -> XcpError {
+    match e.error_code() {
+        Some(XcpErrorCode::ErrSegmentNotValid) => XcpError::SegmentNotValid { segment },
+        _ => e,
+    }
+}
+
+/// Turns the write errors a slave reports for the memory at `address` into
+/// `XcpError::WriteProtected` and `XcpError::AccessLocked`.
+fn write_error(e: XcpError, address: u32) -> XcpError {
+    match e.error_code() {
+        Some(XcpErrorCode::ErrWriteProtected) => XcpError::WriteProtected { address },
+        Some(XcpErrorCode::ErrAccessLocked) => XcpError::AccessLocked { address },
+        _ => e,
+    }
+}
+
+/// Turns ERR_ACCESS_LOCKED during programming into `XcpError::PgmLocked`.
+fn pgm_error(e: XcpError) -> XcpError {
+    match e.error_code() {
+        Some(XcpErrorCode::ErrAccessLocked) => XcpError::PgmLocked,
+        _ => e,
+    }
+}
+
+/// How long `program_reset` waits for a response before assuming the slave reset
+/// without sending one.
+const PROGRAM_RESET_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long `freeze_calibration` waits for the slave to store the frozen pages.
+const FREEZE_STORE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Turns ERR_OUT_OF_RANGE into `XcpError::DaqListNotValid` for `daq_list`.
+fn daq_list_error(e: XcpError, daq_list: u16) -> XcpError {
+    match e.error_code() {
+        Some(XcpErrorCode::ErrOutOfRange) => XcpError::DaqListNotValid { daq_list },
+        _ => e,
+    }
+}
+
+/// Checks that an ODT entry has a non-zero size and a bit offset the slave can use.
+fn check_daq_entry(bit_offset: Option<u8>, size: u8) -> Result<(), XcpError> {
+    if size == 0 || bit_offset.is_some_and(|bit| bit > WriteDaqCommand::MAX_BIT_OFFSET) {
+        return Err(XcpError::InvalidDaqEntry { bit_offset, size });
+    }
+    Ok(())
+}
+
+/// Turns ERR_DAQ_CONFIG and ERR_OUT_OF_RANGE into typed errors for the ODT entry at `ptr`.
+fn daq_entry_error(e: XcpError, ptr: Option<DaqPtr>) -> XcpError {
+    match e.error_code() {
+        Some(XcpErrorCode::ErrDaqConfig) => XcpError::DaqConfigInvalid { ptr },
+        Some(XcpErrorCode::ErrOutOfRange) => XcpError::DaqEntryOutOfRange { ptr },
+        _ => e,
+    }
+}
+
+/// Counters describing the health of the link.
+#[derive(Debug, Default, Clone)]
+pub struct XcpStatistics {
+    /// Response frames dropped because they repeated the previous response.
+    pub duplicate_responses_dropped: u64,
+    /// Frames that had to be transmitted again because the TX queue was full.
+    pub tx_retries: u64,
+    /// Commands that failed because no response arrived in time.
+    pub response_timeouts: u64,
+    /// DTOs dropped while waiting for responses because `DTO_QUEUE_LIMIT` DTOs were
+    /// already queued.
+    pub dtos_dropped: u64,
+}
+
+/// Chunk-wise upload of a memory range, see `XcpMaster::upload_stream`.
+///
+/// Every call to `next` performs exactly one UPLOAD, so the stream can be dropped at
+/// any point and the master used for other commands right away. After an error the
+/// stream ends.
+pub struct UploadStream<'m, 'a> {
+    master: &'m mut XcpMaster<'a>,
+    remaining: usize,
+}
+
+impl<'m, 'a> UploadStream<'m, 'a> {
+    /// Number of bytes not yet uploaded.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<'m, 'a> Iterator for UploadStream<'m, 'a> {
+    type Item = Result<Vec<u8>, XcpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 { return None }
+
+        let (n_elements, chunk_len) = self.master.upload_chunk(self.remaining);
+        match self.master.upload(n_elements) {
+            Ok(mut chunk) => {
+                chunk.truncate(chunk_len);
+                self.remaining -= chunk_len;
+                Some(Ok(chunk))
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// ODT entry addressed by the slave's DAQ list pointer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DaqPtr {
+    pub daq_list: u16,
+    pub odt: u8,
+    pub entry: u8,
+}
+
+/// Progress of a dynamic DAQ configuration, which must be built in the order
+/// FREE_DAQ, ALLOC_DAQ, ALLOC_ODT, ALLOC_ODT_ENTRY.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DaqAllocPhase {
+    /// FREE_DAQ has not been sent in this session.
+    #[default]
+    NotFreed,
+    Freed,
+    DaqAllocated,
+    OdtAllocated,
+    OdtEntryAllocated,
+}
+
+/// An event channel of the slave, as listed by `enumerate_events`.
+#[derive(Debug, Clone)]
+pub struct DaqEventDescriptor {
+    pub event_channel: u16,
+    pub info: GetDaqEventInfoResponse,
+    /// Name of the channel, e.g. "10ms task", if it was requested.
+    pub name: Option<String>,
+}
+
+/// How `write_memory_verified` confirms a write.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WriteVerification {
+    /// Read the range back and compare it byte by byte.
+    ReadBack,
+    /// Compare a BUILD_CHECKSUM over the range with a locally computed checksum,
+    /// which is much faster for large regions.
+    Checksum,
+}
 
 pub struct XcpMaster<'a> {
-    pub tx_id: u32,
-    pub rx_id: u32,
+    pub ids: CanIdPair,
+    /// Largest command/response packet in bytes. Set from CONNECT; once connected,
+    /// transfers are sized by the value the slave reported even if this is changed.
     pub max_cto: usize,
     pub max_dto: usize,
-    pub socket: &'a mut CanSocket
+    pub socket: &'a mut dyn XcpTransport,
+    /// Protocol layer version of the slave, learned from CONNECT and refined by
+    /// `get_version`. Use `set_protocol_version` when the minor version is known
+    /// from elsewhere.
+    pub protocol_version: Option<XcpVersion>,
+    /// Whether the minor version of `protocol_version` is known, rather than the 0
+    /// CONNECT leaves it at. Until then commands are only checked against the major
+    /// version.
+    protocol_minor_known: bool,
+    /// Byte order and address granularity of the slave, learned from CONNECT.
+    pub comm_mode_basic: XcpCommModeBasic,
+    /// Optional communication modes and block transfer limits, learned from
+    /// GET_COMM_MODE_INFO.
+    pub comm_mode_info: Option<GetCommModeInfoResponse>,
+    /// Number of segments and paging properties, learned from GET_PAG_PROCESSOR_INFO.
+    pub pag_processor_info: Option<GetPagProcessorInfoResponse>,
+    /// Capabilities and DTO layout of the DAQ processor, learned from
+    /// GET_DAQ_PROCESSOR_INFO.
+    pub daq_processor_info: Option<GetDaqProcessorInfoResponse>,
+    /// Number of sectors and clear modes of the programming processor, learned from
+    /// GET_PGM_PROCESSOR_INFO.
+    pub pgm_processor_info: Option<GetPgmProcessorInfoResponse>,
+    /// Transfer limits while programming, learned from PROGRAM_START and cleared
+    /// by PROGRAM_RESET.
+    pub pgm_info: Option<ProgramStartResponse>,
+    /// Re-issue SET_MTA every this many UPLOAD chunks in `read_memory`, for slaves
+    /// known to lose the MTA. `None` (the default) relies on auto-increment throughout.
+    pub mta_refresh_interval: Option<usize>,
+    /// The slave implements the optional DOWNLOAD_MAX command (known e.g. from the A2L
+    /// file), so `download_block` may use it in standard mode.
+    pub use_download_max: bool,
+    /// Refuse to send commands the slave's protocol version predates instead of
+    /// sending them and publishing `SessionEvent::UnsupportedCommand`.
+    pub strict: bool,
+    /// Have `configure_daq` check the bus load the lists would cause. `None` (the
+    /// default) skips the check and the GET_DAQ_EVENT_INFO requests it needs.
+    pub bus_load_limit: Option<BusLoadLimit>,
+    /// How long to wait for the response to a command before failing with
+    /// `XcpError::Timeout`, chosen by the class of the command.
+    /// EV_CMD_PENDING from the slave restarts the wait; frames on other CAN IDs do
+    /// not affect it. See `with_timeouts` to change them for a single call.
+    pub timeouts: TimeoutConfig,
+    program_codec: Box<dyn ProgramCodec + 'a>,
+    /// Resource protection last reported by CONNECT, GET_STATUS or UNLOCK; `None`
+    /// once it may be stale.
+    protection: Option<XcpResourceFlags>,
+    seed_key_providers: HashMap<XcpResource, Box<dyn SeedKeyAlgorithm + 'a>>,
+    /// Checksum algorithms by BUILD_CHECKSUM type code, taking precedence over `checksum::compute`.
+    checksum_algorithms: HashMap<u8, LocalChecksum<'a>>,
+    /// Commands beyond the idempotent ones retried after an automatic unlock.
+    unlock_retry: HashSet<XcpCommandCode>,
+    pre_hooks: HashMap<XcpCommandCode, Vec<XcpPreHook<'a>>>,
+    post_hooks: HashMap<XcpCommandCode, Vec<XcpPostHook<'a>>>,
+    in_hook: bool,
+    events: SessionEventBus,
+    duplicates: DuplicateFilter,
+    slave_events: VecDeque<XcpEventCode>,
+    dto_queue: VecDeque<Vec<u8>>,
+    /// Keep DTOs arriving while waiting for responses for `recv_dto`; cleared while
+    /// a `DaqReceiver` thread receives them on its own socket.
+    pub(crate) queue_dtos: bool,
+    negotiated_max_cto: Option<usize>,
+    daq_ptr: Option<DaqPtr>,
+    first_pids: HashMap<u16, u8>,
+    daq_alloc_phase: DaqAllocPhase,
+    stats: XcpStatistics
 }
 
-impl<'a> XcpMaster<'a> {
-    /// Establishes a connection with the XCP server.
+impl<'a> XcpMaster<'a> {
+    /// Creates a master talking on `socket`, usually a `CanSocket`, transmitting
+    /// commands on `ids.cmd` and listening for responses on `ids.res`.
+    ///
+    /// `max_cto` and `max_dto` default to 8, the classic CAN payload size.
+    pub fn new(socket: &'a mut dyn XcpTransport, ids: CanIdPair) -> XcpMaster<'a> {
+        XcpMaster {
+            ids,
+            max_cto: 8,
+            max_dto: 8,
+            socket,
+            protocol_version: None,
+            protocol_minor_known: false,
+            comm_mode_basic: XcpCommModeBasic::default(),
+            comm_mode_info: None,
+            pag_processor_info: None,
+            daq_processor_info: None,
+            pgm_processor_info: None,
+            pgm_info: None,
+            mta_refresh_interval: None,
+            use_download_max: false,
+            strict: false,
+            bus_load_limit: None,
+            timeouts: TimeoutConfig::default(),
+            program_codec: Box::new(IdentityCodec),
+            protection: None,
+            seed_key_providers: HashMap::new(),
+            checksum_algorithms: HashMap::new(),
+            unlock_retry: HashSet::new(),
+            pre_hooks: HashMap::new(),
+            post_hooks: HashMap::new(),
+            in_hook: false,
+            events: SessionEventBus::default(),
+            duplicates: DuplicateFilter::default(),
+            slave_events: VecDeque::new(),
+            dto_queue: VecDeque::new(),
+            queue_dtos: true,
+            negotiated_max_cto: None,
+            daq_ptr: None,
+            first_pids: HashMap::new(),
+            daq_alloc_phase: DaqAllocPhase::NotFreed,
+            stats: XcpStatistics::default()
+        }
+    }
+
+    /// Drops response frames identical to the previous response if they arrive within
+    /// `window` of it, to cope with gateways that deliver frames twice. `None` (the
+    /// default) disables this.
+    ///
+    /// Only copies that arrived before the next command is sent are dropped, so
+    /// identical responses to consecutive commands are never merged.
+    pub fn set_duplicate_window(&mut self, window: Option<Duration>) {
+        self.duplicates = DuplicateFilter::new(window);
+    }
+
+    /// Runs `operation` with `timeouts` instead of the configured ones, e.g. for a
+    /// slave known to clear its flash slowly, and restores them afterwards.
+    pub fn with_timeouts<T>(&mut self, timeouts: TimeoutConfig, operation: impl FnOnce(&mut XcpMaster<'a>) -> T) -> T {
+        let configured = std::mem::replace(&mut self.timeouts, timeouts);
+        let result = operation(self);
+        self.timeouts = configured;
+        result
+    }
+
+    /// Link statistics collected since the master was created.
+    pub fn statistics(&self) -> &XcpStatistics {
+        &self.stats
+    }
+
+    /// Subscribes to the session event stream.
+    ///
+    /// Each subscriber buffers up to `SESSION_EVENT_BUFFER` events; events that do not
+    /// fit are dropped for that subscriber only.
+    pub fn subscribe_events(&mut self) -> Receiver<TimedSessionEvent> {
+        self.events.subscribe(SESSION_EVENT_BUFFER)
+    }
+
+    /// Publishes `event` to the subscribers of `subscribe_events`.
+    pub(crate) fn publish_event(&mut self, event: SessionEvent) {
+        self.events.publish(event);
+    }
+
+    /// Removes and returns the EV packets received since the last call, oldest first.
+    ///
+    /// Events arriving while the master waits for a response are queued here instead
+    /// of being mistaken for the response.
+    pub fn take_events(&mut self) -> Vec<XcpEventCode> {
+        self.slave_events.drain(..).collect()
+    }
+
+    /// Registers a hook executed before every command with the given code.
+    ///
+    /// The hook receives the master and may issue its own commands through it, e.g. a
+    /// proprietary USER_CMD before PROGRAM_CLEAR. Commands issued from within a hook
+    /// do not trigger any hooks themselves.
+    pub fn add_pre_hook<F: FnMut(&mut XcpMaster<'a>) + 'a>(&mut self, code: XcpCommandCode, hook: F) {
+        self.pre_hooks.entry(code).or_default().push(Box::new(hook));
+    }
+
+    /// Registers a hook executed after the response to every command with the given code.
+    ///
+    /// The hook receives the master and the raw response data, which may be a negative
+    /// response. As with pre-hooks, commands issued from within the hook skip all hooks.
+    pub fn add_post_hook<F: FnMut(&mut XcpMaster<'a>, &[u8]) + 'a>(&mut self, code: XcpCommandCode, hook: F) {
+        self.post_hooks.entry(code).or_default().push(Box::new(hook));
+    }
+
+    /// Registers the seed/key algorithms used to unlock resources on demand, replacing
+    /// the ones registered before.
+    ///
+    /// When a command fails with ERR_ACCESS_LOCKED, the resource protecting it is
+    /// unlocked with its provider as by `unlock_resource`, and the command is sent
+    /// once more. Only idempotent commands are retried unless allowed with
+    /// `allow_unlock_retry`, and never the frames of a block transfer. Each automatic
+    /// unlock is published as `SessionEvent::AutoUnlock`, and GET_SEED and UNLOCK run
+    /// the hooks registered for them.
+    pub fn set_seed_key_provider(&mut self, providers: HashMap<XcpResource, Box<dyn SeedKeyAlgorithm + 'a>>) {
+        self.seed_key_providers = providers;
+    }
+
+    /// Registers how to compute the checksum the slave reports as `checksum_type` in
+    /// BUILD_CHECKSUM, e.g. 0xFF for a proprietary user-defined algorithm, replacing
+    /// any built-in one. Used wherever memory is verified, by `verify_block`,
+    /// flashing and `dump_memory`.
+    pub fn register_checksum_algorithm<F: Fn(&[u8]) -> u32 + 'a>(&mut self, checksum_type: u8, algorithm: F) {
+        self.checksum_algorithms.insert(checksum_type, LocalChecksum::Custom(Box::new(algorithm)));
+    }
+
+    /// Like `register_checksum_algorithm`, with the built-in algorithm `name` as
+    /// `ChecksumType::from_name` accepts it, e.g. "XCP_CRC_16_CITT" for a slave
+    /// reporting CRC-16-CCITT as user-defined.
+    ///
+    /// # Returns
+    /// `XcpError::UnknownChecksumAlgorithm` if `name` is not a built-in algorithm.
+    pub fn register_builtin_checksum(&mut self, checksum_type: u8, name: &str) -> Result<(), XcpError> {
+        let builtin = ChecksumType::from_name(name)
+            .ok_or_else(|| XcpError::UnknownChecksumAlgorithm(name.to_string()))?;
+        self.checksum_algorithms.insert(checksum_type, LocalChecksum::Builtin(builtin));
+        Ok(())
+    }
+
+    /// Has commands with `code` retried after an automatic unlock even though
+    /// repeating them is not known to be harmless, e.g. DOWNLOAD after a fresh SET_MTA.
+    pub fn allow_unlock_retry(&mut self, code: XcpCommandCode) {
+        self.unlock_retry.insert(code);
+    }
+
+    /// Establishes a connection with the XCP server.
+    ///
+    /// # Arguments
+    /// * `sock` - A mutable reference to the CAN socket.
+    /// * `mode` - The connection mode.
+    ///
+    /// This function sends a connection request and processes the response.
+    pub fn connect(&mut self, mode: ConnectMode) -> Result<XcpResponseFrame<ConnectResponse>, XcpError> {
+        let mut connect_req = XcpCommandFrame {
+            data: ConnectCommand { mode },
+        };
+
+        let connect_resp = self.send_recv_one_blocking(&mut connect_req, |frame| {
+            XcpResponseFrame::<ConnectResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.protocol_version = Some(XcpVersion::new(connect_resp.data.protocol_version, 0));
+        self.protocol_minor_known = false;
+        self.comm_mode_basic = connect_resp.data.comm_mode_basic;
+        self.max_cto = connect_resp.data.max_cto as usize;
+        self.max_dto = connect_resp.data.max_dto as usize;
+        self.negotiated_max_cto = Some(self.max_cto);
+        self.daq_ptr = None;
+        self.first_pids.clear();
+        self.daq_alloc_phase = DaqAllocPhase::NotFreed;
+        self.protection = Some(connect_resp.data.resource);
+        self.events.publish(SessionEvent::Connected { protection: connect_resp.data.resource });
+        Ok(connect_resp)
+    }
+
+    /// Ends the session with DISCONNECT.
+    pub fn disconnect(&mut self) -> Result<(), XcpError> {
+        let mut disconnect_req = XcpCommandFrame { data: DisconnectCommand };
+        self.protection = None;
+
+        self.send_recv_one_blocking(&mut disconnect_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.events.publish(SessionEvent::Disconnected);
+        Ok(())
+    }
+
+    /// The resource protection last reported by the slave, kept from CONNECT,
+    /// GET_STATUS and UNLOCK. `None` before connecting and after DISCONNECT, the end
+    /// of the session or an unexpected ERR_ACCESS_LOCKED.
+    pub fn protection(&self) -> Option<XcpResourceFlags> {
+        self.protection
+    }
+
+    /// Queries the current session status and resource protection of the slave.
+    ///
+    /// # Returns
+    /// The decoded status, e.g. `status.protection.pgm` tells whether PGM still
+    /// needs to be unlocked.
+    /// Fields the negotiated protocol version does not define are `None`.
+    pub fn get_status(&mut self) -> Result<GetStatusResponse, XcpError> {
+        let mut status_req = XcpCommandFrame { data: GetStatusCommand };
+        let mode = self.comm_mode_basic;
+
+        let status_resp = self.send_recv_one_blocking(&mut status_req, |frame| {
+            XcpResponseFrame::<GetStatusResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        self.protection = Some(status_resp.data.protection);
+        let version = self.protocol_version.unwrap_or(XcpVersion::new(1, 0));
+        Ok(status_resp.data.for_protocol_version(version))
+    }
+
+    /// Queries the optional communication modes of the slave.
+    ///
+    /// The result is also kept in `comm_mode_info` so block transfers can honor
+    /// MAX_BS and MIN_ST.
+    pub fn get_comm_mode_info(&mut self) -> Result<GetCommModeInfoResponse, XcpError> {
+        let mut info_req = XcpCommandFrame { data: GetCommModeInfoCommand };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetCommModeInfoResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.comm_mode_info = Some(info_resp.data);
+        Ok(info_resp.data)
+    }
+
+    /// Queries the protocol and transport layer versions with GET_VERSION, which
+    /// also tells the minor version CONNECT leaves out; `protocol_version` is
+    /// updated with it.
+    ///
+    /// GET_VERSION is sent whatever the protocol version. A slave answering
+    /// ERR_CMD_UNKNOWN predates it, and `protocol_version` becomes x.0 of the major
+    /// version CONNECT reported.
+    pub fn get_version(&mut self) -> Result<GetVersionResponse, XcpError> {
+        let mut version_req = XcpCommandFrame { data: GetVersionCommand };
+
+        let version_resp = self.send_recv_one_blocking(&mut version_req, |frame| {
+            XcpResponseFrame::<GetVersionResponse>::from_can_frame(frame.data())
+        });
+        let version_resp = match version_resp {
+            Err(e) if e.error_code() == Some(XcpErrorCode::ErrCmdUnknown) => {
+                let major = self.protocol_version.map_or(1, |version| version.major);
+                self.set_protocol_version(XcpVersion::new(major, 0));
+                return Err(e);
+            }
+            result => result?,
+        };
+
+        self.set_protocol_version(version_resp.data.protocol_layer);
+        Ok(version_resp.data)
+    }
+
+    /// Sets the protocol layer version of the slave, including the minor version,
+    /// e.g. from the A2L file when the slave does not implement GET_VERSION.
+    pub fn set_protocol_version(&mut self, version: XcpVersion) {
+        self.protocol_version = Some(version);
+        self.protocol_minor_known = true;
+    }
+
+    /// Reads identification data of the given type from the slave.
+    ///
+    /// If the slave does not return the identification in the GET_ID response, it is
+    /// uploaded from the MTA the slave set up, in chunks as large as `max_cto` allows.
+    ///
+    /// # Returns
+    /// The raw identification bytes, `length` as reported by the slave.
+    pub fn get_id(&mut self, id_type: IdType) -> Result<Vec<u8>, XcpError> {
+        self.get_id_with_progress(id_type, None)
+    }
+
+    /// Like `get_id`, reporting `(transferred, total)` bytes to `progress` after
+    /// every uploaded chunk.
+    pub fn get_id_with_progress(&mut self, id_type: IdType, progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<Vec<u8>, XcpError> {
+        let mut id_req = XcpCommandFrame { data: GetIdCommand { id_type } };
+        let mode = self.comm_mode_basic;
+
+        let id_resp = self.send_recv_one_blocking(&mut id_req, |frame| {
+            XcpResponseFrame::<GetIdResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        let length = id_resp.data.length as usize;
+        if id_resp.data.in_response() {
+            let mut id = id_resp.data.identification;
+            id.truncate(length);
+            return Ok(id.to_vec());
+        }
+
+        self.upload_from_mta(length, progress)
+    }
+
+    /// Uploads the ASAM MC2 (A2L) file the slave exposes via GET_ID type 4.
+    ///
+    /// These files are often several megabytes, so `progress` is called with
+    /// `(transferred, total)` bytes after every chunk.
+    pub fn upload_a2l(&mut self, progress: Option<&mut dyn FnMut(usize, usize)>) -> Result<Vec<u8>, XcpError> {
+        self.get_id_with_progress(IdType::FileToUpload, progress)
+    }
+
+    /// Sets the memory transfer address used by the following UPLOAD/DOWNLOAD commands.
+    pub fn set_mta(&mut self, address: u32, address_extension: u8) -> Result<(), XcpError> {
+        let mut mta_req = XcpCommandFrame {
+            data: SetMtaCommand { address, address_extension, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        self.send_recv_one_blocking(&mut mta_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Uploads `n_elements` elements of the slave's address granularity from the
+    /// current MTA, which the slave advances past them.
+    ///
+    /// Only standard mode is supported, so the data must fit into one response CTO.
+    pub fn upload(&mut self, n_elements: u8) -> Result<Vec<u8>, XcpError> {
+        self.upload_frame(n_elements).map(Vec::from)
+    }
+
+    /// Like `upload`, returning the data in the response buffer so loops can copy
+    /// it out without allocating per chunk.
+    fn upload_frame(&mut self, n_elements: u8) -> Result<XcpFrameBuf, XcpError> {
+        let mode = self.comm_mode_basic;
+        let requested = self.upload_size(XcpCommandCode::Upload, n_elements)?;
+        let mut upload_req = XcpCommandFrame { data: UploadCommand { n_elements } };
+
+        let upload_resp = self.send_recv_one_blocking(&mut upload_req, |frame| {
+            XcpResponseFrame::<UploadResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        let mut data = upload_resp.data.data;
+        data.truncate(requested);
+        Ok(data)
+    }
+
+    /// Uploads `n_elements` elements from `address` without a separate SET_MTA.
+    ///
+    /// The data must fit into one response CTO.
+    pub fn short_upload(&mut self, address: u32, address_extension: u8, n_elements: u8) -> Result<Vec<u8>, XcpError> {
+        let mode = self.comm_mode_basic;
+        let requested = self.upload_size(XcpCommandCode::ShortUpload, n_elements)?;
+        let mut upload_req = XcpCommandFrame {
+            data: ShortUploadCommand { n_elements, address, address_extension, byte_order: mode.endianness() }
+        };
+
+        let upload_resp = self.send_recv_one_blocking(&mut upload_req, |frame| {
+            XcpResponseFrame::<ShortUploadResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        let mut data = upload_resp.data.data;
+        data.truncate(requested);
+        Ok(data.to_vec())
+    }
+
+    /// Reads `size` bytes at `address`: with one SHORT_UPLOAD if they fit into a
+    /// response, otherwise with SET_MTA and UPLOAD as `read_memory` does.
+    pub fn read_bytes(&mut self, address: u32, address_extension: u8, size: usize) -> Result<Vec<u8>, XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let elements = size.div_ceil(ag);
+        if elements * ag <= self.cto_limit() - ag && elements <= u8::MAX as usize {
+            let mut value = self.short_upload(address, address_extension, elements as u8)?;
+            value.truncate(size);
+            Ok(value)
+        } else {
+            self.read_memory(address, address_extension, size)
+        }
+    }
+
+    /// Size in bytes of `n_elements` elements, checked against what a response CTO can carry.
+    fn upload_size(&self, command: XcpCommandCode, n_elements: u8) -> Result<usize, XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let requested = n_elements as usize * ag;
+        let max = self.cto_limit() - ag;
+        if requested > max {
+            return Err(XcpError::PayloadTooLarge { command, requested, max });
+        }
+        Ok(requested)
+    }
+
+    /// Writes `data` to the slave's memory starting at the current MTA, in as many
+    /// DOWNLOAD commands as `max_cto` requires.
+    ///
+    /// Write protected or locked memory is reported as a negative response with
+    /// ERR_WRITE_PROTECTED or ERR_ACCESS_LOCKED, see `XcpError::error_code`.
+    pub fn download(&mut self, data: &[u8]) -> Result<(), XcpError> {
+        let granularity = self.comm_mode_basic.granularity();
+        if !data.len().is_multiple_of(granularity.size()) {
+            return Err(XcpError::UnalignedLength { length: data.len(), granularity: granularity.size() });
+        }
+
+        for command in DownloadCommand::split(data, granularity, self.cto_limit()) {
+            let mut download_req = XcpCommandFrame { data: command };
+
+            self.send_recv_one_blocking(&mut download_req, |frame| {
+                XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` to `address` without a separate SET_MTA.
+    ///
+    /// The data must fit into one CTO after the 8 byte header. Responses are handled
+    /// exactly like those to DOWNLOAD.
+    pub fn short_download(&mut self, address: u32, address_extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        let mode = self.comm_mode_basic;
+        let ag = mode.granularity().size();
+        if !data.len().is_multiple_of(ag) {
+            return Err(XcpError::UnalignedLength { length: data.len(), granularity: ag });
+        }
+        let max = self.cto_limit().saturating_sub(ShortDownloadCommand::DATA_OFFSET);
+        if data.len() > max {
+            return Err(XcpError::PayloadTooLarge { command: XcpCommandCode::ShortDownload, requested: data.len(), max });
+        }
+
+        let mut download_req = XcpCommandFrame {
+            data: ShortDownloadCommand {
+                n_elements: (data.len() / ag) as u8,
+                address,
+                address_extension,
+                data: data.to_vec(),
+                byte_order: mode.endianness(),
+            }
+        };
+
+        self.send_recv_one_blocking(&mut download_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Modifies bits of the 32-bit value at the current MTA in a single command, see
+    /// `ModifyBitsCommand`.
+    ///
+    /// Slaves that do not support the shift value answer ERR_OUT_OF_RANGE.
+    pub fn modify_bits(&mut self, shift: u8, and_mask: u16, xor_mask: u16) -> Result<(), XcpError> {
+        let mut modify_req = XcpCommandFrame {
+            data: ModifyBitsCommand { shift, and_mask, xor_mask, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        self.send_recv_one_blocking(&mut modify_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Writes `data` starting at the current MTA using master block mode, so the slave
+    /// acknowledges whole blocks of up to MAX_BS frames instead of every frame.
+    ///
+    /// Falls back to standard mode unless `comm_mode_info` (see `get_comm_mode_info`)
+    /// reports MASTER_BLOCK_MODE; there, full frames are sent with DOWNLOAD_MAX if
+    /// `use_download_max` is set. MIN_ST is honored between the frames of a block.
+    ///
+    /// # Returns
+    /// `XcpError::DownloadSequence` if the slave answers ERR_SEQUENCE. Its `offset`
+    /// is where the data the slave still expects starts, so the caller can retry
+    /// from there.
+    pub fn download_block(&mut self, data: &[u8]) -> Result<(), XcpError> {
+        let granularity = self.comm_mode_basic.granularity();
+        let ag = granularity.size();
+        if !data.len().is_multiple_of(ag) {
+            return Err(XcpError::UnalignedLength { length: data.len(), granularity: ag });
+        }
+
+        let Some(info) = self.comm_mode_info.filter(|info| info.comm_mode_optional.master_block_mode()) else {
+            if !self.use_download_max { return self.download(data) }
+
+            let capacity = self.cto_limit() - ag;
+            let full = data.len() / capacity * capacity;
+            for chunk in data[..full].chunks(capacity) {
+                self.download_max(chunk)?;
+            }
+            return self.download(&data[full..]);
+        };
+
+        let per_frame = (self.cto_limit() - granularity.download_offset()) / ag;
+        let block_elements = usize::min(per_frame * usize::max(info.max_bs as usize, 1), u8::MAX as usize);
+        let min_st = Duration::from_micros(info.min_st as u64 * 100);
+
+        for (i, block) in data.chunks(block_elements * ag).enumerate() {
+            let block_offset = i * block_elements * ag;
+            match self.download_one_block(block, granularity, min_st) {
+                Err(XcpError::NegativeResponse(resp)) if resp.data.error_code == XcpErrorCode::ErrSequence => {
+                    let expected_elements = resp.data.parameters.first().copied().unwrap_or(0);
+                    let received = block.len().saturating_sub(expected_elements as usize * ag);
+                    return Err(XcpError::DownloadSequence { expected_elements, offset: block_offset + received });
+                }
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes one full CTO worth of data (`max_cto` minus the address granularity)
+    /// at the current MTA with DOWNLOAD_MAX.
+    pub fn download_max(&mut self, data: &[u8]) -> Result<(), XcpError> {
+        let granularity = self.comm_mode_basic.granularity();
+        let expected = self.cto_limit() - granularity.size();
+        if data.len() != expected {
+            return Err(XcpError::InvalidPayloadLength { command: XcpCommandCode::DownloadMax, length: data.len(), expected });
+        }
+
+        let mut download_req = XcpCommandFrame { data: DownloadMaxCommand { data: data.to_vec(), granularity } };
+
+        self.send_recv_one_blocking(&mut download_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Sends one block of at most 255 elements and waits for its acknowledgement.
+    fn download_one_block(&mut self, block: &[u8], granularity: AddressGranularity, min_st: Duration) -> Result<(), XcpError> {
+        let (download, next) = DownloadNextCommand::block(block, granularity, self.cto_limit());
+        let mut code = download.get_code();
+        self.send_command(&XcpCommandFrame { data: download })?;
+
+        for command in next {
+            thread::sleep(min_st);
+            code = command.get_code();
+            self.send_command(&XcpCommandFrame { data: command })?;
+        }
+
+        self.recv_response(code, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+        Ok(())
+    }
+
+    /// Has the slave build a checksum over `block_size` elements starting at the MTA.
+    ///
+    /// # Returns
+    /// `XcpError::BlockSizeOutOfRange` carrying the largest supported block size if
+    /// the slave refuses the block with ERR_OUT_OF_RANGE and reports that size.
+    pub fn build_checksum(&mut self, block_size: u32) -> Result<BuildChecksumResponse, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut checksum_req = XcpCommandFrame {
+            data: BuildChecksumCommand { block_size, byte_order: mode.endianness() }
+        };
+
+        let checksum_resp = self.send_recv_one_blocking(&mut checksum_req, |frame| {
+            XcpResponseFrame::<BuildChecksumResponse>::from_can_frame_with_mode(frame.data(), mode)
+        });
+
+        match checksum_resp {
+            Ok(resp) => Ok(resp.data),
+            Err(XcpError::NegativeResponse(resp))
+                if resp.data.error_code == XcpErrorCode::ErrOutOfRange && resp.data.parameters.len() >= 6 => {
+                // the maximum block size sits at the same offset as the checksum
+                let max = mode.endianness().read_u32(&resp.data.parameters[2..6]);
+                Err(XcpError::BlockSizeOutOfRange { requested: block_size, max })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks that the slave's memory at `address` holds `data` by comparing a
+    /// BUILD_CHECKSUM over it with the same checksum computed locally.
+    ///
+    /// `data` should be a whole number of elements of the address granularity.
+    ///
+    /// # Returns
+    /// `XcpError::ChecksumMismatch` with both values if the checksums differ.
+    pub fn verify_block(&mut self, address: u32, address_extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        let (checksum_type, expected, actual) = self.compare_checksum(address, address_extension, data)?;
+        if expected != actual {
+            return Err(XcpError::ChecksumMismatch { checksum_type, expected, actual });
+        }
+        Ok(())
+    }
+
+    /// Builds the checksum over the memory `verify_block` checks, and computes it
+    /// over `data`.
+    ///
+    /// # Returns
+    /// The checksum type, the checksum of `data` and the one the slave built.
+    pub(crate) fn compare_checksum(&mut self, address: u32, address_extension: u8, data: &[u8])
+        -> Result<(ChecksumType, u32, u32), XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        self.set_mta(address, address_extension)?;
+        let built = self.build_checksum((data.len() / ag) as u32)?;
+
+        let expected = self.local_checksum(built.checksum_type, data)?;
+        Ok((built.checksum_type, expected, built.checksum))
+    }
+
+    /// Computes the checksum `checksum_type` over `data`, with the algorithm
+    /// registered for it if any.
+    fn local_checksum(&self, checksum_type: ChecksumType, data: &[u8]) -> Result<u32, XcpError> {
+        let byte_order = self.comm_mode_basic.endianness();
+        let checksum = match self.checksum_algorithms.get(&checksum_type.to_code()) {
+            Some(LocalChecksum::Builtin(builtin)) => checksum::compute(*builtin, data, byte_order),
+            Some(LocalChecksum::Custom(algorithm)) => Some(algorithm(data)),
+            None => checksum::compute(checksum_type, data, byte_order),
+        };
+        checksum.ok_or(XcpError::UnsupportedChecksumType(checksum_type))
+    }
+
+    /// Reads `length` bytes starting at `address`.
+    ///
+    /// The MTA is set once and then advanced by the slave with every UPLOAD, unless
+    /// `mta_refresh_interval` asks for it to be set again periodically. A length that is
+    /// not a multiple of the address granularity is rounded up on the wire.
+    ///
+    /// # Returns
+    /// `XcpError::PartialTransfer` with the data read so far if a command fails midway.
+    pub fn read_memory(&mut self, address: u32, address_extension: u8, length: usize) -> Result<Vec<u8>, XcpError> {
+        let ag = self.comm_mode_basic.granularity().size();
+        let mut data = Vec::<u8>::with_capacity(length);
+        let mut chunks = 0;
+
+        while data.len() < length {
+            let refresh = chunks == 0 || self.mta_refresh_interval.is_some_and(|n| n > 0 && chunks % n == 0);
+            if refresh {
+                if let Err(e) = self.set_mta(address + (data.len() / ag) as u32, address_extension) {
+                    return Err(XcpError::PartialTransfer { offset: data.len(), data, source: Box::new(e) });
+                }
+            }
+
+            let (n_elements, chunk_len) = self.upload_chunk(length - data.len());
+            match self.upload_frame(n_elements) {
+                Ok(chunk) => data.extend_from_slice(&chunk[..usize::min(chunk_len, chunk.len())]),
+                Err(e) => return Err(XcpError::PartialTransfer { offset: data.len(), data, source: Box::new(e) }),
+            }
+            chunks += 1;
+        }
+
+        Ok(data)
+    }
+
+    /// Writes `data` starting at `address`.
+    ///
+    /// Uses master block mode if the slave reported it in GET_COMM_MODE_INFO and
+    /// standard DOWNLOAD otherwise, see `download_block`. Transfers are sized by the
+    /// MAX_CTO learned from CONNECT.
+    ///
+    /// # Returns
+    /// `XcpError::AccessLocked` if CAL/PAG must be unlocked first, and
+    /// `XcpError::WriteProtected` if the memory cannot be written at all.
+    pub fn write_memory(&mut self, address: u32, address_extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        self.set_mta(address, address_extension)
+            .and_then(|_| self.download_block(data))
+            .map_err(|e| write_error(e, address))
+    }
+
+    /// Writes `data` to `address`: with one SHORT_DOWNLOAD if it fits into a command,
+    /// otherwise like `write_memory`.
+    ///
+    /// # Returns
+    /// The same errors as `write_memory`.
+    pub fn write_bytes(&mut self, address: u32, address_extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        if data.len() <= self.cto_limit().saturating_sub(ShortDownloadCommand::DATA_OFFSET) {
+            self.short_download(address, address_extension, data).map_err(|e| write_error(e, address))
+        } else {
+            self.write_memory(address, address_extension, data)
+        }
+    }
+
+    /// Like `write_memory`, then confirms the memory holds `data`.
+    ///
+    /// # Returns
+    /// `XcpError::VerificationFailed` with the first differing byte for
+    /// `WriteVerification::ReadBack`, `XcpError::ChecksumMismatch` for
+    /// `WriteVerification::Checksum`.
+    pub fn write_memory_verified(&mut self, address: u32, address_extension: u8, data: &[u8], verification: WriteVerification)
+        -> Result<(), XcpError> {
+        self.write_memory(address, address_extension, data)?;
+        self.verify_written(address, address_extension, data, verification)
+    }
+
+    /// Confirms the memory at `address` holds `data`, see `write_memory_verified`.
+    pub(crate) fn verify_written(&mut self, address: u32, address_extension: u8, data: &[u8], verification: WriteVerification)
+        -> Result<(), XcpError> {
+        match verification {
+            WriteVerification::ReadBack => {
+                let actual = self.read_bytes(address, address_extension, data.len())?;
+                match data.iter().zip(actual.iter()).position(|(e, a)| e != a) {
+                    Some(offset) => Err(XcpError::VerificationFailed { offset, expected: data[offset], actual: actual[offset] }),
+                    None => Ok(()),
+                }
+            }
+            WriteVerification::Checksum => self.verify_block(address, address_extension, data),
+        }
+    }
+
+    /// Sets the MTA to `address` and returns an iterator uploading `length` bytes
+    /// from there, one UPLOAD of up to MAX_CTO per item.
+    pub fn upload_stream(&mut self, address: u32, address_extension: u8, length: usize)
+        -> Result<UploadStream<'_, 'a>, XcpError> {
+        self.set_mta(address, address_extension)?;
+        Ok(UploadStream { master: self, remaining: length })
+    }
+
+    /// Number of elements to request for the next UPLOAD when `remaining` bytes are
+    /// left, and how many of the returned bytes to keep.
+    pub(crate) fn upload_chunk(&self, remaining: usize) -> (u8, usize) {
+        let ag = self.comm_mode_basic.granularity().size();
+        let max_elements = usize::min((self.cto_limit() - ag) / ag, u8::MAX as usize);
+        let n_elements = usize::min(max_elements, remaining.div_ceil(ag));
+        (n_elements as u8, usize::min(n_elements * ag, remaining))
+    }
+
+    /// Uploads `length` bytes from the current MTA in chunks of as many whole elements
+    /// as fit into `max_cto`. A length that is not a multiple of the address granularity
+    /// is rounded up on the wire and truncated in the result.
+    fn upload_from_mta(&mut self, length: usize, mut progress: Option<&mut dyn FnMut(usize, usize)>)
+        -> Result<Vec<u8>, XcpError> {
+        let mut data = Vec::<u8>::with_capacity(length);
+        while data.len() < length {
+            let (n_elements, chunk_len) = self.upload_chunk(length - data.len());
+            let chunk = self.upload_frame(n_elements)?;
+            data.extend_from_slice(&chunk[..usize::min(chunk_len, chunk.len())]);
+            if let Some(progress) = progress.as_mut() {
+                progress(data.len(), length);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Reads identification data of the given type as text, see `get_id`.
+    pub fn get_id_string(&mut self, id_type: IdType) -> Result<String, XcpError> {
+        let id = self.get_id(id_type)?;
+        Ok(String::from_utf8_lossy(&id).into_owned())
+    }
+
+    /// Asks the slave to store calibration data or the DAQ configuration, or to clear
+    /// the stored DAQ configuration.
+    ///
+    /// This only starts the request; the slave signals completion with an event or by
+    /// clearing the request bit in GET_STATUS. A busy or locked slave answers with
+    /// ERR_CMD_BUSY or ERR_ACCESS_DENIED, available through `XcpError::error_code`.
+    pub fn set_request(&mut self, mode: XcpSetRequestMode, session_configuration_id: u16) -> Result<(), XcpError> {
+        let mut request_req = XcpCommandFrame {
+            data: SetRequestCommand { mode, session_configuration_id, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        self.send_recv_one_blocking(&mut request_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Issues SET_REQUEST and waits until the slave has finished all requested operations.
+    ///
+    /// An operation counts as finished when either its EV_STORE_CAL/EV_STORE_DAQ/EV_CLEAR_DAQ
+    /// event arrives or its request bit in the GET_STATUS session status has cleared,
+    /// whichever is seen first. Only after this returns is it safe to power-cycle the slave.
+    ///
+    /// # Returns
+    /// `XcpError::Timeout` if the operations are still pending after `timeout`.
+    pub fn set_request_and_wait(&mut self, mode: XcpSetRequestMode, session_configuration_id: u16, timeout: Duration)
+        -> Result<(), XcpError> {
+        let start = Instant::now();
+        self.set_request(mode, session_configuration_id)?;
+
+        let mut pending = mode.pending_status();
+        loop {
+            for event in self.take_events() {
+                match event {
+                    XcpEventCode::StoreCal => pending.set_store_cal_req(false),
+                    XcpEventCode::StoreDaq => pending.set_store_daq_req(false),
+                    XcpEventCode::ClearDaq => pending.set_clear_daq_req(false),
+                    other => self.slave_events.push_back(other),
+                }
+            }
+            if pending.0 == 0 { return Ok(()) }
+
+            let status = self.get_status()?;
+            pending = XcpSessionStatus(pending.0 & status.session_status.0);
+            if pending.0 == 0 { return Ok(()) }
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                let command = XcpCommandCode::SetRequest;
+                return Err(XcpError::Timeout { command, class: TimeoutClass::of(command), timeout, elapsed });
+            }
+            self.receive_events(Duration::min(STORE_POLL_INTERVAL, timeout - elapsed))?;
+        }
+    }
+
+    /// Listens for EV packets for up to `duration`, queueing them for `take_events`.
+    fn receive_events(&mut self, duration: Duration) -> Result<(), XcpError> {
+        let deadline = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= deadline { return Ok(()) }
+
+            match self.socket.read_frame_timeout(deadline - now) {
+                Ok(frame) => {
+                    if CanId::from(frame.id()) == self.ids.res
+                        && XcpResponseCode::from_code(frame.data()[0]) == XcpResponseCode::Event {
+                        self.queue_event(frame.data());
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(()),
+                Err(e) => {
+                    self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+                    return Err(XcpError::Io(e));
+                }
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next DTO packet from the slave.
+    ///
+    /// DTOs that arrived while waiting for a command response are returned first.
+    /// Event packets are queued as usual and also returned, so that DTO decoding
+    /// sees EV_DAQ_OVERLOAD.
+    ///
+    /// # Returns
+    /// `None` if no DTO arrived in time.
+    pub(crate) fn recv_dto(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, XcpError> {
+        if let Some(dto) = self.dto_queue.pop_front() {
+            return Ok(Some(dto));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline { return Ok(None) }
+
+            match self.socket.read_frame_timeout(deadline - now) {
+                Ok(frame) => {
+                    if CanId::from(frame.id()) != self.ids.res || frame.data().is_empty() { continue }
+                    match XcpResponseCode::from_code(frame.data()[0]) {
+                        XcpResponseCode::UnknownResponse => return Ok(Some(frame.data().to_vec())),
+                        XcpResponseCode::Event => {
+                            self.queue_event(frame.data());
+                            return Ok(Some(frame.data().to_vec()));
+                        }
+                        _ => self.events.publish(SessionEvent::UnexpectedPacket { data: frame.data().to_vec() }),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => {
+                    self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+                    return Err(XcpError::Io(e));
+                }
+            }
+        }
+    }
+
+    /// Keeps a DTO that arrived while waiting for a response for `recv_dto`.
+    fn queue_dto(&mut self, data: &[u8]) {
+        if !self.queue_dtos { return }
+        if self.dto_queue.len() >= DTO_QUEUE_LIMIT {
+            self.stats.dtos_dropped += 1;
+            return;
+        }
+        self.dto_queue.push_back(data.to_vec());
+    }
+
+    /// Records the EV packet in `data` and notifies subscribers.
+    fn queue_event(&mut self, data: &[u8]) {
+        let Some(&code) = data.get(1) else { return };
+        let code = XcpEventCode::from_code(code);
+        if code == XcpEventCode::SessionTerminated {
+            self.protection = None;
+        }
+        self.slave_events.push_back(code);
+        self.events.publish(SessionEvent::SlaveEvent { code });
+    }
+
+    /// Switches the calibration page of `segment` (or of all segments, with `mode.all`)
+    /// for the ECU and/or XCP access.
+    ///
+    /// # Returns
+    /// `XcpError::PageNotValid`, `XcpError::SegmentNotValid` or `XcpError::ModeNotValid`
+    /// telling exactly which part of the request the slave rejected.
+    pub fn set_cal_page(&mut self, mode: CalPageAccessFlags, segment: u8, page: u8) -> Result<(), XcpError> {
+        let mut page_req = XcpCommandFrame { data: SetCalPageCommand { mode, segment, page } };
+
+        let page_resp = self.send_recv_one_blocking(&mut page_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        });
+
+        match page_resp {
+            Ok(_) => Ok(()),
+            Err(e) => Err(match e.error_code() {
+                Some(XcpErrorCode::ErrPageNotValid) => XcpError::PageNotValid { segment, page },
+                Some(XcpErrorCode::ErrSegmentNotValid) => XcpError::SegmentNotValid { segment },
+                Some(XcpErrorCode::ErrModeNotValid) => XcpError::ModeNotValid { mode },
+                _ => e,
+            }),
+        }
+    }
+
+    /// Queries the number of segments and the properties of the paging processor.
+    ///
+    /// The result is also kept in `pag_processor_info` for the paging helpers.
+    pub fn get_pag_processor_info(&mut self) -> Result<GetPagProcessorInfoResponse, XcpError> {
+        let mut info_req = XcpCommandFrame { data: GetPagProcessorInfoCommand };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetPagProcessorInfoResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.pag_processor_info = Some(info_resp.data);
+        Ok(info_resp.data)
+    }
+
+    /// Queries information about `segment`; the shape of the answer depends on `mode`.
+    ///
+    /// `segment_info` selects the value in basic address and address mapping mode,
+    /// `mapping_index` the mapping in address mapping mode. Both are ignored otherwise.
+    pub fn get_segment_info(&mut self, mode: SegmentInfoMode, segment: u8, segment_info: u8, mapping_index: u8)
+        -> Result<SegmentInfo, XcpError> {
+        let mut info_req = XcpCommandFrame {
+            data: GetSegmentInfoCommand { mode, segment, segment_info, mapping_index }
+        };
+        let comm_mode = self.comm_mode_basic;
+
+        let info = match mode {
+            SegmentInfoMode::BasicAddress => SegmentInfo::Basic(self.send_recv_one_blocking(&mut info_req, |frame| {
+                XcpResponseFrame::<SegmentBasicInfoResponse>::from_can_frame_with_mode(frame.data(), comm_mode)
+            })?.data),
+            SegmentInfoMode::Standard => SegmentInfo::Standard(self.send_recv_one_blocking(&mut info_req, |frame| {
+                XcpResponseFrame::<SegmentStandardInfoResponse>::from_can_frame(frame.data())
+            })?.data),
+            SegmentInfoMode::AddressMapping => SegmentInfo::Mapping(self.send_recv_one_blocking(&mut info_req, |frame| {
+                XcpResponseFrame::<SegmentMappingInfoResponse>::from_can_frame_with_mode(frame.data(), comm_mode)
+            })?.data),
+        };
+
+        Ok(info)
+    }
+
+    /// Queries the access properties and the init segment of `page` in `segment`.
+    pub fn get_page_info(&mut self, segment: u8, page: u8) -> Result<GetPageInfoResponse, XcpError> {
+        let mut info_req = XcpCommandFrame { data: GetPageInfoCommand { segment, page } };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetPageInfoResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(info_resp.data)
+    }
+
+    /// Copies a calibration page, e.g. the flash reference page into the RAM working page.
+    ///
+    /// Only needs CAL/PAG to be unlocked. Which part of the request the slave rejected
+    /// is told by `XcpError::error_code`: ERR_SEGMENT_NOT_VALID and ERR_PAGE_NOT_VALID
+    /// for either the source or the destination, ERR_WRITE_PROTECTED for a destination
+    /// that cannot be written.
+    pub fn copy_cal_page(&mut self, source_segment: u8, source_page: u8, destination_segment: u8, destination_page: u8)
+        -> Result<(), XcpError> {
+        let mut copy_req = XcpCommandFrame {
+            data: CopyCalPageCommand { source_segment, source_page, destination_segment, destination_page }
+        };
+
+        self.send_recv_one_blocking(&mut copy_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(())
+    }
+
+    /// Stores the active calibration pages of all segments into their init segments.
+    ///
+    /// Checks that the slave supports FREEZE mode, sets it for every segment and then
+    /// issues SET_REQUEST with STORE_CAL_REQ, waiting for the store to complete.
+    ///
+    /// # Returns
+    /// The frozen segments. If a segment rejects FREEZE mode, `XcpError::FreezeFailed`
+    /// names it and lists the segments already frozen, so the caller can roll back.
+    pub fn freeze_calibration(&mut self) -> Result<Vec<u8>, XcpError> {
+        let info = self.get_pag_processor_info()?;
+        if !info.properties.freeze_supported() {
+            return Err(XcpError::FreezeNotSupported);
+        }
+
+        let mut freeze = XcpSegmentMode(0);
+        freeze.set_freeze(true);
+        let mut frozen = Vec::new();
+        for segment in 0..info.max_segments {
+            if let Err(e) = self.set_segment_mode(freeze, segment) {
+                return Err(XcpError::FreezeFailed { segment, frozen, source: Box::new(e) });
+            }
+            frozen.push(segment);
+        }
+
+        let mut store = XcpSetRequestMode(0);
+        store.set_store_cal_req(true);
+        self.set_request_and_wait(store, 0, FREEZE_STORE_TIMEOUT)?;
+        Ok(frozen)
+    }
+
+    /// Sets the mode (e.g. FREEZE) of `segment`.
+    ///
+    /// # Returns
+    /// `XcpError::SegmentNotValid` if the segment does not exist.
+    pub fn set_segment_mode(&mut self, mode: XcpSegmentMode, segment: u8) -> Result<(), XcpError> {
+        let mut mode_req = XcpCommandFrame { data: SetSegmentModeCommand { mode, segment } };
+
+        self.send_recv_one_blocking(&mut mode_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(|e| segment_error(e, segment))?;
+
+        Ok(())
+    }
+
+    /// Queries the mode of `segment`.
+    ///
+    /// # Returns
+    /// `XcpError::SegmentNotValid` if the segment does not exist.
+    pub fn get_segment_mode(&mut self, segment: u8) -> Result<XcpSegmentMode, XcpError> {
+        let mut mode_req = XcpCommandFrame { data: GetSegmentModeCommand { segment } };
+
+        let mode_resp = self.send_recv_one_blocking(&mut mode_req, |frame| {
+            XcpResponseFrame::<GetSegmentModeResponse>::from_can_frame(frame.data())
+        }).map_err(|e| segment_error(e, segment))?;
+
+        Ok(mode_resp.data.mode)
+    }
+
+    /// Switches every segment to `page` for the given access.
+    ///
+    /// Uses a single SET_CAL_PAGE with the ALL bit; slaves refusing that mode are
+    /// switched segment by segment, using the segment count from GET_PAG_PROCESSOR_INFO.
+    /// A page the ECU cannot access (per GET_PAGE_INFO of segment 0) is refused for
+    /// ECU access with `XcpError::ModeNotValid` before anything is switched.
+    ///
+    /// # Returns
+    /// `XcpError::PageSwitchFailed` listing the segments GET_CAL_PAGE does not report
+    /// on `page` afterwards.
+    pub fn switch_cal_page_all(&mut self, page: u8, access: PageAccess) -> Result<(), XcpError> {
+        let mode = access.to_flags();
+        if mode.ecu {
+            match self.get_page_info(0, page) {
+                Ok(info) if !info.properties.ecu_access() => return Err(XcpError::ModeNotValid { mode }),
+                Err(e) if e.error_code() != Some(XcpErrorCode::ErrCmdUnknown) => return Err(e),
+                _ => {}
+            }
+        }
+
+        let segments = match self.pag_processor_info {
+            Some(info) => info.max_segments,
+            None => self.get_pag_processor_info()?.max_segments,
+        };
+
+        let mut failed = Vec::new();
+        match self.set_cal_page(CalPageAccessFlags { all: true, ..mode }, 0, page) {
+            Ok(()) => {}
+            Err(XcpError::ModeNotValid { .. }) => {
+                for segment in 0..segments {
+                    if self.set_cal_page(mode, segment, page).is_err() {
+                        failed.push(segment);
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+
+        // GET_CAL_PAGE takes only one of ECU and XCP access
+        let verify_mode = if mode.xcp { PageAccess::Xcp.to_flags() } else { PageAccess::Ecu.to_flags() };
+        for segment in 0..u8::max(segments, 1) {
+            if failed.contains(&segment) { continue }
+            match self.get_cal_page(verify_mode, segment) {
+                Ok(active) if active.page == page => {}
+                _ => failed.push(segment),
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(XcpError::PageSwitchFailed { page, segments: failed });
+        }
+        Ok(())
+    }
+
+    /// Queries the page of `segment` currently active for the ECU or XCP access,
+    /// whichever is set in `mode`.
+    pub fn get_cal_page(&mut self, mode: CalPageAccessFlags, segment: u8) -> Result<GetCalPageResponse, XcpError> {
+        let mut page_req = XcpCommandFrame { data: GetCalPageCommand { mode, segment } };
+
+        let page_resp = self.send_recv_one_blocking(&mut page_req, |frame| {
+            XcpResponseFrame::<GetCalPageResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(page_resp.data)
+    }
+
+    /// Queries the capabilities of the DAQ processor and caches them in
+    /// `daq_processor_info`.
+    pub fn get_daq_processor_info(&mut self) -> Result<GetDaqProcessorInfoResponse, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut info_req = XcpCommandFrame { data: GetDaqProcessorInfoCommand };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetDaqProcessorInfoResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        self.daq_processor_info = Some(info_resp.data);
+        Ok(info_resp.data)
+    }
+
+    /// Queries the ODT entry size limits and the timestamp format of the DAQ processor.
+    ///
+    /// # Returns
+    /// The decoded info; `tick_seconds` converts timestamps to seconds.
+    pub fn get_daq_resolution_info(&mut self) -> Result<GetDaqResolutionInfoResponse, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut info_req = XcpCommandFrame { data: GetDaqResolutionInfoCommand };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetDaqResolutionInfoResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        Ok(info_resp.data)
+    }
+
+    /// Queries the properties and size of `daq_list`.
+    ///
+    /// # Returns
+    /// `XcpError::DaqListNotValid` if the list does not exist.
+    pub fn get_daq_list_info(&mut self, daq_list: u16) -> Result<GetDaqListInfoResponse, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut info_req = XcpCommandFrame {
+            data: GetDaqListInfoCommand { daq_list, byte_order: mode.endianness() }
+        };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetDaqListInfoResponse>::from_can_frame_with_mode(frame.data(), mode)
+        }).map_err(|e| daq_list_error(e, daq_list))?;
+
+        Ok(info_resp.data)
+    }
+
+    /// Queries every DAQ list of the slave, using MAX_DAQ from GET_DAQ_PROCESSOR_INFO
+    /// (queried first unless already cached).
+    ///
+    /// # Returns
+    /// The info of each list, indexed by list number.
+    pub fn enumerate_daq_lists(&mut self) -> Result<Vec<GetDaqListInfoResponse>, XcpError> {
+        let max_daq = match self.daq_processor_info {
+            Some(info) => info.max_daq,
+            None => self.get_daq_processor_info()?.max_daq,
+        };
+
+        (0..max_daq).map(|daq_list| self.get_daq_list_info(daq_list)).collect()
+    }
+
+    /// Queries the properties of `event_channel`.
+    pub fn get_daq_event_info(&mut self, event_channel: u16) -> Result<GetDaqEventInfoResponse, XcpError> {
+        let mut info_req = XcpCommandFrame {
+            data: GetDaqEventInfoCommand { event_channel, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetDaqEventInfoResponse>::from_can_frame(frame.data())
+        })?;
+
+        Ok(info_resp.data)
+    }
+
+    /// Reads the name of `event_channel`.
+    ///
+    /// GET_DAQ_EVENT_INFO only reports the length of the name and points the MTA at
+    /// it, so the name is uploaded right afterwards.
+    pub fn get_daq_event_name(&mut self, event_channel: u16) -> Result<String, XcpError> {
+        let info = self.get_daq_event_info(event_channel)?;
+        self.upload_event_name(&info)
+    }
+
+    /// Uploads the event channel name the MTA points at after GET_DAQ_EVENT_INFO.
+    fn upload_event_name(&mut self, info: &GetDaqEventInfoResponse) -> Result<String, XcpError> {
+        let raw = self.upload_from_mta(info.name_length as usize, None)?;
+        let name = String::from_utf8_lossy(&raw);
+        Ok(name.trim_end_matches('\0').to_string())
+    }
+
+    /// Queries every event channel of the slave, using MAX_EVENT_CHANNEL from
+    /// GET_DAQ_PROCESSOR_INFO (queried first unless already cached).
+    ///
+    /// With `with_names`, the name of every channel is read as well.
+    pub fn enumerate_events(&mut self, with_names: bool) -> Result<Vec<DaqEventDescriptor>, XcpError> {
+        let max_event_channel = match self.daq_processor_info {
+            Some(info) => info.max_event_channel,
+            None => self.get_daq_processor_info()?.max_event_channel,
+        };
+
+        (0..max_event_channel)
+            .map(|event_channel| {
+                let info = self.get_daq_event_info(event_channel)?;
+                let name = if with_names { Some(self.upload_event_name(&info)?) } else { None };
+                Ok(DaqEventDescriptor { event_channel, info, name })
+            })
+            .collect()
+    }
+
+    /// Clears the dynamic DAQ configuration, starting a new allocation.
+    pub fn free_daq(&mut self) -> Result<(), XcpError> {
+        let mut free_req = XcpCommandFrame { data: FreeDaqCommand };
+        self.send_recv_one_blocking(&mut free_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.daq_alloc_phase = DaqAllocPhase::Freed;
+        self.daq_ptr = None;
+        self.first_pids.clear();
+        Ok(())
+    }
+
+    /// Allocates `daq_count` dynamic DAQ lists; must directly follow `free_daq`.
+    ///
+    /// # Returns
+    /// `XcpError::DaqAllocationSequence` if called out of order.
+    pub fn alloc_daq(&mut self, daq_count: u16) -> Result<(), XcpError> {
+        self.check_alloc_phase(XcpCommandCode::AllocDaq, &[DaqAllocPhase::Freed])?;
+
+        let mut alloc_req = XcpCommandFrame {
+            data: AllocDaqCommand { daq_count, byte_order: self.comm_mode_basic.endianness() }
+        };
+        self.send_recv_one_blocking(&mut alloc_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.daq_alloc_phase = DaqAllocPhase::DaqAllocated;
+        Ok(())
+    }
+
+    /// Allocates `odt_count` ODTs for `daq_list`; allowed after `alloc_daq` and
+    /// further `alloc_odt` calls.
+    ///
+    /// # Returns
+    /// `XcpError::DaqAllocationSequence` if called out of order.
+    pub fn alloc_odt(&mut self, daq_list: u16, odt_count: u8) -> Result<(), XcpError> {
+        self.check_alloc_phase(XcpCommandCode::AllocOdt, &[DaqAllocPhase::DaqAllocated, DaqAllocPhase::OdtAllocated])?;
+
+        let mut alloc_req = XcpCommandFrame {
+            data: AllocOdtCommand { daq_list, odt_count, byte_order: self.comm_mode_basic.endianness() }
+        };
+        self.send_recv_one_blocking(&mut alloc_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.daq_alloc_phase = DaqAllocPhase::OdtAllocated;
+        Ok(())
+    }
+
+    /// Allocates `entry_count` entries for `odt` of `daq_list`; allowed after
+    /// `alloc_odt` and further `alloc_odt_entry` calls.
+    ///
+    /// # Returns
+    /// `XcpError::DaqAllocationSequence` if called out of order.
+    pub fn alloc_odt_entry(&mut self, daq_list: u16, odt: u8, entry_count: u8) -> Result<(), XcpError> {
+        self.check_alloc_phase(XcpCommandCode::AllocOdtEntry, &[DaqAllocPhase::OdtAllocated, DaqAllocPhase::OdtEntryAllocated])?;
+
+        let mut alloc_req = XcpCommandFrame {
+            data: AllocOdtEntryCommand { daq_list, odt, entry_count, byte_order: self.comm_mode_basic.endianness() }
+        };
+        self.send_recv_one_blocking(&mut alloc_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.daq_alloc_phase = DaqAllocPhase::OdtEntryAllocated;
+        Ok(())
+    }
+
+    /// Current phase of the dynamic DAQ allocation in this session.
+    pub fn daq_alloc_phase(&self) -> DaqAllocPhase {
+        self.daq_alloc_phase
+    }
+
+    fn check_alloc_phase(&self, command: XcpCommandCode, allowed: &[DaqAllocPhase]) -> Result<(), XcpError> {
+        if !allowed.contains(&self.daq_alloc_phase) {
+            return Err(XcpError::DaqAllocationSequence { command, phase: self.daq_alloc_phase });
+        }
+        Ok(())
+    }
+
+    /// Clears the configuration of `daq_list`, e.g. before reconfiguring it.
+    ///
+    /// # Returns
+    /// `XcpError::DaqListNotValid` if the list does not exist.
+    pub fn clear_daq_list(&mut self, daq_list: u16) -> Result<(), XcpError> {
+        let mut clear_req = XcpCommandFrame {
+            data: ClearDaqListCommand { daq_list, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        self.send_recv_one_blocking(&mut clear_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(|e| daq_list_error(e, daq_list))?;
+
+        Ok(())
+    }
+
+    /// Points the slave's DAQ list pointer at `entry` of `odt` in `daq_list`.
+    pub fn set_daq_ptr(&mut self, daq_list: u16, odt: u8, entry: u8) -> Result<(), XcpError> {
+        let mut ptr_req = XcpCommandFrame {
+            data: SetDaqPtrCommand { daq_list, odt, entry, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        // the slave's pointer is unknown if the command fails
+        self.daq_ptr = None;
+        self.send_recv_one_blocking(&mut ptr_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.daq_ptr = Some(DaqPtr { daq_list, odt, entry });
+        Ok(())
+    }
+
+    /// Configures the ODT entry at the DAQ list pointer to sample `size` elements at
+    /// `address`, or only bit `bit_offset` of them.
+    ///
+    /// The slave advances its pointer to the next entry afterwards, and so does the
+    /// pointer remembered by the master.
+    ///
+    /// # Returns
+    /// `XcpError::InvalidDaqEntry` for a zero size or a bit offset above 31;
+    /// `XcpError::DaqConfigInvalid` or `XcpError::DaqEntryOutOfRange` if the slave
+    /// rejects the entry.
+    pub fn write_daq(&mut self, bit_offset: Option<u8>, size: u8, address_extension: u8, address: u32)
+        -> Result<(), XcpError>
+    {
+        check_daq_entry(bit_offset, size)?;
+
+        let mut write_req = XcpCommandFrame {
+            data: WriteDaqCommand {
+                bit_offset,
+                size,
+                address_extension,
+                address,
+                byte_order: self.comm_mode_basic.endianness(),
+            }
+        };
+
+        let ptr = self.daq_ptr;
+        self.send_recv_one_blocking(&mut write_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(|e| daq_entry_error(e, ptr))?;
+
+        self.advance_daq_ptr(1);
+        Ok(())
+    }
+
+    /// Configures consecutive ODT entries starting at the DAQ list pointer, like
+    /// repeated `write_daq` calls.
     ///
-    /// # Arguments
-    /// * `sock` - A mutable reference to the CAN socket.
-    /// * `mode` - The connection mode.
+    /// Uses WRITE_DAQ_MULTIPLE, split across as many commands as needed, if the
+    /// slave implements protocol version 1.3 and an entry fits into a CTO. As CONNECT
+    /// only reports the major version, this requires `get_version` or
+    /// `set_protocol_version` first. Otherwise falls back to one WRITE_DAQ per entry.
     ///
-    /// This function sends a connection request and processes the response.
-    pub fn connect(&mut self, mode: ConnectMode) -> Result<XcpResponseFrame<ConnectResponse>, XcpResponseFrame<NegativeResponse>> {
-        let mut connect_req = XcpCommandFrame {
-            data: ConnectCommand { mode },
-        };
+    /// # Returns
+    /// The same errors as `write_daq`. Nothing is sent if any entry is invalid.
+    pub fn write_daq_multiple(&mut self, entries: &[DaqEntry]) -> Result<(), XcpError> {
+        for entry in entries {
+            check_daq_entry(entry.bit_offset, entry.size)?;
+        }
 
-        let connect_resp = self.send_recv_one_blocking(&mut connect_req, |frame| {
-            XcpResponseFrame::<ConnectResponse>::from_can_frame(&frame.data())
-        });
+        let multiple = self.protocol_version.is_some_and(|v| v.require(XcpCommandCode::WriteDaqMultiple).is_ok());
+        if !multiple || WriteDaqMultipleCommand::max_entries(self.cto_limit()) == 0 {
+            for entry in entries {
+                self.write_daq(entry.bit_offset, entry.size, entry.address_extension, entry.address)?;
+            }
+            return Ok(());
+        }
+
+        let byte_order = self.comm_mode_basic.endianness();
+        for command in WriteDaqMultipleCommand::split(entries, byte_order, self.cto_limit()) {
+            let written = command.entries.len() as u8;
+            let mut write_req = XcpCommandFrame { data: command };
 
-        let connect_resp = match connect_resp {
-            Ok(resp) => resp,
-            Err(err_resp) => {
-                println!("{:#?}", err_resp);
-                return Err(err_resp);
+            let ptr = self.daq_ptr;
+            self.send_recv_one_blocking(&mut write_req, |frame| {
+                XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+            }).map_err(|e| daq_entry_error(e, ptr))?;
+
+            self.advance_daq_ptr(written);
+        }
+        Ok(())
+    }
+
+    /// Sets the mode, event channel, prescaler and priority of `daq_list`.
+    ///
+    /// # Returns
+    /// `XcpError::DaqListNotValid` if the list does not exist.
+    pub fn set_daq_list_mode(&mut self, daq_list: u16, config: DaqListModeConfig) -> Result<(), XcpError> {
+        let mut mode_req = XcpCommandFrame {
+            data: SetDaqListModeCommand {
+                mode: config.mode,
+                daq_list,
+                event_channel: config.event_channel,
+                prescaler: config.prescaler,
+                priority: config.priority,
+                byte_order: self.comm_mode_basic.endianness(),
             }
         };
 
-        println!("{:#?}", connect_resp);
-        Ok(connect_resp)
+        self.send_recv_one_blocking(&mut mode_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(|e| daq_list_error(e, daq_list))?;
+
+        Ok(())
+    }
+
+    /// Reads the current mode, event channel, prescaler and priority of `daq_list`.
+    ///
+    /// # Returns
+    /// `XcpError::DaqListNotValid` if the list does not exist.
+    pub fn get_daq_list_mode(&mut self, daq_list: u16) -> Result<DaqListModeConfig, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut mode_req = XcpCommandFrame {
+            data: GetDaqListModeCommand { daq_list, byte_order: mode.endianness() }
+        };
+
+        let mode_resp = self.send_recv_one_blocking(&mut mode_req, |frame| {
+            XcpResponseFrame::<GetDaqListModeResponse>::from_can_frame_with_mode(frame.data(), mode)
+        }).map_err(|e| daq_list_error(e, daq_list))?;
+
+        Ok(mode_resp.data.config)
+    }
+
+    /// Starts, stops or selects `daq_list`.
+    ///
+    /// Selecting only marks the list for `start_stop_synch`; it does not start
+    /// transmission. The FIRST_PID the slave reports when starting or selecting is
+    /// remembered, see `first_pid`.
+    ///
+    /// # Returns
+    /// The FIRST_PID of the list, or `XcpError::DaqListNotValid` if the list does
+    /// not exist.
+    pub fn start_stop_daq_list(&mut self, mode: StartStopMode, daq_list: u16) -> Result<u8, XcpError> {
+        let mut start_stop_req = XcpCommandFrame {
+            data: StartStopDaqListCommand { mode, daq_list, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        let start_stop_resp = self.send_recv_one_blocking(&mut start_stop_req, |frame| {
+            XcpResponseFrame::<StartStopDaqListResponse>::from_can_frame(frame.data())
+        }).map_err(|e| daq_list_error(e, daq_list))?;
+
+        let first_pid = start_stop_resp.data.first_pid;
+        if mode != StartStopMode::Stop {
+            self.first_pids.insert(daq_list, first_pid);
+        }
+        match mode {
+            StartStopMode::Start => self.events.publish(SessionEvent::DaqStarted { daq_list: Some(daq_list) }),
+            StartStopMode::Stop => self.events.publish(SessionEvent::DaqStopped { daq_list: Some(daq_list) }),
+            StartStopMode::Select => {}
+        }
+        Ok(first_pid)
+    }
+
+    /// Starts or stops all DAQ lists, or the ones selected with `start_stop_daq_list`,
+    /// at the same time.
+    ///
+    /// # Returns
+    /// `XcpError::FeatureUnsupported` for `PrepareStartSelected` unless the slave
+    /// implements protocol version 1.3.
+    pub fn start_stop_synch(&mut self, mode: StartStopSynchMode) -> Result<(), XcpError> {
+        let required = mode.min_protocol_version();
+        let supported = match self.protocol_version {
+            Some(version) => version >= required,
+            None => required == XcpVersion::new(1, 0),
+        };
+        if !supported {
+            return Err(XcpError::FeatureUnsupported {
+                feature: "START_STOP_SYNCH prepare mode",
+                required,
+                negotiated: self.protocol_version,
+            });
+        }
+
+        let mut synch_req = XcpCommandFrame { data: StartStopSynchCommand { mode } };
+        self.send_recv_one_blocking(&mut synch_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })?;
+
+        match mode {
+            StartStopSynchMode::StartSelected => self.events.publish(SessionEvent::DaqStarted { daq_list: None }),
+            StartStopSynchMode::StopAll | StartStopSynchMode::StopSelected => {
+                self.events.publish(SessionEvent::DaqStopped { daq_list: None })
+            }
+            StartStopSynchMode::PrepareStartSelected => {}
+        }
+        Ok(())
+    }
+
+    /// Selects every list in `daq_lists` and starts them together with
+    /// START_STOP_SYNCH, so that they begin sampling on the same event.
+    pub fn start_daq_lists(&mut self, daq_lists: &[u16]) -> Result<(), XcpError> {
+        for &daq_list in daq_lists {
+            self.start_stop_daq_list(StartStopMode::Select, daq_list)?;
+        }
+        self.start_stop_synch(StartStopSynchMode::StartSelected)
+    }
+
+    /// Reads the slave's free-running DAQ clock.
+    ///
+    /// # Returns
+    /// The raw tick count, plus the trigger info and payload format if the slave
+    /// answers in the extended format of protocol version 1.3.
+    pub fn get_daq_clock(&mut self) -> Result<GetDaqClockResponse, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut clock_req = XcpCommandFrame { data: GetDaqClockCommand };
+
+        let clock_resp = self.send_recv_one_blocking(&mut clock_req, |frame| {
+            XcpResponseFrame::<GetDaqClockResponse>::from_can_frame_with_mode(frame.data(), mode)
+        })?;
+
+        Ok(clock_resp.data)
+    }
+
+    /// The FIRST_PID reported for `daq_list` when it was last started or selected in
+    /// the current session. DTO packets with PID `first_pid + n` belong to ODT `n` of
+    /// the list.
+    pub fn first_pid(&self, daq_list: u16) -> Option<u8> {
+        self.first_pids.get(&daq_list).copied()
+    }
+
+    /// Reads back the ODT entry at the DAQ list pointer.
+    ///
+    /// The slave advances its pointer to the next entry afterwards, and so does the
+    /// pointer remembered by the master.
+    pub fn read_daq(&mut self) -> Result<DaqEntry, XcpError> {
+        let mode = self.comm_mode_basic;
+        let mut read_req = XcpCommandFrame { data: ReadDaqCommand };
+
+        let ptr = self.daq_ptr;
+        let read_resp = self.send_recv_one_blocking(&mut read_req, |frame| {
+            XcpResponseFrame::<ReadDaqResponse>::from_can_frame_with_mode(frame.data(), mode)
+        }).map_err(|e| daq_entry_error(e, ptr))?;
+
+        self.advance_daq_ptr(1);
+        Ok(read_resp.data.entry)
+    }
+
+    /// Reads back every ODT entry of `daq_list`, indexed by ODT and entry number.
+    ///
+    /// The ODTs and entries are found by moving the DAQ list pointer over them until
+    /// the slave reports ERR_OUT_OF_RANGE, so the walk ends at the first ODT without
+    /// entries, and a list that does not exist has no ODTs.
+    pub fn read_daq_list(&mut self, daq_list: u16) -> Result<Vec<Vec<DaqEntry>>, XcpError> {
+        let mut odts = Vec::new();
+
+        for odt in 0..=u8::MAX {
+            let mut entries = Vec::new();
+            for entry in 0..=u8::MAX {
+                match self.set_daq_ptr(daq_list, odt, entry) {
+                    Ok(()) => entries.push(self.read_daq()?),
+                    Err(e) if e.error_code() == Some(XcpErrorCode::ErrOutOfRange) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if entries.is_empty() {
+                break;
+            }
+            odts.push(entries);
+        }
+
+        Ok(odts)
+    }
+
+    /// Moves the remembered DAQ list pointer on by `entries`, as the slave does after
+    /// WRITE_DAQ.
+    fn advance_daq_ptr(&mut self, entries: u8) {
+        if let Some(ptr) = self.daq_ptr.as_mut() {
+            ptr.entry = ptr.entry.wrapping_add(entries);
+        }
+    }
+
+    /// The DAQ list pointer as last set by this master in the current session, if known.
+    pub fn daq_ptr(&self) -> Option<DaqPtr> {
+        self.daq_ptr
+    }
+
+    /// Resynchronizes the slave's command processor.
+    ///
+    /// The slave acknowledges SYNCH with a negative response carrying ERR_CMD_SYNCH,
+    /// which is treated as success here. Any other answer is an error.
+    pub fn synch(&mut self) -> Result<(), XcpError> {
+        let mut synch_req = XcpCommandFrame { data: SynchCommand };
+
+        let synch_resp = self.send_recv_one_blocking(&mut synch_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        });
+
+        match synch_resp {
+            Err(e) if e.error_code() == Some(XcpErrorCode::ErrCmdSynch) => Ok(()),
+            Err(e) => Err(e),
+            Ok(_) => Err(XcpError::UnexpectedResponse { command: XcpCommandCode::Synch }),
+        }
     }
 
     /// Retrieves the full seed from the XCP server.
@@ -51,12 +2099,16 @@ impl<'a> XcpMaster<'a> {
     /// # Arguments
     /// * `resource` - XcpResourceFlags indicating the resource to be unlocked by the seed/key exchange
     ///
+    /// The length the first response reports is the length of the whole seed; it is
+    /// continued with further GET_SEEDs until that many bytes were collected.
+    ///
     /// # Returns
     /// A vector containing the full seed data.
-    pub fn get_seed(&mut self, resource: XcpResourceFlags) -> Result<Vec<u8>, XcpResponseFrame<NegativeResponse>> {
+    pub fn get_seed(&mut self, resource: XcpResourceFlags) -> Result<Vec<u8>, XcpError> {
         println!("{:#?}", resource);
         println!("{:#?}", u8::from(resource));
         let mut seed = Vec::<u8>::new();
+        let mut seed_length = None;
         let mode = GetSeedMode::StartSeed;
 
         let mut getseed_req = XcpCommandFrame {
@@ -78,11 +2130,18 @@ impl<'a> XcpMaster<'a> {
 
             println!("{:#?}", getseed_resp);
 
-            seed.append(&mut getseed_resp.data.seed_data.clone());
+            let seed_length = *seed_length.get_or_insert(getseed_resp.data.remaining_length as usize);
+            let missing = seed_length - seed.len();
+            let chunk = &getseed_resp.data.seed_data;
+            seed.extend_from_slice(&chunk.as_slice()[..usize::min(missing, chunk.len())]);
 
-            if getseed_resp.data.remaining_length as usize == getseed_resp.data.seed_data.len() {
+            if seed.len() == seed_length {
                 break 'seed_loop;
             }
+            // no progress, the slave would be asked for the same bytes forever
+            if chunk.is_empty() {
+                return Err(XcpError::UnexpectedResponse { command: XcpCommandCode::GetSeed });
+            }
 
             getseed_req.data.mode = GetSeedMode::ContinueSeed;
         }
@@ -99,7 +2158,7 @@ impl<'a> XcpMaster<'a> {
     ///                key based on the specification for a particular XCP slave
     ///
     pub fn unlock<F: Fn(&[u8]) -> Vec<u8>>(&mut self, seed: &[u8], key_algo: F) 
-        -> Result<XcpResponseFrame<UnlockResponse>, XcpResponseFrame<NegativeResponse>> {
+        -> Result<XcpResponseFrame<UnlockResponse>, XcpError> {
         let key = key_algo(seed);
         println!("seed: {:x?}, key: {:x?}", seed, key);
 
@@ -107,7 +2166,7 @@ impl<'a> XcpMaster<'a> {
         let mut remaining_len = key.len();
 
         loop {
-            let key_data_capacity = usize::min(self.max_cto - 2, remaining_len);
+            let key_data_capacity = usize::min(self.cto_limit() - 2, remaining_len);
             let last_idx = curr_key_idx + key_data_capacity;
             let keyslice_range = curr_key_idx..last_idx;
             println!("{:x?}", key[curr_key_idx..last_idx].to_vec());
@@ -124,7 +2183,10 @@ impl<'a> XcpMaster<'a> {
             });
 
             match unlock_resp {
-                Ok(_) => {},
+                Ok(ref resp) => {
+                    self.protection = Some(resp.data.resource);
+                    self.events.publish(SessionEvent::ResourceProtectionChanged { protection: resp.data.resource });
+                },
                 Err(e) => return Err(e)
             }
 
@@ -137,6 +2199,353 @@ impl<'a> XcpMaster<'a> {
         };
     }
 
+    /// Unlocks `resource` with the seed and key exchange: GET_SEED (continued until
+    /// the whole seed arrived), then UNLOCK with the key `key_algo` computes.
+    ///
+    /// Nothing is sent if the cached protection status (see `protection`) shows
+    /// the resource unprotected; without a cached status it is refreshed with
+    /// GET_STATUS first. A seed of length 0 also means the resource is not
+    /// protected; UNLOCK is skipped then and the status is taken from GET_STATUS.
+    ///
+    /// # Returns
+    /// The resource protection status after unlocking.
+    pub fn unlock_resource(&mut self, resource: impl Into<XcpResourceFlags>, key_algo: &dyn SeedKeyAlgorithm)
+        -> Result<XcpResourceFlags, XcpError> {
+        let resource = resource.into();
+        let protection = match self.protection {
+            Some(protection) => protection,
+            None => self.get_status()?.protection,
+        };
+        if u8::from(protection) & u8::from(resource) == 0 {
+            return Ok(protection);
+        }
+
+        let seed = self.get_seed(resource)?;
+        if seed.is_empty() {
+            return Ok(self.get_status()?.protection);
+        }
+
+        let unlock_resp = self.unlock(&seed, |seed| key_algo.compute_key(resource, seed))?;
+        Ok(unlock_resp.data.resource)
+    }
+
+    /// Unlocks every resource GET_STATUS reports as protected with `unlock_resource`,
+    /// one at a time as the seed of only one resource may be requested at once.
+    ///
+    /// A failing resource does not keep the others from being unlocked unless
+    /// `fail_fast` is set, which stops at the first failure.
+    ///
+    /// # Returns
+    /// The outcome per resource. Errors are only returned if GET_STATUS fails.
+    pub fn unlock_all(&mut self, key_algo: &dyn SeedKeyAlgorithm, fail_fast: bool) -> Result<UnlockSummary, XcpError> {
+        let protection = self.get_status()?.protection;
+        let mut summary = UnlockSummary { resources: Vec::new(), protection };
+
+        for resource in protection.resources() {
+            let result = self.unlock_resource(resource, key_algo).map(|protection| summary.protection = protection);
+            let failed = result.is_err();
+            summary.resources.push(ResourceUnlock { resource, result });
+            if failed && fail_fast { break }
+        }
+
+        Ok(summary)
+    }
+
+    /// Sends a USER_CMD with the given sub-command and parameters.
+    ///
+    /// The meaning of both is entirely slave specific.
+    ///
+    /// # Returns
+    /// `XcpError::PayloadTooLarge` if the parameters do not fit into a CTO.
+    pub fn user_cmd(&mut self, sub_command: u8, parameters: &[u8])
+        -> Result<XcpResponseFrame<EmptyResponse>, XcpError> {
+        let max = self.cto_limit() - 2;
+        if parameters.len() > max {
+            return Err(XcpError::PayloadTooLarge { command: XcpCommandCode::UserCmd, requested: parameters.len(), max });
+        }
+
+        let mut user_req = XcpCommandFrame {
+            data: UserCmdCommand { sub_command, parameters: parameters.to_vec() }
+        };
+
+        self.send_recv_one_blocking(&mut user_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        })
+    }
+
+    /// Queries the number of sectors and the properties of the programming processor.
+    ///
+    /// The result is also kept in `pgm_processor_info`.
+    pub fn get_pgm_processor_info(&mut self) -> Result<GetPgmProcessorInfoResponse, XcpError> {
+        let mut info_req = XcpCommandFrame { data: GetPgmProcessorInfoCommand };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetPgmProcessorInfoResponse>::from_can_frame(frame.data())
+        })?;
+
+        self.pgm_processor_info = Some(info_resp.data);
+        Ok(info_resp.data)
+    }
+
+    /// Queries the start address or length of `sector`, together with its clear and
+    /// program sequence numbers.
+    pub fn get_sector_info(&mut self, mode: SectorInfoMode, sector: u8) -> Result<GetSectorInfoResponse, XcpError> {
+        let comm_mode = self.comm_mode_basic;
+        let mut info_req = XcpCommandFrame { data: GetSectorInfoCommand { mode, sector } };
+
+        let info_resp = self.send_recv_one_blocking(&mut info_req, |frame| {
+            XcpResponseFrame::<GetSectorInfoResponse>::from_can_frame_with_mode(frame.data(), comm_mode)
+        })?;
+
+        Ok(info_resp.data)
+    }
+
+    /// Begins a programming sequence. The limits the slave returns are kept in
+    /// `pgm_info` and size the transfers of `program`.
+    ///
+    /// # Returns
+    /// `XcpError::PgmLocked` if PGM must be unlocked first.
+    pub fn program_start(&mut self) -> Result<ProgramStartResponse, XcpError> {
+        let mut start_req = XcpCommandFrame { data: ProgramStartCommand };
+
+        let start_resp = self.send_recv_one_blocking(&mut start_req, |frame| {
+            XcpResponseFrame::<ProgramStartResponse>::from_can_frame(frame.data())
+        }).map_err(pgm_error)?;
+
+        self.pgm_info = Some(start_resp.data);
+        Ok(start_resp.data)
+    }
+
+    /// Clears non-volatile memory before it is programmed. In absolute mode the
+    /// range starts at the MTA and is `clear_range` elements long.
+    pub fn program_clear(&mut self, mode: ProgramClearMode, clear_range: u32) -> Result<(), XcpError> {
+        let mut clear_req = XcpCommandFrame {
+            data: ProgramClearCommand { mode, clear_range, byte_order: self.comm_mode_basic.endianness() }
+        };
+
+        self.send_recv_one_blocking(&mut clear_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(pgm_error)?;
+
+        Ok(())
+    }
+
+    /// Replaces the codec `program` encodes data with; `IdentityCodec` by default.
+    pub fn set_program_codec<C: ProgramCodec + 'a>(&mut self, codec: C) {
+        self.program_codec = Box::new(codec);
+    }
+
+    pub fn program_codec(&mut self) -> &mut dyn ProgramCodec {
+        self.program_codec.as_mut()
+    }
+
+    /// Announces the data format of the installed codec with PROGRAM_FORMAT. Send it
+    /// after PROGRAM_CLEAR and before the data is programmed.
+    pub fn program_format(&mut self) -> Result<(), XcpError> {
+        let codec = &self.program_codec;
+        let mut format_req = XcpCommandFrame {
+            data: ProgramFormatCommand {
+                compression_method: codec.compression_method(),
+                encryption_method: codec.encryption_method(),
+                programming_method: codec.programming_method(),
+                access_method: codec.access_method(),
+            }
+        };
+
+        self.send_recv_one_blocking(&mut format_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(pgm_error)?;
+
+        Ok(())
+    }
+
+    /// Programs `data` starting at the current MTA, encoded by the installed codec.
+    /// The encoded data must be a whole number of elements.
+    ///
+    /// Transfers are sized by the MAX_CTO_PGM from PROGRAM_START. If PROGRAM_START
+    /// reported master block mode, blocks of up to MAX_BS_PGM frames are sent with
+    /// PROGRAM_NEXT, MIN_ST_PGM apart; otherwise every PROGRAM is acknowledged.
+    pub fn program(&mut self, data: &[u8]) -> Result<(), XcpError> {
+        let encoded = self.program_codec.encode(data);
+        self.program_with_progress(&encoded, &mut |_| {})
+    }
+
+    /// Programs already encoded `data` like `program`, calling `progress` with the
+    /// number of bytes acknowledged after every command or block.
+    pub(crate) fn program_with_progress(&mut self, data: &[u8], progress: &mut dyn FnMut(usize)) -> Result<(), XcpError> {
+        let granularity = self.comm_mode_basic.granularity();
+        let ag = granularity.size();
+        if !data.len().is_multiple_of(ag) {
+            return Err(XcpError::UnalignedLength { length: data.len(), granularity: ag });
+        }
+
+        let info = self.pgm_info;
+        let max_cto = info.map(|info| info.max_cto_pgm as usize)
+            .filter(|&max| max > granularity.download_offset())
+            .unwrap_or_else(|| self.cto_limit());
+
+        let Some(info) = info.filter(|info| info.comm_mode_pgm.master_block_mode()) else {
+            for command in ProgramCommand::split(data, granularity, max_cto) {
+                let length = command.data.len();
+                let mut program_req = XcpCommandFrame { data: command };
+
+                self.send_recv_one_blocking(&mut program_req, |frame| {
+                    XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+                }).map_err(pgm_error)?;
+                progress(length);
+            }
+            return Ok(());
+        };
+
+        let per_frame = (max_cto - granularity.download_offset()) / ag;
+        let block_elements = usize::min(per_frame * usize::max(info.max_bs_pgm as usize, 1), u8::MAX as usize);
+        let min_st = Duration::from_micros(info.min_st_pgm as u64 * 100);
+
+        for block in data.chunks(block_elements * ag) {
+            let (program, next) = ProgramNextCommand::block(block, granularity, max_cto);
+            let mut code = program.get_code();
+            self.send_command(&XcpCommandFrame { data: program })?;
+
+            for command in next {
+                thread::sleep(min_st);
+                code = command.get_code();
+                self.send_command(&XcpCommandFrame { data: command })?;
+            }
+
+            self.recv_response(code, |frame| {
+                XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+            }).map_err(pgm_error)?;
+            progress(block.len());
+        }
+
+        Ok(())
+    }
+
+    /// Tells the slave that a memory segment is complete, with a PROGRAM without data.
+    pub fn program_segment_end(&mut self) -> Result<(), XcpError> {
+        let granularity = self.comm_mode_basic.granularity();
+        let mut program_req = XcpCommandFrame { data: ProgramCommand { n_elements: 0, data: Vec::new(), granularity } };
+
+        self.send_recv_one_blocking(&mut program_req, |frame| {
+            XcpResponseFrame::<EmptyResponse>::from_can_frame(frame.data())
+        }).map_err(pgm_error)?;
+
+        Ok(())
+    }
+
+    /// Ends the programming sequence; the slave usually resets.
+    ///
+    /// A slave may reset without answering, so no response within
+    /// `PROGRAM_RESET_TIMEOUT` counts as success.
+    pub fn program_reset(&mut self) -> Result<(), XcpError> {
+        let code = XcpCommandCode::ProgramReset;
+        self.send_command(&XcpCommandFrame { data: ProgramResetCommand })?;
+        self.pgm_info = None;
+
+        let deadline = Instant::now() + PROGRAM_RESET_TIMEOUT;
+        loop {
+            let now = Instant::now();
+            if now >= deadline { return Ok(()) }
+
+            match self.socket.read_frame_timeout(deadline - now) {
+                Ok(frame) => {
+                    if CanId::from(frame.id()) != self.ids.res || frame.data().is_empty() { continue }
+                    match XcpResponseCode::from_code(frame.data()[0]) {
+                        XcpResponseCode::PositiveResponse => {
+                            self.run_post_hooks(code, frame.data());
+                            return Ok(());
+                        }
+                        XcpResponseCode::NegativeResponse => {
+                            self.run_post_hooks(code, frame.data());
+                            return Err(negative_response(code, frame.data()));
+                        }
+                        XcpResponseCode::Event => self.queue_event(frame.data()),
+                        _ => {}
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(()),
+                Err(e) => {
+                    self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+                    return Err(XcpError::Io(e));
+                }
+            }
+        }
+    }
+
+    /// MAX_CTO used to size transfers: the value learned from CONNECT, or `max_cto`
+    /// before connecting.
+    fn cto_limit(&self) -> usize {
+        self.negotiated_max_cto.unwrap_or(self.max_cto)
+    }
+
+    /// Runs the pre-hooks registered for `code`, unless we are already inside a hook.
+    fn run_pre_hooks(&mut self, code: XcpCommandCode) {
+        if self.in_hook { return }
+        let Some(mut hooks) = self.pre_hooks.remove(&code) else { return };
+
+        self.in_hook = true;
+        for hook in hooks.iter_mut() {
+            hook(self);
+        }
+        self.in_hook = false;
+
+        // keep hooks registered while these were running
+        if let Some(added) = self.pre_hooks.remove(&code) {
+            hooks.extend(added);
+        }
+        self.pre_hooks.insert(code, hooks);
+    }
+
+    /// Runs the post-hooks registered for `code`, unless we are already inside a hook.
+    fn run_post_hooks(&mut self, code: XcpCommandCode, response: &[u8]) {
+        if self.in_hook { return }
+        let Some(mut hooks) = self.post_hooks.remove(&code) else { return };
+
+        self.in_hook = true;
+        for hook in hooks.iter_mut() {
+            hook(self, response);
+        }
+        self.in_hook = false;
+
+        if let Some(added) = self.post_hooks.remove(&code) {
+            hooks.extend(added);
+        }
+        self.post_hooks.insert(code, hooks);
+    }
+
+    /// Writes `frame` to the socket, waiting with growing delays while the TX queue
+    /// is full (ENOBUFS/EAGAIN) instead of dropping the frame.
+    fn transmit_frame(&mut self, frame: &CanFrame) -> io::Result<()> {
+        let mut backoff = TX_RETRY_BACKOFF;
+        let mut retries = 0;
+
+        loop {
+            match self.socket.write_frame(frame) {
+                Ok(()) => return Ok(()),
+                Err(e) if is_tx_backpressure(&e) && retries < TX_RETRY_LIMIT => {
+                    retries += 1;
+                    self.stats.tx_retries += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Transmits a STIM DTO on the command CAN ID.
+    pub(crate) fn send_dto(&mut self, dto: &[u8]) -> Result<(), XcpError> {
+        let frame = CanFrame::new(Id::from(self.ids.cmd), dto).ok_or_else(|| {
+            XcpError::Io(io::Error::new(io::ErrorKind::InvalidInput, "DTO does not fit into a CAN frame"))
+        })?;
+
+        if let Err(e) = self.transmit_frame(&frame) {
+            self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+            return Err(XcpError::Io(e));
+        }
+        Ok(())
+    }
+
     /// Sends a single XCP command and waits for a response.
     ///
     /// # Arguments
@@ -154,40 +2563,168 @@ impl<'a> XcpMaster<'a> {
         &mut self,
         command: &mut XcpCommandFrame<C>,
         handler: F,
-    ) -> Result<XcpResponseFrame<R>, XcpResponseFrame<NegativeResponse>> {
-        println!("{:#?}", command);
-        let frame_data = command.to_can_frame();
-        let id = StandardId::new(self.tx_id as u16).unwrap();
-        let frame = CanFrame::new(id, &frame_data).unwrap();
-        println!("{:x?}", frame);
+    ) -> Result<XcpResponseFrame<R>, XcpError> {
+        let code = command.data.get_code();
+        self.send_command(command)?;
+        match self.recv_response(code, &handler) {
+            Err(e) if e.error_code() == Some(XcpErrorCode::ErrAccessLocked) => {
+                if !self.unlock_on_demand(code)? { return Err(e) }
+                self.send_command(command)?;
+                self.recv_response(code, handler)
+            }
+            result => result,
+        }
+    }
 
-        match self.socket.transmit(&frame) {
-            Ok(_) => (),
-            Err(e) => println!("Failed to transmit frame! Error: {}", e),
-        };
+    /// Unlocks the resource protecting `code` with its registered provider, see
+    /// `set_seed_key_provider`.
+    ///
+    /// # Returns
+    /// Whether the command should be retried.
+    fn unlock_on_demand(&mut self, code: XcpCommandCode) -> Result<bool, XcpError> {
+        let Some(resource) = protecting_resource(code) else { return Ok(false) };
+        if !is_idempotent(code) && !self.unlock_retry.contains(&code) { return Ok(false) }
+        let Some(provider) = self.seed_key_providers.remove(&resource) else { return Ok(false) };
+
+        self.events.publish(SessionEvent::AutoUnlock { command: code, resource });
+        let unlocked = self.unlock_resource(resource, provider.as_ref());
+        self.seed_key_providers.insert(resource, provider);
+
+        let protection = unlocked?;
+        Ok(u8::from(protection) & u8::from(XcpResourceFlags::from(resource)) == 0)
+    }
+
+    /// Transmits a command without waiting for its response, e.g. inside a block transfer.
+    fn send_command<C: XcpCommand + Debug>(&mut self, command: &XcpCommandFrame<C>) -> Result<(), XcpError> {
+        let code = command.data.get_code();
+        if let Some(negotiated) = self.protocol_version {
+            let required = command.data.min_protocol_version();
+            let supported = if self.protocol_minor_known {
+                negotiated >= required
+            } else {
+                negotiated.major >= required.major
+            };
+            if !supported {
+                if self.strict {
+                    return Err(XcpError::UnsupportedByProtocolVersion { command: code, required, negotiated });
+                }
+                self.events.publish(SessionEvent::UnsupportedCommand { command: code, required, negotiated });
+            }
+        }
+
+        let frame_data = command.to_can_frame().map_err(|e| match e {
+            XcpError::FrameOverflow { requested } => {
+                XcpError::PayloadTooLarge { command: code, requested, max: self.cto_limit() }
+            }
+            e => e,
+        })?;
+        let frame = CanFrame::new(Id::from(self.ids.cmd), frame_data.as_slice()).ok_or_else(|| {
+            XcpError::PayloadTooLarge { command: code, requested: frame_data.len(), max: self.cto_limit() }
+        })?;
+
+        self.run_pre_hooks(code);
+        self.drop_duplicates();
+
+        if let Err(e) = self.transmit_frame(&frame) {
+            self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+            return Err(XcpError::Io(e));
+        }
+        Ok(())
+    }
+
+    /// Drops the copies of the last response that arrived before the next command is
+    /// sent, then forgets that response: an identical frame arriving later answers
+    /// the new command.
+    fn drop_duplicates(&mut self) {
+        if !self.duplicates.is_armed() { return }
+
+        while let Ok(frame) = self.socket.read_frame_timeout(DUPLICATE_POLL) {
+            let received = Instant::now();
+            if CanId::from(frame.id()) != self.ids.res || frame.data().is_empty() { continue }
+            if self.handle_async_packet(frame.data()) { continue }
+            // any other response is late and cannot answer the next command either
+            if self.duplicates.is_duplicate(frame.data(), received) {
+                self.stats.duplicate_responses_dropped += 1;
+            }
+        }
+        self.duplicates.reset();
+    }
+
+    /// Handles the EV, SERV and DTO packets that can arrive at any time.
+    ///
+    /// # Returns
+    /// Whether `data` was such a packet rather than a response.
+    fn handle_async_packet(&mut self, data: &[u8]) -> bool {
+        match XcpResponseCode::from_code(data[0]) {
+            XcpResponseCode::Event => self.queue_event(data),
+            XcpResponseCode::ServiceRequest => self.events.publish(SessionEvent::ServiceRequest { data: data.to_vec() }),
+            XcpResponseCode::UnknownResponse => self.queue_dto(data),
+            XcpResponseCode::PositiveResponse | XcpResponseCode::NegativeResponse => return false,
+        }
+        true
+    }
 
+    /// Waits for the response to the command with the given code.
+    ///
+    /// # Returns
+    /// `XcpError::Timeout` if no response arrives within the timeout of the
+    /// command's class, see `timeouts`.
+    fn recv_response<F: Fn(CanFrame) -> XcpResponseFrame<R>, R: XcpResponse>(&mut self, code: XcpCommandCode, handler: F)
+        -> Result<XcpResponseFrame<R>, XcpError> {
+        let class = TimeoutClass::of(code);
+        let timeout = self.timeouts.get(class);
+        let start = Instant::now();
+        // a deadline rather than a timeout per read, so a flood of unrelated frames
+        // neither extends the wait nor cuts it short
+        let mut deadline = start + timeout;
         loop {
-            if let Ok(frame) = self.socket.read_frame() {
-                let id = match frame.id() {
-                    Id::Standard(id) => id.as_raw() as u32,
-                    Id::Extended(id) => id.as_raw(),
-                };
-                if id == self.rx_id { 
-                    match XcpResponseCode::from_code(frame.data()[0]) {
-                        XcpResponseCode::PositiveResponse => { return Ok(handler(frame)) }
-                        XcpResponseCode::NegativeResponse => { 
-                            return Err(
-                                XcpResponseFrame::<NegativeResponse> { 
-                                    data: NegativeResponse::from_can_frame(frame.data()) 
-                                }
-                            )
-                        }
-                        XcpResponseCode::UnknownResponse => panic!("unknown XcpResponseCode")
+            let now = Instant::now();
+            if now >= deadline {
+                self.stats.response_timeouts += 1;
+                return Err(XcpError::Timeout { command: code, class, timeout, elapsed: now - start });
+            }
+
+            let frame = match self.socket.read_frame_timeout(deadline - now) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    self.events.publish(SessionEvent::LinkError { message: e.to_string() });
+                    return Err(XcpError::Io(e));
+                }
+            };
+            let received = Instant::now();
+            if CanId::from(frame.id()) != self.ids.res || frame.data().is_empty() { continue }
+
+            // asynchronous packets can arrive between a command and its response
+            if frame.data()[0] == XcpResponseCode::Event.to_code()
+                && frame.data().get(1).map(|&event| XcpEventCode::from_code(event)) == Some(XcpEventCode::CmdPending) {
+                // the slave needs more time and asks the master to wait again
+                deadline = received + timeout;
+            }
+            if self.handle_async_packet(frame.data()) { continue }
+            if self.duplicates.is_duplicate(frame.data(), received) {
+                self.stats.duplicate_responses_dropped += 1;
+                continue;
+            }
+            self.run_post_hooks(code, frame.data());
+            match XcpResponseCode::from_code(frame.data()[0]) {
+                XcpResponseCode::PositiveResponse => {
+                    if frame.data().len() < R::MIN_LENGTH {
+                        return Err(XcpError::ResponseTooShort { command: code, length: frame.data().len(), expected: R::MIN_LENGTH });
                     }
+                    return Ok(handler(frame))
+                }
+                XcpResponseCode::NegativeResponse => { 
+                    let e = negative_response(code, frame.data());
+                    if e.error_code() == Some(XcpErrorCode::ErrAccessLocked) {
+                        // the protection cached is evidently wrong
+                        self.protection = None;
+                    }
+                    return Err(e)
+                }
+                XcpResponseCode::Event | XcpResponseCode::ServiceRequest | XcpResponseCode::UnknownResponse => {
+                    return Err(XcpError::UnexpectedResponse { command: code })
                 }
-                else { continue }
-            } else {
-                panic!("Could not read from the bus");
             }
         }
     }
@@ -0,0 +1,479 @@
+//! Recording of DAQ measurements to ASAM MDF 4.1 files (feature `mdf`).
+//!
+//! All lists share one unsorted data group: every DAQ list gets a channel group
+//! with its own record ID, the time channels, one channel per entry and a final
+//! `overrun` channel, and records are appended to a single DT block as samples
+//! arrive.
+//!
+//! The time channels depend on the `TimeBase` of the recorder. Lists recorded
+//! without slave timestamps, and all lists with `TimeBase::Host`, have the
+//! master channel `host_time` only, the seconds since the first recorded DTO was
+//! received. With `TimeBase::Slave` timestamped lists have the master channel
+//! `time`, the seconds since the first sample of the list from the slave
+//! timestamps, followed by `host_time`. With `TimeBase::Ticks` they have the
+//! master channel `host_time`, followed by `ticks`, the timestamp ticks of
+//! unknown unit since the first sample of the list.
+
+use std::collections::HashMap;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::xcp::daq::{DaqListBuilder, DaqSample, DaqTimestamp, TimeBase, ValueFormat};
+use crate::xcp::error::XcpError;
+use crate::xcp::frame::ByteOrder;
+use crate::xcp::xcp_command::XcpCommModeBasic;
+
+const MDF_VERSION: u16 = 410;
+/// Size of the ID block at the start of every MDF file.
+const ID_BLOCK_LEN: u64 = 64;
+/// Size of the header common to all blocks: ID, reserved, length and link count.
+const BLOCK_HEADER_LEN: u64 = 24;
+/// Offset of `id_unfin_flags` in the ID block.
+const UNFIN_FLAGS_OFFSET: u64 = 60;
+/// Unfinalized flags: CG cycle counters and the length of the last DT block are
+/// not yet up to date.
+const UNFIN_FLAGS: u16 = 0x0005;
+/// Byte size of a time channel (seconds as f64, or ticks as u64); records start
+/// with one or two of them.
+const TIME_BYTES: usize = 8;
+
+// cn_data_type values.
+const DATA_TYPE_UINT_LE: u8 = 0;
+const DATA_TYPE_UINT_BE: u8 = 1;
+const DATA_TYPE_INT_LE: u8 = 2;
+const DATA_TYPE_INT_BE: u8 = 3;
+const DATA_TYPE_FLOAT_LE: u8 = 4;
+const DATA_TYPE_FLOAT_BE: u8 = 5;
+const DATA_TYPE_BYTE_ARRAY: u8 = 10;
+
+/// A link to another block, resolved to a file offset when the header is written.
+#[derive(Debug, Clone, Copy)]
+enum Link {
+    Nil,
+    Block(usize),
+}
+
+#[derive(Debug)]
+struct Block {
+    id: &'static [u8; 4],
+    links: Vec<Link>,
+    data: Vec<u8>,
+}
+
+impl Block {
+    fn new(id: &'static [u8; 4], links: Vec<Link>, data: Vec<u8>) -> Block {
+        Block { id, links, data }
+    }
+
+    /// A TX block holding `text`, or an MD block if `id` is `##MD`.
+    fn text(id: &'static [u8; 4], text: &str) -> Block {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        data.resize(data.len().next_multiple_of(8), 0);
+        Block::new(id, Vec::new(), data)
+    }
+
+    fn size(&self) -> u64 {
+        BLOCK_HEADER_LEN + 8 * self.links.len() as u64 + self.data.len() as u64
+    }
+}
+
+/// A channel of a `Mdf4Recorder`.
+#[derive(Debug, Clone)]
+struct MdfChannel {
+    name: String,
+    unit: Option<String>,
+    format: ValueFormat,
+    bytes: usize,
+}
+
+/// A channel group of a `Mdf4Recorder`, i.e. one DAQ list.
+#[derive(Debug, Clone)]
+struct MdfGroup {
+    daq_list: u16,
+    /// The list is timestamped by the slave.
+    timestamped: bool,
+    time_base: TimeBase,
+    channels: Vec<MdfChannel>,
+    /// File offset of the CG block, once the header is written.
+    offset: u64,
+    cycle_count: u64,
+}
+
+impl MdfGroup {
+    /// Size of the time channels at the start of a record.
+    fn time_bytes(&self) -> usize {
+        match self.time_base {
+            TimeBase::Host => TIME_BYTES,
+            TimeBase::Slave { .. } | TimeBase::Ticks => 2 * TIME_BYTES,
+        }
+    }
+
+    /// Size of a record without its record ID.
+    fn record_bytes(&self) -> usize {
+        self.time_bytes() + self.channels.iter().map(|channel| channel.bytes).sum::<usize>() + 1
+    }
+
+    /// Byte offset of the `overrun` channel in a record.
+    fn overrun_offset(&self) -> usize {
+        self.record_bytes() - 1
+    }
+
+    /// Byte offset of the `entry`th channel in a record.
+    fn channel_offset(&self, entry: usize) -> usize {
+        self.time_bytes() + self.channels[..entry].iter().map(|channel| channel.bytes).sum::<usize>()
+    }
+}
+
+/// The record of a list being assembled from its ODTs.
+#[derive(Debug)]
+struct PendingRecord {
+    /// Time since the first recorded DTO was received, and the slave timestamp.
+    received: Duration,
+    timestamp: Option<DaqTimestamp>,
+    data: Vec<u8>,
+    filled: Vec<bool>,
+    overrun: bool,
+}
+
+/// Writes DAQ samples to an MDF 4.1 file.
+///
+/// Channels are named `addr:size` and recorded as raw bytes until set otherwise.
+/// The header is written with the first sample; until `finish` is called the file
+/// is marked unfinalized, which MDF tools know how to repair after a crash.
+/// Samples missing an ODT are not recorded, see `incomplete_samples`; the
+/// `overrun` channel is 1 in records following an overload or lost DTOs.
+pub struct Mdf4Recorder<W: Write + Seek> {
+    writer: W,
+    byte_order: ByteOrder,
+    groups: Vec<MdfGroup>,
+    record_id_size: usize,
+    pending: HashMap<u16, PendingRecord>,
+    header_written: bool,
+    /// File offset of the DT block, once the header is written.
+    dt_offset: u64,
+    dt_data_len: u64,
+    started: Option<Instant>,
+    incomplete: u64,
+}
+
+impl<W: Write + Seek> Mdf4Recorder<W> {
+    /// Records the entries of `lists`; `mode` is the COMM_MODE_BASIC of the slave.
+    pub fn new(writer: W, mode: XcpCommModeBasic, lists: &[DaqListBuilder]) -> Mdf4Recorder<W> {
+        let ag = mode.granularity().size();
+        let groups = lists.iter()
+            .map(|list| MdfGroup {
+                daq_list: list.daq_list(),
+                timestamped: list.is_timestamped(),
+                time_base: TimeBase::Host,
+                channels: list.entries().iter()
+                    .map(|entry| MdfChannel {
+                        name: format!("{:#x}:{}", entry.address, entry.size),
+                        unit: None,
+                        format: ValueFormat::Hex,
+                        bytes: entry.size as usize * ag,
+                    })
+                    .collect(),
+                offset: 0,
+                cycle_count: 0,
+            })
+            .collect::<Vec<_>>();
+        let record_id_size = if groups.len() <= u8::MAX as usize { 1 } else { 2 };
+
+        Mdf4Recorder {
+            writer,
+            byte_order: mode.endianness(),
+            groups,
+            record_id_size,
+            pending: HashMap::new(),
+            header_written: false,
+            dt_offset: 0,
+            dt_data_len: 0,
+            started: None,
+            incomplete: 0,
+        }
+    }
+
+    /// Names the channel of the `entry`th entry of `daq_list` and sets its unit.
+    pub fn channel(mut self, daq_list: u16, entry: usize, name: &str, unit: Option<&str>) -> Mdf4Recorder<W> {
+        if let Some(channel) = self.channel_mut(daq_list, entry) {
+            channel.name = name.to_string();
+            channel.unit = unit.map(str::to_string);
+        }
+        self
+    }
+
+    /// Records the slave timestamps of timestamped lists as `time_base` describes
+    /// them, usually that of the session's decoder (`DaqSession::decoder`). The
+    /// default `TimeBase::Host` only records when DTOs were received.
+    pub fn time_base(mut self, time_base: TimeBase) -> Mdf4Recorder<W> {
+        for group in &mut self.groups {
+            group.time_base = if group.timestamped { time_base } else { TimeBase::Host };
+        }
+        self
+    }
+
+    /// Sets the data type of the channel of the `entry`th entry of `daq_list`.
+    pub fn format(mut self, daq_list: u16, entry: usize, format: ValueFormat) -> Mdf4Recorder<W> {
+        if let Some(channel) = self.channel_mut(daq_list, entry) {
+            channel.format = format;
+        }
+        self
+    }
+
+    fn channel_mut(&mut self, daq_list: u16, entry: usize) -> Option<&mut MdfChannel> {
+        self.groups.iter_mut()
+            .find(|group| group.daq_list == daq_list)
+            .and_then(|group| group.channels.get_mut(entry))
+    }
+
+    /// Number of samples dropped because not all of their ODTs arrived.
+    pub fn incomplete_samples(&self) -> u64 {
+        self.incomplete
+    }
+
+    /// Adds the values of `sample` to the record of its list, and appends the record
+    /// once all entries are filled.
+    pub fn record(&mut self, sample: &DaqSample) -> io::Result<()> {
+        self.write_header()?;
+
+        let Some(index) = self.groups.iter().position(|group| group.daq_list == sample.daq_list) else {
+            return Ok(());
+        };
+
+        let started = *self.started.get_or_insert(sample.received);
+        let received = sample.received.saturating_duration_since(started);
+
+        let mut lost = false;
+        if sample.odt == 0 && self.pending.remove(&sample.daq_list).is_some() {
+            self.incomplete += 1;
+            lost = true;
+        }
+
+        let group = &self.groups[index];
+        let record = self.pending.entry(sample.daq_list).or_insert_with(|| PendingRecord {
+            received,
+            timestamp: sample.timestamp,
+            data: vec![0; group.record_bytes()],
+            filled: vec![false; group.channels.len()],
+            overrun: false,
+        });
+        record.overrun |= sample.overrun || lost;
+
+        for (i, value) in sample.values.iter().enumerate() {
+            let entry = sample.first_entry + i;
+            let Some(channel) = group.channels.get(entry) else { continue };
+            let offset = group.channel_offset(entry);
+            let len = usize::min(channel.bytes, value.len());
+            record.data[offset..offset + len].copy_from_slice(&value[..len]);
+            record.filled[entry] = true;
+        }
+
+        if record.filled.iter().all(|&filled| filled) {
+            let record = self.pending.remove(&sample.daq_list).unwrap();
+            self.write_record(index, record)?;
+        }
+        Ok(())
+    }
+
+    /// Records every sample of `samples`, e.g. `DaqSession::samples`, until it ends
+    /// or yields an error.
+    pub fn record_all<I: IntoIterator<Item = Result<DaqSample, XcpError>>>(&mut self, samples: I) -> Result<(), XcpError> {
+        for sample in samples {
+            self.record(&sample?)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the record counts and the DT block length, marks the file finalized
+    /// and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        self.incomplete += self.pending.len() as u64;
+
+        self.writer.seek(SeekFrom::Start(self.dt_offset + 8))?;
+        self.writer.write_all(&(BLOCK_HEADER_LEN + self.dt_data_len).to_le_bytes())?;
+        for group in &self.groups {
+            // cg_cycle_count follows the six links and cg_record_id.
+            self.writer.seek(SeekFrom::Start(group.offset + BLOCK_HEADER_LEN + 6 * 8 + 8))?;
+            self.writer.write_all(&group.cycle_count.to_le_bytes())?;
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(b"MDF     ")?;
+        self.writer.seek(SeekFrom::Start(UNFIN_FLAGS_OFFSET))?;
+        self.writer.write_all(&[0; 4])?;
+
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_record(&mut self, index: usize, mut record: PendingRecord) -> io::Result<()> {
+        let group = &mut self.groups[index];
+        record.data[group.overrun_offset()] = record.overrun as u8;
+        let record_id = (index + 1) as u16;
+        self.writer.write_all(&record_id.to_le_bytes()[..self.record_id_size])?;
+        let received = record.received.as_secs_f64().to_le_bytes();
+        match group.time_base {
+            TimeBase::Host => self.writer.write_all(&received)?,
+            TimeBase::Slave { .. } => {
+                let time = record.timestamp.and_then(|timestamp| group.time_base.slave_time(&timestamp))
+                    .map_or(f64::NAN, |time| time.as_secs_f64());
+                self.writer.write_all(&time.to_le_bytes())?;
+                self.writer.write_all(&received)?;
+            }
+            TimeBase::Ticks => {
+                self.writer.write_all(&received)?;
+                self.writer.write_all(&record.timestamp.map_or(0, |timestamp| timestamp.elapsed).to_le_bytes())?;
+            }
+        }
+        self.writer.write_all(&record.data[group.time_bytes()..])?;
+
+        group.cycle_count += 1;
+        self.dt_data_len += (self.record_id_size + record.data.len()) as u64;
+        Ok(())
+    }
+
+    /// Writes the ID block, the metadata blocks and the header of the DT block.
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_written { return Ok(()) }
+
+        let mut blocks: Vec<Block> = Vec::new();
+        let start_ns = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+
+        // HD and FH, pointing to the DG at index 3.
+        let mut hd_data = start_ns.to_le_bytes().to_vec();
+        hd_data.extend_from_slice(&[0; 8]); // tz/dst offsets, time flags and class, flags, reserved
+        hd_data.extend_from_slice(&[0; 16]); // start angle and distance
+        blocks.push(Block::new(b"##HD", vec![Link::Block(3), Link::Block(1), Link::Nil, Link::Nil, Link::Nil, Link::Nil], hd_data));
+        let mut fh_data = start_ns.to_le_bytes().to_vec();
+        fh_data.extend_from_slice(&[0; 8]); // tz/dst offsets, time flags, reserved
+        blocks.push(Block::new(b"##FH", vec![Link::Nil, Link::Block(2)], fh_data));
+        blocks.push(Block::text(b"##MD", &format!(
+            "<FHcomment><TX>DAQ recording</TX><tool_id>xcp-tools</tool_id>\
+             <tool_vendor>xcp-tools</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+            env!("CARGO_PKG_VERSION"),
+        )));
+
+        // DG, with its CG and DT links patched once their indices are known.
+        let dg = blocks.len();
+        let mut dg_data = vec![self.record_id_size as u8];
+        dg_data.extend_from_slice(&[0; 7]);
+        blocks.push(Block::new(b"##DG", vec![Link::Nil, Link::Nil, Link::Nil, Link::Nil], dg_data));
+
+        let mut cg_indices: Vec<usize> = Vec::new();
+        for (index, group) in self.groups.iter().enumerate() {
+            let cg = blocks.len();
+            if let Some(&previous) = cg_indices.last() {
+                blocks[previous].links[0] = Link::Block(cg);
+            } else {
+                blocks[dg].links[1] = Link::Block(cg);
+            }
+            cg_indices.push(cg);
+
+            let mut cg_data = ((index + 1) as u64).to_le_bytes().to_vec();
+            cg_data.extend_from_slice(&0u64.to_le_bytes()); // cycle count, set by finish
+            cg_data.extend_from_slice(&[0; 8]); // flags, path separator, reserved
+            cg_data.extend_from_slice(&(group.record_bytes() as u32).to_le_bytes());
+            cg_data.extend_from_slice(&0u32.to_le_bytes()); // invalidation bytes
+            blocks.push(Block::new(b"##CG", vec![Link::Nil, Link::Block(cg + 2), Link::Block(cg + 1), Link::Nil, Link::Nil, Link::Nil], cg_data));
+            blocks.push(Block::text(b"##TX", &format!("DAQ list {}", group.daq_list)));
+
+            // cn_type 2 and cn_sync_type 1 make the first channel the time master
+            let host_time = |cn_type, sync_type, byte_offset| {
+                ("host_time".to_string(), Some("s".to_string()), cn_type, sync_type, DATA_TYPE_FLOAT_LE, byte_offset, TIME_BYTES)
+            };
+            let mut channels = match group.time_base {
+                TimeBase::Host => vec![host_time(2, 1, 0)],
+                TimeBase::Slave { .. } => vec![
+                    ("time".to_string(), Some("s".to_string()), 2, 1, DATA_TYPE_FLOAT_LE, 0, TIME_BYTES),
+                    host_time(0, 0, TIME_BYTES),
+                ],
+                TimeBase::Ticks => vec![
+                    host_time(2, 1, 0),
+                    ("ticks".to_string(), None, 0, 0, DATA_TYPE_UINT_LE, TIME_BYTES, TIME_BYTES),
+                ],
+            };
+            for (entry, channel) in group.channels.iter().enumerate() {
+                let data_type = channel_data_type(channel, self.byte_order);
+                channels.push((channel.name.clone(), channel.unit.clone(), 0, 0, data_type, group.channel_offset(entry), channel.bytes));
+            }
+            channels.push(("overrun".to_string(), None, 0, 0, DATA_TYPE_UINT_LE, group.overrun_offset(), 1));
+
+            let count = channels.len();
+            for (i, (name, unit, cn_type, sync_type, data_type, byte_offset, bytes)) in channels.into_iter().enumerate() {
+                let cn = blocks.len();
+                let next = if i + 1 < count { Link::Block(cn + if unit.is_some() { 3 } else { 2 }) } else { Link::Nil };
+                let unit_link = if unit.is_some() { Link::Block(cn + 2) } else { Link::Nil };
+
+                let mut cn_data = vec![cn_type, sync_type, data_type, 0];
+                cn_data.extend_from_slice(&(byte_offset as u32).to_le_bytes());
+                cn_data.extend_from_slice(&((8 * bytes) as u32).to_le_bytes());
+                cn_data.extend_from_slice(&[0; 12]); // flags, invalidation bit, precision, reserved, attachments
+                cn_data.extend_from_slice(&[0; 48]); // value range and limits
+                blocks.push(Block::new(b"##CN", vec![next, Link::Nil, Link::Block(cn + 1), Link::Nil, Link::Nil, Link::Nil, unit_link, Link::Nil], cn_data));
+                blocks.push(Block::text(b"##TX", &name));
+                if let Some(unit) = unit {
+                    blocks.push(Block::text(b"##TX", &unit));
+                }
+            }
+        }
+
+        let dt = blocks.len();
+        blocks[dg].links[2] = Link::Block(dt);
+        blocks.push(Block::new(b"##DT", Vec::new(), Vec::new()));
+
+        let mut offsets = Vec::with_capacity(blocks.len());
+        let mut offset = ID_BLOCK_LEN;
+        for block in &blocks {
+            offsets.push(offset);
+            offset += block.size();
+        }
+
+        let mut header = Vec::with_capacity(offset as usize);
+        header.extend_from_slice(b"UnFinMF ");
+        header.extend_from_slice(b"4.10    ");
+        header.extend_from_slice(b"xcptools");
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&MDF_VERSION.to_le_bytes());
+        header.extend_from_slice(&[0; 30]);
+        header.extend_from_slice(&UNFIN_FLAGS.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // custom unfinalized flags
+
+        for block in &blocks {
+            header.extend_from_slice(block.id);
+            header.extend_from_slice(&[0; 4]);
+            header.extend_from_slice(&block.size().to_le_bytes());
+            header.extend_from_slice(&(block.links.len() as u64).to_le_bytes());
+            for link in &block.links {
+                let target = match link {
+                    Link::Nil => 0,
+                    Link::Block(index) => offsets[*index],
+                };
+                header.extend_from_slice(&target.to_le_bytes());
+            }
+            header.extend_from_slice(&block.data);
+        }
+
+        self.writer.write_all(&header)?;
+        for (group, &cg) in self.groups.iter_mut().zip(&cg_indices) {
+            group.offset = offsets[cg];
+        }
+        self.dt_offset = offsets[dt];
+        self.header_written = true;
+        Ok(())
+    }
+}
+
+/// The cn_data_type for `channel`; sizes an integer or float cannot have are
+/// recorded as byte arrays.
+fn channel_data_type(channel: &MdfChannel, byte_order: ByteOrder) -> u8 {
+    let big_endian = byte_order == ByteOrder::Motorola;
+    match channel.format {
+        ValueFormat::Unsigned if channel.bytes <= 8 => if big_endian { DATA_TYPE_UINT_BE } else { DATA_TYPE_UINT_LE },
+        ValueFormat::Signed if channel.bytes <= 8 => if big_endian { DATA_TYPE_INT_BE } else { DATA_TYPE_INT_LE },
+        ValueFormat::Float if channel.bytes == 4 || channel.bytes == 8 => {
+            if big_endian { DATA_TYPE_FLOAT_BE } else { DATA_TYPE_FLOAT_LE }
+        }
+        _ => DATA_TYPE_BYTE_ARRAY,
+    }
+}
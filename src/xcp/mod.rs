@@ -1,3 +1,23 @@
 pub mod xcp_command;
 pub mod frame;
 pub mod master;
+pub mod can_ids;
+pub mod transport;
+pub mod session;
+pub mod error;
+pub mod dedup;
+pub mod checksum;
+pub mod dump;
+pub mod snapshot;
+pub mod daq;
+pub mod bypass;
+pub mod polling;
+pub mod flash;
+pub mod checkpoint;
+pub mod info;
+pub mod epk;
+pub mod cal;
+pub mod value;
+pub mod quick;
+#[cfg(feature = "mdf")]
+pub mod mdf;
@@ -0,0 +1,169 @@
+//! Measurement by polling: memory is read with SHORT_UPLOAD at fixed periods, for
+//! slaves without DAQ or when a few slow signals do not justify configuring lists.
+//!
+//! Values are delivered as `DaqSample`s, so they can be recorded the same way as
+//! DAQ measurements.
+
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::xcp::daq::DaqSample;
+use crate::xcp::error::XcpError;
+use crate::xcp::master::XcpMaster;
+
+/// A value to poll, see `Poller::new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollEntry {
+    pub address: u32,
+    pub address_extension: u8,
+    /// Size in bytes.
+    pub size: usize,
+    /// Interval at which the value is read.
+    pub period: Duration,
+}
+
+/// Counters of a `Poller`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PollStats {
+    /// Values read successfully.
+    pub uploads: u64,
+    /// Reads that failed.
+    pub errors: u64,
+    /// Periods skipped because the poller fell behind, per entry.
+    pub missed_periods: Vec<u64>,
+}
+
+/// Earliest-deadline-first schedule of the entries of a `Poller`.
+#[derive(Debug, Clone)]
+pub(crate) struct PollSchedule {
+    periods: Vec<Duration>,
+    due: Vec<Instant>,
+}
+
+impl PollSchedule {
+    /// All entries are due at `start`.
+    pub(crate) fn new(periods: Vec<Duration>, start: Instant) -> PollSchedule {
+        let due = vec![start; periods.len()];
+        PollSchedule { periods, due }
+    }
+
+    /// The time the next entry is due, `None` without entries.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.due.iter().min().copied()
+    }
+
+    /// Takes the entry due earliest if it is due at `now`, and schedules its next read.
+    ///
+    /// # Returns
+    /// The entry and the number of its periods that passed without a read.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Option<(usize, u64)> {
+        let (index, &due) = self.due.iter().enumerate().min_by_key(|(_, &due)| due)?;
+        if due > now { return None }
+
+        let period = self.periods[index];
+        let missed = if period.is_zero() { 0 } else { (now - due).as_nanos() / period.as_nanos() } as u64;
+        self.due[index] = due + period * (missed as u32 + 1);
+        Some((index, missed))
+    }
+}
+
+/// Reads a set of values periodically, see `PollEntry`.
+///
+/// Each call to `poll` issues at most one read, so other commands can be sent
+/// through `master` between polls; they delay the reads that fall due meanwhile.
+/// A read that is due more than one period late skips the periods missed, counts
+/// them in `stats` and marks its sample as overrun.
+pub struct Poller<'m, 'a> {
+    master: &'m mut XcpMaster<'a>,
+    entries: Vec<PollEntry>,
+    schedule: PollSchedule,
+    stats: PollStats,
+}
+
+impl<'m, 'a> Poller<'m, 'a> {
+    /// Starts polling `entries` through `master`, all of them due immediately.
+    pub fn new(master: &'m mut XcpMaster<'a>, entries: Vec<PollEntry>) -> Poller<'m, 'a> {
+        let schedule = PollSchedule::new(entries.iter().map(|entry| entry.period).collect(), Instant::now());
+        let stats = PollStats { missed_periods: vec![0; entries.len()], ..PollStats::default() };
+        Poller { master, entries, schedule, stats }
+    }
+
+    /// The master polling, e.g. to send other commands between polls.
+    pub fn master(&mut self) -> &mut XcpMaster<'a> {
+        self.master
+    }
+
+    pub fn entries(&self) -> &[PollEntry] {
+        &self.entries
+    }
+
+    pub fn stats(&self) -> &PollStats {
+        &self.stats
+    }
+
+    /// When the next value is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.schedule.next_due()
+    }
+
+    /// Reads the value due earliest, if one is due now.
+    ///
+    /// Values that fit into one response are read with SHORT_UPLOAD, larger ones
+    /// with SET_MTA and UPLOAD. The sample has DAQ list and ODT 0 and carries the
+    /// value as its only entry, with the index of the `PollEntry` as `first_entry`.
+    pub fn poll(&mut self) -> Option<Result<DaqSample, XcpError>> {
+        let (index, missed) = self.schedule.take_due(Instant::now())?;
+        self.stats.missed_periods[index] += missed;
+
+        let entry = self.entries[index];
+        let value = match self.read(&entry) {
+            Ok(value) => value,
+            Err(e) => {
+                self.stats.errors += 1;
+                return Some(Err(e));
+            }
+        };
+        self.stats.uploads += 1;
+
+        Some(Ok(DaqSample {
+            daq_list: 0,
+            odt: 0,
+            first_entry: index,
+            values: vec![value],
+            timestamp: None,
+            received: Instant::now(),
+            overrun: missed > 0,
+        }))
+    }
+
+    /// Iterates over the values as they fall due, sleeping in between. The iterator
+    /// does not end by itself.
+    pub fn samples(&mut self) -> PollSamples<'_, 'm, 'a> {
+        PollSamples { poller: self }
+    }
+
+    fn read(&mut self, entry: &PollEntry) -> Result<Vec<u8>, XcpError> {
+        self.master.read_bytes(entry.address, entry.address_extension, entry.size)
+    }
+}
+
+/// Values of a `Poller`, see `Poller::samples`.
+pub struct PollSamples<'p, 'm, 'a> {
+    poller: &'p mut Poller<'m, 'a>,
+}
+
+impl Iterator for PollSamples<'_, '_, '_> {
+    type Item = Result<DaqSample, XcpError>;
+
+    fn next(&mut self) -> Option<Result<DaqSample, XcpError>> {
+        loop {
+            let due = self.poller.next_due()?;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            if let Some(sample) = self.poller.poll() {
+                return Some(sample);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! One call per operation, for bench scripts: each function opens the SocketCAN
+//! interface, connects with the default settings, runs a single operation and
+//! disconnects again.
+//!
+//! The session is ended with DISCONNECT even if the operation fails; the error of
+//! the operation is returned then. Use `XcpMaster` directly to run several
+//! operations in one session.
+
+use socketcan::{CanSocket, Socket};
+use crate::xcp::can_ids::CanIdPair;
+use crate::xcp::error::XcpError;
+use crate::xcp::master::{SeedKeyAlgorithm, XcpMaster};
+use crate::xcp::transport::XcpTransport;
+use crate::xcp::xcp_command::{ConnectMode, XcpResource};
+
+/// Reads `length` bytes at `address` (address extension 0) from the slave on
+/// `iface` that receives commands on `tx` and answers on `rx`.
+///
+/// IDs are raw CAN IDs as `CanId::from_raw` takes them.
+pub fn read(iface: &str, tx: u32, rx: u32, address: u32, length: usize) -> Result<Vec<u8>, XcpError> {
+    on_interface(iface, tx, rx, |master| master.read_memory(address, 0, length))
+}
+
+/// Writes `data` at `address` (address extension 0), like `read`.
+pub fn write(iface: &str, tx: u32, rx: u32, address: u32, data: &[u8]) -> Result<(), XcpError> {
+    on_interface(iface, tx, rx, |master| master.write_memory(address, 0, data))
+}
+
+/// Unlocks `resource` with `key`, then reads like `read`.
+pub fn unlock_and_read(iface: &str, tx: u32, rx: u32, resource: XcpResource, key: &dyn SeedKeyAlgorithm,
+    address: u32, length: usize) -> Result<Vec<u8>, XcpError> {
+    on_interface(iface, tx, rx, |master| {
+        master.unlock_resource(resource, key)?;
+        master.read_memory(address, 0, length)
+    })
+}
+
+/// Runs `operation` in a session over `transport`: connects, runs it and
+/// disconnects, also if it fails.
+///
+/// # Returns
+/// The result of `operation`, or the error of DISCONNECT if only that failed.
+pub fn session<T>(transport: &mut dyn XcpTransport, ids: CanIdPair,
+    operation: impl FnOnce(&mut XcpMaster<'_>) -> Result<T, XcpError>) -> Result<T, XcpError> {
+    let mut master = XcpMaster::new(transport, ids);
+    master.connect(ConnectMode::Normal)?;
+    let result = operation(&mut master);
+    let disconnected = master.disconnect();
+    let value = result?;
+    disconnected?;
+    Ok(value)
+}
+
+/// Runs `operation` in a session on the SocketCAN interface `iface`.
+fn on_interface<T>(iface: &str, tx: u32, rx: u32,
+    operation: impl FnOnce(&mut XcpMaster<'_>) -> Result<T, XcpError>) -> Result<T, XcpError> {
+    let ids = CanIdPair::from_raw(tx, rx)?;
+    let mut socket = CanSocket::open(iface)?;
+    session(&mut socket, ids, operation)
+}
@@ -0,0 +1,115 @@
+//! Session event stream for observers of an `XcpMaster`.
+//!
+//! Frontends subscribe once via `XcpMaster::subscribe_events` and receive every
+//! state change the master goes through, timestamped, over a bounded channel.
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::time::SystemTime;
+use crate::xcp::flash::FlashRecovery;
+use crate::xcp::frame::{XcpCommandCode, XcpEventCode, XcpVersion};
+use crate::xcp::xcp_command::{XcpResource, XcpResourceFlags};
+
+/// Number of events buffered per subscriber before new events are dropped.
+pub const SESSION_EVENT_BUFFER: usize = 256;
+
+/// Something interesting that happened in the session.
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// CONNECT succeeded; carries the resource protection reported by the slave.
+    Connected { protection: XcpResourceFlags },
+    /// DISCONNECT succeeded.
+    Disconnected,
+    /// The resource protection status changed, e.g. after UNLOCK.
+    ResourceProtectionChanged { protection: XcpResourceFlags },
+    /// `command` failed with ERR_ACCESS_LOCKED and the master unlocks `resource`
+    /// with the registered seed/key provider before retrying it.
+    AutoUnlock { command: XcpCommandCode, resource: XcpResource },
+    /// START_STOP_DAQ_LIST started `daq_list`, or START_STOP_SYNCH the selected
+    /// lists if `None`.
+    DaqStarted { daq_list: Option<u16> },
+    /// START_STOP_DAQ_LIST stopped `daq_list`, or START_STOP_SYNCH all or the
+    /// selected lists if `None`.
+    DaqStopped { daq_list: Option<u16> },
+    /// Flashing entered `phase`.
+    FlashPhase { phase: FlashPhase },
+    /// Flashing recovers from an error on its own, see `FlashOptions`.
+    FlashRecovery { recovery: FlashRecovery },
+    /// `configure_daq` expects a bus load `load` above `BusLoadLimit::max_load`
+    /// and starts the measurement anyway as the limit is not strict.
+    BusLoadExceeded { load: f64, max_load: f64 },
+    /// `command` needs protocol version `required` but the slave implements
+    /// `negotiated`; it is sent anyway as `XcpMaster::strict` is off.
+    UnsupportedCommand { command: XcpCommandCode, required: XcpVersion, negotiated: XcpVersion },
+    /// The slave sent an EV packet.
+    SlaveEvent { code: XcpEventCode },
+    /// The slave sent the SERV packet `data`, e.g. a reset request or text to print.
+    ServiceRequest { data: Vec<u8> },
+    /// A response arrived while no command was waiting for one.
+    UnexpectedPacket { data: Vec<u8> },
+    /// Transmitting or receiving on the bus failed.
+    LinkError { message: String },
+}
+
+/// A step of the PGM sequence run by `XcpMaster::flash_ihex` and its siblings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashPhase {
+    /// PROGRAM_START.
+    Start,
+    /// PROGRAM_CLEAR of the region at `address`.
+    Clear { address: u32 },
+    /// PROGRAM of the region at `address`.
+    Program { address: u32 },
+    /// Comparing checksums of the programmed regions.
+    Verify,
+    /// PROGRAM_RESET.
+    Reset,
+    /// The sequence completed.
+    Finished,
+}
+
+/// A `SessionEvent` together with the time it was published.
+#[derive(Debug, Clone)]
+pub struct TimedSessionEvent {
+    pub timestamp: SystemTime,
+    pub event: SessionEvent,
+}
+
+/// Fans session events out to all subscribers.
+///
+/// A subscriber that does not keep up loses the events that do not fit into its
+/// buffer; a subscriber whose receiver was dropped is removed.
+#[derive(Default)]
+pub struct SessionEventBus {
+    subscribers: Vec<SyncSender<TimedSessionEvent>>,
+    dropped: u64,
+}
+
+impl SessionEventBus {
+    /// Adds a subscriber buffering at most `capacity` events.
+    pub fn subscribe(&mut self, capacity: usize) -> Receiver<TimedSessionEvent> {
+        let (tx, rx) = sync_channel(capacity);
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Publishes `event` to every subscriber without blocking.
+    pub fn publish(&mut self, event: SessionEvent) {
+        if self.subscribers.is_empty() { return }
+
+        let timed = TimedSessionEvent { timestamp: SystemTime::now(), event };
+        let mut dropped = 0;
+        self.subscribers.retain(|tx| {
+            match tx.try_send(timed.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => { dropped += 1; true }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        self.dropped += dropped;
+    }
+
+    /// Total number of events dropped because a subscriber's buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
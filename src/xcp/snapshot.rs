@@ -0,0 +1,143 @@
+//! Snapshots of calibration segments, to save the working page before experimenting
+//! and restore it afterwards.
+//!
+//! The file starts with the magic `XCPCAL01`, followed by the slave identification
+//! (u16 length and the bytes of GET_ID type 0) and the number of regions (u32). Each
+//! region is stored as address extension (u8), address (u32), length (u32) and the
+//! data. All integers are little endian.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::xcp::epk::ExpectedEpk;
+use crate::xcp::error::XcpError;
+use crate::xcp::master::XcpMaster;
+use crate::xcp::xcp_command::{IdType, SegmentInfo, SegmentInfoMode};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"XCPCAL01";
+
+/// One contiguous memory region of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRegion {
+    pub address_extension: u8,
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Contents of calibration segments together with the identification of the slave
+/// they were read from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CalSnapshot {
+    pub id: Vec<u8>,
+    pub regions: Vec<SnapshotRegion>,
+}
+
+impl CalSnapshot {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&(self.id.len() as u16).to_le_bytes())?;
+        writer.write_all(&self.id)?;
+        writer.write_all(&(self.regions.len() as u32).to_le_bytes())?;
+        for region in &self.regions {
+            writer.write_all(&[region.address_extension])?;
+            writer.write_all(&region.address.to_le_bytes())?;
+            writer.write_all(&(region.data.len() as u32).to_le_bytes())?;
+            writer.write_all(&region.data)?;
+        }
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<CalSnapshot> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a calibration snapshot"));
+        }
+
+        let id_len = read_u16(&mut reader)? as usize;
+        let mut id = vec![0u8; id_len];
+        reader.read_exact(&mut id)?;
+
+        let count = read_u32(&mut reader)?;
+        let mut regions = Vec::new();
+        for _ in 0..count {
+            let mut ext = [0u8; 1];
+            reader.read_exact(&mut ext)?;
+            let address = read_u32(&mut reader)?;
+            let mut data = vec![0u8; read_u32(&mut reader)? as usize];
+            reader.read_exact(&mut data)?;
+            regions.push(SnapshotRegion { address_extension: ext[0], address, data });
+        }
+
+        Ok(CalSnapshot { id, regions })
+    }
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut raw = [0u8; 2];
+    reader.read_exact(&mut raw)?;
+    Ok(u16::from_le_bytes(raw))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut raw = [0u8; 4];
+    reader.read_exact(&mut raw)?;
+    Ok(u32::from_le_bytes(raw))
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Reads the given calibration segments and saves them to `path`.
+    ///
+    /// Address, length and address extension of every segment come from
+    /// GET_SEGMENT_INFO; the slave identification from GET_ID is recorded as well.
+    pub fn save_cal_page<P: AsRef<Path>>(&mut self, segments: &[u8], path: P) -> Result<CalSnapshot, XcpError> {
+        let mut snapshot = CalSnapshot { id: self.get_id(IdType::AsciiText)?, regions: Vec::new() };
+
+        for &segment in segments {
+            let (address_extension, address, length) = self.segment_region(segment)?;
+            let data = self.read_memory(address, address_extension, length)?;
+            snapshot.regions.push(SnapshotRegion { address_extension, address, data });
+        }
+
+        snapshot.write_to(BufWriter::new(File::create(path)?))?;
+        Ok(snapshot)
+    }
+
+    /// Writes a snapshot saved by `save_cal_page` back and verifies every region with
+    /// BUILD_CHECKSUM.
+    ///
+    /// # Returns
+    /// `XcpError::SnapshotMismatch` if the slave identification differs from the one
+    /// recorded, unless `force` is set, and `XcpError::EpkMismatch` if `epk` is given
+    /// and the slave's EPK differs. Both are checked before anything is written.
+    pub fn restore_cal_page<P: AsRef<Path>>(&mut self, path: P, force: bool, epk: Option<&ExpectedEpk>) -> Result<(), XcpError> {
+        let snapshot = CalSnapshot::read_from(BufReader::new(File::open(path)?))?;
+        self.check_expected_epk(epk)?;
+
+        let id = self.get_id(IdType::AsciiText)?;
+        if id != snapshot.id && !force {
+            return Err(XcpError::SnapshotMismatch {
+                recorded: String::from_utf8_lossy(&snapshot.id).into_owned(),
+                actual: String::from_utf8_lossy(&id).into_owned(),
+            });
+        }
+
+        for region in &snapshot.regions {
+            self.write_memory(region.address, region.address_extension, &region.data)?;
+            self.verify_block(region.address, region.address_extension, &region.data)?;
+        }
+        Ok(())
+    }
+
+    /// Address extension, address and length in bytes of `segment`.
+    fn segment_region(&mut self, segment: u8) -> Result<(u8, u32, usize), XcpError> {
+        // the shape of the answer always matches the requested mode
+        let SegmentInfo::Basic(address) = self.get_segment_info(SegmentInfoMode::BasicAddress, segment, 0, 0)? else { unreachable!() };
+        let SegmentInfo::Basic(length) = self.get_segment_info(SegmentInfoMode::BasicAddress, segment, 1, 0)? else { unreachable!() };
+        let SegmentInfo::Standard(standard) = self.get_segment_info(SegmentInfoMode::Standard, segment, 0, 0)? else { unreachable!() };
+
+        // the length is given in elements of the address granularity
+        let ag = self.comm_mode_basic.granularity().size();
+        Ok((standard.address_extension, address.basic_info, length.basic_info as usize * ag))
+    }
+}
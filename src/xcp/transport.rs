@@ -0,0 +1,152 @@
+//! The link `XcpMaster` exchanges CAN frames over.
+
+use std::io;
+use std::time::Duration;
+use socketcan::{CanFrame, CanSocket, Socket};
+#[cfg(any(test, feature = "mock"))]
+use std::{collections::VecDeque, thread};
+#[cfg(any(test, feature = "mock"))]
+use socketcan::{EmbeddedFrame, Id};
+
+#[cfg(any(test, feature = "mock"))]
+use crate::xcp::can_ids::{CanId, CanIdPair};
+#[cfg(any(test, feature = "mock"))]
+use crate::xcp::master::ENOBUFS;
+
+/// Sends and receives the frames of one XCP session.
+///
+/// Implemented for SocketCAN sockets; other implementations let the master run
+/// over a different link, or against a simulated slave.
+pub trait XcpTransport {
+    /// Transmits `frame`. An error for which `master::is_tx_backpressure` holds
+    /// means the TX queue is momentarily full and the frame may be retried.
+    fn write_frame(&mut self, frame: &CanFrame) -> io::Result<()>;
+
+    /// Waits up to `timeout` for the next frame on the bus, failing with
+    /// `WouldBlock` or `TimedOut` if none arrives.
+    fn read_frame_timeout(&mut self, timeout: Duration) -> io::Result<CanFrame>;
+}
+
+impl XcpTransport for CanSocket {
+    fn write_frame(&mut self, frame: &CanFrame) -> io::Result<()> {
+        Socket::write_frame(self, frame)
+    }
+
+    fn read_frame_timeout(&mut self, timeout: Duration) -> io::Result<CanFrame> {
+        Socket::read_frame_timeout(self, timeout)
+    }
+}
+
+/// Computes the payloads a simulated slave answers a frame with.
+#[cfg(any(test, feature = "mock"))]
+type MockResponder<'r> = Box<dyn FnMut(&[u8]) -> Vec<Vec<u8>> + 'r>;
+
+/// Computes the payloads a simulated slave sends on its own while the master waits.
+#[cfg(any(test, feature = "mock"))]
+type MockIdle<'r> = Box<dyn FnMut() -> Vec<Vec<u8>> + 'r>;
+
+/// A transport that answers frames from a closure instead of a CAN bus, to run the
+/// master against a simulated slave.
+///
+/// Every frame written is passed to the responder, and the payloads it returns are
+/// delivered in order on `ids.res`.
+///
+/// Test infrastructure, available with the `mock` feature.
+#[cfg(any(test, feature = "mock"))]
+pub struct MockTransport<'r> {
+    ids: CanIdPair,
+    responder: MockResponder<'r>,
+    idle: Option<MockIdle<'r>>,
+    pending: VecDeque<CanFrame>,
+    throttle: Option<(usize, usize)>,
+    writes: usize,
+    /// Frames accepted for transmission, oldest first.
+    pub sent: Vec<CanFrame>,
+    /// Writes refused while throttled.
+    pub rejected: usize,
+}
+
+#[cfg(any(test, feature = "mock"))]
+impl<'r> MockTransport<'r> {
+    /// Creates a transport answering each frame written with the payloads returned
+    /// by `responder`.
+    pub fn new(ids: CanIdPair, responder: impl FnMut(&[u8]) -> Vec<Vec<u8>> + 'r) -> MockTransport<'r> {
+        MockTransport {
+            ids,
+            responder: Box::new(responder),
+            idle: None,
+            pending: VecDeque::new(),
+            throttle: None,
+            writes: 0,
+            sent: Vec::new(),
+            rejected: 0,
+        }
+    }
+
+    /// Queues an unsolicited frame from the slave, e.g. an EV packet or a DTO.
+    pub fn push_response(&mut self, payload: &[u8]) {
+        self.push_frame(self.ids.res, payload);
+    }
+
+    /// Queues a frame on an arbitrary identifier.
+    pub fn push_frame(&mut self, id: CanId, payload: &[u8]) {
+        let frame = CanFrame::new(Id::from(id), payload).expect("payload does not fit into a CAN frame");
+        self.pending.push_back(frame);
+    }
+
+    /// Calls `idle` whenever a read finds no frame queued, e.g. to let a simulated
+    /// slave advance its clock and send DTOs. The payloads it returns are received
+    /// in order; the read only times out if it returns none.
+    pub fn on_idle(&mut self, idle: impl FnMut() -> Vec<Vec<u8>> + 'r) {
+        self.idle = Some(Box::new(idle));
+    }
+
+    /// Accepts `accept` writes, then refuses `reject` writes with ENOBUFS as a full
+    /// TX queue would, and so on.
+    pub fn throttle(&mut self, accept: usize, reject: usize) {
+        self.throttle = Some((accept, reject));
+        self.writes = 0;
+    }
+
+    /// Payloads of the frames accepted for transmission, oldest first.
+    pub fn sent_payloads(&self) -> Vec<Vec<u8>> {
+        self.sent.iter().map(|frame| frame.data().to_vec()).collect()
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+impl XcpTransport for MockTransport<'_> {
+    fn write_frame(&mut self, frame: &CanFrame) -> io::Result<()> {
+        if let Some((accept, reject)) = self.throttle {
+            let slot = self.writes % (accept + reject);
+            self.writes += 1;
+            if slot >= accept {
+                self.rejected += 1;
+                return Err(io::Error::from_raw_os_error(ENOBUFS));
+            }
+        }
+
+        self.sent.push(*frame);
+        for payload in (self.responder)(frame.data()) {
+            self.push_response(&payload);
+        }
+        Ok(())
+    }
+
+    fn read_frame_timeout(&mut self, timeout: Duration) -> io::Result<CanFrame> {
+        if self.pending.is_empty() {
+            let payloads = self.idle.as_mut().map(|idle| idle()).unwrap_or_default();
+            for payload in payloads {
+                self.push_response(&payload);
+            }
+        }
+        match self.pending.pop_front() {
+            Some(frame) => Ok(frame),
+            None => {
+                // nothing will arrive before the timeout
+                thread::sleep(timeout);
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            }
+        }
+    }
+}
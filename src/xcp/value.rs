@@ -0,0 +1,185 @@
+//! Reading and writing typed values in slave memory in the slave's byte order.
+
+use crate::xcp::error::XcpError;
+use crate::xcp::frame::{ByteOrder, XcpCommandCode};
+use crate::xcp::master::{WriteVerification, XcpMaster};
+
+/// A fixed-size value `XcpMaster::read_value` can decode from slave memory.
+///
+/// Implemented for the integer and float types and for arrays of them; implement it
+/// for other fixed-size types to read them the same way.
+pub trait FromXcpBytes: Sized {
+    /// Size in bytes.
+    const SIZE: usize;
+
+    /// Decodes the value from exactly `SIZE` bytes stored in `byte_order`.
+    fn from_xcp_bytes(bytes: &[u8], byte_order: ByteOrder) -> Self;
+}
+
+/// A value `XcpMaster::write_value` can encode for slave memory, the counterpart
+/// of `FromXcpBytes`.
+pub trait ToXcpBytes {
+    /// Encodes the value in `byte_order`.
+    fn to_xcp_bytes(&self, byte_order: ByteOrder) -> Vec<u8>;
+}
+
+macro_rules! impl_xcp_bytes {
+    ($($t:ty),*) => {$(
+        impl FromXcpBytes for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn from_xcp_bytes(bytes: &[u8], byte_order: ByteOrder) -> $t {
+                let raw = bytes.try_into().expect("value size");
+                match byte_order {
+                    ByteOrder::Intel => <$t>::from_le_bytes(raw),
+                    ByteOrder::Motorola => <$t>::from_be_bytes(raw),
+                }
+            }
+        }
+
+        impl ToXcpBytes for $t {
+            fn to_xcp_bytes(&self, byte_order: ByteOrder) -> Vec<u8> {
+                match byte_order {
+                    ByteOrder::Intel => self.to_le_bytes().to_vec(),
+                    ByteOrder::Motorola => self.to_be_bytes().to_vec(),
+                }
+            }
+        }
+    )*};
+}
+
+impl_xcp_bytes!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Elements are stored one after the other, each in the slave's byte order.
+impl<T: FromXcpBytes, const N: usize> FromXcpBytes for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn from_xcp_bytes(bytes: &[u8], byte_order: ByteOrder) -> [T; N] {
+        std::array::from_fn(|i| T::from_xcp_bytes(&bytes[i * T::SIZE..(i + 1) * T::SIZE], byte_order))
+    }
+}
+
+impl<T: ToXcpBytes, const N: usize> ToXcpBytes for [T; N] {
+    fn to_xcp_bytes(&self, byte_order: ByteOrder) -> Vec<u8> {
+        self.iter().flat_map(|element| element.to_xcp_bytes(byte_order)).collect()
+    }
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Reads a `T` at `address`, decoded in the byte order the slave reported on
+    /// CONNECT. Uses SHORT_UPLOAD, or SET_MTA and UPLOAD for values too large for
+    /// one response, see `read_bytes`.
+    pub fn read_value<T: FromXcpBytes>(&mut self, address: u32, address_extension: u8) -> Result<T, XcpError> {
+        let bytes = self.read_bytes(address, address_extension, T::SIZE)?;
+        if bytes.len() != T::SIZE {
+            return Err(XcpError::InvalidPayloadLength {
+                command: XcpCommandCode::ShortUpload,
+                length: bytes.len(),
+                expected: T::SIZE,
+            });
+        }
+        Ok(T::from_xcp_bytes(&bytes, self.comm_mode_basic.endianness()))
+    }
+
+    pub fn read_u8(&mut self, address: u32, address_extension: u8) -> Result<u8, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_u16(&mut self, address: u32, address_extension: u8) -> Result<u16, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_u32(&mut self, address: u32, address_extension: u8) -> Result<u32, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_u64(&mut self, address: u32, address_extension: u8) -> Result<u64, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_i8(&mut self, address: u32, address_extension: u8) -> Result<i8, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_i16(&mut self, address: u32, address_extension: u8) -> Result<i16, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_i32(&mut self, address: u32, address_extension: u8) -> Result<i32, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_i64(&mut self, address: u32, address_extension: u8) -> Result<i64, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_f32(&mut self, address: u32, address_extension: u8) -> Result<f32, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    pub fn read_f64(&mut self, address: u32, address_extension: u8) -> Result<f64, XcpError> {
+        self.read_value(address, address_extension)
+    }
+
+    /// Writes `value` to `address`, encoded in the byte order the slave reported on
+    /// CONNECT. Uses SHORT_DOWNLOAD, or SET_MTA and DOWNLOAD for values too large for
+    /// one command, see `write_bytes`.
+    ///
+    /// # Returns
+    /// `XcpError::AccessLocked` if CAL/PAG must be unlocked first,
+    /// `XcpError::WriteProtected` if the memory cannot be written at all, and
+    /// `XcpError::UnalignedLength` if the value is not a whole number of elements.
+    pub fn write_value<T: ToXcpBytes>(&mut self, address: u32, address_extension: u8, value: T) -> Result<(), XcpError> {
+        let bytes = value.to_xcp_bytes(self.comm_mode_basic.endianness());
+        self.write_bytes(address, address_extension, &bytes)
+    }
+
+    /// Like `write_value`, then confirms the memory holds the value, see
+    /// `write_memory_verified`.
+    pub fn write_value_verified<T: ToXcpBytes>(&mut self, address: u32, address_extension: u8, value: T,
+        verification: WriteVerification) -> Result<(), XcpError> {
+        let bytes = value.to_xcp_bytes(self.comm_mode_basic.endianness());
+        self.write_bytes(address, address_extension, &bytes)?;
+        self.verify_written(address, address_extension, &bytes, verification)
+    }
+
+    pub fn write_u8(&mut self, address: u32, address_extension: u8, value: u8) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_u16(&mut self, address: u32, address_extension: u8, value: u16) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_u32(&mut self, address: u32, address_extension: u8, value: u32) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_u64(&mut self, address: u32, address_extension: u8, value: u64) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_i8(&mut self, address: u32, address_extension: u8, value: i8) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_i16(&mut self, address: u32, address_extension: u8, value: i16) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_i32(&mut self, address: u32, address_extension: u8, value: i32) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_i64(&mut self, address: u32, address_extension: u8, value: i64) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_f32(&mut self, address: u32, address_extension: u8, value: f32) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+
+    pub fn write_f64(&mut self, address: u32, address_extension: u8, value: f64) -> Result<(), XcpError> {
+        self.write_value(address, address_extension, value)
+    }
+}
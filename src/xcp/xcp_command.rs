@@ -4,116 +4,2126 @@
 //! for interacting with XCP over CAN bus. It provides traits for encoding commands to CAN
 //! frames and decoding responses from CAN frames.
 
-use crate::xcp::frame::{ XcpCommand, XcpCommandCode, XcpResponse, XcpResponseCode, XcpErrorCode, XcpResponseFrame };
+use crate::xcp::frame::{ XcpCommand, XcpCommandCode, XcpResponse, XcpResponseCode, XcpErrorCode, XcpResponseFrame, XcpFrameBuf, XcpVersion, ByteOrder, AddressGranularity };
+
+use crate::xcp::error::XcpError;
 
 use bitfield::bitfield;
 
-/// XCP "Connect" command structure.
-#[derive(Debug)]
-pub struct ConnectCommand {
-    pub mode: ConnectMode,
+/// XCP "Connect" command structure.
+#[derive(Debug)]
+pub struct ConnectCommand {
+    pub mode: ConnectMode,
+}
+
+impl XcpCommand for ConnectCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Connect }
+}
+
+/// XCP "Disconnect" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct DisconnectCommand;
+
+impl XcpCommand for DisconnectCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Disconnect }
+}
+
+/// XCP "Connect" response structure.
+#[derive(Debug)]
+pub struct ConnectResponse {
+    pub resource: XcpResourceFlags,
+    pub comm_mode_basic: XcpCommModeBasic,
+    pub max_cto: u8,
+    pub max_dto: u8,
+    pub protocol_version: u8,
+    pub transport_version: u8,
+}
+
+impl XcpResponse for ConnectResponse {
+    const MIN_LENGTH: usize = 7;
+
+    fn from_can_frame(can_frame: &[u8]) -> ConnectResponse {
+        ConnectResponse {
+            resource: XcpResourceFlags::from(can_frame[1]),
+            comm_mode_basic: XcpCommModeBasic(can_frame[2]),
+            max_cto: can_frame[3],
+            max_dto: can_frame[4],
+            protocol_version: can_frame[5],
+            transport_version: can_frame[6],
+        }
+    }
+}
+
+/// XCP "Get Seed" command structure.
+#[derive(Debug, Copy, Clone)]
+pub struct GetSeedCommand {
+    pub mode: GetSeedMode,
+    pub resource: XcpResourceFlags,
+}
+
+impl XcpCommand for GetSeedCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        frame_data.push(u8::from(self.resource))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetSeed }
+}
+
+/// XCP "Get Seed" response structure.
+#[derive(Debug, Clone)]
+pub struct GetSeedResponse {
+    pub requested_resource_is_protected: bool,
+    pub remaining_length: u8,
+    /// The seed bytes in this response, without the padding of the frame beyond
+    /// `remaining_length`.
+    pub seed_data: XcpFrameBuf,
+}
+
+impl XcpResponse for GetSeedResponse {
+    const MIN_LENGTH: usize = 2;
+
+    fn from_can_frame(can_frame: &[u8]) -> GetSeedResponse {
+        let payload = &can_frame[2..];
+        let length = usize::min(can_frame[1] as usize, payload.len());
+        GetSeedResponse {
+            requested_resource_is_protected: can_frame[1] != 0,
+            remaining_length: can_frame[1],
+            seed_data: XcpFrameBuf::from_prefix(&payload[..length]),
+        }
+    }
+}
+
+/// XCP "Unlock" command structure
+#[derive(Debug, Clone)]
+pub struct UnlockCommand {
+    pub remaining_length: u8,
+    pub key_data: Vec<u8>
+}
+
+impl XcpCommand for UnlockCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.remaining_length)?;
+        frame_data.extend_from_slice(&self.key_data)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Unlock }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnlockResponse {
+    pub resource: XcpResourceFlags
+}
+
+impl XcpResponse for UnlockResponse {
+    const MIN_LENGTH: usize = 2;
+
+    fn from_can_frame(frame: &[u8]) -> UnlockResponse {
+        UnlockResponse { resource: XcpResourceFlags::from(frame[1]) }
+    }
+}
+
+/// XCP "User Command" command structure.
+#[derive(Debug, Clone)]
+pub struct UserCmdCommand {
+    pub sub_command: u8,
+    pub parameters: Vec<u8>
+}
+
+impl XcpCommand for UserCmdCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.sub_command)?;
+        frame_data.extend_from_slice(&self.parameters)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::UserCmd }
+}
+
+/// Positive response which carries no data beyond the PID.
+#[derive(Debug, Clone)]
+pub struct EmptyResponse;
+
+impl XcpResponse for EmptyResponse {
+    fn from_can_frame(_frame: &[u8]) -> EmptyResponse {
+        EmptyResponse
+    }
+}
+
+/// XCP "Get Status" command structure.
+#[derive(Debug, Clone)]
+pub struct GetStatusCommand;
+
+impl XcpCommand for GetStatusCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        XcpFrameBuf::from_slice(&[self.get_code().to_code()])
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetStatus }
+}
+
+/// XCP "Synch" command structure.
+#[derive(Debug, Clone)]
+pub struct SynchCommand;
+
+impl XcpCommand for SynchCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        XcpFrameBuf::from_slice(&[self.get_code().to_code()])
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Synch }
+}
+
+/// XCP "Get Comm Mode Info" command structure.
+#[derive(Debug, Clone)]
+pub struct GetCommModeInfoCommand;
+
+impl XcpCommand for GetCommModeInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        XcpFrameBuf::from_slice(&[self.get_code().to_code()])
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetCommModeInfo }
+}
+
+bitfield! {
+    /// Optional communication modes supported by the slave.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpCommModeOptional(u8);
+    impl Debug;
+
+    pub master_block_mode, set_master_block_mode: 0;
+    pub interleaved_mode, set_interleaved_mode: 1;
+}
+
+/// XCP "Get Comm Mode Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetCommModeInfoResponse {
+    pub comm_mode_optional: XcpCommModeOptional,
+    /// Maximum number of frames in a master block transfer.
+    pub max_bs: u8,
+    /// Minimum separation time between frames of a block transfer, in units of 100 µs.
+    pub min_st: u8,
+    /// Maximum number of queued requests in interleaved mode.
+    pub queue_size: u8,
+    pub driver_version: u8,
+}
+
+impl XcpResponse for GetCommModeInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetCommModeInfoResponse {
+        GetCommModeInfoResponse {
+            comm_mode_optional: XcpCommModeOptional(frame[2]),
+            max_bs: frame[4],
+            min_st: frame[5],
+            queue_size: frame[6],
+            driver_version: frame[7],
+        }
+    }
+}
+
+/// XCP "Get Version" command structure, a level 1 command.
+#[derive(Debug, Clone, Copy)]
+pub struct GetVersionCommand;
+
+impl XcpCommand for GetVersionCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        XcpFrameBuf::from_slice(&[self.get_code().to_code(), 0x00])
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Level1Command }
+
+    // it is how the minor version is learned in the first place, and slaves
+    // predating it answer ERR_CMD_UNKNOWN
+    fn min_protocol_version(&self) -> XcpVersion { XcpVersion::new(1, 0) }
+}
+
+/// XCP "Get Version" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetVersionResponse {
+    pub protocol_layer: XcpVersion,
+    pub transport_layer: XcpVersion,
+}
+
+impl XcpResponse for GetVersionResponse {
+    const MIN_LENGTH: usize = 6;
+
+    fn from_can_frame(frame: &[u8]) -> GetVersionResponse {
+        GetVersionResponse {
+            protocol_layer: XcpVersion::new(frame[2], frame[3]),
+            transport_layer: XcpVersion::new(frame[4], frame[5]),
+        }
+    }
+}
+
+/// Identification types for GET_ID.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IdType {
+    /// ASCII text, typically the ECU name.
+    AsciiText,
+    /// ASAM-MC2 (A2L) filename without path and extension.
+    FilenameWithoutPath,
+    /// ASAM-MC2 filename with path and extension.
+    FilenameWithPath,
+    /// URL where the ASAM-MC2 file can be found.
+    Url,
+    /// The ASAM-MC2 file itself, to be uploaded.
+    FileToUpload,
+    /// Vendor specific identification, 128..=255.
+    UserDefined(u8),
+}
+
+impl IdType {
+    pub fn to_code(&self) -> u8 {
+        match self {
+            IdType::AsciiText => 0,
+            IdType::FilenameWithoutPath => 1,
+            IdType::FilenameWithPath => 2,
+            IdType::Url => 3,
+            IdType::FileToUpload => 4,
+            IdType::UserDefined(code) => *code,
+        }
+    }
+}
+
+/// XCP "Get ID" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetIdCommand {
+    pub id_type: IdType,
+}
+
+impl XcpCommand for GetIdCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.id_type.to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetId }
+}
+
+/// XCP "Get ID" response structure.
+#[derive(Debug, Clone)]
+pub struct GetIdResponse {
+    pub mode: u8,
+    /// Length of the identification in bytes.
+    pub length: u32,
+    /// Identification data carried in the response itself, if any.
+    pub identification: XcpFrameBuf,
+}
+
+impl GetIdResponse {
+    /// Whether the identification is contained in the response (MODE bit 0) rather
+    /// than having to be uploaded from the MTA the slave just set.
+    pub fn in_response(&self) -> bool {
+        self.mode & 0x01 != 0
+    }
+}
+
+impl XcpResponse for GetIdResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetIdResponse {
+        GetIdResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetIdResponse {
+        GetIdResponse {
+            mode: frame[1],
+            length: mode.endianness().read_u32(&frame[4..8]),
+            identification: XcpFrameBuf::from_prefix(&frame[8..]),
+        }
+    }
+}
+
+/// XCP "Set MTA" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetMtaCommand {
+    pub address: u32,
+    pub address_extension: u8,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for SetMtaCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.extend_from_slice(&[0x00, 0x00])?; // reserved
+        frame_data.push(self.address_extension)?;
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.address))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetMta }
+}
+
+/// XCP "Upload" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadCommand {
+    pub n_elements: u8,
+}
+
+impl XcpCommand for UploadCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.n_elements)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Upload }
+}
+
+/// XCP "Upload" response structure.
+///
+/// `data` holds everything after the PID and the alignment bytes the address
+/// granularity requires, which may include padding beyond the requested number
+/// of elements.
+#[derive(Debug, Clone)]
+pub struct UploadResponse {
+    pub data: XcpFrameBuf,
+}
+
+impl XcpResponse for UploadResponse {
+    fn from_can_frame(frame: &[u8]) -> UploadResponse {
+        UploadResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> UploadResponse {
+        let offset = usize::min(mode.granularity().size(), frame.len());
+        UploadResponse { data: XcpFrameBuf::from_prefix(&frame[offset..]) }
+    }
+}
+
+/// XCP "Short Upload" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ShortUploadCommand {
+    pub n_elements: u8,
+    pub address: u32,
+    pub address_extension: u8,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for ShortUploadCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.n_elements)?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.push(self.address_extension)?;
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.address))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ShortUpload }
+}
+
+/// XCP "Short Upload" response structure, laid out like the UPLOAD response.
+pub type ShortUploadResponse = UploadResponse;
+
+/// Checksum algorithm reported by BUILD_CHECKSUM.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumType {
+    /// Sum of bytes into a byte.
+    Add11,
+    /// Sum of bytes into a word.
+    Add12,
+    /// Sum of bytes into a dword.
+    Add14,
+    /// Sum of words into a word.
+    Add22,
+    /// Sum of words into a dword.
+    Add24,
+    /// Sum of dwords into a dword.
+    Add44,
+    Crc16,
+    Crc16Ccitt,
+    Crc32,
+    /// Slave specific algorithm, e.g. implemented in an external library.
+    UserDefined,
+    Unknown(u8),
+}
+
+impl ChecksumType {
+    /// Convert a raw checksum type to a `ChecksumType` enum variant.
+    pub fn from_code(code: u8) -> ChecksumType {
+        match code {
+            0x01 => ChecksumType::Add11,
+            0x02 => ChecksumType::Add12,
+            0x03 => ChecksumType::Add14,
+            0x04 => ChecksumType::Add22,
+            0x05 => ChecksumType::Add24,
+            0x06 => ChecksumType::Add44,
+            0x07 => ChecksumType::Crc16,
+            0x08 => ChecksumType::Crc16Ccitt,
+            0x09 => ChecksumType::Crc32,
+            0xFF => ChecksumType::UserDefined,
+            code => ChecksumType::Unknown(code),
+        }
+    }
+
+    /// The built-in algorithm named as in the A2L CHECKSUM_TYPE keyword, e.g.
+    /// "XCP_CRC_32"; the "XCP_" prefix and case do not matter.
+    pub fn from_name(name: &str) -> Option<ChecksumType> {
+        let name = name.to_ascii_uppercase();
+        let checksum_type = match name.strip_prefix("XCP_").unwrap_or(&name) {
+            "ADD_11" => ChecksumType::Add11,
+            "ADD_12" => ChecksumType::Add12,
+            "ADD_14" => ChecksumType::Add14,
+            "ADD_22" => ChecksumType::Add22,
+            "ADD_24" => ChecksumType::Add24,
+            "ADD_44" => ChecksumType::Add44,
+            "CRC_16" => ChecksumType::Crc16,
+            "CRC_16_CITT" => ChecksumType::Crc16Ccitt,
+            "CRC_32" => ChecksumType::Crc32,
+            _ => return None,
+        };
+        Some(checksum_type)
+    }
+
+    /// Convert a `ChecksumType` enum variant to its raw checksum type.
+    pub fn to_code(&self) -> u8 {
+        match self {
+            ChecksumType::Add11 => 0x01,
+            ChecksumType::Add12 => 0x02,
+            ChecksumType::Add14 => 0x03,
+            ChecksumType::Add22 => 0x04,
+            ChecksumType::Add24 => 0x05,
+            ChecksumType::Add44 => 0x06,
+            ChecksumType::Crc16 => 0x07,
+            ChecksumType::Crc16Ccitt => 0x08,
+            ChecksumType::Crc32 => 0x09,
+            ChecksumType::UserDefined => 0xFF,
+            ChecksumType::Unknown(code) => *code,
+        }
+    }
+}
+
+/// XCP "Build Checksum" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildChecksumCommand {
+    /// Number of elements, starting at the MTA, to build the checksum over.
+    pub block_size: u32,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for BuildChecksumCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.extend_from_slice(&[0x00, 0x00, 0x00])?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.block_size))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::BuildChecksum }
+}
+
+/// XCP "Build Checksum" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct BuildChecksumResponse {
+    pub checksum_type: ChecksumType,
+    pub checksum: u32,
+}
+
+impl XcpResponse for BuildChecksumResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> BuildChecksumResponse {
+        BuildChecksumResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> BuildChecksumResponse {
+        BuildChecksumResponse {
+            checksum_type: ChecksumType::from_code(frame[1]),
+            checksum: mode.endianness().read_u32(&frame[4..8]),
+        }
+    }
+}
+
+/// XCP "Download" command structure.
+#[derive(Debug, Clone)]
+pub struct DownloadCommand {
+    pub n_elements: u8,
+    pub data: Vec<u8>,
+    pub granularity: AddressGranularity,
+}
+
+impl DownloadCommand {
+    /// Splits `data` into DOWNLOAD commands carrying as many whole elements as fit
+    /// into `max_cto` each. `data` must be a whole number of elements.
+    pub fn split(data: &[u8], granularity: AddressGranularity, max_cto: usize) -> Vec<DownloadCommand> {
+        let ag = granularity.size();
+        let max_elements = usize::min((max_cto - granularity.download_offset()) / ag, u8::MAX as usize);
+
+        data.chunks(max_elements * ag)
+            .map(|chunk| DownloadCommand { n_elements: (chunk.len() / ag) as u8, data: chunk.to_vec(), granularity })
+            .collect()
+    }
+}
+
+impl XcpCommand for DownloadCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        download_frame(self.get_code(), self.n_elements, self.granularity, &self.data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Download }
+}
+
+/// XCP "Download Next" command structure, continuing a master block mode download.
+#[derive(Debug, Clone)]
+pub struct DownloadNextCommand {
+    /// Elements remaining in the block, including the ones in this frame.
+    pub n_elements: u8,
+    pub data: Vec<u8>,
+    pub granularity: AddressGranularity,
+}
+
+impl DownloadNextCommand {
+    /// Splits one block of at most 255 elements into the initial DOWNLOAD and the
+    /// DOWNLOAD_NEXT frames following it. Every frame carries the number of elements
+    /// remaining in the block.
+    pub fn block(data: &[u8], granularity: AddressGranularity, max_cto: usize) -> (DownloadCommand, Vec<DownloadNextCommand>) {
+        let ag = granularity.size();
+        let per_frame = (max_cto - granularity.download_offset()) / ag;
+        let mut remaining = data.len() / ag;
+        let mut chunks = data.chunks(per_frame * ag);
+
+        let first = chunks.next().unwrap_or_default();
+        let download = DownloadCommand { n_elements: remaining as u8, data: first.to_vec(), granularity };
+        remaining -= first.len() / ag;
+
+        let next = chunks.map(|chunk| {
+            let command = DownloadNextCommand { n_elements: remaining as u8, data: chunk.to_vec(), granularity };
+            remaining -= chunk.len() / ag;
+            command
+        }).collect();
+
+        (download, next)
+    }
+}
+
+impl XcpCommand for DownloadNextCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        download_frame(self.get_code(), self.n_elements, self.granularity, &self.data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::DownloadNext }
+}
+
+/// XCP "Download Max" command structure.
+///
+/// Carries as many elements as fit into a CTO, without an element count; the data
+/// starts at the offset of the address granularity.
+#[derive(Debug, Clone)]
+pub struct DownloadMaxCommand {
+    pub data: Vec<u8>,
+    pub granularity: AddressGranularity,
+}
+
+impl XcpCommand for DownloadMaxCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        while frame_data.len() < self.granularity.size() {
+            frame_data.push(0x00)?; // alignment
+        }
+        frame_data.extend_from_slice(&self.data)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::DownloadMax }
+}
+
+/// XCP "Short Download" command structure.
+///
+/// The data follows the 8 byte header, so on classic CAN with a MAX_CTO of 8 there
+/// is no room for any.
+#[derive(Debug, Clone)]
+pub struct ShortDownloadCommand {
+    pub n_elements: u8,
+    pub address: u32,
+    pub address_extension: u8,
+    pub data: Vec<u8>,
+    pub byte_order: ByteOrder,
+}
+
+impl ShortDownloadCommand {
+    /// Offset of the first data byte.
+    pub const DATA_OFFSET: usize = 8;
+}
+
+impl XcpCommand for ShortDownloadCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.n_elements)?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.push(self.address_extension)?;
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.address))?;
+        frame_data.extend_from_slice(&self.data)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ShortDownload }
+}
+
+/// XCP "Modify Bits" command structure.
+///
+/// The slave computes `(x & !((!and_mask as u32) << shift)) ^ ((xor_mask as u32) << shift)`
+/// on the 32-bit value at the MTA: bits cleared in `and_mask` are cleared, bits set in
+/// `xor_mask` are toggled.
+#[derive(Debug, Clone, Copy)]
+pub struct ModifyBitsCommand {
+    pub shift: u8,
+    pub and_mask: u16,
+    pub xor_mask: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for ModifyBitsCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.shift)?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.and_mask))?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.xor_mask))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ModifyBits }
+}
+
+/// Encodes the layout shared by DOWNLOAD and DOWNLOAD_NEXT.
+fn download_frame(code: XcpCommandCode, n_elements: u8, granularity: AddressGranularity, data: &[u8]) -> Result<XcpFrameBuf, XcpError> {
+    let mut frame_data = XcpFrameBuf::new();
+    frame_data.push(code.to_code())?;
+    frame_data.push(n_elements)?;
+    while frame_data.len() < granularity.download_offset() {
+        frame_data.push(0x00)?; // alignment
+    }
+    frame_data.extend_from_slice(data)?;
+    Ok(frame_data)
+}
+
+/// XCP "Set Cal Page" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetCalPageCommand {
+    pub mode: CalPageAccessFlags,
+    pub segment: u8,
+    pub page: u8,
+}
+
+impl XcpCommand for SetCalPageCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(u8::from(self.mode))?;
+        frame_data.push(self.segment)?;
+        frame_data.push(self.page)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetCalPage }
+}
+
+/// XCP "Get Cal Page" command structure.
+///
+/// Only one of `ecu` and `xcp` may be set in `mode`.
+#[derive(Debug, Clone, Copy)]
+pub struct GetCalPageCommand {
+    pub mode: CalPageAccessFlags,
+    pub segment: u8,
+}
+
+impl XcpCommand for GetCalPageCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(u8::from(self.mode))?;
+        frame_data.push(self.segment)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetCalPage }
+}
+
+/// XCP "Get Cal Page" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetCalPageResponse {
+    /// Logical number of the active page.
+    pub page: u8,
+}
+
+impl XcpResponse for GetCalPageResponse {
+    const MIN_LENGTH: usize = 4;
+
+    fn from_can_frame(frame: &[u8]) -> GetCalPageResponse {
+        // bytes 1 and 2 are reserved
+        GetCalPageResponse { page: frame[3] }
+    }
+}
+
+/// XCP "Get Pag Processor Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPagProcessorInfoCommand;
+
+impl XcpCommand for GetPagProcessorInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetPagProcessorInfo }
+}
+
+bitfield! {
+    /// General properties of the paging processor.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpPagProperties(u8);
+    impl Debug;
+
+    pub freeze_supported, set_freeze_supported: 0;
+}
+
+/// XCP "Get Pag Processor Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPagProcessorInfoResponse {
+    /// Number of segments; they are numbered from 0 to `max_segments - 1`.
+    pub max_segments: u8,
+    pub properties: XcpPagProperties,
+}
+
+impl XcpResponse for GetPagProcessorInfoResponse {
+    const MIN_LENGTH: usize = 3;
+
+    fn from_can_frame(frame: &[u8]) -> GetPagProcessorInfoResponse {
+        GetPagProcessorInfoResponse {
+            max_segments: frame[1],
+            properties: XcpPagProperties(frame[2]),
+        }
+    }
+}
+
+/// Kind of information requested with GET_SEGMENT_INFO.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentInfoMode {
+    /// Address (segment info 0) or length (segment info 1) of the segment.
+    BasicAddress = 0x00,
+    /// Number of pages, address extension, mapping count and compression/encryption.
+    Standard = 0x01,
+    /// Source (0), destination (1) or length (2) of an address mapping.
+    AddressMapping = 0x02,
+}
+
+/// XCP "Get Segment Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetSegmentInfoCommand {
+    pub mode: SegmentInfoMode,
+    pub segment: u8,
+    /// Selects the returned value in the basic address and address mapping modes.
+    pub segment_info: u8,
+    /// Address mapping to query in address mapping mode.
+    pub mapping_index: u8,
+}
+
+impl XcpCommand for GetSegmentInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        frame_data.push(self.segment)?;
+        frame_data.push(self.segment_info)?;
+        frame_data.push(self.mapping_index)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetSegmentInfo }
+}
+
+/// XCP "Get Segment Info" response in basic address mode.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentBasicInfoResponse {
+    pub basic_info: u32,
+}
+
+impl XcpResponse for SegmentBasicInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> SegmentBasicInfoResponse {
+        SegmentBasicInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> SegmentBasicInfoResponse {
+        SegmentBasicInfoResponse { basic_info: mode.endianness().read_u32(&frame[4..8]) }
+    }
+}
+
+/// XCP "Get Segment Info" response in standard mode.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentStandardInfoResponse {
+    pub max_pages: u8,
+    pub address_extension: u8,
+    pub max_mapping: u8,
+    pub compression_method: u8,
+    pub encryption_method: u8,
+}
+
+impl XcpResponse for SegmentStandardInfoResponse {
+    const MIN_LENGTH: usize = 6;
+
+    fn from_can_frame(frame: &[u8]) -> SegmentStandardInfoResponse {
+        SegmentStandardInfoResponse {
+            max_pages: frame[1],
+            address_extension: frame[2],
+            max_mapping: frame[3],
+            compression_method: frame[4],
+            encryption_method: frame[5],
+        }
+    }
+}
+
+/// XCP "Get Segment Info" response in address mapping mode.
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentMappingInfoResponse {
+    pub mapping_info: u32,
+}
+
+impl XcpResponse for SegmentMappingInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> SegmentMappingInfoResponse {
+        SegmentMappingInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> SegmentMappingInfoResponse {
+        SegmentMappingInfoResponse { mapping_info: mode.endianness().read_u32(&frame[4..8]) }
+    }
+}
+
+/// The mode specific answer to GET_SEGMENT_INFO.
+#[derive(Debug, Clone, Copy)]
+pub enum SegmentInfo {
+    Basic(SegmentBasicInfoResponse),
+    Standard(SegmentStandardInfoResponse),
+    Mapping(SegmentMappingInfoResponse),
+}
+
+/// XCP "Get Page Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPageInfoCommand {
+    pub segment: u8,
+    pub page: u8,
+}
+
+impl XcpCommand for GetPageInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.push(self.segment)?;
+        frame_data.push(self.page)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetPageInfo }
+}
+
+bitfield! {
+    /// Who may access a page, and whether that depends on the other party's access.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpPageProperties(u8);
+    impl Debug;
+
+    pub ecu_access_without_xcp, set_ecu_access_without_xcp: 0;
+    pub ecu_access_with_xcp, set_ecu_access_with_xcp: 1;
+    pub xcp_read_access_without_ecu, set_xcp_read_access_without_ecu: 2;
+    pub xcp_read_access_with_ecu, set_xcp_read_access_with_ecu: 3;
+    pub xcp_write_access_without_ecu, set_xcp_write_access_without_ecu: 4;
+    pub xcp_write_access_with_ecu, set_xcp_write_access_with_ecu: 5;
+}
+
+impl XcpPageProperties {
+    /// Whether the ECU may use the page at all.
+    pub fn ecu_access(&self) -> bool {
+        self.ecu_access_without_xcp() || self.ecu_access_with_xcp()
+    }
+
+    /// Whether XCP may read the page at all.
+    pub fn xcp_read_access(&self) -> bool {
+        self.xcp_read_access_without_ecu() || self.xcp_read_access_with_ecu()
+    }
+
+    /// Whether XCP may write the page at all.
+    pub fn xcp_write_access(&self) -> bool {
+        self.xcp_write_access_without_ecu() || self.xcp_write_access_with_ecu()
+    }
+}
+
+/// XCP "Get Page Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPageInfoResponse {
+    pub properties: XcpPageProperties,
+    /// Segment holding the initial data of the page.
+    pub init_segment: u8,
+}
+
+impl XcpResponse for GetPageInfoResponse {
+    const MIN_LENGTH: usize = 3;
+
+    fn from_can_frame(frame: &[u8]) -> GetPageInfoResponse {
+        GetPageInfoResponse {
+            properties: XcpPageProperties(frame[1]),
+            init_segment: frame[2],
+        }
+    }
+}
+
+/// XCP "Copy Cal Page" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct CopyCalPageCommand {
+    pub source_segment: u8,
+    pub source_page: u8,
+    pub destination_segment: u8,
+    pub destination_page: u8,
+}
+
+impl XcpCommand for CopyCalPageCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.source_segment)?;
+        frame_data.push(self.source_page)?;
+        frame_data.push(self.destination_segment)?;
+        frame_data.push(self.destination_page)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::CopyCalPage }
+}
+
+bitfield! {
+    /// Mode of a calibration segment.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpSegmentMode(u8);
+    impl Debug;
+
+    pub freeze, set_freeze: 0;
+}
+
+/// XCP "Set Segment Mode" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetSegmentModeCommand {
+    pub mode: XcpSegmentMode,
+    pub segment: u8,
+}
+
+impl XcpCommand for SetSegmentModeCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode.0)?;
+        frame_data.push(self.segment)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetSegmentMode }
+}
+
+/// XCP "Get Segment Mode" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetSegmentModeCommand {
+    pub segment: u8,
+}
+
+impl XcpCommand for GetSegmentModeCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.push(self.segment)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetSegmentMode }
+}
+
+/// XCP "Get Segment Mode" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetSegmentModeResponse {
+    pub mode: XcpSegmentMode,
+}
+
+impl XcpResponse for GetSegmentModeResponse {
+    const MIN_LENGTH: usize = 3;
+
+    fn from_can_frame(frame: &[u8]) -> GetSegmentModeResponse {
+        // byte 1 is reserved
+        GetSegmentModeResponse { mode: XcpSegmentMode(frame[2]) }
+    }
+}
+
+/// XCP "Clear DAQ List" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ClearDaqListCommand {
+    pub daq_list: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for ClearDaqListCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ClearDaqList }
+}
+
+/// XCP "Set DAQ List Pointer" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetDaqPtrCommand {
+    pub daq_list: u16,
+    pub odt: u8,
+    pub entry: u8,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for SetDaqPtrCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        frame_data.push(self.odt)?;
+        frame_data.push(self.entry)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetDaqPtr }
+}
+
+/// XCP "Write DAQ" command structure, describing the ODT entry at the DAQ list pointer.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteDaqCommand {
+    /// Bit of the element to sample, or `None` to sample the whole element.
+    pub bit_offset: Option<u8>,
+    /// Size of the element in address granularity units.
+    pub size: u8,
+    pub address_extension: u8,
+    pub address: u32,
+    pub byte_order: ByteOrder,
+}
+
+impl WriteDaqCommand {
+    /// BIT_OFFSET value meaning the entry is not a single bit.
+    pub const NO_BIT_OFFSET: u8 = 0xFF;
+
+    /// Highest bit offset a 32-bit element can have.
+    pub const MAX_BIT_OFFSET: u8 = 0x1F;
+}
+
+impl XcpCommand for WriteDaqCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.bit_offset.unwrap_or(WriteDaqCommand::NO_BIT_OFFSET))?;
+        frame_data.push(self.size)?;
+        frame_data.push(self.address_extension)?;
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.address))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::WriteDaq }
+}
+
+/// One ODT entry as configured by WRITE_DAQ_MULTIPLE.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DaqEntry {
+    /// Bit of the element to sample, or `None` to sample the whole element.
+    pub bit_offset: Option<u8>,
+    /// Size of the element in address granularity units.
+    pub size: u8,
+    pub address_extension: u8,
+    pub address: u32,
+}
+
+/// XCP "Write DAQ Multiple" command structure, describing consecutive ODT entries
+/// starting at the DAQ list pointer.
+#[derive(Debug, Clone)]
+pub struct WriteDaqMultipleCommand {
+    pub entries: Vec<DaqEntry>,
+    pub byte_order: ByteOrder,
+}
+
+impl WriteDaqMultipleCommand {
+    /// Offset of the first entry, after the command code and the entry count.
+    pub const ENTRIES_OFFSET: usize = 2;
+
+    /// Bytes per entry, including one byte of alignment.
+    pub const ENTRY_LEN: usize = 8;
+
+    /// Number of entries fitting into a CTO of `max_cto` bytes.
+    pub fn max_entries(max_cto: usize) -> usize {
+        let fitting = max_cto.saturating_sub(WriteDaqMultipleCommand::ENTRIES_OFFSET) / WriteDaqMultipleCommand::ENTRY_LEN;
+        usize::min(fitting, u8::MAX as usize)
+    }
+
+    /// Splits `entries` into WRITE_DAQ_MULTIPLE commands carrying as many entries as
+    /// fit into `max_cto` each. At least one entry must fit.
+    pub fn split(entries: &[DaqEntry], byte_order: ByteOrder, max_cto: usize) -> Vec<WriteDaqMultipleCommand> {
+        entries.chunks(WriteDaqMultipleCommand::max_entries(max_cto))
+            .map(|chunk| WriteDaqMultipleCommand { entries: chunk.to_vec(), byte_order })
+            .collect()
+    }
+}
+
+impl XcpCommand for WriteDaqMultipleCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.entries.len() as u8)?;
+        for entry in &self.entries {
+            frame_data.push(entry.bit_offset.unwrap_or(WriteDaqCommand::NO_BIT_OFFSET))?;
+            frame_data.push(entry.size)?;
+            frame_data.extend_from_slice(&self.byte_order.u32_bytes(entry.address))?;
+            frame_data.push(entry.address_extension)?;
+            frame_data.push(0x00)?; // alignment
+        }
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::WriteDaqMultiple }
+}
+
+bitfield! {
+    /// Mode of a DAQ list.
+    ///
+    /// SET_DAQ_LIST_MODE uses bit 0 for alternating mode, GET_DAQ_LIST_MODE reports
+    /// whether the list is selected for START_STOP_SYNCH in the same bit. `running`
+    /// and `resume` are only reported by GET_DAQ_LIST_MODE.
+    #[derive(Copy, Clone, Default, PartialEq, Eq)]
+    pub struct XcpDaqListMode(u8);
+    impl Debug;
+
+    pub alternating, set_alternating: 0;
+    pub selected, set_selected: 0;
+    /// STIM instead of DAQ.
+    pub stim, set_stim: 1;
+    pub timestamp, set_timestamp: 4;
+    pub pid_off, set_pid_off: 5;
+    pub running, set_running: 6;
+    pub resume, set_resume: 7;
+}
+
+/// How a DAQ list is transmitted, as set by SET_DAQ_LIST_MODE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DaqListModeConfig {
+    pub mode: XcpDaqListMode,
+    /// Event channel triggering the list.
+    pub event_channel: u16,
+    /// Transmit the list on every `prescaler`th event; at least 1.
+    pub prescaler: u8,
+    /// Priority of the list relative to other lists; 0 is the lowest.
+    pub priority: u8,
+}
+
+impl Default for DaqListModeConfig {
+    fn default() -> DaqListModeConfig {
+        DaqListModeConfig { mode: XcpDaqListMode::default(), event_channel: 0, prescaler: 1, priority: 0 }
+    }
+}
+
+/// XCP "Set DAQ List Mode" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetDaqListModeCommand {
+    pub mode: XcpDaqListMode,
+    pub daq_list: u16,
+    pub event_channel: u16,
+    pub prescaler: u8,
+    pub priority: u8,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for SetDaqListModeCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode.0)?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.event_channel))?;
+        frame_data.push(self.prescaler)?;
+        frame_data.push(self.priority)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetDaqListMode }
+}
+
+/// XCP "Get DAQ List Mode" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqListModeCommand {
+    pub daq_list: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for GetDaqListModeCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqListMode }
+}
+
+/// XCP "Get DAQ List Mode" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqListModeResponse {
+    /// Current mode, event channel, prescaler and priority of the list. The mode
+    /// reports `selected`, `running` and `resume` rather than `alternating`.
+    pub config: DaqListModeConfig,
+}
+
+impl XcpResponse for GetDaqListModeResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetDaqListModeResponse {
+        GetDaqListModeResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetDaqListModeResponse {
+        // bytes 2 and 3 are reserved
+        GetDaqListModeResponse {
+            config: DaqListModeConfig {
+                mode: XcpDaqListMode(frame[1]),
+                event_channel: mode.endianness().read_u16(&frame[4..6]),
+                prescaler: frame[6],
+                priority: frame[7],
+            }
+        }
+    }
+}
+
+/// What START_STOP_DAQ_LIST does with a DAQ list.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StartStopMode {
+    Stop = 0x00,
+    Start = 0x01,
+    /// Mark the list for a later START_STOP_SYNCH without starting it.
+    Select = 0x02,
+}
+
+/// XCP "Start Stop DAQ List" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct StartStopDaqListCommand {
+    pub mode: StartStopMode,
+    pub daq_list: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for StartStopDaqListCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::StartStopDaqList }
+}
+
+/// XCP "Start Stop DAQ List" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct StartStopDaqListResponse {
+    /// Absolute PID of the first ODT of the list in DTO packets.
+    pub first_pid: u8,
+}
+
+impl XcpResponse for StartStopDaqListResponse {
+    const MIN_LENGTH: usize = 2;
+
+    fn from_can_frame(frame: &[u8]) -> StartStopDaqListResponse {
+        StartStopDaqListResponse { first_pid: frame[1] }
+    }
+}
+
+/// What START_STOP_SYNCH does with the DAQ lists.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StartStopSynchMode {
+    StopAll = 0x00,
+    StartSelected = 0x01,
+    StopSelected = 0x02,
+    /// Prepare the selected lists so that a following START_STOP_SYNCH starts them
+    /// with minimal delay. Added in protocol version 1.3.
+    PrepareStartSelected = 0x03,
+}
+
+impl StartStopSynchMode {
+    /// Minimum protocol layer version a slave must implement to know this mode.
+    pub fn min_protocol_version(&self) -> XcpVersion {
+        match self {
+            StartStopSynchMode::PrepareStartSelected => XcpVersion::new(1, 3),
+            _ => XcpVersion::new(1, 0),
+        }
+    }
+}
+
+/// XCP "Start Stop Synch" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct StartStopSynchCommand {
+    pub mode: StartStopSynchMode,
+}
+
+impl XcpCommand for StartStopSynchCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::StartStopSynch }
+}
+
+/// XCP "Get DAQ Clock" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqClockCommand;
+
+impl XcpCommand for GetDaqClockCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqClock }
+}
+
+/// XCP "Get DAQ Clock" response structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetDaqClockResponse {
+    /// Slave clock in ticks when the command was received.
+    pub timestamp: u32,
+    /// TRIGGER_INFO of the extended (protocol version 1.3) format, `None` for the
+    /// legacy format.
+    pub trigger_info: Option<u8>,
+    /// PAYLOAD_FMT of the extended format, `None` for the legacy format.
+    pub payload_format: Option<u8>,
+}
+
+impl XcpResponse for GetDaqClockResponse {
+    fn from_can_frame(frame: &[u8]) -> GetDaqClockResponse {
+        GetDaqClockResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetDaqClockResponse {
+        // slaves with a timestamp shorter than 4 bytes may send a shorter frame
+        let mut padded = [0u8; 8];
+        let len = usize::min(frame.len(), padded.len());
+        padded[..len].copy_from_slice(&frame[..len]);
+
+        // the legacy format leaves bytes 2 and 3 reserved, the extended format always
+        // sets the slave clock format in PAYLOAD_FMT
+        let extended = padded[3] != 0;
+        GetDaqClockResponse {
+            timestamp: mode.endianness().read_u32(&padded[4..8]),
+            trigger_info: extended.then_some(padded[2]),
+            payload_format: extended.then_some(padded[3]),
+        }
+    }
+}
+
+/// XCP "Read DAQ" command structure, reading the ODT entry at the DAQ list pointer.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadDaqCommand;
+
+impl XcpCommand for ReadDaqCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ReadDaq }
+}
+
+/// XCP "Read DAQ" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadDaqResponse {
+    pub entry: DaqEntry,
+}
+
+impl XcpResponse for ReadDaqResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> ReadDaqResponse {
+        ReadDaqResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> ReadDaqResponse {
+        ReadDaqResponse {
+            entry: DaqEntry {
+                bit_offset: (frame[1] != WriteDaqCommand::NO_BIT_OFFSET).then_some(frame[1]),
+                size: frame[2],
+                address_extension: frame[3],
+                address: mode.endianness().read_u32(&frame[4..8]),
+            }
+        }
+    }
+}
+
+/// XCP "Get DAQ Processor Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqProcessorInfoCommand;
+
+impl XcpCommand for GetDaqProcessorInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqProcessorInfo }
+}
+
+/// Whether the DAQ lists are fixed by the slave or allocated by the master.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaqConfigType {
+    Static,
+    Dynamic,
+}
+
+bitfield! {
+    /// General properties of the DAQ processor.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpDaqProperties(u8);
+    impl Debug;
+
+    pub dynamic, set_dynamic: 0;
+    pub prescaler_supported, set_prescaler_supported: 1;
+    pub resume_supported, set_resume_supported: 2;
+    pub bit_stim_supported, set_bit_stim_supported: 3;
+    pub timestamp_supported, set_timestamp_supported: 4;
+    pub pid_off_supported, set_pid_off_supported: 5;
+    pub overload_msb, set_overload_msb: 6;
+    pub overload_event, set_overload_event: 7;
+}
+
+impl XcpDaqProperties {
+    pub fn config_type(&self) -> DaqConfigType {
+        if self.dynamic() { DaqConfigType::Dynamic } else { DaqConfigType::Static }
+    }
+
+    /// `None` if both overload bits are set, which the specification does not allow.
+    pub fn overload_indication(&self) -> Option<DaqOverloadIndication> {
+        DaqOverloadIndication::from_properties(self.0)
+    }
+}
+
+/// Which ODT entries must share an address extension.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaqAddressExtension {
+    /// Every entry may use its own address extension.
+    Free,
+    /// All entries of an ODT use the same address extension.
+    SameWithinOdt,
+    /// All entries of a DAQ list use the same address extension.
+    SameWithinDaq,
+}
+
+/// How DTO packets identify the ODT, and DAQ list, they belong to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaqIdentificationFieldType {
+    /// One byte holding the absolute ODT number.
+    AbsolutePid,
+    /// Relative ODT number followed by the DAQ list number as a byte.
+    RelativeOdtAbsoluteDaqByte,
+    /// Relative ODT number followed by the DAQ list number as a word.
+    RelativeOdtAbsoluteDaqWord,
+    /// Like `RelativeOdtAbsoluteDaqWord`, with a fill byte aligning the DAQ list number.
+    RelativeOdtAbsoluteDaqWordAligned,
+}
+
+impl DaqIdentificationFieldType {
+    /// Size in bytes of the identification field at the start of a DTO.
+    pub fn size(&self) -> usize {
+        match self {
+            DaqIdentificationFieldType::AbsolutePid => 1,
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte => 2,
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord => 3,
+            DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned => 4,
+        }
+    }
+}
+
+bitfield! {
+    /// DAQ_KEY_BYTE: optimisation type, address extension policy and identification
+    /// field type of the DAQ processor.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpDaqKeyByte(u8);
+    impl Debug;
+
+    pub u8, optimisation, set_optimisation: 3, 0;
+    pub u8, address_extension, set_address_extension: 5, 4;
+    pub u8, identification_field, set_identification_field: 7, 6;
+}
+
+impl XcpDaqKeyByte {
+    pub fn optimisation_type(&self) -> DaqOptimisationType {
+        DaqOptimisationType::from_key_byte(self.0)
+    }
+
+    pub fn address_extension_type(&self) -> DaqAddressExtension {
+        match self.address_extension() {
+            0 => DaqAddressExtension::Free,
+            1 => DaqAddressExtension::SameWithinOdt,
+            // 2 is not allowed; bit 5 alone is taken as the stricter policy
+            _ => DaqAddressExtension::SameWithinDaq,
+        }
+    }
+
+    pub fn identification_field_type(&self) -> DaqIdentificationFieldType {
+        match self.identification_field() {
+            0 => DaqIdentificationFieldType::AbsolutePid,
+            1 => DaqIdentificationFieldType::RelativeOdtAbsoluteDaqByte,
+            2 => DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWord,
+            _ => DaqIdentificationFieldType::RelativeOdtAbsoluteDaqWordAligned,
+        }
+    }
+}
+
+/// XCP "Get DAQ Processor Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqProcessorInfoResponse {
+    pub properties: XcpDaqProperties,
+    /// Total number of DAQ lists, predefined and configurable.
+    pub max_daq: u16,
+    /// Number of event channels; 0 means the number is not known.
+    pub max_event_channel: u16,
+    /// Number of predefined DAQ lists; they are numbered from 0 to `min_daq - 1`.
+    pub min_daq: u8,
+    pub key_byte: XcpDaqKeyByte,
+}
+
+impl XcpResponse for GetDaqProcessorInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetDaqProcessorInfoResponse {
+        GetDaqProcessorInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetDaqProcessorInfoResponse {
+        GetDaqProcessorInfoResponse {
+            properties: XcpDaqProperties(frame[1]),
+            max_daq: mode.endianness().read_u16(&frame[2..4]),
+            max_event_channel: mode.endianness().read_u16(&frame[4..6]),
+            min_daq: frame[6],
+            key_byte: XcpDaqKeyByte(frame[7]),
+        }
+    }
+}
+
+/// XCP "Get DAQ Resolution Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqResolutionInfoCommand;
+
+impl XcpCommand for GetDaqResolutionInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqResolutionInfo }
+}
+
+/// Unit of DAQ timestamps and event channel cycle times.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XcpTimeUnit {
+    Ns1,
+    Ns10,
+    Ns100,
+    Us1,
+    Us10,
+    Us100,
+    Ms1,
+    Ms10,
+    Ms100,
+    S1,
+    Ps1,
+    Ps10,
+    Ps100,
+    Unknown(u8),
+}
+
+impl XcpTimeUnit {
+    /// Convert a raw unit code to an `XcpTimeUnit` enum variant.
+    pub fn from_code(code: u8) -> XcpTimeUnit {
+        match code {
+            0x00 => XcpTimeUnit::Ns1,
+            0x01 => XcpTimeUnit::Ns10,
+            0x02 => XcpTimeUnit::Ns100,
+            0x03 => XcpTimeUnit::Us1,
+            0x04 => XcpTimeUnit::Us10,
+            0x05 => XcpTimeUnit::Us100,
+            0x06 => XcpTimeUnit::Ms1,
+            0x07 => XcpTimeUnit::Ms10,
+            0x08 => XcpTimeUnit::Ms100,
+            0x09 => XcpTimeUnit::S1,
+            0x0A => XcpTimeUnit::Ps1,
+            0x0B => XcpTimeUnit::Ps10,
+            0x0C => XcpTimeUnit::Ps100,
+            code => XcpTimeUnit::Unknown(code),
+        }
+    }
+
+    /// Length of one unit in seconds, `None` for unknown units.
+    pub fn seconds(&self) -> Option<f64> {
+        let seconds = match self {
+            XcpTimeUnit::Ns1 => 1e-9,
+            XcpTimeUnit::Ns10 => 1e-8,
+            XcpTimeUnit::Ns100 => 1e-7,
+            XcpTimeUnit::Us1 => 1e-6,
+            XcpTimeUnit::Us10 => 1e-5,
+            XcpTimeUnit::Us100 => 1e-4,
+            XcpTimeUnit::Ms1 => 1e-3,
+            XcpTimeUnit::Ms10 => 1e-2,
+            XcpTimeUnit::Ms100 => 1e-1,
+            XcpTimeUnit::S1 => 1.0,
+            XcpTimeUnit::Ps1 => 1e-12,
+            XcpTimeUnit::Ps10 => 1e-11,
+            XcpTimeUnit::Ps100 => 1e-10,
+            XcpTimeUnit::Unknown(_) => return None,
+        };
+        Some(seconds)
+    }
+}
+
+bitfield! {
+    /// Size, unit and fixed flag of the timestamps in DTO packets.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpTimestampMode(u8);
+    impl Debug;
+
+    /// Timestamp size in bytes; 0 if the slave does not send timestamps.
+    pub u8, size, set_size: 2, 0;
+    /// Timestamps are always sent, whatever the DAQ list mode says.
+    pub fixed, set_fixed: 3;
+    pub u8, unit_code, set_unit_code: 7, 4;
+}
+
+impl XcpTimestampMode {
+    pub fn unit(&self) -> XcpTimeUnit {
+        XcpTimeUnit::from_code(self.unit_code())
+    }
+}
+
+/// XCP "Get DAQ Resolution Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqResolutionInfoResponse {
+    /// ODT entry sizes for DAQ must be a multiple of this many bytes.
+    pub granularity_odt_entry_size_daq: u8,
+    /// Largest ODT entry for DAQ, in granularity units.
+    pub max_odt_entry_size_daq: u8,
+    pub granularity_odt_entry_size_stim: u8,
+    pub max_odt_entry_size_stim: u8,
+    pub timestamp_mode: XcpTimestampMode,
+    /// Units per timestamp tick.
+    pub timestamp_ticks: u16,
+}
+
+impl GetDaqResolutionInfoResponse {
+    /// Length of one timestamp tick in seconds, `None` if the unit is unknown.
+    pub fn tick_seconds(&self) -> Option<f64> {
+        self.timestamp_mode.unit().seconds().map(|unit| unit * self.timestamp_ticks as f64)
+    }
+}
+
+impl XcpResponse for GetDaqResolutionInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetDaqResolutionInfoResponse {
+        GetDaqResolutionInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetDaqResolutionInfoResponse {
+        GetDaqResolutionInfoResponse {
+            granularity_odt_entry_size_daq: frame[1],
+            max_odt_entry_size_daq: frame[2],
+            granularity_odt_entry_size_stim: frame[3],
+            max_odt_entry_size_stim: frame[4],
+            timestamp_mode: XcpTimestampMode(frame[5]),
+            timestamp_ticks: mode.endianness().read_u16(&frame[6..8]),
+        }
+    }
+}
+
+/// XCP "Get DAQ List Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqListInfoCommand {
+    pub daq_list: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for GetDaqListInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqListInfo }
+}
+
+bitfield! {
+    /// Properties of a DAQ list.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpDaqListProperties(u8);
+    impl Debug;
+
+    /// The list is predefined by the slave and cannot be reconfigured.
+    pub predefined, set_predefined: 0;
+    /// The list is bound to a fixed event channel.
+    pub event_fixed, set_event_fixed: 1;
+    /// The list can be used for DAQ.
+    pub daq, set_daq: 2;
+    /// The list can be used for STIM.
+    pub stim, set_stim: 3;
+}
+
+/// XCP "Get DAQ List Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqListInfoResponse {
+    pub properties: XcpDaqListProperties,
+    /// Number of ODTs in the list.
+    pub max_odt: u8,
+    /// Number of entries in each ODT of the list.
+    pub max_odt_entries: u8,
+    /// Event channel the list is bound to, if `properties.event_fixed()`.
+    pub fixed_event: Option<u16>,
+}
+
+impl XcpResponse for GetDaqListInfoResponse {
+    const MIN_LENGTH: usize = 6;
+
+    fn from_can_frame(frame: &[u8]) -> GetDaqListInfoResponse {
+        GetDaqListInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetDaqListInfoResponse {
+        let properties = XcpDaqListProperties(frame[1]);
+        GetDaqListInfoResponse {
+            properties,
+            max_odt: frame[2],
+            max_odt_entries: frame[3],
+            fixed_event: properties.event_fixed().then(|| mode.endianness().read_u16(&frame[4..6])),
+        }
+    }
+}
+
+/// XCP "Get DAQ Event Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqEventInfoCommand {
+    pub event_channel: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for GetDaqEventInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.event_channel))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetDaqEventInfo }
+}
+
+/// Level at which the slave keeps the data sampled for an event consistent.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaqEventConsistency {
+    Odt,
+    Daq,
+    Event,
+}
+
+bitfield! {
+    /// Properties of an event channel.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpDaqEventProperties(u8);
+    impl Debug;
+
+    /// The event can trigger DAQ lists.
+    pub daq, set_daq: 2;
+    /// The event can trigger STIM lists.
+    pub stim, set_stim: 3;
+    pub u8, consistency_code, set_consistency_code: 7, 6;
+}
+
+impl XcpDaqEventProperties {
+    /// `None` for the reserved value 3.
+    pub fn consistency(&self) -> Option<DaqEventConsistency> {
+        match self.consistency_code() {
+            0 => Some(DaqEventConsistency::Odt),
+            1 => Some(DaqEventConsistency::Daq),
+            2 => Some(DaqEventConsistency::Event),
+            _ => None,
+        }
+    }
+}
+
+/// XCP "Get DAQ Event Info" response structure.
+///
+/// The slave also points the MTA at the name of the event channel.
+#[derive(Debug, Clone, Copy)]
+pub struct GetDaqEventInfoResponse {
+    pub properties: XcpDaqEventProperties,
+    /// Maximum number of DAQ lists the event can trigger.
+    pub max_daq_list: u8,
+    /// Length of the event channel name in bytes.
+    pub name_length: u8,
+    /// Cycle time in `time_unit`; 0 if the event is not cyclic.
+    pub time_cycle: u8,
+    pub time_unit: XcpTimeUnit,
+    /// 0 is the lowest priority, 0xFF the highest.
+    pub priority: u8,
+}
+
+impl GetDaqEventInfoResponse {
+    /// Cycle time in seconds, `None` if the event is not cyclic or the unit unknown.
+    pub fn cycle_seconds(&self) -> Option<f64> {
+        if self.time_cycle == 0 {
+            return None;
+        }
+        self.time_unit.seconds().map(|unit| unit * self.time_cycle as f64)
+    }
 }
 
-impl XcpCommand for ConnectCommand {
-    fn to_can_frame(&self) -> Vec<u8> {
-        let mut frame_data = Vec::<u8>::new();
-        frame_data.push(self.get_code().to_code());
-        frame_data.push(self.mode as u8);
-        frame_data
+impl XcpResponse for GetDaqEventInfoResponse {
+    const MIN_LENGTH: usize = 7;
+
+    fn from_can_frame(frame: &[u8]) -> GetDaqEventInfoResponse {
+        GetDaqEventInfoResponse {
+            properties: XcpDaqEventProperties(frame[1]),
+            max_daq_list: frame[2],
+            name_length: frame[3],
+            time_cycle: frame[4],
+            time_unit: XcpTimeUnit::from_code(frame[5]),
+            priority: frame[6],
+        }
     }
+}
 
-    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Connect }
+/// XCP "Free DAQ" command structure, clearing the dynamic DAQ configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct FreeDaqCommand;
+
+impl XcpCommand for FreeDaqCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::FreeDaq }
 }
 
-/// XCP "Connect" response structure.
-#[derive(Debug)]
-pub struct ConnectResponse {
-    pub resource: XcpResourceFlags,
-    pub comm_mode_basic: XcpCommModeBasic,
-    pub max_cto: u8,
-    pub max_dto: u8,
-    pub protocol_version: u8,
-    pub transport_version: u8,
+/// XCP "Alloc DAQ" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocDaqCommand {
+    pub daq_count: u16,
+    pub byte_order: ByteOrder,
 }
 
-impl XcpResponse for ConnectResponse {
-    fn from_can_frame(can_frame: &[u8]) -> ConnectResponse {
-        ConnectResponse {
-            resource: XcpResourceFlags::from(can_frame[1]),
-            comm_mode_basic: XcpCommModeBasic(can_frame[2]),
-            max_cto: can_frame[3],
-            max_dto: can_frame[4],
-            protocol_version: can_frame[5],
-            transport_version: can_frame[5],
-        }
+impl XcpCommand for AllocDaqCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_count))?;
+        Ok(frame_data)
     }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::AllocDaq }
 }
 
-/// XCP "Get Seed" command structure.
-#[derive(Debug, Copy, Clone)]
-pub struct GetSeedCommand {
-    pub mode: GetSeedMode,
-    pub resource: XcpResourceFlags,
+/// XCP "Alloc ODT" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocOdtCommand {
+    pub daq_list: u16,
+    pub odt_count: u8,
+    pub byte_order: ByteOrder,
 }
 
-impl XcpCommand for GetSeedCommand {
-    fn to_can_frame(&self) -> Vec<u8> {
-        let mut frame_data = Vec::<u8>::new();
-        frame_data.push(self.get_code().to_code());
-        frame_data.push(self.mode as u8);
-        frame_data.push(u8::from(self.resource));
-        frame_data
+impl XcpCommand for AllocOdtCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        frame_data.push(self.odt_count)?;
+        Ok(frame_data)
     }
 
-    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetSeed }
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::AllocOdt }
 }
 
-/// XCP "Get Seed" response structure.
-#[derive(Debug, Clone)]
-pub struct GetSeedResponse {
-    pub requested_resource_is_protected: bool,
-    pub remaining_length: u8,
-    pub seed_data: Vec<u8>,
+/// XCP "Alloc ODT Entry" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocOdtEntryCommand {
+    pub daq_list: u16,
+    pub odt: u8,
+    pub entry_count: u8,
+    pub byte_order: ByteOrder,
 }
 
-impl XcpResponse for GetSeedResponse {
-    fn from_can_frame(can_frame: &[u8]) -> GetSeedResponse {
-        GetSeedResponse {
-            requested_resource_is_protected: can_frame[1] != 0,
-            remaining_length: can_frame[1],
-            seed_data: can_frame[2..].into(),
-        }
+impl XcpCommand for AllocOdtEntryCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.daq_list))?;
+        frame_data.push(self.odt)?;
+        frame_data.push(self.entry_count)?;
+        Ok(frame_data)
     }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::AllocOdtEntry }
 }
 
-/// XCP "Unlock" command structure
+bitfield! {
+    /// Current session status reported by GET_STATUS.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpSessionStatus(u8);
+    impl Debug;
+
+    pub store_cal_req, set_store_cal_req: 0;
+    pub store_daq_req, set_store_daq_req: 2;
+    pub clear_daq_req, set_clear_daq_req: 3;
+    pub daq_running, set_daq_running: 6;
+    pub resume, set_resume: 7;
+}
+
+/// XCP "Get Status" response structure.
+///
+/// Slaves differ in how much of the response they fill in: some only send the first
+/// four bytes, and the byte after the protection status is reserved in older protocol
+/// versions. Fields that may be missing or undefined are `None` in that case.
 #[derive(Debug, Clone)]
-pub struct UnlockCommand {
-    pub remaining_length: u8,
-    pub key_data: Vec<u8>
+pub struct GetStatusResponse {
+    pub session_status: XcpSessionStatus,
+    /// Resources that are still protected by seed & key.
+    pub protection: XcpResourceFlags,
+    /// Current ECU state number. Reserved before protocol version 1.2.
+    pub state_number: Option<u8>,
+    /// Session configuration id; absent in the short four byte form.
+    pub session_configuration_id: Option<u16>,
 }
 
-impl XcpCommand for UnlockCommand {
-    fn to_can_frame(&self) -> Vec<u8> {
-        let mut frame_data = Vec::<u8>::new();
-        frame_data.push(self.get_code().to_code());
-        frame_data.push(self.remaining_length);
-        frame_data.append(&mut self.key_data.clone());
-        frame_data
+impl GetStatusResponse {
+    /// Protocol version from which the state number byte is defined.
+    pub const STATE_NUMBER_VERSION: XcpVersion = XcpVersion { major: 1, minor: 2 };
+
+    /// Drops fields the given protocol version does not define.
+    pub fn for_protocol_version(mut self, version: XcpVersion) -> GetStatusResponse {
+        if version < GetStatusResponse::STATE_NUMBER_VERSION {
+            self.state_number = None;
+        }
+        self
     }
+}
 
-    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Unlock }
+impl XcpResponse for GetStatusResponse {
+    fn from_can_frame(frame: &[u8]) -> GetStatusResponse {
+        GetStatusResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetStatusResponse {
+        GetStatusResponse {
+            session_status: XcpSessionStatus(frame.get(1).copied().unwrap_or(0)),
+            protection: XcpResourceFlags::from(frame.get(2).copied().unwrap_or(0)),
+            state_number: frame.get(3).copied(),
+            session_configuration_id: frame.get(4..6).map(|id| mode.endianness().read_u16(id)),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct UnlockResponse {
-    pub resource: XcpResourceFlags
+bitfield! {
+    /// Requests that can be made with SET_REQUEST.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpSetRequestMode(u8);
+    impl Debug;
+
+    pub store_cal_req, set_store_cal_req: 0;
+    pub store_daq_req_no_resume, set_store_daq_req_no_resume: 1;
+    pub store_daq_req, set_store_daq_req: 2;
+    pub clear_daq_req, set_clear_daq_req: 3;
 }
 
-impl XcpResponse for UnlockResponse {
-    fn from_can_frame(frame: &[u8]) -> UnlockResponse {
-        UnlockResponse { resource: XcpResourceFlags::from(frame[1]) }
+impl XcpSetRequestMode {
+    /// The GET_STATUS session status bits that stay set while these requests are pending.
+    /// Both STORE_DAQ variants are reported as STORE_DAQ_REQ.
+    pub fn pending_status(&self) -> XcpSessionStatus {
+        let mut status = XcpSessionStatus(0);
+        status.set_store_cal_req(self.store_cal_req());
+        status.set_store_daq_req(self.store_daq_req() || self.store_daq_req_no_resume());
+        status.set_clear_daq_req(self.clear_daq_req());
+        status
+    }
+}
+
+/// XCP "Set Request" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct SetRequestCommand {
+    pub mode: XcpSetRequestMode,
+    pub session_configuration_id: u16,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for SetRequestCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode.0)?;
+        frame_data.extend_from_slice(&self.byte_order.u16_bytes(self.session_configuration_id))?;
+        Ok(frame_data)
     }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::SetRequest }
 }
 
 /// Enumeration for XCP connection modes.
@@ -130,6 +2140,7 @@ pub enum GetSeedMode {
     ContinueSeed,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum XcpResource {
     CalPage,
     Daq,
@@ -137,7 +2148,8 @@ pub enum XcpResource {
     Pgm
 }
 
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct XcpResourceFlags {
     pub cal_page: bool,
     pub daq: bool,
@@ -147,6 +2159,14 @@ pub struct XcpResourceFlags {
 
 
 impl XcpResourceFlags {
+    /// The resources whose flag is set, in the order CAL_PAG, DAQ, STIM, PGM.
+    pub fn resources(&self) -> Vec<XcpResource> {
+        [(self.cal_page, XcpResource::CalPage), (self.daq, XcpResource::Daq),
+         (self.stim, XcpResource::Stim), (self.pgm, XcpResource::Pgm)]
+            .into_iter()
+            .filter_map(|(set, resource)| set.then_some(resource))
+            .collect()
+    }
 }
 
 impl From<XcpResource> for XcpResourceFlags {
@@ -180,6 +2200,155 @@ impl From<XcpResourceFlags> for u8 {
     }
 }
 
+/// Memory areas erased by PROGRAM_CLEAR in functional access mode.
+#[derive(Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClearAccessFlags {
+    pub calibration: bool,
+    pub code: bool,
+    pub nvram: bool
+}
+
+impl ClearAccessFlags {
+    /// Bits the specification reserves; they must be zero on the wire.
+    pub const RESERVED_MASK: u8 = 0xF8;
+
+    pub fn new() -> ClearAccessFlags {
+        ClearAccessFlags::default()
+    }
+
+    pub fn with_calibration(mut self) -> ClearAccessFlags { self.calibration = true; self }
+    pub fn with_code(mut self) -> ClearAccessFlags { self.code = true; self }
+    pub fn with_nvram(mut self) -> ClearAccessFlags { self.nvram = true; self }
+
+    fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.calibration { names.push("CAL") }
+        if self.code { names.push("CODE") }
+        if self.nvram { names.push("NVRAM") }
+        names
+    }
+}
+
+impl From<ClearAccessFlags> for u8 {
+    fn from(flags: ClearAccessFlags) -> u8 {
+        (if flags.nvram {0x04} else {0x00}) |
+        (if flags.code {0x02} else {0x00}) |
+        (if flags.calibration {0x01} else {0x00})
+    }
+}
+
+/// Fails if any reserved bit is set.
+impl TryFrom<u8> for ClearAccessFlags {
+    type Error = XcpError;
+
+    fn try_from(val: u8) -> Result<ClearAccessFlags, XcpError> {
+        if val & ClearAccessFlags::RESERVED_MASK != 0 {
+            return Err(XcpError::ReservedBitsSet { value: val, reserved: ClearAccessFlags::RESERVED_MASK });
+        }
+        Ok(ClearAccessFlags { calibration: val & 0x01 != 0, code: val & 0x02 != 0, nvram: val & 0x04 != 0 })
+    }
+}
+
+/// Shows the set flags by name, e.g. `CAL | CODE`, or `NONE`.
+impl std::fmt::Display for ClearAccessFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self.names();
+        if names.is_empty() { write!(f, "NONE") } else { write!(f, "{}", names.join(" | ")) }
+    }
+}
+
+impl std::fmt::Debug for ClearAccessFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ClearAccessFlags({})", self)
+    }
+}
+
+/// Access mode of SET_CAL_PAGE and GET_CAL_PAGE.
+#[derive(Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalPageAccessFlags {
+    /// The page is used by the ECU.
+    pub ecu: bool,
+    /// The page is used for XCP access.
+    pub xcp: bool,
+    /// Applies to all segments (SET_CAL_PAGE only).
+    pub all: bool
+}
+
+impl CalPageAccessFlags {
+    /// Bits the specification reserves; they must be zero on the wire.
+    pub const RESERVED_MASK: u8 = 0x7C;
+
+    pub fn new() -> CalPageAccessFlags {
+        CalPageAccessFlags::default()
+    }
+
+    pub fn with_ecu(mut self) -> CalPageAccessFlags { self.ecu = true; self }
+    pub fn with_xcp(mut self) -> CalPageAccessFlags { self.xcp = true; self }
+    pub fn with_all(mut self) -> CalPageAccessFlags { self.all = true; self }
+
+    fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.ecu { names.push("ECU") }
+        if self.xcp { names.push("XCP") }
+        if self.all { names.push("ALL") }
+        names
+    }
+}
+
+impl From<CalPageAccessFlags> for u8 {
+    fn from(flags: CalPageAccessFlags) -> u8 {
+        (if flags.all {0x80} else {0x00}) |
+        (if flags.xcp {0x02} else {0x00}) |
+        (if flags.ecu {0x01} else {0x00})
+    }
+}
+
+/// Fails if any reserved bit is set.
+impl TryFrom<u8> for CalPageAccessFlags {
+    type Error = XcpError;
+
+    fn try_from(val: u8) -> Result<CalPageAccessFlags, XcpError> {
+        if val & CalPageAccessFlags::RESERVED_MASK != 0 {
+            return Err(XcpError::ReservedBitsSet { value: val, reserved: CalPageAccessFlags::RESERVED_MASK });
+        }
+        Ok(CalPageAccessFlags { ecu: val & 0x01 != 0, xcp: val & 0x02 != 0, all: val & 0x80 != 0 })
+    }
+}
+
+/// Shows the set flags by name, e.g. `ECU | XCP`, or `NONE`.
+impl std::fmt::Display for CalPageAccessFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self.names();
+        if names.is_empty() { write!(f, "NONE") } else { write!(f, "{}", names.join(" | ")) }
+    }
+}
+
+impl std::fmt::Debug for CalPageAccessFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CalPageAccessFlags({})", self)
+    }
+}
+
+/// Which party a page switch applies to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PageAccess {
+    Ecu,
+    Xcp,
+    EcuAndXcp,
+}
+
+impl PageAccess {
+    pub fn to_flags(&self) -> CalPageAccessFlags {
+        match self {
+            PageAccess::Ecu => CalPageAccessFlags::new().with_ecu(),
+            PageAccess::Xcp => CalPageAccessFlags::new().with_xcp(),
+            PageAccess::EcuAndXcp => CalPageAccessFlags::new().with_ecu().with_xcp(),
+        }
+    }
+}
+
 bitfield! {
     /// Flags representing XCP resources.
     #[derive(Copy, Clone)]
@@ -194,7 +2363,7 @@ bitfield! {
 
 bitfield! {
     /// Flags representing XCP communication mode.
-    #[derive(Copy, Clone)]
+    #[derive(Copy, Clone, Default)]
     pub struct XcpCommModeBasic(u8);
     impl Debug;
 
@@ -210,6 +2379,22 @@ bitfield! {
     // }
 // }
 
+impl XcpCommModeBasic {
+    /// Byte order the slave uses for multi-byte values.
+    pub fn endianness(&self) -> ByteOrder {
+        if self.byte_order() { ByteOrder::Motorola } else { ByteOrder::Intel }
+    }
+
+    /// Address granularity of the slave. The reserved value 3 is treated as BYTE.
+    pub fn granularity(&self) -> AddressGranularity {
+        match self.address_granularity() {
+            1 => AddressGranularity::Word,
+            2 => AddressGranularity::DWord,
+            _ => AddressGranularity::Byte,
+        }
+    }
+}
+
 impl From<XcpCommModeBasic> for u8 {
     fn from(v: XcpCommModeBasic) -> u8 {
         v.0 as u8
@@ -218,13 +2403,19 @@ impl From<XcpCommModeBasic> for u8 {
 
 #[derive(Debug)]
 pub struct NegativeResponse {
-    pub error_code: XcpErrorCode
+    pub error_code: XcpErrorCode,
+    /// Bytes following the error code. Some commands use them to report details,
+    /// e.g. the maximum block size of BUILD_CHECKSUM.
+    pub parameters: XcpFrameBuf
 }
 
 impl XcpResponse for NegativeResponse {
+    const MIN_LENGTH: usize = 2;
+
     fn from_can_frame(frame: &[u8]) -> Self {
         NegativeResponse {
-            error_code: XcpErrorCode::from_code(frame[1])
+            error_code: XcpErrorCode::from_code(frame[1]),
+            parameters: XcpFrameBuf::from_prefix(frame.get(2..).unwrap_or_default())
         }
     }
 }
@@ -235,3 +2426,368 @@ pub enum XcpResponseType {
     PositiveUnlockResponse(XcpResponseFrame<UnlockResponse>),
     NegativeResponse(XcpResponseFrame<NegativeResponse>)
 }
+
+/// Overload indication mechanism advertised in DAQ_PROPERTIES (bits 6 and 7).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DaqOverloadIndication {
+    /// The slave gives no indication of an overload.
+    #[default]
+    NoIndication,
+    /// The most significant bit of the PID is set in the first DTO after an overload.
+    PidMsb,
+    /// The slave transmits an EV_DAQ_OVERLOAD event packet.
+    EventPacket,
+}
+
+impl DaqOverloadIndication {
+    /// Decode the overload indication from a raw DAQ_PROPERTIES byte.
+    ///
+    /// Returns `None` if both OVERLOAD_MSB and OVERLOAD_EVENT are set, which the
+    /// specification does not allow.
+    pub fn from_properties(properties: u8) -> Option<DaqOverloadIndication> {
+        match (properties & 0x40 != 0, properties & 0x80 != 0) {
+            (false, false) => Some(DaqOverloadIndication::NoIndication),
+            (true, false) => Some(DaqOverloadIndication::PidMsb),
+            (false, true) => Some(DaqOverloadIndication::EventPacket),
+            (true, true) => None,
+        }
+    }
+}
+
+/// ODT optimisation method advertised in the DAQ_KEY_BYTE (bits 0..3).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DaqOptimisationType {
+    #[default]
+    Default = 0x00,
+    OdtType16 = 0x01,
+    OdtType32 = 0x02,
+    OdtType64 = 0x03,
+    OdtTypeAlignment = 0x04,
+    MaxEntrySize = 0x05,
+}
+
+impl DaqOptimisationType {
+    /// Decode the optimisation type from a raw DAQ_KEY_BYTE.
+    ///
+    /// Unknown values fall back to `Default`, which places no constraint on entry ordering.
+    pub fn from_key_byte(key_byte: u8) -> DaqOptimisationType {
+        match key_byte & 0x0F {
+            0x01 => DaqOptimisationType::OdtType16,
+            0x02 => DaqOptimisationType::OdtType32,
+            0x03 => DaqOptimisationType::OdtType64,
+            0x04 => DaqOptimisationType::OdtTypeAlignment,
+            0x05 => DaqOptimisationType::MaxEntrySize,
+            _ => DaqOptimisationType::Default,
+        }
+    }
+
+    /// Element size, in bytes, the slave prefers ODT entries to be grouped by, if any.
+    pub fn preferred_entry_size(&self) -> Option<usize> {
+        match self {
+            DaqOptimisationType::OdtType16 => Some(2),
+            DaqOptimisationType::OdtType32 => Some(4),
+            DaqOptimisationType::OdtType64 => Some(8),
+            _ => None,
+        }
+    }
+}
+
+/// XCP "Program Start" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramStartCommand;
+
+impl XcpCommand for ProgramStartCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ProgramStart }
+}
+
+bitfield! {
+    /// Communication modes the slave supports while programming.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpCommModePgm(u8);
+    impl Debug;
+
+    pub master_block_mode, set_master_block_mode: 0;
+    pub interleaved_mode, set_interleaved_mode: 1;
+    pub slave_block_mode, set_slave_block_mode: 6;
+}
+
+/// XCP "Program Start" response structure.
+///
+/// The limits apply to PROGRAM and PROGRAM_NEXT until PROGRAM_RESET.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramStartResponse {
+    pub comm_mode_pgm: XcpCommModePgm,
+    /// Largest command packet while programming.
+    pub max_cto_pgm: u8,
+    /// Maximum number of frames in a master block transfer.
+    pub max_bs_pgm: u8,
+    /// Minimum separation time between frames of a block transfer, in units of 100 µs.
+    pub min_st_pgm: u8,
+    /// Maximum number of queued requests in interleaved mode.
+    pub queue_size_pgm: u8,
+}
+
+impl XcpResponse for ProgramStartResponse {
+    const MIN_LENGTH: usize = 7;
+
+    fn from_can_frame(frame: &[u8]) -> ProgramStartResponse {
+        ProgramStartResponse {
+            comm_mode_pgm: XcpCommModePgm(frame[2]),
+            max_cto_pgm: frame[3],
+            max_bs_pgm: frame[4],
+            min_st_pgm: frame[5],
+            queue_size_pgm: frame[6],
+        }
+    }
+}
+
+/// How PROGRAM_CLEAR selects the memory to clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramClearMode {
+    /// The range starts at the MTA and is `clear_range` elements long.
+    Absolute = 0x00,
+    /// `clear_range` is a bit mask of functional areas (calibration data, code, ...).
+    Functional = 0x01,
+}
+
+/// XCP "Program Clear" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramClearCommand {
+    pub mode: ProgramClearMode,
+    pub clear_range: u32,
+    pub byte_order: ByteOrder,
+}
+
+impl XcpCommand for ProgramClearCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        frame_data.push(0x00)?; // reserved
+        frame_data.push(0x00)?; // reserved
+        frame_data.extend_from_slice(&self.byte_order.u32_bytes(self.clear_range))?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ProgramClear }
+}
+
+/// XCP "Program" command structure. The layout is the one of DOWNLOAD.
+///
+/// A command without elements marks the end of a memory segment.
+#[derive(Debug, Clone)]
+pub struct ProgramCommand {
+    pub n_elements: u8,
+    pub data: Vec<u8>,
+    pub granularity: AddressGranularity,
+}
+
+impl ProgramCommand {
+    /// Splits `data` into PROGRAM commands carrying as many whole elements as fit
+    /// into `max_cto_pgm` each. `data` must be a whole number of elements.
+    pub fn split(data: &[u8], granularity: AddressGranularity, max_cto_pgm: usize) -> Vec<ProgramCommand> {
+        let ag = granularity.size();
+        let max_elements = usize::min((max_cto_pgm - granularity.download_offset()) / ag, u8::MAX as usize);
+
+        data.chunks(max_elements * ag)
+            .map(|chunk| ProgramCommand { n_elements: (chunk.len() / ag) as u8, data: chunk.to_vec(), granularity })
+            .collect()
+    }
+}
+
+impl XcpCommand for ProgramCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        download_frame(self.get_code(), self.n_elements, self.granularity, &self.data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::Program }
+}
+
+/// XCP "Program Next" command structure, continuing a master block mode PROGRAM.
+#[derive(Debug, Clone)]
+pub struct ProgramNextCommand {
+    /// Elements remaining in the block, including the ones in this frame.
+    pub n_elements: u8,
+    pub data: Vec<u8>,
+    pub granularity: AddressGranularity,
+}
+
+impl ProgramNextCommand {
+    /// Splits one block of at most 255 elements into the initial PROGRAM and the
+    /// PROGRAM_NEXT frames following it, like `DownloadNextCommand::block`.
+    pub fn block(data: &[u8], granularity: AddressGranularity, max_cto_pgm: usize) -> (ProgramCommand, Vec<ProgramNextCommand>) {
+        let ag = granularity.size();
+        let per_frame = (max_cto_pgm - granularity.download_offset()) / ag;
+        let mut remaining = data.len() / ag;
+        let mut chunks = data.chunks(per_frame * ag);
+
+        let first = chunks.next().unwrap_or_default();
+        let program = ProgramCommand { n_elements: remaining as u8, data: first.to_vec(), granularity };
+        remaining -= first.len() / ag;
+
+        let next = chunks.map(|chunk| {
+            let command = ProgramNextCommand { n_elements: remaining as u8, data: chunk.to_vec(), granularity };
+            remaining -= chunk.len() / ag;
+            command
+        }).collect();
+
+        (program, next)
+    }
+}
+
+impl XcpCommand for ProgramNextCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        download_frame(self.get_code(), self.n_elements, self.granularity, &self.data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ProgramNext }
+}
+
+/// XCP "Program Format" command structure, announcing the format of the data the
+/// following PROGRAM commands carry. 0 selects the default for every method.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgramFormatCommand {
+    pub compression_method: u8,
+    pub encryption_method: u8,
+    pub programming_method: u8,
+    pub access_method: u8,
+}
+
+impl XcpCommand for ProgramFormatCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.compression_method)?;
+        frame_data.push(self.encryption_method)?;
+        frame_data.push(self.programming_method)?;
+        frame_data.push(self.access_method)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ProgramFormat }
+}
+
+/// XCP "Program Reset" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramResetCommand;
+
+impl XcpCommand for ProgramResetCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::ProgramReset }
+}
+
+/// XCP "Get Pgm Processor Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPgmProcessorInfoCommand;
+
+impl XcpCommand for GetPgmProcessorInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetPgmProcessorInfo }
+}
+
+bitfield! {
+    /// Clear modes and data formats supported by the programming processor.
+    #[derive(Copy, Clone, Default)]
+    pub struct XcpPgmProperties(u8);
+    impl Debug;
+
+    pub absolute_mode, set_absolute_mode: 0;
+    pub functional_mode, set_functional_mode: 1;
+    pub compression_supported, set_compression_supported: 2;
+    pub compression_required, set_compression_required: 3;
+    pub encryption_supported, set_encryption_supported: 4;
+    pub encryption_required, set_encryption_required: 5;
+    pub non_seq_pgm_supported, set_non_seq_pgm_supported: 6;
+    pub non_seq_pgm_required, set_non_seq_pgm_required: 7;
+}
+
+/// XCP "Get Pgm Processor Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPgmProcessorInfoResponse {
+    pub properties: XcpPgmProperties,
+    /// Number of sectors; they are numbered from 0 to `max_sector - 1`.
+    pub max_sector: u8,
+}
+
+impl XcpResponse for GetPgmProcessorInfoResponse {
+    const MIN_LENGTH: usize = 3;
+
+    fn from_can_frame(frame: &[u8]) -> GetPgmProcessorInfoResponse {
+        GetPgmProcessorInfoResponse {
+            properties: XcpPgmProperties(frame[1]),
+            max_sector: frame[2],
+        }
+    }
+}
+
+/// Value requested with GET_SECTOR_INFO.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SectorInfoMode {
+    StartAddress = 0x00,
+    Length = 0x01,
+}
+
+/// XCP "Get Sector Info" command structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetSectorInfoCommand {
+    pub mode: SectorInfoMode,
+    pub sector: u8,
+}
+
+impl XcpCommand for GetSectorInfoCommand {
+    fn to_can_frame(&self) -> Result<XcpFrameBuf, XcpError> {
+        let mut frame_data = XcpFrameBuf::new();
+        frame_data.push(self.get_code().to_code())?;
+        frame_data.push(self.mode as u8)?;
+        frame_data.push(self.sector)?;
+        Ok(frame_data)
+    }
+
+    fn get_code(&self) -> XcpCommandCode { XcpCommandCode::GetSectorInfo }
+}
+
+/// XCP "Get Sector Info" response structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GetSectorInfoResponse {
+    /// Position of the sector in the order sectors must be cleared in.
+    pub clear_sequence_number: u8,
+    /// Position of the sector in the order sectors must be programmed in.
+    pub program_sequence_number: u8,
+    /// 0 for sequential programming, other values are slave specific.
+    pub programming_method: u8,
+    /// Start address or length of the sector, depending on the mode.
+    pub sector_info: u32,
+}
+
+impl XcpResponse for GetSectorInfoResponse {
+    const MIN_LENGTH: usize = 8;
+
+    fn from_can_frame(frame: &[u8]) -> GetSectorInfoResponse {
+        GetSectorInfoResponse::from_can_frame_with_mode(frame, XcpCommModeBasic::default())
+    }
+
+    fn from_can_frame_with_mode(frame: &[u8], mode: XcpCommModeBasic) -> GetSectorInfoResponse {
+        GetSectorInfoResponse {
+            clear_sequence_number: frame[1],
+            program_sequence_number: frame[2],
+            programming_method: frame[3],
+            sector_info: mode.endianness().read_u32(&frame[4..8]),
+        }
+    }
+}